@@ -78,3 +78,68 @@ fn parallel_requests(bencher: &mut test::Bencher) {
         }
     });
 }
+
+// Compares the throughput of large file downloads with and without page cache hints, with the
+// `fadvise` feature enabled. Run manually with
+// `TINY_HTTP_BENCH_FILE=<large file> cargo bench --features fadvise -- --ignored large_file`.
+#[bench]
+#[ignore]
+fn large_file_with_hints(bencher: &mut test::Bencher) {
+    large_file(
+        bencher,
+        &[
+            tiny_http::FileAccessHint::Sequential,
+            tiny_http::FileAccessHint::DropCacheAfterSend,
+        ],
+    );
+}
+
+#[bench]
+#[ignore]
+fn large_file_without_hints(bencher: &mut test::Bencher) {
+    large_file(bencher, &[]);
+}
+
+fn large_file(bencher: &mut test::Bencher, hints: &[tiny_http::FileAccessHint]) {
+    use std::io::Read;
+
+    let path = match std::env::var_os("TINY_HTTP_BENCH_FILE") {
+        Some(path) => path,
+        None => return,
+    };
+
+    let server = tiny_http::Server::http("0.0.0.0:0").unwrap();
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut body = Vec::new();
+
+    bencher.iter(|| {
+        (write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+
+        let request = server.recv().unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        let length = file.metadata().unwrap().len() as usize;
+        let response = hints
+            .iter()
+            .fold(tiny_http::Response::from_file(file), |response, hint| {
+                response.with_file_access_hint(*hint)
+            })
+            .with_chunked_threshold(usize::MAX);
+        let sender = std::thread::spawn(move || request.respond(response).unwrap());
+
+        // skipping the head, then reading the whole body
+        body.clear();
+        let mut head = Vec::new();
+        let mut byte = [0];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        (&mut stream)
+            .take(length as u64)
+            .read_to_end(&mut body)
+            .unwrap();
+        assert_eq!(body.len(), length);
+        sender.join().unwrap();
+    });
+}
@@ -1,9 +1,9 @@
 extern crate tiny_http;
 
 fn main() {
-    use tiny_http::{Response, Server};
+    use tiny_http::{Response, ServerBuilder};
 
-    let server = Server::http("0.0.0.0:8000").unwrap();
+    let server = ServerBuilder::new().with_port(8000).build().unwrap();
 
     for request in server.incoming_requests() {
         println!(
@@ -0,0 +1,226 @@
+//! Chained construction of a `Server`, see `ServerBuilder`.
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::config::{BufferingMode, ConnectionLimitMode, ServerConfigAdvanced};
+use crate::connection::ConfigListenAddr;
+use crate::{PreparedServer, Server, ServerConfig, SslConfig};
+
+/// Builder of a `Server`, an alternative to filling a `ServerConfig`.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use tiny_http::ServerBuilder;
+/// let server = ServerBuilder::new()
+///     .with_port(8000)
+///     .with_keep_alive_timeout(Duration::from_secs(30))
+///     .with_max_body_size(1 << 20)
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// The settings that have no shortcut here can be set with `with_advanced()`.
+#[derive(Debug, Clone)]
+pub struct ServerBuilder {
+    config: ServerConfig,
+    advanced: ServerConfigAdvanced,
+}
+
+impl ServerBuilder {
+    /// Starts a builder listening on port 80 of every IPv4 interface, without SSL and with
+    /// the default `ServerConfigAdvanced`.
+    pub fn new() -> ServerBuilder {
+        ServerBuilder {
+            config: ServerConfig {
+                addr: ConfigListenAddr::IP(vec![SocketAddr::from(([0, 0, 0, 0], 80))]),
+                ssl: None,
+            },
+            advanced: ServerConfigAdvanced::default(),
+        }
+    }
+
+    /// Replaces the addresses to listen to.
+    pub fn with_addr(mut self, addr: ConfigListenAddr) -> ServerBuilder {
+        self.config.addr = addr;
+        self
+    }
+
+    /// Listens on this port of every IPv4 interface.
+    pub fn with_port(self, port: u16) -> ServerBuilder {
+        self.with_addr(ConfigListenAddr::IP(vec![SocketAddr::from((
+            [0, 0, 0, 0],
+            port,
+        ))]))
+    }
+
+    /// Listens on a port chosen by the system, which `Server::server_addr()` returns once the
+    /// server is built.
+    pub fn with_random_port(self) -> ServerBuilder {
+        self.with_port(0)
+    }
+
+    /// Encrypts the communications with SSL, which requires one of the `ssl-*` features.
+    pub fn with_ssl(mut self, ssl: SslConfig) -> ServerBuilder {
+        self.config.ssl = Some(ssl);
+        self
+    }
+
+    /// Replaces all the less commonly needed settings, including the ones set by the other
+    /// methods of this builder.
+    pub fn with_advanced(mut self, advanced: ServerConfigAdvanced) -> ServerBuilder {
+        self.advanced = advanced;
+        self
+    }
+
+    /// See `ServerConfigAdvanced::with_worker_threads()`.
+    pub fn with_worker_threads(self, count: usize) -> ServerBuilder {
+        self.map_advanced(|advanced| advanced.with_worker_threads(count))
+    }
+
+    /// See `ServerConfigAdvanced::with_read_buffering()`.
+    pub fn with_read_buffering(self, mode: BufferingMode) -> ServerBuilder {
+        self.map_advanced(|advanced| advanced.with_read_buffering(mode))
+    }
+
+    /// See `ServerConfigAdvanced::with_write_buffering()`.
+    pub fn with_write_buffering(self, mode: BufferingMode) -> ServerBuilder {
+        self.map_advanced(|advanced| advanced.with_write_buffering(mode))
+    }
+
+    /// See `ServerConfigAdvanced::with_keep_alive_timeout()`.
+    pub fn with_keep_alive_timeout(self, timeout: Duration) -> ServerBuilder {
+        self.map_advanced(|advanced| advanced.with_keep_alive_timeout(timeout))
+    }
+
+    /// See `ServerConfigAdvanced::with_max_requests_per_connection()`.
+    pub fn with_max_requests_per_connection(self, requests: usize) -> ServerBuilder {
+        self.map_advanced(|advanced| advanced.with_max_requests_per_connection(requests))
+    }
+
+    /// See `ServerConfigAdvanced::with_max_header_read_time()`.
+    pub fn with_max_header_read_time(self, time: Duration) -> ServerBuilder {
+        self.map_advanced(|advanced| advanced.with_max_header_read_time(time))
+    }
+
+    /// See `ServerConfigAdvanced::with_max_body_size()`.
+    pub fn with_max_body_size(self, bytes: u64) -> ServerBuilder {
+        self.map_advanced(|advanced| advanced.with_max_body_size(bytes))
+    }
+
+    /// See `ServerConfigAdvanced::with_max_connections()`.
+    pub fn with_max_connections(self, limit: usize, mode: ConnectionLimitMode) -> ServerBuilder {
+        self.map_advanced(|advanced| advanced.with_max_connections(limit, mode))
+    }
+
+    /// Returns the addresses and SSL settings built so far.
+    ///
+    /// The `ServerConfigAdvanced` settings are not part of a `ServerConfig` and are left out.
+    pub fn into_config(self) -> ServerConfig {
+        self.config
+    }
+
+    /// Builds the server, see `Server::new()`.
+    pub fn build(self) -> Result<Server, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(self.prepare()?.start())
+    }
+
+    /// Binds the server without accepting connections yet, see `Server::prepare()`.
+    pub fn prepare(self) -> Result<PreparedServer, Box<dyn Error + Send + Sync + 'static>> {
+        Server::prepare_with_advanced(self.config, self.advanced)
+    }
+
+    fn map_advanced(
+        mut self,
+        f: impl FnOnce(ServerConfigAdvanced) -> ServerConfigAdvanced,
+    ) -> ServerBuilder {
+        self.advanced = f(self.advanced);
+        self
+    }
+}
+
+impl Default for ServerBuilder {
+    fn default() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+}
+
+impl From<ServerConfig> for ServerBuilder {
+    fn from(config: ServerConfig) -> ServerBuilder {
+        ServerBuilder {
+            config,
+            advanced: ServerConfigAdvanced::default(),
+        }
+    }
+}
+
+impl From<ServerBuilder> for ServerConfig {
+    fn from(builder: ServerBuilder) -> ServerConfig {
+        builder.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ServerBuilder;
+    use crate::{
+        BufferingMode, ConfigListenAddr, ConnectionLimitMode, ServerConfig, ServerConfigAdvanced,
+        SslConfig,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn config_round_trip() {
+        let config = ServerConfig {
+            addr: ConfigListenAddr::from_socket_addrs("127.0.0.1:8080").unwrap(),
+            ssl: Some(SslConfig {
+                certificate: b"certificate".to_vec(),
+                private_key: b"key".to_vec(),
+            }),
+        };
+        let expected = format!("{:?}", config);
+        let config = ServerConfig::from(ServerBuilder::from(config));
+        assert_eq!(format!("{:?}", config), expected);
+    }
+
+    #[test]
+    fn setters_match_advanced_config() {
+        let builder = ServerBuilder::new()
+            .with_random_port()
+            .with_worker_threads(2)
+            .with_read_buffering(BufferingMode::Unbuffered)
+            .with_write_buffering(BufferingMode::Unbuffered)
+            .with_keep_alive_timeout(Duration::from_secs(5))
+            .with_max_requests_per_connection(100)
+            .with_max_header_read_time(Duration::from_secs(2))
+            .with_max_body_size(4096)
+            .with_max_connections(10, ConnectionLimitMode::RespondUnavailable);
+
+        let advanced = ServerConfigAdvanced::default()
+            .with_worker_threads(2)
+            .with_read_buffering(BufferingMode::Unbuffered)
+            .with_write_buffering(BufferingMode::Unbuffered)
+            .with_keep_alive_timeout(Duration::from_secs(5))
+            .with_max_requests_per_connection(100)
+            .with_max_header_read_time(Duration::from_secs(2))
+            .with_max_body_size(4096)
+            .with_max_connections(10, ConnectionLimitMode::RespondUnavailable);
+        assert_eq!(format!("{:?}", builder.advanced), format!("{:?}", advanced));
+        let config = builder.into_config();
+        assert_eq!(
+            format!("{:?}", config.addr),
+            format!(
+                "{:?}",
+                ConfigListenAddr::from_socket_addrs("0.0.0.0:0").unwrap()
+            )
+        );
+        assert!(config.ssl.is_none());
+    }
+
+    #[test]
+    fn builds_a_server() {
+        let server = ServerBuilder::new().with_random_port().build().unwrap();
+        assert_ne!(server.server_addr().to_ip().unwrap().port(), 0);
+    }
+}
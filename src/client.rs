@@ -2,21 +2,43 @@ use ascii::AsciiString;
 
 use std::io::Error as IoError;
 use std::io::Result as IoResult;
-use std::io::{BufReader, BufWriter, ErrorKind, Read};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
 
-use std::net::SocketAddr;
-use std::str::FromStr;
+use std::net::{Shutdown, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::common::{HTTPVersion, Method};
-use crate::util::RefinedTcpStream;
+use crate::common::{HTTPVersion, Header, Method};
+use crate::config::ServerConfigAdvanced;
+use crate::connection::Connection;
+use crate::handoff::Handoff;
+use crate::log;
+#[cfg(feature = "profiling")]
+use crate::profiling::{PhaseTimer, Profile};
+use crate::request::{BodyKind, RequestCreationError};
+use crate::response::PrintContext;
+use crate::ssl::TlsSession;
+use crate::stats::Counters;
+use crate::target::{self, RequestTarget};
+#[cfg(feature = "tcp-diagnostics")]
+use crate::tcp_diagnostics::TcpAddrs;
+use crate::util::{Lingerer, RefinedTcpStream};
 use crate::util::{SequentialReader, SequentialReaderBuilder, SequentialWriterBuilder};
-use crate::Request;
+use crate::{Request, Response};
+
+/// Initial capacity of the buffer that holds the line being read.
+const LINE_BUFFER_CAPACITY: usize = 256;
+
+/// A connection that received no request during this interval is considered idle, and its
+/// buffers may be shrunk.
+const BUFFER_SHRINK_INTERVAL: Duration = Duration::from_secs(1);
 
 /// A ClientConnection is an object that will store a socket to a client
 /// and return Request objects.
 pub struct ClientConnection {
     // address of the client
-    remote_addr: IoResult<Option<SocketAddr>>,
+    remote_addr: Option<SocketAddr>,
 
     // sequence of Readers to the stream, so that the data is not read in
     //  the wrong order
@@ -32,8 +54,88 @@ pub struct ClientConnection {
     // set to true if we know that the previous request is the last one
     no_more_requests: bool,
 
+    // number of the connection, see `Request::connection_id`
+    id: u64,
+
+    // number of requests returned so far, see `with_max_requests_per_connection`
+    requests: usize,
+
     // true if the connection goes through SSL
     secure: bool,
+
+    // what the TLS handshake told about the client, None for plain connections
+    tls: Option<Arc<TlsSession>>,
+
+    // settings of the server that accepted the connection
+    config: Arc<ServerConfigAdvanced>,
+
+    // true if the requests can take the connection away from the server
+    handoff: bool,
+
+    // lets the requests stop the connection from reading other requests, its socket also
+    // receives the read timeouts
+    closer: Arc<ConnectionCloser>,
+
+    // true if the read timeout of the socket was set to enforce the header deadline
+    header_deadline_armed: bool,
+
+    // reused to read each line of the headers
+    line_buf: Vec<u8>,
+
+    // when the headers of the last request were read
+    last_request: Instant,
+
+    // source of the current time, replaced by tests
+    clock: fn() -> Instant,
+
+    stats: Arc<Counters>,
+
+    // addresses of the socket if this is a plain TCP connection
+    #[cfg(feature = "tcp-diagnostics")]
+    tcp_addrs: Option<TcpAddrs>,
+
+    // histograms of the server
+    #[cfg(feature = "profiling")]
+    profile: Arc<Profile>,
+}
+
+/// Lets the requests of a connection prevent it from reading more requests, once their
+/// response can't be followed by another one.
+#[derive(Debug)]
+pub(crate) struct ConnectionCloser {
+    closed: AtomicBool,
+    // handle to the socket, under the TLS layer if there is one; shut down to wake the
+    // connection up if it is waiting for the next request
+    socket: Option<Connection>,
+    // None for the connections of `testing::run_raw`
+    lingerer: Option<Arc<Lingerer>>,
+}
+
+impl ConnectionCloser {
+    /// Stops reading requests from the connection. The requests that were already read can
+    /// still be answered.
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        if let Some(ref socket) = self.socket {
+            let _ = socket.shutdown(Shutdown::Read);
+        }
+    }
+
+    /// Closes the connection once its last response was written, without resetting it even
+    /// if the client sent more, see `Lingerer`.
+    pub(crate) fn close_gracefully(&self) {
+        // shutting down the read half already woke up the connection, if needed
+        let read_shut_down = self.closed.swap(true, Ordering::SeqCst);
+        if let (Some(ref socket), Some(ref lingerer)) = (&self.socket, &self.lingerer) {
+            if let Ok(socket) = socket.try_clone() {
+                lingerer.close(socket, read_shut_down);
+            }
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
 }
 
 /// Error that can happen when reading a request.
@@ -43,129 +145,523 @@ enum ReadError {
     WrongHeader(HTTPVersion),
     /// the client sent an unrecognized `Expect` header
     ExpectationFailed(HTTPVersion),
+    /// the client didn't start sending a request before the keep-alive timeout
+    KeepAliveTimeout,
+    /// the request line is over the limit of the header lines
+    RequestLineTooLong,
+    /// a header line or all the headers are over the configured limits
+    HeadersTooLarge(HTTPVersion),
+    /// the `Content-Length` of the request is over the configured limit
+    BodyTooLarge(HTTPVersion),
+    /// the length of the body can't be told for sure from the headers
+    AmbiguousLength(HTTPVersion),
+    /// the body has a `Content-Encoding`, which the server is configured to reject
+    UnsupportedContentEncoding(HTTPVersion),
     ReadIoError(IoError),
 }
 
 impl ClientConnection {
     /// Creates a new `ClientConnection` that takes ownership of the `TcpStream`.
-    pub fn new(
+    ///
+    /// `remote_addr` is the result of querying the peer address of the socket. This can fail
+    /// if the client already went away, in which case the requests are still read but their
+    /// remote address is unknown.
+    pub(crate) fn new(
         write_socket: RefinedTcpStream,
-        mut read_socket: RefinedTcpStream,
+        read_socket: RefinedTcpStream,
+        remote_addr: IoResult<Option<SocketAddr>>,
+        config: Arc<ServerConfigAdvanced>,
+        stats: &Arc<Counters>,
+        lingerer: Option<Arc<Lingerer>>,
     ) -> ClientConnection {
-        let remote_addr = read_socket.peer_addr();
+        let remote_addr = match remote_addr {
+            Ok(addr) => addr,
+            Err(_err) => {
+                log::debug!("Unable to get the address of a new client: {}", _err);
+                stats.unknown_peer_connections.fetch_add(1, Relaxed);
+                None
+            }
+        };
         let secure = read_socket.secure();
+        let tls = read_socket.tls_session().map(Arc::new);
+        let handoff = config.connection_handoff && read_socket.is_tcp();
+        #[cfg(feature = "tcp-diagnostics")]
+        let tcp_addrs = read_socket.tcp_stream().and_then(|stream| {
+            Some(TcpAddrs {
+                local: stream.local_addr().ok()?,
+                peer: stream.peer_addr().ok()?,
+            })
+        });
+
+        let closer = Arc::new(ConnectionCloser {
+            closed: AtomicBool::new(false),
+            socket: read_socket.try_clone_socket().ok().flatten(),
+            lingerer,
+        });
 
-        let mut source = SequentialReaderBuilder::new(BufReader::with_capacity(1024, read_socket));
-        let first_header = source.next().unwrap();
+        let mut source = SequentialReaderBuilder::new(BufReader::with_capacity(
+            config.read_buffering.capacity(),
+            read_socket,
+        ));
+        let first_header = source.next_reader();
 
         ClientConnection {
             source,
-            sink: SequentialWriterBuilder::new(BufWriter::with_capacity(1024, write_socket)),
+            sink: SequentialWriterBuilder::new(BufWriter::with_capacity(
+                config.write_buffering.capacity(),
+                write_socket,
+            )),
             remote_addr,
             next_header_source: first_header,
             no_more_requests: false,
+            id: stats.connections.fetch_add(1, Relaxed) + 1,
+            requests: 0,
             secure,
+            tls,
+            config,
+            handoff,
+            closer,
+            header_deadline_armed: false,
+            line_buf: Vec::with_capacity(LINE_BUFFER_CAPACITY),
+            last_request: Instant::now(),
+            clock: Instant::now,
+            stats: stats.clone(),
+            #[cfg(feature = "tcp-diagnostics")]
+            tcp_addrs,
+            #[cfg(feature = "profiling")]
+            profile: stats.profile.clone(),
         }
     }
 
+    /// Number of the connection, the first connection accepted by the server being 1.
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
     /// true if the connection is HTTPS
     pub fn secure(&self) -> bool {
         self.secure
     }
 
+    /// Settings of the server this connection belongs to.
+    pub fn config(&self) -> &ServerConfigAdvanced {
+        &self.config
+    }
+
     /// Reads the next line from self.next_header_source.
     ///
     /// Reads until `CRLF` is reached. The next read will start
     ///  at the first byte of the new line.
-    fn read_next_line(&mut self) -> IoResult<AsciiString> {
-        let mut buf = Vec::new();
+    ///
+    /// Fails with `ErrorKind::TimedOut` if the line isn't complete at `deadline`, and returns
+    /// `Ok(None)` as soon as the line is longer than `max_len`.
+    fn read_next_line(
+        &mut self,
+        deadline: Option<Instant>,
+        max_len: Option<usize>,
+    ) -> IoResult<Option<AsciiString>> {
+        self.line_buf.clear();
         let mut prev_byte_was_cr = false;
 
         loop {
+            if let Some(deadline) = deadline {
+                self.arm_header_deadline(deadline)?;
+            }
+
+            // `next_header_source` wraps a `BufReader`, so reading byte by byte is cheap
+            #[allow(clippy::unbuffered_bytes)]
             let byte = self.next_header_source.by_ref().bytes().next();
 
             let byte = match byte {
+                Some(Err(ref err)) if err.kind() == ErrorKind::WouldBlock => {
+                    return Err(IoError::new(ErrorKind::TimedOut, "Header read deadline"))
+                }
                 Some(b) => b?,
                 None => return Err(IoError::new(ErrorKind::ConnectionAborted, "Unexpected EOF")),
             };
 
             if byte == b'\n' && prev_byte_was_cr {
-                buf.pop(); // removing the '\r'
-                return AsciiString::from_ascii(buf)
+                self.line_buf.pop(); // removing the '\r'
+                return AsciiString::from_ascii(self.line_buf.as_slice())
+                    .map(Some)
                     .map_err(|_| IoError::new(ErrorKind::InvalidInput, "Header is not in ASCII"));
             }
 
             prev_byte_was_cr = byte == b'\r';
 
-            buf.push(byte);
+            self.line_buf.push(byte);
+
+            // a '\r' at the end may be the start of the line break
+            if let Some(max_len) = max_len {
+                if self.line_buf.len() - usize::from(prev_byte_was_cr) > max_len {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Makes the next read from the socket fail once `deadline` is reached, if the bytes
+    /// already buffered don't suffice.
+    fn arm_header_deadline(&mut self, deadline: Instant) -> IoResult<()> {
+        let socket = match self.closer.socket {
+            Some(ref socket) => socket,
+            None => return Ok(()),
+        };
+        match self.next_header_source.try_inner_mut() {
+            Some(source) if source.buffer().is_empty() => (),
+            _ => return Ok(()),
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return Err(IoError::new(ErrorKind::TimedOut, "Header read deadline"));
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        self.header_deadline_armed = true;
+        Ok(())
+    }
+
+    /// Replaces the read timeout set by `arm_header_deadline()` with the body read timeout, so
+    /// that the body of the request can be read at any pace unless it is configured.
+    fn disarm_header_deadline(&mut self) -> IoResult<()> {
+        let body_timeout = self.config.body_read_timeout;
+        if !self.header_deadline_armed && body_timeout.is_none() {
+            return Ok(());
+        }
+        if let Some(ref socket) = self.closer.socket {
+            // removed by `wait_for_request()` once the body is read
+            socket.set_read_timeout(body_timeout)?;
+        }
+        self.header_deadline_armed = false;
+        Ok(())
+    }
+
+    /// Waits for the first byte of the next request, for at most the keep-alive timeout if
+    /// there is one.
+    ///
+    /// The buffers that grew are shrunk if the client stays idle for `BUFFER_SHRINK_INTERVAL`
+    /// meanwhile. The errors of the socket are left for `read_next_line()` to report.
+    fn wait_for_request(&mut self) -> Result<(), ReadError> {
+        let grown = self.has_grown_buffers();
+        let socket = match self.closer.socket {
+            Some(ref socket) => socket,
+            None => return Ok(()),
+        };
+        // the previous request may still be reading its body, which must not time out
+        let source = match self.next_header_source.wait_inner_mut() {
+            Some(source) => source,
+            None => return Ok(()),
+        };
+        // the body read timeout of the previous request doesn't apply to this one
+        if self.config.body_read_timeout.is_some() {
+            socket
+                .set_read_timeout(None)
+                .map_err(ReadError::ReadIoError)?;
+        }
+        if !source.buffer().is_empty() {
+            return Ok(());
+        }
+
+        let mut timeout = self.config.keep_alive_timeout;
+        // a client that stays idle for a whole interval doesn't get to keep the grown buffers,
+        // whether or not it sends another request later
+        if grown {
+            let interval = timeout.map_or(BUFFER_SHRINK_INTERVAL, |timeout| {
+                timeout.min(BUFFER_SHRINK_INTERVAL)
+            });
+            if wait_readable(socket, source, Some(interval))? {
+                return Ok(());
+            }
+            shrink_line_buf(&mut self.line_buf, &self.stats);
+            timeout = timeout.map(|timeout| timeout - interval);
+            if timeout == Some(Duration::from_secs(0)) {
+                return Err(ReadError::KeepAliveTimeout);
+            }
+        }
+
+        if wait_readable(socket, source, timeout)? {
+            Ok(())
+        } else {
+            Err(ReadError::KeepAliveTimeout)
+        }
+    }
+
+    /// Returns true if the buffers grew above
+    /// `ServerConfigAdvanced::with_buffer_shrink_threshold`.
+    fn has_grown_buffers(&self) -> bool {
+        match self.config.buffer_shrink_threshold {
+            Some(threshold) => self.line_buf.capacity() > threshold,
+            None => false,
+        }
+    }
+
+    /// Shrinks the grown buffers unless the client kept sending requests and is likely to need
+    /// them again.
+    ///
+    /// Called when the request line of a new request is read, which ends the idle time since
+    /// the last request. The idle connections are also shrunk while they wait, see
+    /// `wait_for_request()`.
+    fn shrink_idle_buffers(&mut self) {
+        let idle = (self.clock)().saturating_duration_since(self.last_request);
+        if idle >= BUFFER_SHRINK_INTERVAL && self.has_grown_buffers() {
+            shrink_line_buf(&mut self.line_buf, &self.stats);
+        }
+    }
+
+    /// Discards the bytes that the client sent after the last request of the connection, and
+    /// that were already read from the socket.
+    ///
+    /// Does nothing if the last request is still reading its body, as its reader is dropped
+    /// along with the connection anyway.
+    fn discard_surplus(&mut self) {
+        let source = match self.next_header_source.try_inner_mut() {
+            Some(source) => source,
+            None => return,
+        };
+        let surplus = source.buffer().len();
+        if surplus == 0 {
+            return;
         }
+        source.consume(surplus);
+
+        log::debug!(
+            "conn#{}: {} surplus bytes after close (from {:?})",
+            self.id,
+            surplus,
+            self.remote_addr
+        );
+        self.stats
+            .surplus_bytes_after_close
+            .fetch_add(surplus, Relaxed);
+    }
+
+    /// Writes a response generated by tiny-http itself, for example to report an error.
+    fn send_response<R: Read>(
+        &mut self,
+        response: Response<R>,
+        http_version: HTTPVersion,
+        do_not_send_body: bool,
+    ) {
+        let mut writer = self.sink.next_writer();
+        let ctx = PrintContext {
+            http_version,
+            request_headers: &[],
+            do_not_send_body,
+            upgrade: None,
+            legacy_client: false,
+            last_on_connection: false,
+            secure: self.secure,
+            config: &self.config,
+        };
+        response.print(&mut writer, &ctx).ok();
+        writer.flush().ok();
     }
 
     /// Reads a request from the stream.
     /// Blocks until the header has been read.
     fn read(&mut self) -> Result<Request, ReadError> {
-        let (method, path, version, headers) = {
+        #[cfg(feature = "profiling")]
+        let mut timer = None;
+
+        self.wait_for_request()?;
+        // the client started sending the request
+        let deadline = self
+            .config
+            .max_header_read_time
+            .map(|time| Instant::now() + time);
+
+        let (method, target, version, headers, received) = {
             // reading the request line
-            let (method, path, version) = {
-                let line = self.read_next_line().map_err(ReadError::ReadIoError)?;
+            let (method, target, version, received) = {
+                let line = self
+                    .read_next_line(deadline, self.config.max_header_line_length)
+                    .map_err(ReadError::ReadIoError)?
+                    .ok_or(ReadError::RequestLineTooLong)?;
+                let received = (Instant::now(), SystemTime::now());
+                self.shrink_idle_buffers();
+
+                // the previous request may have been answered in a way that breaks the framing
+                if self.closer.is_closed() {
+                    return Err(ReadError::ReadIoError(IoError::new(
+                        ErrorKind::NotConnected,
+                        "Connection closed by a previous request",
+                    )));
+                }
+
+                // not measuring the time spent waiting for the client before
+                #[cfg(feature = "profiling")]
+                timer.replace(PhaseTimer::start());
 
-                parse_request_line(
+                let (method, target, version) = parse_request_line(
                     line.as_str().trim(), // TODO: remove this conversion
-                )?
+                )?;
+                (method, target, version, received)
             };
 
             // getting all headers
             let headers = {
                 let mut headers = Vec::new();
+                // size of the header lines read so far, line breaks included
+                let mut total_len = 0;
                 loop {
-                    let line = self.read_next_line().map_err(ReadError::ReadIoError)?;
+                    // the line break of the line must fit as well
+                    let max_line_len = self
+                        .config
+                        .max_total_headers_length
+                        .map(|max| max.saturating_sub(total_len + 2));
+                    let max_line_len = match (self.config.max_header_line_length, max_line_len) {
+                        (Some(a), Some(b)) => Some(a.min(b)),
+                        (a, b) => a.or(b),
+                    };
+                    let line = self
+                        .read_next_line(deadline, max_line_len)
+                        .map_err(ReadError::ReadIoError)?
+                        .ok_or(ReadError::HeadersTooLarge(version.clone()))?;
 
                     if line.is_empty() {
                         break;
                     };
-                    headers.push(match FromStr::from_str(line.as_str().trim()) {
-                        // TODO: remove this conversion
-                        Ok(h) => h,
-                        _ => return Err(ReadError::WrongHeader(version)),
+                    total_len += line.len() + 2;
+
+                    // obsolete line folding, which a proxy could interpret differently
+                    if line.as_str().starts_with(|c| c == ' ' || c == '\t') {
+                        let unfolded = match headers.pop() {
+                            Some(previous) if self.config.unfold_headers => {
+                                unfold_header_line(&previous, line.as_str())
+                            }
+                            _ => None,
+                        };
+                        match unfolded {
+                            Some(header) => headers.push(header),
+                            None => return Err(ReadError::WrongHeader(version)),
+                        }
+                        continue;
+                    }
+
+                    headers.push(match parse_header_line(line.as_str()) {
+                        Some(h) => h,
+                        None => return Err(ReadError::WrongHeader(version)),
                     });
                 }
 
                 headers
             };
 
-            (method, path, version, headers)
+            if self.config.require_host_header
+                && version >= HTTPVersion(1, 1)
+                && !target::has_valid_host(&headers)
+            {
+                return Err(ReadError::WrongHeader(version));
+            }
+
+            (method, target, version, headers, received)
         };
+        self.disarm_header_deadline()
+            .map_err(ReadError::ReadIoError)?;
 
         // building the writer for the request
-        let writer = self.sink.next().unwrap();
+        let writer = self.sink.next_writer();
+
+        self.last_request = (self.clock)();
 
         // follow-up for next potential request
-        let mut data_source = self.source.next().unwrap();
+        let mut data_source = self.source.next_reader();
         std::mem::swap(&mut self.next_header_source, &mut data_source);
 
         // building the next reader
-        let request = crate::request::new_request(
+        let request = if self.handoff {
+            let (handoff, data_source, writer) = Handoff::new(data_source, writer);
+            self.new_request(
+                method,
+                target,
+                version.clone(),
+                headers,
+                data_source,
+                writer,
+                received,
+            )
+            .map(|rq| rq.with_handoff(handoff))
+        } else {
+            self.new_request(
+                method,
+                target,
+                version.clone(),
+                headers,
+                data_source,
+                writer,
+                received,
+            )
+        }
+        .map_err(|e| match e {
+            RequestCreationError::CreationIoError(e) => ReadError::ReadIoError(e),
+            RequestCreationError::ExpectationFailed => ReadError::ExpectationFailed(version),
+            RequestCreationError::BodyTooLarge => ReadError::BodyTooLarge(version),
+            RequestCreationError::AmbiguousLength => ReadError::AmbiguousLength(version),
+            RequestCreationError::InvalidContentLength => ReadError::WrongHeader(version),
+            RequestCreationError::UnsupportedContentEncoding => {
+                ReadError::UnsupportedContentEncoding(version)
+            }
+        })?;
+
+        // the raw stream of an upgraded connection is read at any pace
+        if request.body_kind() == BodyKind::UpgradeRaw && self.config.body_read_timeout.is_some() {
+            if let Some(ref socket) = self.closer.socket {
+                socket
+                    .set_read_timeout(None)
+                    .map_err(ReadError::ReadIoError)?;
+            }
+        }
+
+        #[cfg(feature = "tcp-diagnostics")]
+        let request = request.with_tcp_addrs(self.tcp_addrs);
+
+        let request = request
+            .with_connection(self.id, self.requests as u64 + 1)
+            .with_tls_session(self.tls.clone());
+
+        self.stats.parsed_requests.fetch_add(1, Relaxed);
+        let request = request.with_stats(self.stats.clone());
+
+        #[cfg(feature = "profiling")]
+        let request = {
+            if let Some(timer) = timer {
+                self.profile.record_parse(timer);
+            }
+            request.with_profile(self.profile.clone())
+        };
+
+        // return the request
+        Ok(request)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn new_request<R, W>(
+        &self,
+        method: Method,
+        target: RequestTarget,
+        version: HTTPVersion,
+        headers: Vec<Header>,
+        data_source: R,
+        writer: W,
+        received: (Instant, SystemTime),
+    ) -> Result<Request, RequestCreationError>
+    where
+        R: BufRead + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        crate::request::new_request(
             self.secure,
             method,
-            path,
-            version.clone(),
+            target,
+            version,
             headers,
-            *self.remote_addr.as_ref().unwrap(),
+            self.remote_addr,
             data_source,
             writer,
+            self.config.clone(),
+            Some(self.closer.clone()),
+            received,
         )
-        .map_err(|e| {
-            use crate::request;
-            match e {
-                request::RequestCreationError::CreationIoError(e) => ReadError::ReadIoError(e),
-                request::RequestCreationError::ExpectationFailed => {
-                    ReadError::ExpectationFailed(version)
-                }
-            }
-        })?;
-
-        // return the request
-        Ok(request)
     }
 }
 
@@ -175,51 +671,87 @@ impl Iterator for ClientConnection {
     /// Blocks until the next Request is available.
     /// Returns None when no new Requests will come from the client.
     fn next(&mut self) -> Option<Request> {
-        use crate::{Response, StatusCode};
+        use crate::StatusCode;
 
         // the client sent a "connection: close" header in this previous request
         //  or is using HTTP 1.0, meaning that no new request will come
-        if self.no_more_requests {
+        if self.no_more_requests || self.closer.is_closed() {
+            // never parsing what a misbehaving client sent after that
+            self.discard_surplus();
             return None;
         }
 
         loop {
-            let rq = match self.read() {
+            let mut rq = match self.read() {
                 Err(ReadError::WrongRequestLine) => {
-                    let writer = self.sink.next().unwrap();
                     let response = Response::new_empty(StatusCode(400));
-                    response
-                        .raw_print(writer, HTTPVersion(1, 1), &[], false, None)
-                        .ok();
+                    self.send_response(response, HTTPVersion(1, 1), false);
                     return None; // we don't know where the next request would start,
                                  // se we have to close
                 }
 
                 Err(ReadError::WrongHeader(ver)) => {
-                    let writer = self.sink.next().unwrap();
                     let response = Response::new_empty(StatusCode(400));
-                    response.raw_print(writer, ver, &[], false, None).ok();
+                    self.send_response(response, ver, false);
                     return None; // we don't know where the next request would start,
                                  // se we have to close
                 }
 
                 Err(ReadError::ReadIoError(ref err)) if err.kind() == ErrorKind::TimedOut => {
                     // request timeout
-                    let writer = self.sink.next().unwrap();
                     let response = Response::new_empty(StatusCode(408));
-                    response
-                        .raw_print(writer, HTTPVersion(1, 1), &[], false, None)
-                        .ok();
+                    self.send_response(response, HTTPVersion(1, 1), false);
                     return None; // closing the connection
                 }
 
                 Err(ReadError::ExpectationFailed(ver)) => {
-                    let writer = self.sink.next().unwrap();
                     let response = Response::new_empty(StatusCode(417));
-                    response.raw_print(writer, ver, &[], true, None).ok();
+                    self.send_response(response, ver, true);
                     return None; // TODO: should be recoverable, but needs handling in case of body
                 }
 
+                Err(ReadError::RequestLineTooLong) => {
+                    let response = Response::new_empty(StatusCode(414));
+                    self.send_response(response, HTTPVersion(1, 1), false);
+                    return None; // the rest of the request line would have to be skipped
+                }
+
+                Err(ReadError::HeadersTooLarge(ver)) => {
+                    let response = Response::new_empty(StatusCode(431));
+                    self.send_response(response, ver, false);
+                    return None; // the rest of the headers would have to be skipped
+                }
+
+                Err(ReadError::BodyTooLarge(ver)) => {
+                    let response = Response::new_empty(StatusCode(413));
+                    self.send_response(response, ver, false);
+                    return None; // the body isn't read
+                }
+
+                Err(ReadError::AmbiguousLength(ver)) => {
+                    log::debug!(
+                        "Rejecting a request with an ambiguous length from {:?}",
+                        self.remote_addr
+                    );
+                    let response = Response::new_empty(StatusCode(400));
+                    self.send_response(response, ver, false);
+                    return None; // we don't know where the next request would start
+                }
+
+                Err(ReadError::UnsupportedContentEncoding(ver)) => {
+                    let response = Response::new_empty(StatusCode(415));
+                    self.send_response(response, ver, false);
+                    // the body isn't read, and must not make the connection reset
+                    self.closer.close_gracefully();
+                    return None;
+                }
+
+                Err(ReadError::KeepAliveTimeout) => {
+                    log::debug!("Keep-alive timeout of {:?} expired", self.remote_addr);
+                    self.stats.keep_alive_timeouts.fetch_add(1, Relaxed);
+                    return None;
+                }
+
                 Err(ReadError::ReadIoError(_)) => return None,
 
                 Ok(rq) => rq,
@@ -227,17 +759,22 @@ impl Iterator for ClientConnection {
 
             // checking HTTP version
             if *rq.http_version() > (1, 1) {
-                let writer = self.sink.next().unwrap();
                 let response = Response::from_string(
                     "This server only supports HTTP versions 1.0 and 1.1".to_owned(),
                 )
                 .with_status_code(StatusCode(505));
-                response
-                    .raw_print(writer, HTTPVersion(1, 1), &[], false, None)
-                    .ok();
+                self.send_response(response, HTTPVersion(1, 1), false);
                 continue;
             }
 
+            // answering as if the request was made with HTTP 1.0, then closing
+            if let Some(ref legacy_client) = self.config.legacy_client {
+                if legacy_client.matches(&rq) {
+                    rq.set_legacy_client();
+                    self.no_more_requests = true;
+                }
+            }
+
             // updating the status of the connection
             let connection_header = rq
                 .headers()
@@ -259,12 +796,60 @@ impl Iterator for ClientConnection {
                 _ => (),
             };
 
+            // the requests pipelined after the last one are left unread
+            self.requests += 1;
+            if let Some(max) = self.config.max_requests_per_connection {
+                if self.requests >= max {
+                    rq.set_last_on_connection();
+                    self.no_more_requests = true;
+                }
+            }
+
             // returning the request
             return Some(rq);
         }
     }
 }
 
+/// Waits for `source` to have data to read, for at most `timeout` if there is one.
+///
+/// Returns `false` if the timeout expires first. The other errors are left for the next read
+/// to report.
+fn wait_readable(
+    socket: &Connection,
+    source: &mut impl BufRead,
+    timeout: Option<Duration>,
+) -> Result<bool, ReadError> {
+    if timeout.is_none() {
+        let _ = source.fill_buf();
+        return Ok(true);
+    }
+    socket
+        .set_read_timeout(timeout)
+        .map_err(ReadError::ReadIoError)?;
+    let result = source.fill_buf().map(|_| ());
+    // the rest of the request must not time out
+    socket
+        .set_read_timeout(None)
+        .map_err(ReadError::ReadIoError)?;
+
+    match result {
+        Err(ref err)
+            if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut =>
+        {
+            Ok(false)
+        }
+        _ => Ok(true),
+    }
+}
+
+/// Replaces a line buffer that grew, counting the reclaimed bytes.
+fn shrink_line_buf(line_buf: &mut Vec<u8>, stats: &Counters) {
+    let reclaimed = line_buf.capacity() - LINE_BUFFER_CAPACITY;
+    *line_buf = Vec::with_capacity(LINE_BUFFER_CAPACITY);
+    stats.reclaimed_buffer_bytes.fetch_add(reclaimed, Relaxed);
+}
+
 /// Parses a "HTTP/1.1" string.
 fn parse_http_version(version: &str) -> Result<HTTPVersion, ReadError> {
     let (major, minor) = match version {
@@ -279,31 +864,287 @@ fn parse_http_version(version: &str) -> Result<HTTPVersion, ReadError> {
     Ok(HTTPVersion(major, minor))
 }
 
+/// Parses a header line received from a client.
+///
+/// Unlike `Header::from_str`, follows RFC 7230 §3.2.4 strictly so that the headers can't be
+/// interpreted differently by a proxy in front of the server: the line can't start with
+/// whitespace (obsolete line folding isn't supported), the field name must be a token
+/// directly followed by the colon, and only the value is trimmed.
+pub(crate) fn parse_header_line(line: &str) -> Option<Header> {
+    let colon = line.find(':')?;
+    let (field, value) = (&line[..colon], &line[colon + 1..]);
+
+    if field.is_empty() || !field.bytes().all(is_tchar) {
+        return None;
+    }
+    let value = value.trim_matches(|c| c == ' ' || c == '\t');
+
+    Header::from_bytes(field, value).ok()
+}
+
+/// Appends the continuation line of a folded header to its value, replacing the fold with a
+/// space.
+fn unfold_header_line(header: &Header, continuation: &str) -> Option<Header> {
+    let continuation = continuation.trim_matches(|c| c == ' ' || c == '\t');
+    let value = match (header.value.as_str(), continuation) {
+        (value, "") => value.to_owned(),
+        ("", continuation) => continuation.to_owned(),
+        (value, continuation) => format!("{} {}", value, continuation),
+    };
+    Header::from_bytes(header.field.as_str().as_bytes(), value).ok()
+}
+
+/// Returns true for the characters allowed in a token (RFC 7230 §3.2.6).
+fn is_tchar(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
 /// Parses the request line of the request.
 /// eg. GET / HTTP/1.1
-fn parse_request_line(line: &str) -> Result<(Method, String, HTTPVersion), ReadError> {
+fn parse_request_line(line: &str) -> Result<(Method, RequestTarget, HTTPVersion), ReadError> {
     let mut parts = line.split(' ');
 
-    let method = parts.next().and_then(|w| w.parse().ok());
-    let path = parts.next().map(ToOwned::to_owned);
+    let method: Option<Method> = parts.next().and_then(|w| w.parse().ok());
+    let target = parts.next();
     let version = parts.next().and_then(|w| parse_http_version(w).ok());
 
     method
-        .and_then(|method| Some((method, path?, version?)))
+        .and_then(|method| {
+            let target = target::parse(&method, target?)?;
+            Some((method, target, version?))
+        })
         .ok_or(ReadError::WrongRequestLine)
 }
 
 #[cfg(test)]
 mod test {
+    #[test]
+    fn test_parse_header_line() {
+        use super::parse_header_line;
+
+        let header = parse_header_line("Content-Length: 5").unwrap();
+        assert!(header.field.equiv("Content-Length"));
+        assert_eq!(header.value.as_str(), "5");
+        let header = parse_header_line("X-Empty:").unwrap();
+        assert_eq!(header.value.as_str(), "");
+        let header = parse_header_line("X-Time: 12:30 \t").unwrap();
+        assert_eq!(header.value.as_str(), "12:30");
+
+        assert!(parse_header_line("Content-Length : 5").is_none());
+        assert!(parse_header_line("Content-Length\t: 5").is_none());
+        assert!(parse_header_line(" Content-Length: 5").is_none());
+        assert!(parse_header_line("\tContent-Length: 5").is_none());
+        assert!(parse_header_line(": 5").is_none());
+        assert!(parse_header_line("Content-Length 5").is_none());
+        assert!(parse_header_line("Content(Length): 5").is_none());
+    }
+
+    #[test]
+    fn test_unfold_header_line() {
+        use super::{parse_header_line, unfold_header_line};
+
+        let header = parse_header_line("User-Agent: Mozilla/5.0").unwrap();
+        let header = unfold_header_line(&header, " \t(X11; Linux) ").unwrap();
+        assert!(header.field.equiv("User-Agent"));
+        assert_eq!(header.value.as_str(), "Mozilla/5.0 (X11; Linux)");
+
+        let header = parse_header_line("X-Empty:").unwrap();
+        assert_eq!(
+            unfold_header_line(&header, "  a").unwrap().value.as_str(),
+            "a"
+        );
+        assert!(unfold_header_line(&header, " caf\u{e9}").is_none());
+    }
+
     #[test]
     fn test_parse_request_line() {
-        let (method, path, ver) = super::parse_request_line("GET /hello HTTP/1.1").unwrap();
+        let (method, target, ver) = super::parse_request_line("GET /hello HTTP/1.1").unwrap();
 
         assert!(method == crate::Method::Get);
-        assert!(path == "/hello");
+        assert!(target.url() == "/hello");
         assert!(ver == crate::common::HTTPVersion(1, 1));
 
+        let (_, target, _) = super::parse_request_line("OPTIONS * HTTP/1.1").unwrap();
+        assert!(target.url() == "*");
+        let (_, target, _) = super::parse_request_line("CONNECT example.com:443 HTTP/1.1").unwrap();
+        assert!(target.url() == "example.com:443");
+
         assert!(super::parse_request_line("GET /hello").is_err());
         assert!(super::parse_request_line("qsd qsd qsd").is_err());
+        assert!(super::parse_request_line("GET hello HTTP/1.1").is_err());
+        assert!(super::parse_request_line("CONNECT example.com HTTP/1.1").is_err());
+    }
+
+    #[test]
+    fn unknown_peer_address() {
+        use std::io::{Error, ErrorKind, Read, Write};
+        use std::net::{TcpListener, TcpStream};
+        use std::sync::atomic::Ordering::Relaxed;
+        use std::sync::Arc;
+
+        use crate::connection::Connection;
+        use crate::stats::Counters;
+        use crate::util::RefinedTcpStream;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (sock, _) = listener.accept().unwrap();
+
+        let stats = Arc::new(Counters::default());
+        let (read, write) = RefinedTcpStream::new(Connection::from(sock)).unwrap();
+        let mut connection = super::ClientConnection::new(
+            write,
+            read,
+            Err(Error::from(ErrorKind::NotConnected)),
+            Arc::default(),
+            &stats,
+            None,
+        );
+        assert_eq!(stats.unknown_peer_connections.load(Relaxed), 1);
+
+        write!(
+            client,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+        let rq = connection.next().unwrap();
+        assert!(rq.remote_addr().is_none());
+        assert!(rq.peer_certificates().is_none());
+        assert!(rq.sni_hostname().is_none());
+        assert_eq!(
+            format!("{:?}", rq),
+            "Request(GET / from unknown, connection 1 #1)"
+        );
+        rq.respond(crate::Response::from_string("hello")).unwrap();
+        drop(connection);
+
+        let mut content = String::new();
+        client.read_to_string(&mut content).unwrap();
+        assert!(content.starts_with("HTTP/1.1 200"));
+        assert!(content.ends_with("hello"));
+    }
+
+    #[test]
+    fn shrink_idle_buffers() {
+        use std::cell::Cell;
+        use std::io::Write;
+        use std::net::{TcpListener, TcpStream};
+        use std::sync::atomic::Ordering::Relaxed;
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        use crate::config::ServerConfigAdvanced;
+        use crate::connection::Connection;
+        use crate::stats::Counters;
+        use crate::util::RefinedTcpStream;
+
+        thread_local! {
+            static NOW: Cell<Option<Instant>> = Cell::new(None);
+        }
+        fn clock() -> Instant {
+            NOW.with(|now| now.get().unwrap())
+        }
+        let advance = |millis| {
+            NOW.with(|now| now.set(Some(now.get().unwrap() + Duration::from_millis(millis))))
+        };
+        NOW.with(|now| now.set(Some(Instant::now())));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (sock, _) = listener.accept().unwrap();
+
+        let stats = Arc::new(Counters::default());
+        let config = ServerConfigAdvanced::default().with_buffer_shrink_threshold(1024);
+        let (read, write) = RefinedTcpStream::new(Connection::from(sock)).unwrap();
+        let mut connection =
+            super::ClientConnection::new(write, read, Ok(None), Arc::new(config), &stats, None);
+        connection.clock = clock;
+        connection.last_request = clock();
+        let baseline = connection.line_buf.capacity();
+
+        let small = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let large = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nX-Large: {}\r\n\r\n",
+            "a".repeat(16 * 1024)
+        );
+
+        // pipelined requests keep the grown buffer
+        write!(client, "{}{}{}", small, large, small).unwrap();
+        for _ in 0..3 {
+            connection.next().unwrap();
+        }
+        assert!(connection.line_buf.capacity() > 16 * 1024);
+
+        // a client sending a request more often than once per interval keeps it as well
+        for _ in 0..5 {
+            advance(900);
+            write!(client, "{}", small).unwrap();
+            connection.next().unwrap();
+            assert!(connection.line_buf.capacity() > 16 * 1024);
+        }
+        assert_eq!(stats.reclaimed_buffer_bytes.load(Relaxed), 0);
+
+        // the buffer is shrunk once the connection was idle for a whole interval
+        advance(1000);
+        write!(client, "{}", small).unwrap();
+        connection.next().unwrap();
+        assert_eq!(connection.line_buf.capacity(), baseline);
+        assert!(stats.reclaimed_buffer_bytes.load(Relaxed) >= 16 * 1024);
+
+        // a single large request after an idle gap is shrunk after the next gap
+        advance(2000);
+        write!(client, "{}", large).unwrap();
+        connection.next().unwrap();
+        advance(2000);
+        write!(client, "{}", small).unwrap();
+        connection.next().unwrap();
+        assert_eq!(connection.line_buf.capacity(), baseline);
+    }
+
+    #[test]
+    fn shrink_buffers_of_waiting_connection() {
+        use std::io::Write;
+        use std::net::{TcpListener, TcpStream};
+        use std::sync::atomic::Ordering::Relaxed;
+        use std::sync::Arc;
+        use std::thread;
+        use std::time::Duration;
+
+        use crate::config::ServerConfigAdvanced;
+        use crate::connection::Connection;
+        use crate::stats::Counters;
+        use crate::util::RefinedTcpStream;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (sock, _) = listener.accept().unwrap();
+
+        let stats = Arc::new(Counters::default());
+        let config = ServerConfigAdvanced::default().with_buffer_shrink_threshold(1024);
+        let (read, write) = RefinedTcpStream::new(Connection::from(sock)).unwrap();
+        let mut connection =
+            super::ClientConnection::new(write, read, Ok(None), Arc::new(config), &stats, None);
+        let baseline = connection.line_buf.capacity();
+
+        write!(
+            client,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nX-Large: {}\r\n\r\n",
+            "a".repeat(16 * 1024)
+        )
+        .unwrap();
+        connection.next().unwrap();
+        assert!(connection.line_buf.capacity() > 16 * 1024);
+
+        // the buffer is shrunk while the connection waits, without a next request
+        let waiting = thread::spawn(move || {
+            connection.next().unwrap();
+            connection
+        });
+        thread::sleep(Duration::from_millis(1500));
+        assert!(stats.reclaimed_buffer_bytes.load(Relaxed) >= 16 * 1024);
+
+        write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let connection = waiting.join().unwrap();
+        assert_eq!(connection.line_buf.capacity(), baseline);
     }
 }
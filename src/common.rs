@@ -1,7 +1,9 @@
 use ascii::{AsciiStr, AsciiString, FromAsciiError};
+use httpdate::HttpDate;
 use std::cmp::Ordering;
 use std::fmt::{self, Display, Formatter};
 use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
 /// Status code of a request or response.
 #[derive(Eq, PartialEq, Copy, Clone, Debug, Ord, PartialOrd)]
@@ -178,6 +180,10 @@ impl Header {
     }
 }
 
+/// Parses a `Field: value` line, for example to build the headers of a response.
+///
+/// Whitespace around the value is trimmed. The headers of the requests received by the server
+/// are parsed more strictly, see RFC 7230 §3.2.4.
 impl FromStr for Header {
     type Err = ();
 
@@ -249,6 +255,212 @@ impl PartialEq for HeaderField {
     }
 }
 
+/// A cookie to set on the client, serialized as a `Set-Cookie` header by
+/// `Response::with_cookie`.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use tiny_http::{Cookie, Response, SameSite};
+/// let cookie = Cookie::new("session", "a3fWa")
+///     .with_path("/")
+///     .with_max_age(Duration::from_secs(3600))
+///     .with_http_only(true)
+///     .with_same_site(SameSite::Lax);
+/// assert_eq!(
+///     cookie.to_string(),
+///     "session=a3fWa; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax"
+/// );
+/// let response = Response::empty(204).with_cookie(cookie);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+/// Value of the `SameSite` attribute of a `Cookie`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SameSite {
+    /// The cookie is only sent with requests coming from the same site.
+    Strict,
+    /// The cookie is also sent when the user navigates to the site from another site.
+    Lax,
+    /// The cookie is sent with every request. Browsers require the cookie to be `Secure`.
+    None,
+}
+
+impl Cookie {
+    /// Creates a cookie without any attribute, which the client keeps until it is closed.
+    ///
+    /// The bytes of `value` that aren't allowed in a cookie, such as spaces, quotes, `;` or
+    /// line breaks, are percent-encoded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is empty or contains characters other than the ones of an HTTP token.
+    pub fn new<N, V>(name: N, value: V) -> Cookie
+    where
+        N: Into<String>,
+        V: AsRef<str>,
+    {
+        let name = name.into();
+        assert!(
+            !name.is_empty() && name.bytes().all(is_token_byte),
+            "Invalid cookie name"
+        );
+        Cookie {
+            name,
+            value: percent_encode(value.as_ref(), is_cookie_octet),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// Returns the name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the value of the cookie, as sent to the client.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Restricts the cookie to the paths starting with `path`. The control characters and
+    /// `;` are percent-encoded.
+    pub fn with_path(mut self, path: &str) -> Cookie {
+        self.path = Some(percent_encode(path, |b| {
+            b != b';' && b != b'%' && (0x21..0x7f).contains(&b)
+        }));
+        self
+    }
+
+    /// Sends the cookie to `domain` and its subdomains, instead of only the host that set it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `domain` contains characters that can't be part of a domain name.
+    pub fn with_domain(mut self, domain: &str) -> Cookie {
+        assert!(
+            !domain.is_empty()
+                && domain
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'-'),
+            "Invalid cookie domain"
+        );
+        self.domain = Some(domain.to_owned());
+        self
+    }
+
+    /// Makes the client drop the cookie after `max_age`, rounded down to the second. A zero
+    /// duration removes the cookie.
+    pub fn with_max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Makes the client drop the cookie at `expires`. Clients that support it prefer
+    /// `with_max_age`.
+    pub fn with_expires(mut self, expires: SystemTime) -> Cookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Only sends the cookie over HTTPS if `secure` is true.
+    pub fn with_secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    /// Hides the cookie from the scripts of the page if `http_only` is true.
+    pub fn with_http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Sets when the cookie is sent with requests coming from other sites.
+    pub fn with_same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+/// Formats the value of the `Set-Cookie` header.
+impl Display for Cookie {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(formatter, "{}={}", self.name, self.value)?;
+        if let Some(ref path) = self.path {
+            write!(formatter, "; Path={}", path)?;
+        }
+        if let Some(ref domain) = self.domain {
+            write!(formatter, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(formatter, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(formatter, "; Expires={}", httpdate::fmt_http_date(expires))?;
+        }
+        if self.secure {
+            formatter.write_str("; Secure")?;
+        }
+        if self.http_only {
+            formatter.write_str("; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => formatter.write_str("; SameSite=Strict"),
+            Some(SameSite::Lax) => formatter.write_str("; SameSite=Lax"),
+            Some(SameSite::None) => formatter.write_str("; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<Cookie> for Header {
+    fn from(cookie: Cookie) -> Header {
+        // every part of the cookie is printable ASCII
+        Header::from_bytes(&b"Set-Cookie"[..], cookie.to_string()).unwrap()
+    }
+}
+
+/// Whether `byte` can be part of a token (RFC 7230 §3.2.6).
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// Whether `byte` can be part of the value of a cookie without being encoded
+/// (RFC 6265 §4.1.1). `%` is encoded too, so that values can be decoded unambiguously.
+fn is_cookie_octet(byte: u8) -> bool {
+    match byte {
+        0x21 | 0x23..=0x24 | 0x26..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e => true,
+        _ => false,
+    }
+}
+
+pub(crate) fn percent_encode(input: &str, keep: impl Fn(u8) -> bool) -> String {
+    let mut output = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if keep(byte) {
+            output.push(byte as char);
+        } else {
+            output.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    output
+}
+
 /// HTTP request methods
 ///
 /// As per [RFC 7231](https://tools.ietf.org/html/rfc7231#section-4.1) and
@@ -393,9 +605,56 @@ impl From<(u8, u8)> for HTTPVersion {
     }
 }
 
+/// A date as written in HTTP headers such as `Last-Modified`, with a precision of one second.
+///
+/// The dates are compared chronologically.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HTTPDate(HttpDate);
+
+impl HTTPDate {
+    /// Returns the current date.
+    pub fn now() -> HTTPDate {
+        HTTPDate::from(SystemTime::now())
+    }
+
+    /// Parses a date in any of the three formats that RFC 7231 §7.1.1.1 requires to accept:
+    /// the preferred one of RFC 1123 (`Sun, 06 Nov 1994 08:49:37 GMT`), the one of RFC 850
+    /// (`Sunday, 06-Nov-94 08:49:37 GMT`) and the one of asctime (`Sun Nov  6 08:49:37 1994`).
+    ///
+    /// Returns `None` if the date is malformed, or before 1970.
+    pub fn parse(value: &str) -> Option<HTTPDate> {
+        value.trim().parse().ok().map(HTTPDate)
+    }
+}
+
+impl From<SystemTime> for HTTPDate {
+    /// Converts a time, rounded down to the second.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the time is before 1970 or after 9999.
+    fn from(time: SystemTime) -> HTTPDate {
+        HTTPDate(HttpDate::from(time))
+    }
+}
+
+impl From<HTTPDate> for SystemTime {
+    fn from(date: HTTPDate) -> SystemTime {
+        SystemTime::from(date.0)
+    }
+}
+
+impl Display for HTTPDate {
+    /// Writes the date in the format of RFC 1123, which is the one to send.
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(formatter, "{}", self.0)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::Header;
+    use super::{Cookie, HTTPDate, Header, SameSite};
     use httpdate::HttpDate;
     use std::time::{Duration, SystemTime};
 
@@ -416,6 +675,32 @@ mod test {
         assert_eq!(http_date.to_string(), "Wed, 04 May 1983 11:17:00 GMT")
     }
 
+    #[test]
+    fn http_dates() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        let date = HTTPDate::from(time);
+        assert_eq!(date.to_string(), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(SystemTime::from(date), time);
+        assert_eq!(HTTPDate::parse(&date.to_string()), Some(date));
+
+        // the three formats of RFC 7231
+        assert_eq!(HTTPDate::parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(date));
+        assert_eq!(
+            HTTPDate::parse("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Some(date)
+        );
+        assert_eq!(HTTPDate::parse("Sun Nov  6 08:49:37 1994"), Some(date));
+
+        // the precision is one second
+        assert_eq!(HTTPDate::from(time + Duration::from_millis(999)), date);
+        assert!(HTTPDate::from(time + Duration::from_secs(1)) > date);
+        assert!(HTTPDate::parse("Sat, 05 Nov 1994 08:49:37 GMT").unwrap() < date);
+
+        assert_eq!(HTTPDate::parse("Sun, 06 Nov 1994 08:49:37"), None);
+        assert_eq!(HTTPDate::parse("yesterday"), None);
+        assert_eq!(HTTPDate::parse(""), None);
+    }
+
     #[test]
     fn test_parse_header_with_doublecolon() {
         let header: Header = "Time: 20: 34".parse().unwrap();
@@ -437,4 +722,51 @@ mod test {
         assert!("Transfer-Encoding: chunked ".parse::<Header>().is_ok());
         assert!("Transfer-Encoding:   chunked ".parse::<Header>().is_ok());
     }
+
+    #[test]
+    fn cookie_serialization() {
+        assert_eq!(Cookie::new("id", "42").to_string(), "id=42");
+
+        let cookie = Cookie::new("id", "42")
+            .with_path("/app")
+            .with_domain("example.com")
+            .with_max_age(Duration::from_millis(7_200_900))
+            .with_expires(SystemTime::UNIX_EPOCH + Duration::from_secs(420895020))
+            .with_secure(true)
+            .with_http_only(true)
+            .with_same_site(SameSite::Strict);
+        assert_eq!(
+            cookie.to_string(),
+            "id=42; Path=/app; Domain=example.com; Max-Age=7200; \
+             Expires=Wed, 04 May 1983 11:17:00 GMT; Secure; HttpOnly; SameSite=Strict"
+        );
+
+        let header = Header::from(Cookie::new("id", "42").with_same_site(SameSite::None));
+        assert!(header.field.equiv("set-cookie"));
+        assert_eq!(header.value.as_str(), "id=42; SameSite=None");
+    }
+
+    #[test]
+    fn cookie_values_are_encoded() {
+        let cookie = Cookie::new("id", "a b;\r\nSet-Cookie: admin=1\"%é");
+        assert_eq!(
+            cookie.value(),
+            "a%20b%3B%0D%0ASet-Cookie:%20admin=1%22%25%C3%A9"
+        );
+
+        let cookie = Cookie::new("id", "1").with_path("/a;b\r\n c");
+        assert_eq!(cookie.to_string(), "id=1; Path=/a%3Bb%0D%0A%20c");
+    }
+
+    #[test]
+    #[should_panic]
+    fn cookie_name_with_line_break() {
+        Cookie::new("id\r\nSet-Cookie: admin", "1");
+    }
+
+    #[test]
+    #[should_panic]
+    fn cookie_domain_with_separator() {
+        Cookie::new("id", "1").with_domain("example.com; Secure");
+    }
 }
@@ -0,0 +1,673 @@
+//! Compression of response bodies, enabled by the `compression` feature. The `brotli`
+//! feature adds the `br` encoding.
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Result as IoResult, Write};
+use std::sync::{Arc, Mutex};
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+
+use crate::conditional;
+use crate::util::parse_header_value;
+use crate::{Header, Request, Response, ResponseBox, StatusCode};
+
+/// A `Content-Encoding` that the library can produce.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Encoding {
+    /// `br`, only available with the `brotli` feature.
+    #[cfg(feature = "brotli")]
+    Brotli,
+    /// `gzip`
+    Gzip,
+    /// `deflate`, the zlib format.
+    Deflate,
+}
+
+/// The encodings produced by the library, from the most to the least preferred when the
+/// client accepts several of them equally.
+const PREFERRED_ENCODINGS: &[Encoding] = &[
+    #[cfg(feature = "brotli")]
+    Encoding::Brotli,
+    Encoding::Gzip,
+    Encoding::Deflate,
+];
+
+/// Quality of the brotli compression, from 0 to 11. The highest ones are too slow to
+/// compress responses on the fly.
+#[cfg(feature = "brotli")]
+const BROTLI_QUALITY: u32 = 5;
+
+/// Size of the window of the brotli compression, as a power of two.
+#[cfg(feature = "brotli")]
+const BROTLI_WINDOW: u32 = 22;
+
+impl Encoding {
+    /// Returns the value of the `Content-Encoding` header for this encoding.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Compresses `data` with this encoding.
+    pub fn compress(&self, data: &[u8]) -> IoResult<Vec<u8>> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => {
+                let mut encoder = brotli_crate::CompressorWriter::new(
+                    Vec::new(),
+                    4096,
+                    BROTLI_QUALITY,
+                    BROTLI_WINDOW,
+                );
+                encoder.write_all(data)?;
+                Ok(encoder.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    /// Returns the encoding named by a `Content-Encoding` header, in lowercase, or `None` if
+    /// the library can't decompress it.
+    pub(crate) fn from_content_coding(coding: &str) -> Option<Encoding> {
+        match coding {
+            #[cfg(feature = "brotli")]
+            "br" => Some(Encoding::Brotli),
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+
+    /// Returns a reader decompressing the data of `reader`, compressed with this encoding.
+    pub(crate) fn decoder(
+        &self,
+        reader: Box<dyn Read + Send + 'static>,
+    ) -> Box<dyn Read + Send + 'static> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Box::new(brotli_crate::Decompressor::new(reader, 4096)),
+            Encoding::Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+            Encoding::Deflate => Box::new(flate2::read::ZlibDecoder::new(reader)),
+        }
+    }
+
+    /// Returns the encoding to use for the response to a request with these headers, or `None`
+    /// if the body must not be compressed.
+    pub(crate) fn negotiate(request_headers: &[Header]) -> Option<Encoding> {
+        let accept_encoding = request_headers
+            .iter()
+            .find(|h| h.field.equiv("Accept-Encoding"))?;
+        choose_content_encoding(accept_encoding.value.as_str(), PREFERRED_ENCODINGS)
+    }
+}
+
+/// Chooses the encoding of a response among the `available` ones, from the value of the
+/// `Accept-Encoding` header of the request. Returns `None` if the body must be sent
+/// unchanged.
+///
+/// The encoding with the highest q-value wins, the encodings not listed by the client getting
+/// the q-value of `*`, if any. When several encodings have the same q-value, the first one
+/// of `available` is chosen. No encoding is chosen if `identity` is explicitly given a higher
+/// q-value.
+///
+/// ```
+/// # use tiny_http::{choose_content_encoding, Encoding};
+/// let available = [Encoding::Gzip, Encoding::Deflate];
+/// assert_eq!(choose_content_encoding("deflate, gzip", &available), Some(Encoding::Gzip));
+/// assert_eq!(choose_content_encoding("gzip;q=0.5, deflate", &available), Some(Encoding::Deflate));
+/// assert_eq!(choose_content_encoding("gzip;q=0, identity", &available), None);
+/// ```
+pub fn choose_content_encoding(accept_encoding: &str, available: &[Encoding]) -> Option<Encoding> {
+    let values = parse_header_value(accept_encoding);
+    let quality = |name: &str| {
+        values
+            .iter()
+            .find(|(value, _)| value.eq_ignore_ascii_case(name))
+            .map(|&(_, q)| q)
+    };
+    let any = quality("*");
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for &encoding in available {
+        let q = quality(encoding.as_str()).or(any).unwrap_or(0.0);
+        if q > 0.0 && best.map_or(true, |(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+
+    let (encoding, q) = best?;
+    match quality("identity") {
+        Some(identity) if identity > q => None,
+        _ => Some(encoding),
+    }
+}
+
+/// Responses announcing a shorter body aren't compressed by `Response::with_compression()`,
+/// as the compressed body would hardly be smaller.
+const MIN_COMPRESSED_LENGTH: usize = 1024;
+
+/// A `Read` returning the body of a response built by `Response::with_compression()`, either
+/// compressed or unchanged.
+pub struct CompressedReader<R>
+where
+    R: Read,
+{
+    inner: CompressedInner<R>,
+}
+
+enum CompressedInner<R>
+where
+    R: Read,
+{
+    Identity(R),
+    #[cfg(feature = "brotli")]
+    // boxed, as its state takes several kilobytes
+    Brotli(Box<brotli_crate::CompressorReader<R>>),
+    Gzip(flate2::read::GzEncoder<R>),
+    Deflate(flate2::read::ZlibEncoder<R>),
+}
+
+impl<R> CompressedReader<R>
+where
+    R: Read,
+{
+    fn new(reader: R, encoding: Option<Encoding>) -> CompressedReader<R> {
+        let inner = match encoding {
+            None => CompressedInner::Identity(reader),
+            #[cfg(feature = "brotli")]
+            Some(Encoding::Brotli) => CompressedInner::Brotli(Box::new(
+                brotli_crate::CompressorReader::new(reader, 4096, BROTLI_QUALITY, BROTLI_WINDOW),
+            )),
+            Some(Encoding::Gzip) => {
+                CompressedInner::Gzip(flate2::read::GzEncoder::new(reader, Compression::default()))
+            }
+            Some(Encoding::Deflate) => CompressedInner::Deflate(flate2::read::ZlibEncoder::new(
+                reader,
+                Compression::default(),
+            )),
+        };
+        CompressedReader { inner }
+    }
+
+    /// Returns the encoding of the body, or `None` if it isn't compressed.
+    pub fn encoding(&self) -> Option<Encoding> {
+        match self.inner {
+            CompressedInner::Identity(_) => None,
+            #[cfg(feature = "brotli")]
+            CompressedInner::Brotli(_) => Some(Encoding::Brotli),
+            CompressedInner::Gzip(_) => Some(Encoding::Gzip),
+            CompressedInner::Deflate(_) => Some(Encoding::Deflate),
+        }
+    }
+}
+
+impl<R> Read for CompressedReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        match &mut self.inner {
+            CompressedInner::Identity(reader) => reader.read(buf),
+            #[cfg(feature = "brotli")]
+            CompressedInner::Brotli(encoder) => encoder.read(buf),
+            CompressedInner::Gzip(encoder) => encoder.read(buf),
+            CompressedInner::Deflate(encoder) => encoder.read(buf),
+        }
+    }
+}
+
+impl<R> Response<R>
+where
+    R: Read,
+{
+    /// Compresses the body with the best encoding accepted by the `Accept-Encoding` header of
+    /// `request`, adding the matching `Content-Encoding` header.
+    ///
+    /// The length of the compressed body isn't known in advance, so it is sent with the
+    /// chunked transfer encoding, or until the connection is closed for HTTP/1.0 clients.
+    ///
+    /// The body is sent unchanged if the response already has a `Content-Encoding`, if it
+    /// is shorter than 1 KiB, if its `Content-Type` is already compressed (images, audio,
+    /// video, fonts and archives), or if its status code is `1xx`, `204`, `206` or `304`. The
+    /// response gets a `Vary: Accept-Encoding` header whenever the choice depended on the
+    /// request.
+    ///
+    /// ```no_run
+    /// # let server = tiny_http::Server::http("0.0.0.0:0").unwrap();
+    /// let request = server.recv().unwrap();
+    /// let response = tiny_http::Response::from_string("{\"widgets\": []}");
+    /// let response = response.with_compression(&request);
+    /// request.respond(response).unwrap();
+    /// ```
+    pub fn with_compression(self, request: &Request) -> Response<CompressedReader<R>> {
+        let compressible = is_compressible(&self);
+        let encoding = if compressible {
+            Encoding::negotiate(request.headers())
+        } else {
+            None
+        };
+
+        // the length of the compressed body is unknown
+        let data_length = match encoding {
+            Some(_) => None,
+            None => self.data_length(),
+        };
+        let mut response = self.map_reader(
+            |reader| CompressedReader::new(reader, encoding),
+            data_length,
+        );
+        if let Some(encoding) = encoding {
+            response.add_header(
+                Header::from_bytes(&b"Content-Encoding"[..], encoding.as_str().as_bytes()).unwrap(),
+            );
+        }
+        if compressible {
+            response.add_vary("Accept-Encoding");
+        }
+        response
+    }
+}
+
+/// Returns true if the body of `response` is worth compressing.
+fn is_compressible<R>(response: &Response<R>) -> bool
+where
+    R: Read,
+{
+    let status = response.status_code().0;
+    if status < 200 || status == 204 || status == 206 || status == 304 {
+        return false;
+    }
+    if matches!(response.data_length(), Some(len) if len < MIN_COMPRESSED_LENGTH) {
+        return false;
+    }
+
+    let mut content_type = None;
+    for header in response.headers() {
+        if header.field.equiv("Content-Encoding") {
+            return false;
+        } else if header.field.equiv("Content-Type") {
+            content_type = Some(header.value.as_str());
+        }
+    }
+    !content_type.map_or(false, is_compressed_type)
+}
+
+/// Returns true if the media type of a `Content-Type` header is a compressed format.
+fn is_compressed_type(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    let media_type = media_type.to_ascii_lowercase();
+    let (kind, subtype) = media_type.split_once('/').unwrap_or((&media_type, ""));
+    match kind {
+        "image" => subtype != "svg+xml" && subtype != "bmp",
+        "audio" | "video" => true,
+        "font" => subtype == "woff" || subtype == "woff2",
+        "application" => matches!(
+            subtype,
+            "gzip"
+                | "x-gzip"
+                | "zip"
+                | "zstd"
+                | "x-bzip2"
+                | "x-xz"
+                | "x-7z-compressed"
+                | "x-rar-compressed"
+                | "vnd.rar"
+                | "pdf"
+        ),
+        _ => false,
+    }
+}
+
+/// Head of a response built by `CompressedCache::respond_compressed_cached()`.
+struct CachedHead {
+    status_code: StatusCode,
+    headers: Vec<Header>,
+}
+
+struct Entry {
+    body: Arc<[u8]>,
+    head: Option<Arc<CachedHead>>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    // `None` is the variant sent without compression
+    entries: HashMap<(String, Option<Encoding>), Entry>,
+    total_bytes: usize,
+    // incremented on each access, to find the least recently used entry
+    clock: u64,
+}
+
+impl CacheState {
+    fn get(&mut self, key: &(String, Option<Encoding>)) -> Option<&Entry> {
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = clock;
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: (String, Option<Encoding>), mut entry: Entry) {
+        self.clock += 1;
+        entry.last_used = self.clock;
+        self.total_bytes += entry.body.len();
+        if let Some(previous) = self.entries.insert(key, entry) {
+            self.total_bytes -= previous.body.len();
+        }
+    }
+
+    fn evict_until(&mut self, max_entries: usize, max_bytes: usize) {
+        while self.entries.len() > max_entries || self.total_bytes > max_bytes {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            match oldest.and_then(|key| self.entries.remove(&key)) {
+                Some(entry) => self.total_bytes -= entry.body.len(),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Cache of compressed bodies, for responses that are served many times with the same
+/// content.
+///
+/// Each body is stored once per encoding under a key chosen by the user, and the least recently
+/// used ones are evicted once there are more than `max_entries` of them, or once they take
+/// more than `max_bytes` in total (32 MiB by default).
+///
+/// The cache can be shared between the threads that handle requests. Bodies that aren't cached
+/// yet may be built by several threads at the same time.
+pub struct CompressedCache {
+    max_entries: usize,
+    max_bytes: usize,
+    state: Mutex<CacheState>,
+}
+
+impl CompressedCache {
+    /// Builds an empty cache holding at most `max_entries` bodies.
+    pub fn new(max_entries: usize) -> CompressedCache {
+        CompressedCache {
+            max_entries,
+            max_bytes: 32 * 1024 * 1024,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Limits the total size of the cached bodies to `max_bytes`.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> CompressedCache {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Returns the body stored for `key`, compressed with `encoding`. If it isn't in the cache,
+    /// `build` is called to get the uncompressed body, which is compressed and stored.
+    pub fn get_or_compress<F>(&self, key: &str, encoding: Encoding, build: F) -> Arc<[u8]>
+    where
+        F: FnOnce() -> Vec<u8>,
+    {
+        let cache_key = (key.to_owned(), Some(encoding));
+        if let Some(entry) = self.state.lock().unwrap().get(&cache_key) {
+            return entry.body.clone();
+        }
+
+        let data = build();
+        // compressing into memory can't fail
+        let body: Arc<[u8]> = encoding.compress(&data).unwrap().into();
+        self.store(
+            cache_key,
+            Entry {
+                body: body.clone(),
+                head: None,
+                last_used: 0,
+            },
+        );
+        body
+    }
+
+    /// Answers `request` with the response returned by `build`, compressed with the best
+    /// encoding accepted by the client and cached under `key`.
+    ///
+    /// The key is also sent as the `ETag` of the response, so it must change whenever the body
+    /// changes. A request whose `If-None-Match` header matches the key gets a `304 Not
+    /// Modified` response without looking up the cache. The response gets the headers of the
+    /// response returned by `build` when it was cached, and a `Vary: Accept-Encoding` header.
+    pub fn respond_compressed_cached<F>(
+        &self,
+        request: &Request,
+        key: &str,
+        build: F,
+    ) -> ResponseBox
+    where
+        F: FnOnce() -> ResponseBox,
+    {
+        let etag = conditional::quote_etag(key);
+        let etag_header = Header::from_bytes(&b"ETag"[..], etag.as_bytes()).unwrap();
+
+        let not_modified = request
+            .headers()
+            .iter()
+            .filter(|h| h.field.equiv("If-None-Match"))
+            .any(|h| if_none_match_matches(h.value.as_str(), &etag));
+        if not_modified {
+            let mut response = Response::empty(304).with_header(etag_header);
+            response.add_vary("Accept-Encoding");
+            return response.boxed();
+        }
+
+        let encoding = Encoding::negotiate(request.headers());
+        let cache_key = (key.to_owned(), encoding);
+        let cached = self
+            .state
+            .lock()
+            .unwrap()
+            .get(&cache_key)
+            .and_then(|entry| Some((entry.body.clone(), entry.head.clone()?)));
+
+        let (body, head) = match cached {
+            Some(cached) => cached,
+            None => {
+                let response = build();
+                let head = Arc::new(CachedHead {
+                    status_code: response.status_code(),
+                    headers: response.headers().to_vec(),
+                });
+                let mut data = Vec::new();
+                if response.into_reader().read_to_end(&mut data).is_err() {
+                    // not caching a body that couldn't be read entirely
+                    return Response::empty(500).boxed();
+                }
+                let body: Arc<[u8]> = match encoding {
+                    Some(encoding) => encoding.compress(&data).unwrap().into(),
+                    None => data.into(),
+                };
+                self.store(
+                    cache_key,
+                    Entry {
+                        body: body.clone(),
+                        head: Some(head.clone()),
+                        last_used: 0,
+                    },
+                );
+                (body, head)
+            }
+        };
+
+        let headers = head
+            .headers
+            .iter()
+            .filter(|h| !h.field.equiv("ETag") && !h.field.equiv("Content-Encoding"))
+            .cloned();
+        let len = body.len();
+        let mut response = Response::new(
+            head.status_code,
+            headers.collect(),
+            Cursor::new(body),
+            Some(len),
+            None,
+        )
+        .with_header(etag_header);
+        if let Some(encoding) = encoding {
+            response.add_header(
+                Header::from_bytes(&b"Content-Encoding"[..], encoding.as_str().as_bytes()).unwrap(),
+            );
+        }
+        response.add_vary("Accept-Encoding");
+        response.boxed()
+    }
+
+    /// Removes all the bodies stored for `key`.
+    pub fn invalidate(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        let keys: Vec<_> = state
+            .entries
+            .keys()
+            .filter(|(k, _)| k == key)
+            .cloned()
+            .collect();
+        for key in keys {
+            if let Some(entry) = state.entries.remove(&key) {
+                state.total_bytes -= entry.body.len();
+            }
+        }
+    }
+
+    /// Returns the number of bodies in the cache.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// Returns true if the cache doesn't contain any body.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn store(&self, key: (String, Option<Encoding>), entry: Entry) {
+        let mut state = self.state.lock().unwrap();
+        state.insert(key, entry);
+        state.evict_until(self.max_entries, self.max_bytes);
+    }
+}
+
+/// Returns true if the value of an `If-None-Match` header matches `etag`, using the weak
+/// comparison of RFC 7232.
+fn if_none_match_matches(value: &str, etag: &str) -> bool {
+    conditional::list_matches(value, Some(conditional::parse_etag(etag)), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        choose_content_encoding, if_none_match_matches, is_compressed_type, CompressedCache,
+        Encoding,
+    };
+    use crate::Header;
+    use std::str::FromStr;
+
+    #[test]
+    fn choose_encoding() {
+        let available = [Encoding::Gzip, Encoding::Deflate];
+        let choose = |value: &str| choose_content_encoding(value, &available);
+        assert_eq!(choose("gzip, deflate"), Some(Encoding::Gzip));
+        // the server preference breaks ties
+        assert_eq!(choose("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(choose("GZIP;q=0.5, deflate;q=0.8"), Some(Encoding::Deflate));
+        assert_eq!(choose("*"), Some(Encoding::Gzip));
+        assert_eq!(choose("gzip;q=0, *"), Some(Encoding::Deflate));
+        assert_eq!(choose("gzip;q=0, deflate;q=0"), None);
+        assert_eq!(choose("br, compress"), None);
+        assert_eq!(choose(""), None);
+        assert_eq!(choose("gzip;q=0.5, identity"), None);
+        assert_eq!(choose("gzip, identity;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(choose("identity;q=0"), None);
+        assert_eq!(choose_content_encoding("gzip", &[]), None);
+        assert_eq!(
+            choose_content_encoding("gzip, deflate", &[Encoding::Deflate, Encoding::Gzip]),
+            Some(Encoding::Deflate)
+        );
+    }
+
+    #[test]
+    fn negotiate_encoding() {
+        let negotiate = |value: &str| {
+            Encoding::negotiate(
+                &[Header::from_str(&format!("Accept-Encoding: {}", value)).unwrap()],
+            )
+        };
+        assert_eq!(negotiate("deflate, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate"), Some(Encoding::Deflate));
+        #[cfg(feature = "brotli")]
+        assert_eq!(negotiate("gzip, br"), Some(Encoding::Brotli));
+        #[cfg(not(feature = "brotli"))]
+        assert_eq!(negotiate("gzip, br"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate(&[]), None);
+    }
+
+    #[test]
+    fn compressed_types() {
+        assert!(is_compressed_type("image/png"));
+        assert!(is_compressed_type("Video/MP4"));
+        assert!(is_compressed_type("application/zip; foo=bar"));
+        assert!(is_compressed_type("font/woff2"));
+        assert!(!is_compressed_type("image/svg+xml"));
+        assert!(!is_compressed_type("text/html; charset=utf-8"));
+        assert!(!is_compressed_type("application/json"));
+        assert!(!is_compressed_type("font/ttf"));
+    }
+
+    #[test]
+    fn if_none_match() {
+        assert!(if_none_match_matches("\"v1\"", "\"v1\""));
+        assert!(if_none_match_matches("\"v0\", W/\"v1\"", "\"v1\""));
+        assert!(if_none_match_matches("*", "\"v1\""));
+        assert!(!if_none_match_matches("\"v2\"", "\"v1\""));
+    }
+
+    #[test]
+    fn lru_eviction() {
+        let cache = CompressedCache::new(2);
+        cache.get_or_compress("a", Encoding::Gzip, || b"a".to_vec());
+        cache.get_or_compress("b", Encoding::Gzip, || b"b".to_vec());
+        // "a" becomes the most recently used
+        cache.get_or_compress("a", Encoding::Gzip, || unreachable!());
+        cache.get_or_compress("c", Encoding::Gzip, || b"c".to_vec());
+        assert_eq!(cache.len(), 2);
+
+        cache.get_or_compress("a", Encoding::Gzip, || unreachable!());
+        let mut rebuilt = false;
+        cache.get_or_compress("b", Encoding::Gzip, || {
+            rebuilt = true;
+            b"b".to_vec()
+        });
+        assert!(rebuilt);
+    }
+
+    #[test]
+    fn bytes_cap() {
+        let cache = CompressedCache::new(10).with_max_bytes(100);
+        cache.get_or_compress("a", Encoding::Gzip, || vec![1; 10]);
+        assert_eq!(cache.len(), 1);
+        // random bytes don't compress
+        let noise: Vec<u8> = (0..200u32).map(|i| (i * 7919 % 251) as u8).collect();
+        cache.get_or_compress("b", Encoding::Gzip, || noise);
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+//! Conditional requests (RFC 7232), see `Request::evaluate_preconditions()`.
+
+use crate::common;
+use crate::{HTTPDate, Header, Method};
+
+/// What to do with a conditional request, returned by `Request::evaluate_preconditions()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Precondition {
+    /// The request isn't conditional, or its conditions are true: the request should be
+    /// handled normally.
+    Proceed,
+    /// The client already has the current representation: the request should be answered
+    /// with `Response::not_modified()`.
+    NotModified,
+    /// A condition is false: the request should be answered with
+    /// `412 Precondition Failed`, without doing anything.
+    Failed,
+}
+
+/// Evaluates the conditional headers of a request, in the order of RFC 7232 §6, for a
+/// resource whose current entity tag is `etag` and whose last modification is
+/// `last_modified`. The entity tag is `None` if the resource has no representation.
+///
+/// The dates are only compared when the corresponding entity tag header is absent, and the
+/// malformed ones are ignored.
+pub(crate) fn evaluate(
+    method: &Method,
+    headers: &[Header],
+    etag: Option<&str>,
+    last_modified: Option<HTTPDate>,
+) -> Precondition {
+    let etag = etag.map(quote_etag);
+    let etag = etag.as_deref().map(parse_etag);
+    let values = |name: &'static str| {
+        let mut values = headers
+            .iter()
+            .filter(move |h| h.field.equiv(name))
+            .map(|h| h.value.as_str())
+            .peekable();
+        values.peek().is_some().then(|| values)
+    };
+    let date = |name: &'static str| {
+        let value = headers.iter().find(|h| h.field.equiv(name))?;
+        HTTPDate::parse(value.value.as_str())
+    };
+    let is_get = matches!(method, Method::Get | Method::Head);
+
+    if let Some(mut values) = values("If-Match") {
+        if !values.any(|value| list_matches(value, etag, true)) {
+            return Precondition::Failed;
+        }
+    } else if let (Some(since), Some(last_modified)) = (date("If-Unmodified-Since"), last_modified)
+    {
+        if last_modified > since {
+            return Precondition::Failed;
+        }
+    }
+
+    if let Some(mut values) = values("If-None-Match") {
+        if values.any(|value| list_matches(value, etag, false)) {
+            return if is_get {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if let (Some(since), Some(last_modified)) = (date("If-Modified-Since"), last_modified) {
+        if is_get && last_modified <= since {
+            return Precondition::NotModified;
+        }
+    }
+
+    Precondition::Proceed
+}
+
+/// An entity tag: whether it is weak, and its opaque part, quotes included.
+type EntityTag<'a> = (bool, &'a str);
+
+/// Returns true if the value of an `If-Match` or `If-None-Match` header matches `etag`, with
+/// the strong or the weak comparison of RFC 7232 §2.3.2. `*` matches any entity tag.
+pub(crate) fn list_matches(value: &str, etag: Option<EntityTag<'_>>, strong: bool) -> bool {
+    let etag = match etag {
+        Some(etag) => etag,
+        None => return false,
+    };
+    if value.trim() == "*" {
+        return true;
+    }
+    entity_tags(value).any(|(weak, opaque)| opaque == etag.1 && !(strong && (weak || etag.0)))
+}
+
+/// Splits an entity tag into its weakness and its opaque part.
+pub(crate) fn parse_etag(etag: &str) -> EntityTag<'_> {
+    let etag = etag.trim();
+    match etag.strip_prefix("W/") {
+        Some(opaque) => (true, opaque),
+        None => (false, etag),
+    }
+}
+
+/// Iterates over the entity tags of a comma-separated list, skipping the malformed ones.
+/// The opaque parts can contain commas.
+fn entity_tags(mut list: &str) -> impl Iterator<Item = EntityTag<'_>> {
+    std::iter::from_fn(move || loop {
+        list = list.trim_start_matches(|c| c == ',' || c == ' ' || c == '\t');
+        if list.is_empty() {
+            return None;
+        }
+        let (weak, rest) = match list.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, list),
+        };
+        let end = match rest.strip_prefix('"').and_then(|rest| rest.find('"')) {
+            Some(end) => end + 2,
+            None => {
+                // skipping the malformed tag up to the next comma
+                list = list.find(',').map_or("", |comma| &list[comma..]);
+                continue;
+            }
+        };
+        list = &rest[end..];
+        return Some((weak, &rest[..end]));
+    })
+}
+
+/// Returns `etag` as an entity tag, adding quotes if it has none. The characters that can't
+/// be part of an entity tag (RFC 7232 §2.3), such as control characters, spaces, quotes or
+/// non-ASCII characters, are percent-encoded.
+pub(crate) fn quote_etag(etag: &str) -> String {
+    let (weak, opaque) = if let Some(rest) = etag.strip_prefix("W/\"") {
+        ("W/", rest.strip_suffix('"').unwrap_or(rest))
+    } else if let Some(rest) = etag.strip_prefix('"') {
+        ("", rest.strip_suffix('"').unwrap_or(rest))
+    } else {
+        ("", etag)
+    };
+    let opaque = common::percent_encode(opaque, |b| b == 0x21 || (0x23..0x7f).contains(&b));
+    format!("{}\"{}\"", weak, opaque)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{entity_tags, evaluate, quote_etag, Precondition};
+    use crate::{HTTPDate, Header, Method};
+    use std::str::FromStr;
+
+    fn check(method: Method, headers: &[&str], etag: Option<&str>) -> Precondition {
+        let headers: Vec<_> = headers
+            .iter()
+            .map(|h| Header::from_str(h).unwrap())
+            .collect();
+        evaluate(&method, &headers, etag, None)
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote_etag("v1"), r#""v1""#);
+        assert_eq!(quote_etag(r#""v1""#), r#""v1""#);
+        assert_eq!(quote_etag(r#"W/"v1""#), r#"W/"v1""#);
+        assert_eq!(
+            quote_etag("caf\u{e9} \"1\"\r\n"),
+            r#""caf%C3%A9%20%221%22%0D%0A""#
+        );
+    }
+
+    #[test]
+    fn tag_lists() {
+        let tags: Vec<_> = entity_tags(r#""a", W/"b",,"c,d" , bad, "e""#).collect();
+        assert_eq!(
+            tags,
+            [
+                (false, r#""a""#),
+                (true, r#""b""#),
+                (false, r#""c,d""#),
+                (false, r#""e""#)
+            ]
+        );
+        assert_eq!(entity_tags("").count(), 0);
+        assert_eq!(entity_tags(r#""unterminated"#).count(), 0);
+    }
+
+    #[test]
+    fn if_none_match() {
+        let etag = Some(r#""v1""#);
+        let not_modified = |headers: &[&str]| check(Method::Get, headers, etag);
+        assert_eq!(not_modified(&[]), Precondition::Proceed);
+        assert_eq!(
+            not_modified(&[r#"If-None-Match: "v1""#]),
+            Precondition::NotModified
+        );
+        // weak comparison
+        assert_eq!(
+            not_modified(&[r#"If-None-Match: W/"v1""#]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            check(Method::Get, &[r#"If-None-Match: "v1""#], Some(r#"W/"v1""#)),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            not_modified(&[r#"If-None-Match: "v0", "v1""#]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            not_modified(&[r#"If-None-Match: "v0""#, r#"If-None-Match: "v1""#]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            not_modified(&[r#"If-None-Match: "v0", "v2""#]),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            not_modified(&["If-None-Match: *"]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            check(Method::Get, &["If-None-Match: *"], None),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn if_none_match_unsafe_methods() {
+        let headers = [r#"If-None-Match: "v1""#];
+        assert_eq!(
+            check(Method::Head, &headers, Some(r#""v1""#)),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            check(Method::Put, &headers, Some(r#""v1""#)),
+            Precondition::Failed
+        );
+        // creating a resource only if it doesn't exist yet
+        assert_eq!(
+            check(Method::Put, &["If-None-Match: *"], None),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn if_match() {
+        let etag = Some(r#""v1""#);
+        assert_eq!(
+            check(Method::Put, &[r#"If-Match: "v1""#], etag),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            check(Method::Put, &[r#"If-Match: "v0", "v1""#], etag),
+            Precondition::Proceed
+        );
+        // strong comparison
+        assert_eq!(
+            check(Method::Put, &[r#"If-Match: W/"v1""#], etag),
+            Precondition::Failed
+        );
+        assert_eq!(
+            check(Method::Put, &[r#"If-Match: "v1""#], Some(r#"W/"v1""#)),
+            Precondition::Failed
+        );
+        assert_eq!(
+            check(Method::Put, &["If-Match: *"], etag),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            check(Method::Put, &["If-Match: *"], None),
+            Precondition::Failed
+        );
+        // If-Match is evaluated first
+        assert_eq!(
+            check(
+                Method::Get,
+                &[r#"If-Match: "v0""#, r#"If-None-Match: "v1""#],
+                etag
+            ),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn dates() {
+        let last_modified = HTTPDate::parse("Sun, 06 Nov 1994 08:49:37 GMT");
+        let check = |method: Method, headers: &[&str], etag: Option<&str>| {
+            let headers: Vec<_> = headers
+                .iter()
+                .map(|h| Header::from_str(h).unwrap())
+                .collect();
+            evaluate(&method, &headers, etag, last_modified)
+        };
+
+        let since = |name: &str, date: &str| format!("{}: {}", name, date);
+        let before = "Sat, 05 Nov 1994 08:49:37 GMT";
+        let same = "Sunday, 06-Nov-94 08:49:37 GMT";
+        let after = "Sun Nov  6 08:49:38 1994";
+
+        let modified_since =
+            |date: &str| check(Method::Get, &[&since("If-Modified-Since", date)], None);
+        assert_eq!(modified_since(before), Precondition::Proceed);
+        assert_eq!(modified_since(same), Precondition::NotModified);
+        assert_eq!(modified_since(after), Precondition::NotModified);
+        assert_eq!(modified_since("invalid"), Precondition::Proceed);
+        assert_eq!(
+            check(Method::Post, &[&since("If-Modified-Since", same)], None),
+            Precondition::Proceed
+        );
+
+        let unmodified_since =
+            |date: &str| check(Method::Put, &[&since("If-Unmodified-Since", date)], None);
+        assert_eq!(unmodified_since(before), Precondition::Failed);
+        assert_eq!(unmodified_since(same), Precondition::Proceed);
+        assert_eq!(unmodified_since(after), Precondition::Proceed);
+
+        // the entity tags take precedence over the dates
+        assert_eq!(
+            check(
+                Method::Get,
+                &[r#"If-None-Match: "v0""#, &since("If-Modified-Since", after)],
+                Some("v1")
+            ),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            check(
+                Method::Put,
+                &[r#"If-Match: "v1""#, &since("If-Unmodified-Since", before)],
+                Some("v1")
+            ),
+            Precondition::Proceed
+        );
+
+        // without a date for the resource, the dates are ignored
+        let headers = [Header::from_str(&since("If-Modified-Since", after)).unwrap()];
+        assert_eq!(
+            evaluate(&Method::Get, &headers, None, None),
+            Precondition::Proceed
+        );
+    }
+}
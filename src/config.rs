@@ -0,0 +1,1303 @@
+//! Less commonly needed server settings.
+
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind, Result as IoResult, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::common::{Header, StatusCode};
+use crate::request::Request;
+use crate::stats::AccessLogEntry;
+use crate::util::random_f64;
+
+/// Additional settings of a server.
+///
+/// The default value reproduces the historical behavior of tiny-http, so you only need to
+/// touch the options you care about, then pass them to `ServerBuilder::with_advanced()`:
+///
+/// ```no_run
+/// # use tiny_http::{SecurityHeaders, ServerBuilder, ServerConfigAdvanced};
+/// let advanced = ServerConfigAdvanced::default()
+///     .with_default_content_type(Some("application/octet-stream"))
+///     .with_security_headers(SecurityHeaders::new());
+/// let server = ServerBuilder::new().with_port(8000).with_advanced(advanced).build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerConfigAdvanced {
+    pub(crate) default_content_type: Option<Header>,
+    pub(crate) security_headers: Option<SecurityHeaders>,
+    pub(crate) inline_fairness_budget: usize,
+    pub(crate) worker_threads: usize,
+    pub(crate) min_idle_threads: usize,
+    pub(crate) static_response_headers: Option<Arc<StaticHeaders>>,
+    pub(crate) queue_latency_warning: Option<Duration>,
+    pub(crate) read_buffering: BufferingMode,
+    pub(crate) write_buffering: BufferingMode,
+    pub(crate) connection_handoff: bool,
+    pub(crate) load_shedding: Option<Arc<LoadShedder>>,
+    pub(crate) path_allowlist: Option<Arc<PathAllowlist>>,
+    pub(crate) buffer_shrink_threshold: Option<usize>,
+    pub(crate) max_concurrent_tls_handshakes: Option<usize>,
+    pub(crate) tls_handshake_queue_timeout: Duration,
+    pub(crate) max_queued_tls_handshakes: usize,
+    pub(crate) tls_handshake_timeout: Duration,
+    pub(crate) tls_client_auth: Option<(Vec<u8>, TlsClientAuthMode)>,
+    pub(crate) keep_alive_timeout: Option<Duration>,
+    pub(crate) max_requests_per_connection: Option<usize>,
+    pub(crate) max_header_read_time: Option<Duration>,
+    pub(crate) body_read_timeout: Option<Duration>,
+    pub(crate) max_connections: Option<(usize, ConnectionLimitMode)>,
+    pub(crate) task_queue_limit: Option<(usize, TaskQueueLimitMode)>,
+    pub(crate) connection_filter: Option<ConnectionFilter>,
+    pub(crate) connection_setup: Option<ConnectionSetup>,
+    pub(crate) accept_error_handler: Option<AcceptErrorHandler>,
+    pub(crate) access_log: Option<AccessLog>,
+    pub(crate) max_response_header_size: usize,
+    pub(crate) max_response_header_value_len: usize,
+    pub(crate) max_header_line_length: Option<usize>,
+    pub(crate) max_total_headers_length: Option<usize>,
+    pub(crate) max_body_size: Option<u64>,
+    pub(crate) max_chunk_size: Option<u64>,
+    pub(crate) tcp_nodelay: bool,
+    pub(crate) tcp_keepalive: Option<Duration>,
+    pub(crate) reuse_port: bool,
+    pub(crate) legacy_client: Option<LegacyClientMatcher>,
+    pub(crate) legacy_client_http10_status: bool,
+    pub(crate) accept_proxy_protocol: bool,
+    pub(crate) require_host_header: bool,
+    pub(crate) unfold_headers: bool,
+    pub(crate) automatic_continue: bool,
+    pub(crate) reject_encoded_bodies: bool,
+}
+
+impl Default for ServerConfigAdvanced {
+    fn default() -> ServerConfigAdvanced {
+        ServerConfigAdvanced {
+            default_content_type: None,
+            security_headers: None,
+            inline_fairness_budget: 16,
+            worker_threads: 0,
+            min_idle_threads: 4,
+            static_response_headers: None,
+            queue_latency_warning: None,
+            read_buffering: BufferingMode::Buffered,
+            write_buffering: BufferingMode::Buffered,
+            connection_handoff: false,
+            load_shedding: None,
+            path_allowlist: None,
+            buffer_shrink_threshold: None,
+            max_concurrent_tls_handshakes: None,
+            tls_handshake_queue_timeout: Duration::from_secs(10),
+            max_queued_tls_handshakes: 1024,
+            tls_handshake_timeout: Duration::from_secs(10),
+            tls_client_auth: None,
+            keep_alive_timeout: None,
+            max_requests_per_connection: None,
+            max_header_read_time: None,
+            body_read_timeout: None,
+            max_connections: None,
+            task_queue_limit: None,
+            connection_filter: None,
+            connection_setup: None,
+            accept_error_handler: None,
+            access_log: None,
+            max_response_header_size: 64 * 1024,
+            max_response_header_value_len: 32 * 1024,
+            max_header_line_length: None,
+            max_total_headers_length: None,
+            max_body_size: None,
+            max_chunk_size: None,
+            tcp_nodelay: false,
+            tcp_keepalive: None,
+            reuse_port: false,
+            legacy_client: None,
+            legacy_client_http10_status: false,
+            accept_proxy_protocol: false,
+            require_host_header: true,
+            unfold_headers: false,
+            automatic_continue: true,
+            reject_encoded_bodies: false,
+        }
+    }
+}
+
+impl ServerConfigAdvanced {
+    /// Sets the `Content-Type` added to responses that don't have one.
+    ///
+    /// The header is only added if the response has a body, and never replaces a
+    /// `Content-Type` that was set on the response. `None`, the default, disables this.
+    /// Responses built with `Response::without_default_headers()` are left alone.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the content type is not a valid header value.
+    pub fn with_default_content_type(mut self, content_type: Option<&'static str>) -> Self {
+        self.default_content_type = content_type.map(|value| {
+            Header::from_bytes(&b"Content-Type"[..], value.as_bytes())
+                .expect("Invalid default content type")
+        });
+        self
+    }
+
+    /// Sets security-related headers to add to every response, including the error
+    /// responses generated by tiny-http itself, but not to the responses built with
+    /// `Response::without_default_headers()`.
+    pub fn with_security_headers(mut self, headers: SecurityHeaders) -> Self {
+        self.security_headers = Some(headers);
+        self
+    }
+
+    /// Sets the number of requests of a connection that are read in a row before the thread
+    /// reading them moves on to the other pending connections.
+    ///
+    /// A client pipelining lots of requests on a keep-alive connection would otherwise hold
+    /// its worker thread for as long as it keeps sending. Once the budget is exhausted, the
+    /// rest of the connection is scheduled again behind the work already queued, which doesn't
+    /// change the order in which the requests of that connection are handled. `0` disables
+    /// this. Defaults to 16.
+    pub fn with_inline_fairness_budget(mut self, requests: usize) -> Self {
+        self.inline_fairness_budget = requests;
+        self
+    }
+
+    /// Serves the connections with exactly `count` worker threads, started with the server.
+    ///
+    /// When all of them are busy, the new connections wait for one of them to be free. A
+    /// keep-alive connection holds its thread until it is closed, or until it exhausts its
+    /// `with_inline_fairness_budget`. A thread that panics is replaced by a new one. `0`, the
+    /// default, starts a new thread whenever all the threads are busy and stops the idle ones
+    /// after a few seconds.
+    pub fn with_worker_threads(mut self, count: usize) -> Self {
+        self.worker_threads = count;
+        self
+    }
+
+    /// Sets the number of worker threads started with the server and kept when idle, if
+    /// their count isn't fixed by `with_worker_threads`. Defaults to 4.
+    pub fn with_min_idle_threads(mut self, count: usize) -> Self {
+        self.min_idle_threads = count;
+        self
+    }
+
+    /// Sets headers that are added to every response, including the error responses
+    /// generated by tiny-http itself, for example to identify the deployment that served them.
+    ///
+    /// The headers are serialized once here, so adding them to a response is cheap. A response
+    /// that already has a header with the same name keeps its own value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if one of the headers is a header that tiny-http uses to frame the response
+    /// (`Content-Length`, `Transfer-Encoding`, `Connection`...) or `Date`, or if a header
+    /// contains a line break.
+    pub fn with_static_response_headers(mut self, headers: Vec<Header>) -> Self {
+        self.static_response_headers = if headers.is_empty() {
+            None
+        } else {
+            Some(Arc::new(StaticHeaders::new(headers)))
+        };
+        self
+    }
+
+    /// Sets the maximum size in bytes of the status line and the headers of a response,
+    /// including the headers added by tiny-http. Defaults to 64 kiB.
+    ///
+    /// A response over the limit isn't sent, see `RespondError`.
+    pub fn with_max_response_header_size(mut self, bytes: usize) -> Self {
+        self.max_response_header_size = bytes;
+        self
+    }
+
+    /// Sets the maximum length in bytes of the value of a header of a response. Defaults to
+    /// 32 kiB.
+    ///
+    /// A response over the limit isn't sent, see `RespondError`.
+    pub fn with_max_response_header_value_len(mut self, bytes: usize) -> Self {
+        self.max_response_header_value_len = bytes;
+        self
+    }
+
+    /// Sets the maximum length in bytes of a header line of a request, line break excluded.
+    /// `0`, the default, means no limit.
+    ///
+    /// A request with a longer header line is answered with
+    /// `431 Request Header Fields Too Large`, and the connection is closed. The limit applies
+    /// to the request line as well, and a longer one is answered with `414 URI Too Long`.
+    pub fn with_max_header_line_length(mut self, bytes: usize) -> Self {
+        self.max_header_line_length = Some(bytes).filter(|&bytes| bytes != 0);
+        self
+    }
+
+    /// Sets the maximum size in bytes of all the header lines of a request together, line
+    /// breaks included. The request line doesn't count. `0`, the default, means no limit.
+    ///
+    /// A request with larger headers is answered with `431 Request Header Fields Too Large`,
+    /// and the connection is closed.
+    pub fn with_max_total_headers_length(mut self, bytes: usize) -> Self {
+        self.max_total_headers_length = Some(bytes).filter(|&bytes| bytes != 0);
+        self
+    }
+
+    /// Sets the maximum size in bytes of the body of a request. The default is no limit.
+    ///
+    /// A request whose `Content-Length` is over the limit is answered with
+    /// `413 Payload Too Large` instead of being returned by `Server::recv()`, without reading
+    /// its body nor sending a `100 Continue`, and the connection is closed. The reader of a
+    /// chunked body fails with `ErrorKind::InvalidData` once the decoded body exceeds the
+    /// limit, and the connection is closed after the response.
+    pub fn with_max_body_size(mut self, bytes: u64) -> Self {
+        self.max_body_size = Some(bytes);
+        self
+    }
+
+    /// Sets the maximum size in bytes of a single chunk of a chunked request body. The default
+    /// is no limit, although the sizes that don't fit in a `usize` are always rejected.
+    ///
+    /// The reader of the body fails with `ErrorKind::InvalidData` when it reaches a larger
+    /// chunk, and the connection is closed after the response. `with_max_body_size` limits
+    /// the size of the whole decoded body.
+    pub fn with_max_chunk_size(mut self, bytes: u64) -> Self {
+        self.max_chunk_size = Some(bytes);
+        self
+    }
+
+    /// Sets how the data read from the clients is buffered. Defaults to
+    /// `BufferingMode::Buffered`.
+    ///
+    /// Request lines and headers are read one byte at a time, so disabling read buffering
+    /// costs one system call per byte. A large buffer helps with clients that pipeline lots
+    /// of requests.
+    pub fn with_read_buffering(mut self, mode: BufferingMode) -> Self {
+        self.read_buffering = mode;
+        self
+    }
+
+    /// Sets how the data written to the clients is buffered. Defaults to
+    /// `BufferingMode::Buffered`.
+    ///
+    /// Without buffering, everything written by a response, such as a streamed body, reaches
+    /// the socket right away. This is independent from the read side.
+    pub fn with_write_buffering(mut self, mode: BufferingMode) -> Self {
+        self.write_buffering = mode;
+        self
+    }
+
+    /// Allows taking connections away from the server with `Request::into_raw_connection`,
+    /// for example to pass them to another process. Disabled by default.
+    ///
+    /// When enabled, a request keeps exclusive access to its connection until it is answered:
+    /// the next request of a connection is only read once the previous one is done, and
+    /// bodies are never read in advance.
+    pub fn with_connection_handoff(mut self, enabled: bool) -> Self {
+        self.connection_handoff = enabled;
+        self
+    }
+
+    /// Answers some of the new requests with `503 Service Unavailable` when too many requests
+    /// are waiting to be received, instead of making the queue grow. Disabled by default.
+    ///
+    /// Shed requests are never returned by `Server::recv()`; they are counted in
+    /// `ServerStats::shed_requests`. The 503 response is serialized once here to keep it cheap,
+    /// so it only has the `Retry-After`, `Content-Length` and `Connection: close` headers,
+    /// without `Date`, static or security headers. Since the body of a shed request isn't
+    /// read, the connection is closed after the response.
+    pub fn with_load_shedding(mut self, load_shedding: LoadShedding) -> Self {
+        self.load_shedding = Some(Arc::new(LoadShedder::new(load_shedding)));
+        self
+    }
+
+    /// Answers the requests whose path doesn't start with one of `prefixes` with an empty
+    /// response of status `reject_status`, usually `StatusCode(404)`, before they are queued.
+    /// Disabled by default.
+    ///
+    /// A prefix only matches at a segment boundary: `/api` matches `/api` and `/api/users`, but
+    /// not `/apix`. The prefix `/` only matches the root path itself. Paths are compared as
+    /// sent by the client, before percent-decoding, and without their query string.
+    ///
+    /// Rejected requests are never returned by `Server::recv()`; they are counted in
+    /// `ServerStats::rejected_paths`. The rejection is serialized once here to keep it cheap, so
+    /// it only has the `Content-Length` and `Connection: close` headers, without `Date`, static
+    /// or security headers. Since the body of a rejected request isn't read, the connection is
+    /// closed after the rejection.
+    pub fn with_path_prefix_allowlist(
+        mut self,
+        prefixes: Vec<String>,
+        reject_status: StatusCode,
+    ) -> Self {
+        self.path_allowlist = Some(Arc::new(PathAllowlist::new(prefixes, reject_status)));
+        self
+    }
+
+    /// Frees the buffers of a connection that grew above `bytes`, for example after a request
+    /// with very long header lines, instead of keeping them for the whole life of the
+    /// connection. Disabled by default.
+    ///
+    /// The buffers are only shrunk once the connection was idle for at least a second, while
+    /// it waits for the next request, so that clients sending requests steadily don't
+    /// reallocate them each time. The freed memory is counted in
+    /// `ServerStats::reclaimed_buffer_bytes`.
+    pub fn with_buffer_shrink_threshold(mut self, bytes: usize) -> Self {
+        self.buffer_shrink_threshold = Some(bytes);
+        self
+    }
+
+    /// Logs a warning when a request waited longer than `threshold` between being read from
+    /// its connection and being returned by `Server::recv()`, which is usually a sign that the
+    /// handlers can't keep up.
+    ///
+    /// To avoid flooding the logs, at most one warning is logged every 10 seconds. The number
+    /// of warnings is available in `ServerStats::queue_latency_warnings`.
+    pub fn with_queue_latency_warning(mut self, threshold: Duration) -> Self {
+        self.queue_latency_warning = Some(threshold);
+        self
+    }
+
+    /// Closes the connections that don't start sending a request within `timeout`, instead of
+    /// keeping their thread waiting forever. Disabled by default.
+    ///
+    /// The timeout only applies while waiting for the first byte of a request, the first
+    /// request of a connection included; reading the rest of the request and its body isn't
+    /// limited. The connection is closed without a response, and counted in
+    /// `ServerStats::keep_alive_timeouts`.
+    ///
+    /// For TLS connections, the timeout starts once the TLS handshake is done.
+    pub fn with_keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Closes a connection after it received `requests` requests. The default is no limit.
+    ///
+    /// The response to the last request tells the client with `Connection: close`, and any
+    /// request pipelined after it is never read, so that the client sends it again on a new
+    /// connection. This spreads long-lived clients over the instances behind a load balancer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `requests` is 0.
+    pub fn with_max_requests_per_connection(mut self, requests: usize) -> Self {
+        assert!(
+            requests != 0,
+            "A connection must be able to serve one request"
+        );
+        self.max_requests_per_connection = Some(requests);
+        self
+    }
+
+    /// Answers with `408 Request Timeout` and closes the connection when the request line and
+    /// the headers of a request take longer than `time` to arrive, counted from the first
+    /// byte of the request. Disabled by default.
+    ///
+    /// This protects against clients that keep connections busy by sending their headers very
+    /// slowly. The body of the request isn't limited.
+    pub fn with_max_header_read_time(mut self, time: Duration) -> Self {
+        self.max_header_read_time = Some(time);
+        self
+    }
+
+    /// Makes the reads of the body of a request fail with `ErrorKind::TimedOut` when the
+    /// client sends nothing for `timeout`. Disabled by default.
+    ///
+    /// This protects the handlers against clients that stop sending in the middle of a body.
+    /// The connection is closed after the response, usually a `408 Request Timeout`, since the
+    /// rest of the body would be mistaken for the next request. The timeout doesn't apply to
+    /// the wait for the next request of the connection, see `with_keep_alive_timeout`.
+    pub fn with_body_read_timeout(mut self, timeout: Duration) -> Self {
+        self.body_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Disables Nagle's algorithm on the accepted TCP connections if `nodelay` is true, so
+    /// that small responses are sent right away. Disabled by default.
+    ///
+    /// This has no effect on connections through Unix sockets.
+    pub fn with_tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp_nodelay = nodelay;
+        self
+    }
+
+    /// Enables TCP keep-alive probes on the accepted TCP connections, sent after `idle` without
+    /// any data, so that the connections of clients that went away are eventually closed.
+    /// Disabled by default.
+    ///
+    /// This has no effect on connections through Unix sockets, and is only supported on
+    /// Linux and Android.
+    pub fn with_tcp_keepalive(mut self, idle: Duration) -> Self {
+        self.tcp_keepalive = Some(idle);
+        self
+    }
+
+    /// Sets `SO_REUSEADDR` and `SO_REUSEPORT` on the sockets bound by `Server::new()` and
+    /// `Server::prepare()`, so that several servers, typically in different processes, can
+    /// listen to the same port, the system distributing the new connections between them.
+    /// This allows restarting a server without downtime. Disabled by default.
+    ///
+    /// This is only supported for TCP on Linux and Android; on other systems, binding fails.
+    pub fn with_reuse_port(mut self, enabled: bool) -> Self {
+        self.reuse_port = enabled;
+        self
+    }
+
+    /// Expects each new connection to start with the header of the PROXY protocol, version 1
+    /// or 2, as sent by load balancers such as HAProxy, and uses the address of the client it
+    /// contains as the `Request::remote_addr()` of the requests of the connection. Disabled
+    /// by default.
+    ///
+    /// The header is read before the TLS handshake, by the thread serving the connection. A
+    /// connection that doesn't start with a valid header is closed without a response. The
+    /// headers that don't carry the address of a TCP client, such as the health checks of
+    /// the load balancer, keep the address of the peer. The header must arrive within the
+    /// `with_keep_alive_timeout`, or within 10 seconds if there is none.
+    ///
+    /// Only enable this behind a load balancer, since anyone able to connect to the server can
+    /// claim any address otherwise. `with_connection_filter` and `with_connection_setup` still
+    /// see the address of the load balancer.
+    pub fn with_accept_proxy_protocol(mut self, enabled: bool) -> Self {
+        self.accept_proxy_protocol = enabled;
+        self
+    }
+
+    /// Answers `400 Bad Request` to the HTTP/1.1 requests without a `Host` header, or with
+    /// several `Host` headers whose values differ, as required by RFC 7230 §5.4. Enabled by
+    /// default.
+    ///
+    /// HTTP/1.0 requests are accepted without a `Host` header either way.
+    pub fn with_require_host_header(mut self, enabled: bool) -> Self {
+        self.require_host_header = enabled;
+        self
+    }
+
+    /// Accepts the headers continued on the next line with leading whitespace, an obsolete
+    /// syntax (RFC 7230 §3.2.4), by joining each continuation to the value of the previous
+    /// header with a space. Disabled by default, in which case the requests with folded
+    /// headers are answered with `400 Bad Request`.
+    pub fn with_header_unfolding(mut self, enabled: bool) -> Self {
+        self.unfold_headers = enabled;
+        self
+    }
+
+    /// Sends the `100 Continue` expected by a request with an `Expect: 100-continue` header
+    /// the first time `Request::as_reader()` is called. Enabled by default.
+    ///
+    /// When disabled, the handler decides with `Request::expects_continue()`: it calls
+    /// `Request::send_continue()` before reading the body, or answers right away with
+    /// `Request::reject_expectation()`. A client usually sends the body anyway after waiting
+    /// for a while, so reading it without sending a `100 Continue` only delays the request.
+    pub fn with_automatic_continue(mut self, enabled: bool) -> Self {
+        self.automatic_continue = enabled;
+        self
+    }
+
+    /// Answers `415 Unsupported Media Type` to the requests whose body has a
+    /// `Content-Encoding` other than `identity`, without reading the body. Disabled by default.
+    ///
+    /// When disabled, the `gzip` and `deflate` bodies are decompressed if the `compression`
+    /// feature is enabled (and `br` with the `brotli` feature): `Request::as_reader()` returns
+    /// the decompressed body, `Request::body_length()` returns `None`, and the
+    /// `Content-Encoding` header is removed from the request. The other bodies are handed
+    /// over unchanged.
+    pub fn with_reject_encoded_bodies(mut self, enabled: bool) -> Self {
+        self.reject_encoded_bodies = enabled;
+        self
+    }
+
+    /// Calls `filter` with the address of the client of each new TCP connection, right after it
+    /// is accepted, to decide whether the connection is served. Nothing is read from the
+    /// connections that are dropped or rejected, and they are counted in
+    /// `ServerStats::filtered_connections`.
+    ///
+    /// The decision is made before the TLS handshake; rejected TLS connections are closed
+    /// without a response. A panic of `filter` drops the connection. `filter` is called by the
+    /// thread accepting the connections, so it must not block. Connections through Unix
+    /// sockets are never passed to it.
+    ///
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use tiny_http::{ConnectionDecision, ServerConfigAdvanced, StatusCode};
+    /// // internal-only server
+    /// let advanced = ServerConfigAdvanced::default().with_connection_filter(Arc::new(|addr| {
+    ///     if addr.ip().is_loopback() {
+    ///         ConnectionDecision::Accept
+    ///     } else {
+    ///         ConnectionDecision::RejectWith(StatusCode(403))
+    ///     }
+    /// }));
+    /// ```
+    pub fn with_connection_filter(mut self, filter: Arc<ConnectionFilterFn>) -> Self {
+        self.connection_filter = Some(ConnectionFilter(filter));
+        self
+    }
+
+    /// Calls `setup` with each new TCP connection and the address of its client, right after
+    /// it is accepted and before the TLS handshake, for example to set socket options that
+    /// depend on the client.
+    ///
+    /// If `setup` returns an error or panics, the connection is closed without reading
+    /// anything from it, and counted in `ServerStats::rejected_connections`.
+    ///
+    /// `setup` is called by the thread accepting the connections, so it must not block.
+    /// Connections through Unix sockets are never passed to it.
+    pub fn with_connection_setup(mut self, setup: Arc<SetupFn>) -> Self {
+        self.connection_setup = Some(ConnectionSetup(setup));
+        self
+    }
+
+    /// Calls `handler` with the errors of `accept()` that the server recovers from, such as a
+    /// connection aborted before it was accepted or the process running out of file
+    /// descriptors. Once out of resources, the server waits a bit before accepting again.
+    ///
+    /// These errors are also logged and counted in `ServerStats::accept_errors`. The other
+    /// errors stop the server, see `ShutdownReason::AcceptThreadFailed`.
+    ///
+    /// `handler` is called by the thread accepting the connections, so it must not block.
+    pub fn with_accept_error_handler(mut self, handler: Arc<AcceptErrorFn>) -> Self {
+        self.accept_error_handler = Some(AcceptErrorHandler(handler));
+        self
+    }
+
+    /// Calls `hook` with a summary of each request answered with `Request::respond()`, or with
+    /// the `500 Internal Server Error` sent when a request is dropped without a response, once
+    /// the response is written. Disabled by default.
+    ///
+    /// The responses sent by the server itself, for example to malformed requests, and the
+    /// requests turned into writers or upgraded aren't passed to `hook`. A panic of `hook` is
+    /// ignored. `hook` is called by the thread writing the response, after it has been
+    /// flushed.
+    ///
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use tiny_http::ServerConfigAdvanced;
+    /// let advanced = ServerConfigAdvanced::default().with_access_log(Arc::new(|entry| {
+    ///     println!(
+    ///         "{:?} \"{} {:?} HTTP/{}\" {} {}",
+    ///         entry.remote_addr,
+    ///         entry.method,
+    ///         entry.path,
+    ///         entry.http_version,
+    ///         entry.status_code.0,
+    ///         entry.body_bytes
+    ///     );
+    /// }));
+    /// ```
+    pub fn with_access_log(mut self, hook: Arc<AccessLogFn>) -> Self {
+        self.access_log = Some(AccessLog(hook));
+        self
+    }
+
+    /// Answers the requests for which `matcher` returns true as if they were made with
+    /// HTTP 1.0, whatever version they claim, for clients that mishandle the features of
+    /// HTTP 1.1. Disabled by default.
+    ///
+    /// The responses to these requests are never chunked, a body of unknown length being
+    /// buffered to send its length, and have a `Connection: close` header, after which the
+    /// connection is closed. Their status line keeps the version of the request, unless
+    /// `with_legacy_client_http10_status` is enabled.
+    ///
+    /// `matcher` is called by the thread reading the requests, before they are queued, so it
+    /// must not block. It typically looks at the `User-Agent` header or at the address of the
+    /// client. A panic counts as not matching.
+    pub fn with_legacy_client_mode(mut self, matcher: Arc<LegacyClientFn>) -> Self {
+        self.legacy_client = Some(LegacyClientMatcher(matcher));
+        self
+    }
+
+    /// Writes `HTTP/1.0` in the status line of the responses to the requests matched by
+    /// `with_legacy_client_mode`, instead of the version of the request. Disabled by default.
+    pub fn with_legacy_client_http10_status(mut self, enabled: bool) -> Self {
+        self.legacy_client_http10_status = enabled;
+        self
+    }
+
+    /// Limits the number of connections open at the same time, `mode` telling what happens to
+    /// the new connections over the limit. The default is no limit.
+    ///
+    /// A connection counts from the moment it is accepted until it is closed, including
+    /// during its TLS handshake.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is 0.
+    pub fn with_max_connections(mut self, limit: usize, mode: ConnectionLimitMode) -> Self {
+        assert!(limit != 0, "At least one connection must be allowed");
+        self.max_connections = Some((limit, mode));
+        self
+    }
+
+    /// Limits the number of new connections waiting for a worker thread, `mode` telling what
+    /// happens to the new connections over the limit. The default is no limit.
+    ///
+    /// The connections only wait when no thread is idle and no thread can be started, which
+    /// happens with `with_worker_threads`. The keep-alive connections that are scheduled again
+    /// after their `with_inline_fairness_budget` aren't limited.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is 0.
+    pub fn with_task_queue_limit(mut self, limit: usize, mode: TaskQueueLimitMode) -> Self {
+        assert!(
+            limit != 0,
+            "At least one connection must be allowed to wait"
+        );
+        self.task_queue_limit = Some((limit, mode));
+        self
+    }
+
+    /// Limits the number of TLS handshakes done at the same time, so that a flood of new
+    /// connections can't take all the threads away from the established ones. The default
+    /// is no limit.
+    ///
+    /// The connections over the limit wait for their handshake in the order they were
+    /// accepted, without holding a thread. Those that wait longer than the timeout set with
+    /// `with_tls_handshake_queue_timeout` are closed, and counted in
+    /// `ServerStats::tls_handshake_queue_timeouts`. When `with_max_queued_tls_handshakes`
+    /// connections are already waiting, the new ones are closed right away, and counted in
+    /// `ServerStats::tls_handshake_queue_rejections`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is 0.
+    pub fn with_max_concurrent_tls_handshakes(mut self, limit: usize) -> Self {
+        assert!(limit != 0, "At least one TLS handshake must be allowed");
+        self.max_concurrent_tls_handshakes = Some(limit);
+        self
+    }
+
+    /// Sets how long a new connection waits for its TLS handshake to start when the limit
+    /// set with `with_max_concurrent_tls_handshakes` is reached. The default is 10 seconds.
+    pub fn with_tls_handshake_queue_timeout(mut self, timeout: Duration) -> Self {
+        self.tls_handshake_queue_timeout = timeout;
+        self
+    }
+
+    /// Sets how many new connections can wait for their TLS handshake when the limit set
+    /// with `with_max_concurrent_tls_handshakes` is reached. The default is 1024.
+    pub fn with_max_queued_tls_handshakes(mut self, limit: usize) -> Self {
+        self.max_queued_tls_handshakes = limit;
+        self
+    }
+
+    /// Sets how long the socket of a new connection can block on a read or a write during
+    /// its TLS handshake, after which the connection is closed. The default is 10 seconds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `timeout` is zero.
+    pub fn with_tls_handshake_timeout(mut self, timeout: Duration) -> Self {
+        assert!(
+            timeout != Duration::ZERO,
+            "The TLS handshake timeout can't be zero"
+        );
+        self.tls_handshake_timeout = timeout;
+        self
+    }
+
+    /// Asks the clients for a certificate during the TLS handshake, which must be signed by
+    /// one of the authorities of `ca_certificates`, in PEM format. Disabled by default.
+    ///
+    /// The certificates of a client are then returned by `Request::peer_certificates()`. A
+    /// client whose certificate isn't signed by one of the authorities, or that sends none
+    /// in `TlsClientAuthMode::Required` mode, fails its handshake.
+    ///
+    /// This isn't supported by the `ssl-native-tls` backend, with which the server fails to
+    /// start.
+    pub fn with_tls_client_auth(
+        mut self,
+        ca_certificates: Vec<u8>,
+        mode: TlsClientAuthMode,
+    ) -> Self {
+        self.tls_client_auth = Some((ca_certificates, mode));
+        self
+    }
+}
+
+/// Signature of the callback set with `ServerConfigAdvanced::with_connection_setup`.
+type SetupFn = dyn Fn(&TcpStream, SocketAddr) -> IoResult<()> + Send + Sync;
+
+/// Callback set with `ServerConfigAdvanced::with_connection_setup`.
+#[derive(Clone)]
+pub(crate) struct ConnectionSetup(Arc<SetupFn>);
+
+impl ConnectionSetup {
+    /// Calls the callback, turning a panic into an error.
+    pub(crate) fn call(&self, stream: &TcpStream, addr: SocketAddr) -> IoResult<()> {
+        panic::catch_unwind(AssertUnwindSafe(|| (self.0)(stream, addr))).unwrap_or_else(|_| {
+            Err(IoError::new(
+                ErrorKind::Other,
+                "The connection setup callback panicked",
+            ))
+        })
+    }
+}
+
+impl fmt::Debug for ConnectionSetup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ConnectionSetup")
+    }
+}
+
+/// What to do with a new connection, returned by the filter set with
+/// `ServerConfigAdvanced::with_connection_filter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionDecision {
+    /// The connection is served.
+    Accept,
+    /// The connection is closed right away.
+    Drop,
+    /// An empty response with this status code is sent, then the connection is closed.
+    RejectWith(StatusCode),
+}
+
+/// Signature of the filter set with `ServerConfigAdvanced::with_connection_filter`.
+type ConnectionFilterFn = dyn Fn(&SocketAddr) -> ConnectionDecision + Send + Sync;
+
+/// Filter set with `ServerConfigAdvanced::with_connection_filter`.
+#[derive(Clone)]
+pub(crate) struct ConnectionFilter(Arc<ConnectionFilterFn>);
+
+impl ConnectionFilter {
+    /// Calls the filter, a panic dropping the connection.
+    pub(crate) fn decide(&self, addr: &SocketAddr) -> ConnectionDecision {
+        panic::catch_unwind(AssertUnwindSafe(|| (self.0)(addr))).unwrap_or(ConnectionDecision::Drop)
+    }
+}
+
+impl fmt::Debug for ConnectionFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ConnectionFilter")
+    }
+}
+
+/// Signature of the handler set with `ServerConfigAdvanced::with_accept_error_handler`.
+type AcceptErrorFn = dyn Fn(&IoError) + Send + Sync;
+
+/// Handler set with `ServerConfigAdvanced::with_accept_error_handler`.
+#[derive(Clone)]
+pub(crate) struct AcceptErrorHandler(Arc<AcceptErrorFn>);
+
+impl AcceptErrorHandler {
+    /// Calls the handler, ignoring its panics.
+    pub(crate) fn call(&self, err: &IoError) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| (self.0)(err)));
+    }
+}
+
+impl fmt::Debug for AcceptErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AcceptErrorHandler")
+    }
+}
+
+/// Signature of the hook set with `ServerConfigAdvanced::with_access_log`.
+type AccessLogFn = dyn Fn(&AccessLogEntry) + Send + Sync;
+
+/// Hook set with `ServerConfigAdvanced::with_access_log`.
+#[derive(Clone)]
+pub(crate) struct AccessLog(Arc<AccessLogFn>);
+
+impl AccessLog {
+    /// Calls the hook, ignoring its panics.
+    pub(crate) fn record(&self, entry: &AccessLogEntry) {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| (self.0)(entry)));
+    }
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AccessLog")
+    }
+}
+
+/// Signature of the matcher set with `ServerConfigAdvanced::with_legacy_client_mode`.
+type LegacyClientFn = dyn Fn(&Request) -> bool + Send + Sync;
+
+/// Matcher set with `ServerConfigAdvanced::with_legacy_client_mode`.
+#[derive(Clone)]
+pub(crate) struct LegacyClientMatcher(Arc<LegacyClientFn>);
+
+impl LegacyClientMatcher {
+    /// Calls the matcher, a panic counting as not matching.
+    pub(crate) fn matches(&self, rq: &Request) -> bool {
+        panic::catch_unwind(AssertUnwindSafe(|| (self.0)(rq))).unwrap_or(false)
+    }
+}
+
+impl fmt::Debug for LegacyClientMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LegacyClientMatcher")
+    }
+}
+
+/// What happens to the new connections once the limit set with
+/// `ServerConfigAdvanced::with_max_connections` is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionLimitMode {
+    /// The server stops accepting connections until one of them is closed. The new
+    /// connections wait in the backlog of the listening socket.
+    StopAccepting,
+    /// The new connections are accepted and closed right away, after sending them a
+    /// `503 Service Unavailable` response without reading their request. TLS connections are
+    /// closed without a response. They are counted in
+    /// `ServerStats::connection_limit_rejections`.
+    RespondUnavailable,
+}
+
+/// What happens to the new connections once the limit set with
+/// `ServerConfigAdvanced::with_task_queue_limit` is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskQueueLimitMode {
+    /// The server stops accepting connections until a worker thread takes one of the waiting
+    /// connections. The new connections wait in the backlog of the listening socket.
+    Block,
+    /// The new connections are closed right away. They are counted in
+    /// `ServerStats::task_queue_rejections`.
+    Drop,
+}
+
+/// Whether the clients must send a certificate, see
+/// `ServerConfigAdvanced::with_tls_client_auth`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsClientAuthMode {
+    /// The clients may connect without a certificate, and `Request::peer_certificates()` then
+    /// returns `None`.
+    Optional,
+    /// The clients without a certificate fail their handshake.
+    Required,
+}
+
+/// How the data exchanged with a client is buffered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferingMode {
+    /// Every read or write goes directly to the socket.
+    Unbuffered,
+    /// Data goes through a buffer of the default size (1 kiB).
+    Buffered,
+    /// Data goes through a buffer of the given size in bytes.
+    BufferedWithCapacity(usize),
+}
+
+impl BufferingMode {
+    pub(crate) fn capacity(self) -> usize {
+        match self {
+            // `BufReader` and `BufWriter` don't buffer anything with an empty buffer
+            BufferingMode::Unbuffered => 0,
+            BufferingMode::Buffered => 1024,
+            BufferingMode::BufferedWithCapacity(capacity) => capacity,
+        }
+    }
+}
+
+/// Settings of the load shedding, see `ServerConfigAdvanced::with_load_shedding`.
+///
+/// Once `queue_depth_threshold` requests are waiting to be received, new requests are shed
+/// with a probability that grows linearly from `shed_probability_at_threshold` to
+/// `max_shed_probability`, which is reached at twice the threshold.
+#[derive(Debug, Clone)]
+pub struct LoadShedding {
+    /// Number of pending requests from which requests start being shed.
+    pub queue_depth_threshold: usize,
+    /// Probability to shed a request when the threshold is reached, between 0 and 1.
+    pub shed_probability_at_threshold: f64,
+    /// Probability to shed a request at twice the threshold and above, between 0 and 1.
+    pub max_shed_probability: f64,
+    /// Value of the `Retry-After` header of the 503 responses.
+    pub retry_after: Duration,
+    /// Requests whose path starts with one of these prefixes are never shed, for example
+    /// health checks. As with `ServerConfigAdvanced::with_path_prefix_allowlist`, a prefix only
+    /// matches at a segment boundary: `/health` matches `/health` and `/health/ready`, but not
+    /// `/healthz`, and the prefix `/` only matches the root path itself.
+    pub exempt_path_prefixes: Vec<String>,
+}
+
+impl LoadShedding {
+    /// Returns the probability to shed a request when `queue_depth` requests are pending.
+    pub(crate) fn probability(&self, queue_depth: usize) -> f64 {
+        let threshold = self.queue_depth_threshold;
+        if queue_depth < threshold {
+            return 0.0;
+        }
+        if threshold == 0 || queue_depth >= 2 * threshold {
+            return self.max_shed_probability;
+        }
+
+        let ramp = (queue_depth - threshold) as f64 / threshold as f64;
+        self.shed_probability_at_threshold
+            + (self.max_shed_probability - self.shed_probability_at_threshold) * ramp
+    }
+
+    /// Returns true if the path of `url` starts with one of the exempt prefixes, at a segment
+    /// boundary.
+    pub(crate) fn is_exempt(&self, url: &str) -> bool {
+        let path = url.split('?').next().unwrap_or("");
+        self.exempt_path_prefixes
+            .iter()
+            .any(|prefix| match prefix.trim_end_matches('/') {
+                "" => path == "/",
+                prefix => match path.strip_prefix(prefix) {
+                    Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                    None => false,
+                },
+            })
+    }
+
+    /// Decides whether to shed a request.
+    pub(crate) fn should_shed(&self, queue_depth: usize, path: &str) -> bool {
+        let probability = self.probability(queue_depth);
+        probability > 0.0 && !self.is_exempt(path) && random_f64() < probability
+    }
+}
+
+/// Load shedding of a server, see `ServerConfigAdvanced::with_load_shedding`.
+#[derive(Debug)]
+pub(crate) struct LoadShedder {
+    settings: LoadShedding,
+    // the empty 503 response sent to the shed requests, serialized once
+    rejection: Vec<u8>,
+}
+
+impl LoadShedder {
+    fn new(settings: LoadShedding) -> LoadShedder {
+        let rejection = format!(
+            "503 Service Unavailable\r\nRetry-After: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            settings.retry_after.as_secs()
+        )
+        .into_bytes();
+        LoadShedder {
+            settings,
+            rejection,
+        }
+    }
+
+    /// Returns the response sent to the shed requests, without the HTTP version of its status
+    /// line.
+    pub(crate) fn rejection(&self) -> &[u8] {
+        &self.rejection
+    }
+
+    /// Decides whether to shed a request, see `LoadShedding::should_shed`.
+    pub(crate) fn should_shed(&self, queue_depth: usize, url: &str) -> bool {
+        self.settings.should_shed(queue_depth, url)
+    }
+}
+
+/// Value of the `X-Frame-Options` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOptions {
+    /// `DENY`
+    Deny,
+    /// `SAMEORIGIN`
+    SameOrigin,
+}
+
+/// Set of security headers added to every response.
+///
+/// Headers that were already set on a response are never overridden.
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    headers: Vec<Header>,
+    strict_transport_security: Option<Header>,
+}
+
+impl SecurityHeaders {
+    /// Builds the default set of headers, which only contains `X-Content-Type-Options: nosniff`.
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders {
+            headers: vec![
+                Header::from_bytes(&b"X-Content-Type-Options"[..], &b"nosniff"[..]).unwrap(),
+            ],
+            strict_transport_security: None,
+        }
+    }
+
+    /// Adds an `X-Frame-Options` header.
+    pub fn with_frame_options(mut self, options: FrameOptions) -> SecurityHeaders {
+        let value = match options {
+            FrameOptions::Deny => &b"DENY"[..],
+            FrameOptions::SameOrigin => &b"SAMEORIGIN"[..],
+        };
+        self.headers.retain(|h| !h.field.equiv("X-Frame-Options"));
+        self.headers
+            .push(Header::from_bytes(&b"X-Frame-Options"[..], value).unwrap());
+        self
+    }
+
+    /// Adds a `Strict-Transport-Security` header.
+    ///
+    /// As required by RFC 6797, this header is only sent on requests made through HTTPS.
+    pub fn with_strict_transport_security(
+        mut self,
+        max_age: Duration,
+        include_subdomains: bool,
+    ) -> SecurityHeaders {
+        let mut value = format!("max-age={}", max_age.as_secs());
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        self.strict_transport_security =
+            Some(Header::from_bytes(&b"Strict-Transport-Security"[..], value).unwrap());
+        self
+    }
+
+    /// Returns the headers that apply to a request, depending on whether it was made through
+    /// HTTPS.
+    pub(crate) fn headers(&self, secure: bool) -> impl Iterator<Item = &Header> {
+        let hsts = if secure {
+            self.strict_transport_security.as_ref()
+        } else {
+            None
+        };
+        self.headers.iter().chain(hsts)
+    }
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> SecurityHeaders {
+        SecurityHeaders::new()
+    }
+}
+
+/// Prefixes of the paths accepted by a server, see
+/// `ServerConfigAdvanced::with_path_prefix_allowlist`.
+#[derive(Debug)]
+pub(crate) struct PathAllowlist {
+    // sorted, without trailing slashes except for the root
+    prefixes: Vec<String>,
+    pub(crate) reject_status: StatusCode,
+    // the empty response sent to the rejected requests, serialized once
+    rejection: Vec<u8>,
+}
+
+impl PathAllowlist {
+    fn new(prefixes: Vec<String>, reject_status: StatusCode) -> PathAllowlist {
+        let mut prefixes: Vec<String> = prefixes
+            .into_iter()
+            .map(|prefix| match prefix.trim_end_matches('/') {
+                "" => "/".to_owned(),
+                trimmed => trimmed.to_owned(),
+            })
+            .collect();
+        prefixes.sort();
+        prefixes.dedup();
+
+        let rejection = format!(
+            "{} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            reject_status.0,
+            reject_status.default_reason_phrase()
+        )
+        .into_bytes();
+
+        PathAllowlist {
+            prefixes,
+            reject_status,
+            rejection,
+        }
+    }
+
+    /// Returns the response sent to the requests that aren't allowed, without the HTTP version
+    /// of its status line.
+    pub(crate) fn rejection(&self) -> &[u8] {
+        &self.rejection
+    }
+
+    /// Returns true if the path of `url` starts with one of the prefixes.
+    pub(crate) fn allows(&self, url: &str) -> bool {
+        let path = url.split('?').next().unwrap_or("");
+        if path == "/" {
+            return self.contains("/");
+        }
+
+        // looking up every part of the path that ends at a segment boundary
+        let path = path.trim_end_matches('/');
+        path.match_indices('/')
+            .map(|(index, _)| &path[..index])
+            .filter(|prefix| !prefix.is_empty())
+            .chain(std::iter::once(path))
+            .any(|prefix| self.contains(prefix))
+    }
+
+    fn contains(&self, prefix: &str) -> bool {
+        self.prefixes
+            .binary_search_by(|p| p.as_str().cmp(prefix))
+            .is_ok()
+    }
+}
+
+/// Headers added to every response, pre-serialized.
+#[derive(Debug)]
+pub(crate) struct StaticHeaders {
+    headers: Vec<Header>,
+    serialized: Vec<u8>,
+}
+
+/// Headers that are computed by tiny-http for each response.
+const RESERVED_HEADERS: &[&str] = &[
+    "Connection",
+    "Content-Length",
+    "Date",
+    "Keep-Alive",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+impl StaticHeaders {
+    fn new(headers: Vec<Header>) -> StaticHeaders {
+        let mut serialized = Vec::new();
+        for header in &headers {
+            let field = header.field.as_str().as_str();
+            assert!(
+                !RESERVED_HEADERS.iter().any(|r| header.field.equiv(r)),
+                "The {} header can't be a static response header",
+                field
+            );
+            assert!(
+                !field.is_empty()
+                    && !field
+                        .bytes()
+                        .any(|b| b == b':' || b.is_ascii_whitespace() || b.is_ascii_control()),
+                "Invalid static response header name: {:?}",
+                field
+            );
+            assert!(
+                !header
+                    .value
+                    .as_bytes()
+                    .iter()
+                    .any(|&b| b == b'\r' || b == b'\n' || b == 0),
+                "Invalid value for static response header {}",
+                field
+            );
+
+            serialized.extend_from_slice(field.as_bytes());
+            serialized.extend_from_slice(b": ");
+            serialized.extend_from_slice(header.value.as_bytes());
+            serialized.extend_from_slice(b"\r\n");
+        }
+
+        StaticHeaders {
+            headers,
+            serialized,
+        }
+    }
+
+    /// Returns true if one of the static headers has this name.
+    pub(crate) fn contains(&self, field: &'static str) -> bool {
+        self.headers.iter().any(|h| h.field.equiv(field))
+    }
+
+    /// Writes the static headers that aren't part of `response_headers`.
+    pub(crate) fn write_to<W: Write>(
+        &self,
+        mut writer: W,
+        response_headers: &[Header],
+    ) -> IoResult<()> {
+        let overridden = |header: &Header| response_headers.iter().any(|h| h.field == header.field);
+
+        if !self.headers.iter().any(overridden) {
+            return writer.write_all(&self.serialized);
+        }
+
+        for header in self.headers.iter().filter(|h| !overridden(h)) {
+            writer.write_all(header.field.as_str().as_bytes())?;
+            writer.write_all(b": ")?;
+            writer.write_all(header.value.as_bytes())?;
+            writer.write_all(b"\r\n")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoadShedding, PathAllowlist};
+    use crate::StatusCode;
+    use std::time::Duration;
+
+    fn load_shedding() -> LoadShedding {
+        LoadShedding {
+            queue_depth_threshold: 10,
+            shed_probability_at_threshold: 0.2,
+            max_shed_probability: 0.6,
+            retry_after: Duration::from_secs(1),
+            exempt_path_prefixes: vec!["/health".to_owned()],
+        }
+    }
+
+    #[test]
+    fn shed_probability_ramp() {
+        let load_shedding = load_shedding();
+        assert_eq!(load_shedding.probability(0), 0.0);
+        assert_eq!(load_shedding.probability(9), 0.0);
+        assert!((load_shedding.probability(10) - 0.2).abs() < 1e-9);
+        assert!((load_shedding.probability(15) - 0.4).abs() < 1e-9);
+        assert!((load_shedding.probability(20) - 0.6).abs() < 1e-9);
+        assert!((load_shedding.probability(1000) - 0.6).abs() < 1e-9);
+    }
+
+    #[test]
+    fn shed_rate() {
+        let load_shedding = load_shedding();
+        let shed = (0..10000)
+            .filter(|_| load_shedding.should_shed(15, "/"))
+            .count();
+        assert!(shed > 3500 && shed < 4500, "{} requests shed", shed);
+
+        assert!(!(0..1000).any(|_| load_shedding.should_shed(9, "/")));
+        assert!(!(0..1000).any(|_| load_shedding.should_shed(1000, "/health/ready")));
+    }
+
+    #[test]
+    fn shed_exemption_boundaries() {
+        let mut load_shedding = load_shedding();
+        load_shedding.exempt_path_prefixes = vec!["/health".to_owned(), "/static/".to_owned()];
+        assert!(load_shedding.is_exempt("/health"));
+        assert!(load_shedding.is_exempt("/health/"));
+        assert!(load_shedding.is_exempt("/health/ready"));
+        assert!(load_shedding.is_exempt("/health?full=1"));
+        assert!(load_shedding.is_exempt("/static"));
+        assert!(load_shedding.is_exempt("/static/app.js"));
+        assert!(!load_shedding.is_exempt("/healthz"));
+        assert!(!load_shedding.is_exempt("/health-check"));
+        assert!(!load_shedding.is_exempt("/staticfiles"));
+        assert!(!load_shedding.is_exempt("/"));
+
+        load_shedding.exempt_path_prefixes = vec!["/".to_owned()];
+        assert!(load_shedding.is_exempt("/"));
+        assert!(load_shedding.is_exempt("/?q=1"));
+        assert!(!load_shedding.is_exempt("/health"));
+    }
+
+    #[test]
+    fn path_allowlist_boundaries() {
+        let allowlist = PathAllowlist::new(
+            vec!["/api".to_owned(), "/static/".to_owned(), "/a-b".to_owned()],
+            StatusCode(404),
+        );
+        assert!(allowlist.allows("/api"));
+        assert!(allowlist.allows("/api/"));
+        assert!(allowlist.allows("/api/users?page=2"));
+        assert!(allowlist.allows("/api?x=1"));
+        assert!(allowlist.allows("/static/app.js"));
+        assert!(allowlist.allows("/static"));
+        assert!(allowlist.allows("/a-b/c"));
+        assert!(!allowlist.allows("/apix"));
+        assert!(!allowlist.allows("/ap"));
+        assert!(!allowlist.allows("/a"));
+        assert!(!allowlist.allows("/"));
+        assert!(!allowlist.allows("/wp-login.php"));
+        assert!(!allowlist.allows("*"));
+        // not percent-decoded
+        assert!(!allowlist.allows("/%61pi/users"));
+    }
+
+    #[test]
+    fn path_allowlist_root() {
+        let allowlist =
+            PathAllowlist::new(vec!["/".to_owned(), "/api".to_owned()], StatusCode(404));
+        assert!(allowlist.allows("/"));
+        assert!(allowlist.allows("/?q=1"));
+        assert!(allowlist.allows("/api/x"));
+        assert!(!allowlist.allows("/other"));
+    }
+}
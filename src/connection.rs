@@ -5,8 +5,11 @@ use std::os::unix::net as unix_net;
 use std::{
     net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
     path::PathBuf,
+    time::Duration,
 };
 
+use crate::log;
+
 /// Unified listener. Either a [`TcpListener`] or [`std::os::unix::net::UnixListener`]
 pub enum Listener {
     Tcp(TcpListener),
@@ -22,6 +25,30 @@ impl Listener {
         }
     }
 
+    /// Makes `accept()` return an error of kind `WouldBlock` when no client connected after
+    /// `timeout`.
+    ///
+    /// This is only supported on Linux and Android; on other systems, an error is returned.
+    pub(crate) fn set_accept_timeout(&self, timeout: Duration) -> std::io::Result<()> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            let socket = match self {
+                Self::Tcp(l) => socket2::SockRef::from(l),
+                Self::Unix(l) => socket2::SockRef::from(l),
+            };
+            socket.set_read_timeout(Some(timeout))
+        }
+
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            let _ = timeout;
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "accept timeouts are not supported on this platform",
+            ))
+        }
+    }
+
     pub(crate) fn accept(&self) -> std::io::Result<(Connection, Option<SocketAddr>)> {
         match self {
             Self::Tcp(l) => l
@@ -95,6 +122,65 @@ impl Connection {
         }
     }
 
+    /// Sets `TCP_NODELAY` and the TCP keep-alive idle time of a TCP connection. Does nothing
+    /// for Unix sockets.
+    ///
+    /// The keep-alive is only supported on Linux and Android; on other systems, it is ignored.
+    pub(crate) fn set_tcp_options(
+        &self,
+        nodelay: bool,
+        keepalive: Option<Duration>,
+    ) -> std::io::Result<()> {
+        #[allow(irrefutable_let_patterns)]
+        let stream = match self {
+            Self::Tcp(s) => s,
+            #[cfg(unix)]
+            Self::Unix(_) => return Ok(()),
+        };
+        if nodelay {
+            stream.set_nodelay(true)?;
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Some(time) = keepalive {
+            let params = socket2::TcpKeepalive::new().with_time(time);
+            socket2::SockRef::from(stream).set_tcp_keepalive(&params)?;
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let _ = keepalive;
+
+        Ok(())
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(s) => s.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Self::Unix(s) => s.set_read_timeout(timeout),
+        }
+    }
+
+    #[cfg(any(
+        feature = "ssl-openssl",
+        feature = "ssl-rustls",
+        feature = "ssl-native-tls"
+    ))]
+    pub(crate) fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(s) => s.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Self::Unix(s) => s.set_write_timeout(timeout),
+        }
+    }
+
+    pub(crate) fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(s) => s.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Self::Unix(s) => s.set_nonblocking(nonblocking),
+        }
+    }
+
     pub(crate) fn try_clone(&self) -> std::io::Result<Self> {
         match self {
             Self::Tcp(s) => s.try_clone().map(Self::from),
@@ -132,15 +218,86 @@ impl ConfigListenAddr {
         Self::Unix(path.into())
     }
 
-    pub(crate) fn bind(&self) -> std::io::Result<Listener> {
+    /// Binds every address. The addresses that can't be bound are skipped with a warning; an
+    /// error is only returned if none of them could be bound.
+    ///
+    /// The addresses with the port 0 are bound to the port picked by the system for the first
+    /// listener, so that a name resolving to several addresses, such as `localhost:0`, is
+    /// reachable on a single port.
+    ///
+    /// If `reuse_port` is true, `SO_REUSEPORT` is set on the TCP sockets before binding them.
+    pub(crate) fn bind(&self, reuse_port: bool) -> std::io::Result<Vec<Listener>> {
         match self {
-            Self::IP(a) => TcpListener::bind(a.as_slice()).map(Listener::from),
+            Self::IP(addrs) => {
+                let mut listeners = Vec::with_capacity(addrs.len());
+                let mut last_err = None;
+                let mut shared_port = None;
+                for addr in addrs {
+                    let mut addr = *addr;
+                    if let (0, Some(port)) = (addr.port(), shared_port) {
+                        addr.set_port(port);
+                    }
+                    let listener = if reuse_port {
+                        bind_reusable(&addr)
+                    } else {
+                        TcpListener::bind(addr)
+                    };
+                    match listener {
+                        Ok(listener) => {
+                            if shared_port.is_none() {
+                                shared_port = listener.local_addr().ok().map(|a| a.port());
+                            }
+                            listeners.push(Listener::from(listener))
+                        }
+                        Err(err) => {
+                            log::warn!("Unable to listen on {}: {}", addr, err);
+                            last_err = Some(err);
+                        }
+                    }
+                }
+                match last_err {
+                    Some(err) if listeners.is_empty() => Err(err),
+                    None if listeners.is_empty() => Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "could not resolve to any addresses",
+                    )),
+                    _ => Ok(listeners),
+                }
+            }
             #[cfg(unix)]
-            Self::Unix(a) => unix_net::UnixListener::bind(a).map(Listener::from),
+            Self::Unix(a) => unix_net::UnixListener::bind(a).map(|l| vec![Listener::from(l)]),
         }
     }
 }
 
+/// Binds a TCP listener with `SO_REUSEADDR` and `SO_REUSEPORT`, so that other sockets can
+/// listen to the same address.
+///
+/// This is only supported on Linux and Android; on other systems, an error is returned.
+fn bind_reusable(addr: &SocketAddr) -> std::io::Result<TcpListener> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use socket2::{Domain, Socket, Type};
+
+        let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+        socket.set_reuse_address(true)?;
+        socket.set_reuse_port(true)?;
+        socket.bind(&(*addr).into())?;
+        // the backlog used by `TcpListener::bind`
+        socket.listen(128)?;
+        Ok(socket.into())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = addr;
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "SO_REUSEPORT is not supported on this platform",
+        ))
+    }
+}
+
 /// Unified listen socket address. Either a [`SocketAddr`] or [`std::os::unix::net::SocketAddr`].
 #[derive(Debug, Clone)]
 pub enum ListenAddr {
@@ -0,0 +1,108 @@
+//! Server-Sent Events, see `Request::into_event_stream()`.
+
+use std::io::{Error as IoError, ErrorKind, Result as IoResult, Write};
+use std::time::Duration;
+
+use crate::FramedWriter;
+
+/// Writer of a `text/event-stream` response, returned by `Request::into_event_stream()`.
+///
+/// Each method writes a complete event as a single chunk and flushes it, so that the client
+/// receives it immediately whatever the `BufferingMode` of the server.
+///
+/// The response ends when the writer is finished with `finish()` or dropped. The connection
+/// can then be reused, as for `FramedWriter`.
+pub struct EventWriter {
+    writer: FramedWriter,
+}
+
+impl EventWriter {
+    pub(crate) fn new(writer: FramedWriter) -> EventWriter {
+        EventWriter { writer }
+    }
+
+    /// Sends an event of type `name` with `data` as its data.
+    ///
+    /// The lines of `data` are sent as separate `data` fields, the client joins them back with
+    /// line feeds. Fails with `ErrorKind::InvalidInput` if `name` contains a line break.
+    pub fn send_event(&mut self, name: &str, data: &str) -> IoResult<()> {
+        if name.contains(|c| c == '\r' || c == '\n') {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "line break in the name of an event",
+            ));
+        }
+        let mut event = format!("event: {}\n", name);
+        push_field(&mut event, "data", data);
+        self.send(event)
+    }
+
+    /// Sends an event without a type, that the client receives as a `message` event.
+    pub fn send_data(&mut self, data: &str) -> IoResult<()> {
+        let mut event = String::new();
+        push_field(&mut event, "data", data);
+        self.send(event)
+    }
+
+    /// Sends a comment, which the client ignores. Sending one regularly keeps the connection
+    /// from being closed by idle proxies.
+    pub fn send_comment(&mut self, comment: &str) -> IoResult<()> {
+        let mut event = String::new();
+        push_field(&mut event, "", comment);
+        self.send(event)
+    }
+
+    /// Tells the client how long to wait before reconnecting once the stream is closed.
+    pub fn send_retry(&mut self, delay: Duration) -> IoResult<()> {
+        self.send(format!("retry: {}\n", delay.as_millis()))
+    }
+
+    /// Ends the response, see `FramedWriter::finish()`.
+    pub fn finish(self) -> IoResult<()> {
+        self.writer.finish()
+    }
+
+    /// Writes `fields` followed by the empty line that ends an event, and flushes them.
+    fn send(&mut self, mut fields: String) -> IoResult<()> {
+        fields.push('\n');
+        self.writer.write_all(fields.as_bytes())?;
+        self.writer.flush()
+    }
+}
+
+/// Appends `value` as one `name: line` field per line. Lines may end with CR, LF or CRLF, as
+/// for the parser of the client. An empty name makes a comment.
+fn push_field(event: &mut String, name: &str, value: &str) {
+    for line in value
+        .split("\r\n")
+        .flat_map(|line| line.split(|c| c == '\r' || c == '\n'))
+    {
+        event.push_str(name);
+        event.push_str(": ");
+        event.push_str(line);
+        event.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::push_field;
+
+    fn field(name: &str, value: &str) -> String {
+        let mut event = String::new();
+        push_field(&mut event, name, value);
+        event
+    }
+
+    #[test]
+    fn line_breaks() {
+        assert_eq!(field("data", "hello"), "data: hello\n");
+        assert_eq!(field("data", ""), "data: \n");
+        assert_eq!(
+            field("data", "a\nb\r\nc\rd\n"),
+            "data: a\ndata: b\ndata: c\ndata: d\ndata: \n"
+        );
+        assert_eq!(field("data", " x\r\n\r\n"), "data:  x\ndata: \ndata: \n");
+        assert_eq!(field("", "keep-alive"), ": keep-alive\n");
+    }
+}
@@ -0,0 +1,127 @@
+//! Page cache hints for responses whose body is a file.
+
+use std::fs::File;
+#[cfg(all(feature = "fadvise", any(target_os = "linux", target_os = "android")))]
+use std::io::Error as IoError;
+use std::io::Result as IoResult;
+
+/// Hint given to the kernel about how the file of a response is accessed, see
+/// `Response::with_file_access_hint`.
+///
+/// The hints are only applied on Linux and Android when the `fadvise` feature is enabled, and
+/// are silently ignored otherwise.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FileAccessHint {
+    /// The file will be read from start to end, so it is worth reading ahead aggressively
+    /// (`POSIX_FADV_SEQUENTIAL`).
+    Sequential,
+    /// The file will be read only once (`POSIX_FADV_NOREUSE`).
+    Noreuse,
+    /// Evicts the file from the page cache once it has been sent (`POSIX_FADV_DONTNEED`), so
+    /// that one-time downloads don't push more useful data out of the cache.
+    DropCacheAfterSend,
+}
+
+/// Hints attached to a response, applied while its body is written.
+pub(crate) struct FileHints {
+    #[cfg(all(feature = "fadvise", any(target_os = "linux", target_os = "android")))]
+    file: File,
+    hints: Vec<FileAccessHint>,
+}
+
+impl FileHints {
+    /// Returns `None` if the hints can't be applied to this file on this platform.
+    #[cfg(all(feature = "fadvise", any(target_os = "linux", target_os = "android")))]
+    pub(crate) fn new(file: &File) -> Option<FileHints> {
+        // the advice applies to the file itself, so a duplicated descriptor works as well
+        let file = file.try_clone().ok()?;
+        Some(FileHints {
+            file,
+            hints: Vec::new(),
+        })
+    }
+
+    #[cfg(not(all(feature = "fadvise", any(target_os = "linux", target_os = "android"))))]
+    pub(crate) fn new(_file: &File) -> Option<FileHints> {
+        None
+    }
+
+    pub(crate) fn add(&mut self, hint: FileAccessHint) {
+        if !self.hints.contains(&hint) {
+            self.hints.push(hint);
+        }
+    }
+
+    /// Called before the first byte of the body is read.
+    pub(crate) fn before_send(&self) {
+        for hint in &self.hints {
+            match hint {
+                FileAccessHint::Sequential | FileAccessHint::Noreuse => {
+                    // hints are best-effort, a failure doesn't prevent sending the file
+                    let _ = self.apply(*hint);
+                }
+                FileAccessHint::DropCacheAfterSend => (),
+            }
+        }
+    }
+
+    /// Called once the whole body has been written.
+    pub(crate) fn after_send(&self) {
+        if self.hints.contains(&FileAccessHint::DropCacheAfterSend) {
+            let _ = self.apply(FileAccessHint::DropCacheAfterSend);
+        }
+    }
+
+    #[cfg(all(feature = "fadvise", any(target_os = "linux", target_os = "android")))]
+    fn apply(&self, hint: FileAccessHint) -> IoResult<()> {
+        use nix::fcntl::{posix_fadvise, PosixFadviseAdvice};
+        use std::os::unix::io::AsRawFd;
+
+        let advice = match hint {
+            FileAccessHint::Sequential => PosixFadviseAdvice::POSIX_FADV_SEQUENTIAL,
+            FileAccessHint::Noreuse => PosixFadviseAdvice::POSIX_FADV_NOREUSE,
+            FileAccessHint::DropCacheAfterSend => PosixFadviseAdvice::POSIX_FADV_DONTNEED,
+        };
+
+        // a length of 0 means "until the end of the file"
+        posix_fadvise(self.file.as_raw_fd(), 0, 0, advice).map_err(IoError::from)
+    }
+
+    #[cfg(not(all(feature = "fadvise", any(target_os = "linux", target_os = "android"))))]
+    fn apply(&self, _hint: FileAccessHint) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileHints;
+    use std::fs::File;
+
+    #[test]
+    #[cfg(all(feature = "fadvise", any(target_os = "linux", target_os = "android")))]
+    fn hints_on_file() {
+        use super::FileAccessHint;
+
+        let file = File::open(file!()).unwrap();
+        let mut hints = FileHints::new(&file).unwrap();
+        hints.add(FileAccessHint::Sequential);
+        hints.add(FileAccessHint::Noreuse);
+        hints.add(FileAccessHint::DropCacheAfterSend);
+        hints.add(FileAccessHint::Sequential);
+        assert_eq!(hints.hints.len(), 3);
+
+        for hint in &hints.hints {
+            hints.apply(*hint).unwrap();
+        }
+        hints.before_send();
+        hints.after_send();
+    }
+
+    #[test]
+    #[cfg(not(all(feature = "fadvise", any(target_os = "linux", target_os = "android"))))]
+    fn hints_unsupported() {
+        let file = File::open(file!()).unwrap();
+        assert!(FileHints::new(&file).is_none());
+    }
+}
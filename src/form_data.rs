@@ -0,0 +1,406 @@
+//! Streaming parser of `multipart/form-data` request bodies, see `Request::multipart`.
+
+use std::cell::RefCell;
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::{Header, Request};
+
+/// Size of the chunks read from the body of the request.
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Maximum size of the headers of a part.
+const MAX_PART_HEADERS: usize = 16 * 1024;
+
+/// Iterator over the parts of a `multipart/form-data` body, returned by
+/// `Request::multipart()`.
+///
+/// The body is read as the parts are consumed, so that uploaded files are never buffered
+/// whole. Moving to the next part skips what is left of the current one, which then reads
+/// as empty. Iteration stops after the first error.
+pub struct Multipart<'a> {
+    state: Rc<RefCell<State<'a>>>,
+}
+
+/// A part of a `multipart/form-data` body, whose content is read with `Read`.
+pub struct Part<'a> {
+    state: Rc<RefCell<State<'a>>>,
+    id: u64,
+    headers: Vec<Header>,
+    name: Option<String>,
+    filename: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Phase {
+    /// Before the first boundary.
+    Preamble,
+    /// In the body of the part with this identifier.
+    Part(u64),
+    /// Right after a delimiter.
+    Boundary,
+    /// After the closing boundary, or after an error.
+    Done,
+}
+
+struct State<'a> {
+    request: &'a mut Request,
+    /// `CRLF--boundary`
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    /// Position of the first byte of `buffer` that wasn't consumed yet.
+    position: usize,
+    phase: Phase,
+    parts: u64,
+}
+
+impl<'a> Multipart<'a> {
+    /// Parses the body of `request`, or returns `None` if it isn't `multipart/form-data`
+    /// with a valid boundary.
+    pub(crate) fn new(request: &'a mut Request) -> Option<Multipart<'a>> {
+        let content_type = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Content-Type"))?;
+        let boundary = boundary(content_type.value.as_str())?;
+
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+        Some(Multipart {
+            state: Rc::new(RefCell::new(State {
+                request,
+                delimiter,
+                // the first boundary may be at the very start of the body, without a line
+                // break before it
+                buffer: b"\r\n".to_vec(),
+                position: 0,
+                phase: Phase::Preamble,
+                parts: 0,
+            })),
+        })
+    }
+}
+
+impl<'a> Iterator for Multipart<'a> {
+    type Item = IoResult<Part<'a>>;
+
+    fn next(&mut self) -> Option<IoResult<Part<'a>>> {
+        let mut state = self.state.borrow_mut();
+        if state.phase == Phase::Done {
+            return None;
+        }
+        match state.next_part() {
+            Ok(Some(headers)) => {
+                let (name, filename) = content_disposition(&headers);
+                Some(Ok(Part {
+                    state: self.state.clone(),
+                    id: state.parts,
+                    headers,
+                    name,
+                    filename,
+                }))
+            }
+            Ok(None) => None,
+            Err(err) => {
+                state.phase = Phase::Done;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl Part<'_> {
+    /// Returns the headers of the part.
+    pub fn headers(&self) -> &[Header] {
+        &self.headers
+    }
+
+    /// Returns the name of the form field, from the `Content-Disposition` header.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the name of the uploaded file, from the `Content-Disposition` header, or
+    /// `None` if the part isn't a file.
+    ///
+    /// The name is sent by the client, so it must not be used as a path as it is.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// Returns the value of the `Content-Type` header of the part, if any.
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|h| h.field.equiv("Content-Type"))
+            .map(|h| h.value.as_str())
+    }
+}
+
+impl Read for Part<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let mut state = self.state.borrow_mut();
+        if state.phase != Phase::Part(self.id) || buf.is_empty() {
+            return Ok(0);
+        }
+        let result = state.read_body(buf);
+        if result.is_err() {
+            state.phase = Phase::Done;
+        }
+        result
+    }
+}
+
+impl State<'_> {
+    /// Reads more data from the request. Returns `false` at the end of the body.
+    fn fill(&mut self) -> IoResult<bool> {
+        self.buffer.drain(..self.position);
+        self.position = 0;
+        let len = self.buffer.len();
+        self.buffer.resize(len + READ_CHUNK, 0);
+        let result = self.request.as_reader().read(&mut self.buffer[len..]);
+        let read = *result.as_ref().unwrap_or(&0);
+        self.buffer.truncate(len + read);
+        result.map(|read| read != 0)
+    }
+
+    /// Reads the body of the current part, or of the preamble, up to the next delimiter.
+    /// Returns `0` once the delimiter is reached, and consumes it.
+    fn read_body(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        loop {
+            let available = &self.buffer[self.position..];
+            let (len, end) = match find(available, &self.delimiter) {
+                Some(index) => (index, index == 0),
+                // the end of the buffer could be the start of a delimiter
+                None => (
+                    available.len().saturating_sub(self.delimiter.len() - 1),
+                    false,
+                ),
+            };
+            if end {
+                self.position += self.delimiter.len();
+                self.phase = Phase::Boundary;
+                return Ok(0);
+            }
+            if len > 0 {
+                let len = len.min(buf.len());
+                buf[..len].copy_from_slice(&available[..len]);
+                self.position += len;
+                return Ok(len);
+            }
+            if !self.fill()? {
+                return Err(unexpected_eof());
+            }
+        }
+    }
+
+    /// Skips the rest of the current part, and reads the headers of the next one. Returns
+    /// `None` after the closing boundary.
+    fn next_part(&mut self) -> IoResult<Option<Vec<Header>>> {
+        let mut scratch = [0; 1024];
+        while self.phase != Phase::Boundary {
+            self.read_body(&mut scratch)?;
+        }
+
+        // `--` after the delimiter closes the body, the epilogue is ignored
+        while self.buffer.len() - self.position < 2 {
+            if !self.fill()? {
+                return Err(unexpected_eof());
+            }
+        }
+        if self.buffer[self.position..].starts_with(b"--") {
+            self.phase = Phase::Done;
+            return Ok(None);
+        }
+        let line = self.read_line(MAX_PART_HEADERS)?;
+        if !line.iter().all(|&b| b == b' ' || b == b'\t') {
+            return Err(invalid("invalid multipart boundary"));
+        }
+
+        let mut headers = Vec::new();
+        let mut budget = MAX_PART_HEADERS;
+        loop {
+            let line = self.read_line(budget)?;
+            if line.is_empty() {
+                break;
+            }
+            budget = budget
+                .checked_sub(line.len() + 2)
+                .ok_or_else(|| invalid("headers of multipart body too large"))?;
+            let header = std::str::from_utf8(&line)
+                .ok()
+                .and_then(|line| Header::from_str(line).ok())
+                .ok_or_else(|| invalid("invalid header in multipart body"))?;
+            headers.push(header);
+        }
+
+        self.parts += 1;
+        self.phase = Phase::Part(self.parts);
+        Ok(Some(headers))
+    }
+
+    /// Reads a line ending with CRLF, and returns it without the line break.
+    fn read_line(&mut self, max_len: usize) -> IoResult<Vec<u8>> {
+        loop {
+            let available = &self.buffer[self.position..];
+            if let Some(index) = find(available, b"\r\n") {
+                let line = available[..index].to_vec();
+                self.position += index + 2;
+                return Ok(line);
+            }
+            if available.len() > max_len {
+                return Err(invalid("headers of multipart body too large"));
+            }
+            if !self.fill()? {
+                return Err(unexpected_eof());
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn unexpected_eof() -> IoError {
+    IoError::new(
+        ErrorKind::UnexpectedEof,
+        "multipart body ended before its closing boundary",
+    )
+}
+
+fn invalid(message: &'static str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, message)
+}
+
+/// Returns the boundary of a `multipart/form-data` content type (RFC 2046 §5.1.1).
+fn boundary(content_type: &str) -> Option<String> {
+    let mut params = split_params(content_type);
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    let boundary = params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("boundary") {
+            Some(unquote(value.trim()))
+        } else {
+            None
+        }
+    })?;
+    let valid = (1..=70).contains(&boundary.len())
+        && !boundary.ends_with(' ')
+        && boundary
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"'()+_,-./:=? ".contains(&b));
+    if valid {
+        Some(boundary)
+    } else {
+        None
+    }
+}
+
+/// Returns the `name` and `filename` parameters of the `Content-Disposition` header.
+fn content_disposition(headers: &[Header]) -> (Option<String>, Option<String>) {
+    let header = match headers
+        .iter()
+        .find(|h| h.field.equiv("Content-Disposition"))
+    {
+        Some(header) => header,
+        None => return (None, None),
+    };
+
+    let (mut name, mut filename) = (None, None);
+    for param in split_params(header.value.as_str()).skip(1) {
+        if let Some((key, value)) = param.split_once('=') {
+            let key = key.trim();
+            if key.eq_ignore_ascii_case("name") {
+                name = Some(unquote(value.trim()));
+            } else if key.eq_ignore_ascii_case("filename") {
+                filename = Some(unquote(value.trim()));
+            }
+        }
+    }
+    (name, filename)
+}
+
+/// Splits a header value at the semicolons that aren't in a quoted string.
+fn split_params(value: &str) -> impl Iterator<Item = &str> {
+    let mut rest = Some(value);
+    std::iter::from_fn(move || {
+        let input = rest?;
+        let (mut quoted, mut escaped) = (false, false);
+        for (index, c) in input.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                ';' if !quoted => {
+                    rest = Some(&input[index + 1..]);
+                    return Some(&input[..index]);
+                }
+                _ => (),
+            }
+        }
+        rest = None;
+        Some(input)
+    })
+}
+
+/// Removes the quotes and the escaping of a quoted string, or returns a token as it is.
+fn unquote(value: &str) -> String {
+    let inner = match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(inner) => inner,
+        None => return value.to_owned(),
+    };
+    let mut output = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => output.extend(chars.next()),
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{boundary, unquote};
+
+    #[test]
+    fn boundaries() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=abc").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data;charset=utf-8;BOUNDARY=\"a b=c\"").as_deref(),
+            Some("a b=c")
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("multipart/mixed; boundary=abc"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+        assert_eq!(boundary("multipart/form-data; boundary=\"abc \""), None);
+        assert_eq!(
+            boundary(&format!("multipart/form-data; boundary={}", "a".repeat(71))),
+            None
+        );
+    }
+
+    #[test]
+    fn quoted_strings() {
+        assert_eq!(unquote("token"), "token");
+        assert_eq!(unquote("\"a \\\"quoted\\\" name\""), "a \"quoted\" name");
+        assert_eq!(unquote("\"back\\\\slash\""), "back\\slash");
+    }
+}
@@ -0,0 +1,268 @@
+//! Address of the client of a request that went through proxies, see
+//! `Request::real_remote_addr`.
+//!
+//! The addresses added by the proxies are read from the `Forwarded` headers (RFC 7239), or
+//! from the `X-Forwarded-For` headers if there are none.
+
+use std::net::{IpAddr, Ipv6Addr};
+
+use crate::Header;
+
+/// Returns the address of the client, given the headers of a request and the address of the
+/// peer that sent it.
+///
+/// The addresses of the chain are walked from the right, as long as the address that
+/// appended them is one of `trusted_proxies`. If one of the values read this way isn't an
+/// address, the headers can't be relied on and `peer` is returned.
+pub(crate) fn real_remote_addr(
+    headers: &[Header],
+    peer: IpAddr,
+    trusted_proxies: &[IpAddr],
+) -> IpAddr {
+    let chain = if headers.iter().any(|h| h.field.equiv("Forwarded")) {
+        forwarded_chain(headers)
+    } else {
+        x_forwarded_for_chain(headers)
+    };
+
+    let mut client = peer;
+    for node in chain.iter().rev() {
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+        client = match node.and_then(parse_node) {
+            Some(addr) => addr,
+            None => return peer,
+        };
+    }
+    client
+}
+
+/// Returns the values of the `for` parameters of the `Forwarded` headers, from the first
+/// proxy to the last one. An element without a `for` parameter gives `None`.
+fn forwarded_chain(headers: &[Header]) -> Vec<Option<&str>> {
+    headers
+        .iter()
+        .filter(|h| h.field.equiv("Forwarded"))
+        .flat_map(|h| split_unquoted(h.value.as_str(), b','))
+        .map(|element| {
+            split_unquoted(element, b';').into_iter().find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case("for") {
+                    Some(value.trim())
+                } else {
+                    None
+                }
+            })
+        })
+        .collect()
+}
+
+/// Splits `value` at each `separator` that isn't part of a quoted string, such as the commas
+/// of `for=192.0.2.1;host="a,b"`.
+fn split_unquoted(value: &str, separator: u8) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, b) in value.bytes().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if quoted && b == b'\\' {
+            escaped = true;
+        } else if b == b'"' {
+            quoted = !quoted;
+        } else if !quoted && b == separator {
+            parts.push(&value[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// Returns the addresses of the `X-Forwarded-For` headers, from the first proxy to the last
+/// one.
+fn x_forwarded_for_chain(headers: &[Header]) -> Vec<Option<&str>> {
+    headers
+        .iter()
+        .filter(|h| h.field.equiv("X-Forwarded-For"))
+        .flat_map(|h| h.value.as_str().split(','))
+        .map(|node| Some(node.trim()))
+        .collect()
+}
+
+/// Parses an address such as `192.0.2.1`, `192.0.2.1:5000`, `2001:db8::1` or
+/// `"[2001:db8::1]:5000"`, ignoring the port. Returns `None` for the obfuscated identifiers
+/// and `unknown`, which can't be told apart from a forged value.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node
+        .strip_prefix('"')
+        .and_then(|node| node.strip_suffix('"'))
+        .unwrap_or(node);
+
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, port) = rest.split_once(']')?;
+        if !port.is_empty() {
+            parse_port(port.strip_prefix(':')?)?;
+        }
+        return ip.parse::<Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    let (ip, port) = node.rsplit_once(':')?;
+    parse_port(port)?;
+    ip.parse::<std::net::Ipv4Addr>().ok().map(IpAddr::V4)
+}
+
+fn parse_port(port: &str) -> Option<u16> {
+    if port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    port.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::real_remote_addr;
+    use crate::Header;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    const PEER: &str = "10.0.0.1";
+
+    fn client(headers: &[&str], trusted: &[&str]) -> IpAddr {
+        let headers: Vec<Header> = headers
+            .iter()
+            .map(|h| Header::from_str(h).unwrap())
+            .collect();
+        let trusted: Vec<IpAddr> = trusted.iter().map(|ip| ip.parse().unwrap()).collect();
+        real_remote_addr(&headers, PEER.parse().unwrap(), &trusted)
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        assert_eq!(client(&["X-Forwarded-For: 192.0.2.1"], &[]), ip(PEER));
+        assert_eq!(
+            client(&["Forwarded: for=192.0.2.1"], &["10.0.0.2"]),
+            ip(PEER)
+        );
+        assert_eq!(client(&[], &[PEER]), ip(PEER));
+    }
+
+    #[test]
+    fn x_forwarded_for() {
+        let cases: &[(&[&str], &[&str], &str)] = &[
+            (&["X-Forwarded-For: 192.0.2.1"], &[PEER], "192.0.2.1"),
+            (&["x-forwarded-for: 192.0.2.1:5000"], &[PEER], "192.0.2.1"),
+            (&["X-Forwarded-For: 2001:db8::1"], &[PEER], "2001:db8::1"),
+            (
+                &["X-Forwarded-For: [2001:db8::1]:5000"],
+                &[PEER],
+                "2001:db8::1",
+            ),
+            // the client can prepend anything, only the trusted proxies are believed
+            (
+                &["X-Forwarded-For: 198.51.100.1, 192.0.2.1, 10.0.0.2"],
+                &[PEER, "10.0.0.2"],
+                "192.0.2.1",
+            ),
+            (
+                &[
+                    "X-Forwarded-For: 198.51.100.1, 192.0.2.1",
+                    "X-Forwarded-For: 10.0.0.2",
+                ],
+                &[PEER, "10.0.0.2"],
+                "192.0.2.1",
+            ),
+            // every address is trusted
+            (
+                &["X-Forwarded-For: 10.0.0.2"],
+                &[PEER, "10.0.0.2"],
+                "10.0.0.2",
+            ),
+        ];
+        for (headers, trusted, expected) in cases {
+            assert_eq!(client(headers, trusted), ip(expected), "{:?}", headers);
+        }
+    }
+
+    #[test]
+    fn forwarded() {
+        let cases: &[(&[&str], &[&str], &str)] = &[
+            (&["Forwarded: for=192.0.2.1"], &[PEER], "192.0.2.1"),
+            (&["Forwarded: For=\"192.0.2.1:5000\""], &[PEER], "192.0.2.1"),
+            (
+                &["Forwarded: for=\"[2001:db8::1]\""],
+                &[PEER],
+                "2001:db8::1",
+            ),
+            (
+                &["Forwarded: proto=https;for=\"[2001:db8::1]:5000\";by=10.0.0.1"],
+                &[PEER],
+                "2001:db8::1",
+            ),
+            (
+                &[
+                    "Forwarded: for=198.51.100.1, for=192.0.2.1",
+                    "Forwarded: for=10.0.0.2",
+                ],
+                &[PEER, "10.0.0.2"],
+                "192.0.2.1",
+            ),
+            // separators within quoted strings
+            (
+                &["Forwarded: for=192.0.2.1;host=\"a,b\", for=10.0.0.2"],
+                &[PEER, "10.0.0.2"],
+                "192.0.2.1",
+            ),
+            (
+                &["Forwarded: ext=\"a;for=198.51.100.1\";for=192.0.2.1"],
+                &[PEER],
+                "192.0.2.1",
+            ),
+            (
+                &["Forwarded: for=192.0.2.1;ext=\"\\\",for=198.51.100.1\""],
+                &[PEER],
+                "192.0.2.1",
+            ),
+            // X-Forwarded-For is ignored when Forwarded is present
+            (
+                &["X-Forwarded-For: 198.51.100.1", "Forwarded: for=192.0.2.1"],
+                &[PEER],
+                "192.0.2.1",
+            ),
+        ];
+        for (headers, trusted, expected) in cases {
+            assert_eq!(client(headers, trusted), ip(expected), "{:?}", headers);
+        }
+    }
+
+    #[test]
+    fn bogus_values() {
+        let cases: &[&str] = &[
+            "X-Forwarded-For: not an address",
+            "X-Forwarded-For: 192.0.2.1, ",
+            "X-Forwarded-For: 192.0.2.1:port",
+            "X-Forwarded-For: [2001:db8::1",
+            "X-Forwarded-For: [192.0.2.1]:5000",
+            "X-Forwarded-For: 2001:db8::1:99999",
+            "Forwarded: for=unknown",
+            "Forwarded: for=_hidden",
+            "Forwarded: by=192.0.2.1",
+            "Forwarded: for=\"[2001:db8::1]junk\"",
+        ];
+        for header in cases {
+            assert_eq!(client(&[header], &[PEER]), ip(PEER), "{}", header);
+        }
+
+        // a bogus value behind an untrusted address isn't looked at
+        let headers = ["X-Forwarded-For: bogus, 192.0.2.1"];
+        assert_eq!(client(&headers, &[PEER]), ip("192.0.2.1"));
+    }
+}
@@ -0,0 +1,174 @@
+//! Writing the body of a response whose head was sent by `Request::into_framed_writer()`.
+
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use crate::client::ConnectionCloser;
+use crate::response::BodyFraming;
+
+/// Writer of a response body that enforces the length declared by
+/// `Request::into_framed_writer()`, so that the connection can be reused for the next request.
+///
+/// With a declared length, writing more data fails with `ErrorKind::InvalidInput`; the bytes
+/// that fit are still written. Without a declared length, the data is sent with the chunked
+/// encoding, unless the client uses HTTP 1.0 in which case the connection is closed after
+/// the body.
+///
+/// The body must be completed with `finish()`. Dropping the writer finishes it as well, but
+/// ignores the errors.
+pub struct FramedWriter {
+    writer: Box<dyn Write + Send + 'static>,
+    state: State,
+    pad_short_body: bool,
+    closer: Option<Arc<ConnectionCloser>>,
+    notify_when_responded: Option<Sender<()>>,
+}
+
+enum State {
+    Discard,
+    Length { remaining: u64 },
+    Chunked,
+    UntilClose,
+    Finished,
+}
+
+impl FramedWriter {
+    pub(crate) fn new(
+        writer: Box<dyn Write + Send + 'static>,
+        framing: BodyFraming,
+        closer: Option<Arc<ConnectionCloser>>,
+        notify_when_responded: Option<Sender<()>>,
+    ) -> FramedWriter {
+        let state = match framing {
+            BodyFraming::Discard => State::Discard,
+            BodyFraming::Length(remaining) => State::Length { remaining },
+            BodyFraming::Chunked => State::Chunked,
+            BodyFraming::UntilClose => State::UntilClose,
+        };
+        FramedWriter {
+            writer,
+            state,
+            pad_short_body: false,
+            closer,
+            notify_when_responded,
+        }
+    }
+
+    /// If `pad` is true, a body shorter than its declared length is completed with zeros by
+    /// `finish()`. Otherwise, the default, `finish()` fails and the connection is closed, since
+    /// the client would wait for the missing bytes.
+    pub fn with_short_body_padding(mut self, pad: bool) -> FramedWriter {
+        self.pad_short_body = pad;
+        self
+    }
+
+    /// Returns the number of bytes that can still be written, or `None` if the length of the
+    /// body wasn't declared.
+    pub fn remaining(&self) -> Option<u64> {
+        match self.state {
+            State::Length { remaining } => Some(remaining),
+            State::Discard | State::Finished => Some(0),
+            State::Chunked | State::UntilClose => None,
+        }
+    }
+
+    /// Completes the body and flushes it.
+    ///
+    /// Fails with `ErrorKind::InvalidData` if fewer bytes than the declared length were
+    /// written and padding is disabled.
+    pub fn finish(mut self) -> IoResult<()> {
+        self.finish_impl()
+    }
+
+    fn finish_impl(&mut self) -> IoResult<()> {
+        let result = match std::mem::replace(&mut self.state, State::Finished) {
+            State::Length { remaining: 0 } | State::Discard => Ok(()),
+            State::Length { remaining } if self.pad_short_body => {
+                std::io::copy(&mut std::io::repeat(0).take(remaining), &mut self.writer).map(|_| ())
+            }
+            State::Length { remaining } => {
+                self.close_connection();
+                Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("{} bytes of the declared length weren't written", remaining),
+                ))
+            }
+            State::Chunked => self.writer.write_all(b"0\r\n\r\n"),
+            State::UntilClose => {
+                self.close_connection();
+                Ok(())
+            }
+            State::Finished => return Ok(()),
+        };
+
+        let result = result.and_then(|()| self.writer.flush());
+        if result.is_err() {
+            self.close_connection();
+        }
+        result
+    }
+
+    fn close_connection(&self) {
+        if let Some(ref closer) = self.closer {
+            closer.close();
+        }
+    }
+}
+
+impl Write for FramedWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        match self.state {
+            State::Discard => Ok(buf.len()),
+            State::Length { ref mut remaining } => {
+                if buf.is_empty() {
+                    return Ok(0);
+                }
+                if *remaining == 0 {
+                    return Err(IoError::new(
+                        ErrorKind::InvalidInput,
+                        "More data than the declared length",
+                    ));
+                }
+                let len = (buf.len() as u64).min(*remaining) as usize;
+                let written = self.writer.write(&buf[..len])?;
+                *remaining -= written as u64;
+                Ok(written)
+            }
+            State::Chunked => {
+                // an empty chunk would end the body
+                if buf.is_empty() {
+                    return Ok(0);
+                }
+                let result = self
+                    .writer
+                    .write_all(format!("{:x}\r\n", buf.len()).as_bytes())
+                    .and_then(|()| self.writer.write_all(buf))
+                    .and_then(|()| self.writer.write_all(b"\r\n"));
+                if result.is_err() {
+                    // the chunk may have been cut anywhere
+                    self.close_connection();
+                }
+                result.map(|()| buf.len())
+            }
+            State::UntilClose => self.writer.write(buf),
+            State::Finished => Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "The body is already finished",
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.writer.flush()
+    }
+}
+
+impl Drop for FramedWriter {
+    fn drop(&mut self) {
+        let _ = self.finish_impl();
+        if let Some(sender) = self.notify_when_responded.take() {
+            let _ = sender.send(());
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Handing connections off to other processes.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, BufReader, BufWriter, Error as IoError, Read, Result as IoResult, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+
+use crate::util::{RefinedTcpStream, SequentialReader, SequentialWriter};
+use crate::{HTTPVersion, Header, Method};
+
+type SocketReader = SequentialReader<BufReader<RefinedTcpStream>>;
+type SocketWriter = SequentialWriter<BufWriter<RefinedTcpStream>>;
+
+/// Error returned by [`Request::into_raw_connection`](crate::Request::into_raw_connection).
+#[derive(Debug)]
+pub enum HandoffError {
+    /// The server wasn't built with `ServerConfigAdvanced::with_connection_handoff`.
+    NotEnabled,
+    /// The request doesn't come from a plain TCP connection, for example it went through TLS.
+    Unsupported,
+    /// Another request of the same connection hasn't been fully answered yet.
+    PipelinedRequests,
+    /// Error while flushing the data that was already written to the connection.
+    Io(IoError),
+}
+
+impl fmt::Display for HandoffError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandoffError::NotEnabled => write!(formatter, "Connection handoff is not enabled"),
+            HandoffError::Unsupported => {
+                write!(
+                    formatter,
+                    "Connection handoff is only supported for plain TCP"
+                )
+            }
+            HandoffError::PipelinedRequests => {
+                write!(
+                    formatter,
+                    "Other requests of the connection are still pending"
+                )
+            }
+            HandoffError::Io(err) => write!(formatter, "{}", err),
+        }
+    }
+}
+
+impl Error for HandoffError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HandoffError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Everything needed to continue handling a connection outside of tiny-http, returned by
+/// [`Request::into_raw_connection`](crate::Request::into_raw_connection).
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct RawConnectionParts {
+    /// The socket of the connection.
+    pub stream: TcpStream,
+    /// Bytes that were received from the socket but not consumed yet. They come before
+    /// anything that is read from `stream`.
+    pub leftover: Vec<u8>,
+    /// Number of bytes of the body that haven't been read yet, including the ones that are in
+    /// `leftover`. `None` if the length of the body isn't known, such as for chunked bodies.
+    pub body_remaining: Option<usize>,
+    /// Method of the request.
+    pub method: Method,
+    /// Target of the request.
+    pub url: String,
+    /// HTTP version of the request.
+    pub http_version: HTTPVersion,
+    /// Headers of the request.
+    pub headers: Vec<Header>,
+}
+
+/// Keeps the reading and writing ends of a connection reachable from a request, so that they
+/// can be taken away from the server.
+pub(crate) struct Handoff {
+    reader: Arc<Mutex<Option<SocketReader>>>,
+    writer: Arc<Mutex<Option<SocketWriter>>>,
+    body_read: Arc<AtomicUsize>,
+}
+
+impl Handoff {
+    /// Puts the reader and writer of a request in shared slots. The returned reader and writer
+    /// must be used by the request instead of the original ones.
+    pub(crate) fn new(
+        reader: SocketReader,
+        writer: SocketWriter,
+    ) -> (Handoff, SlotReader, SlotWriter) {
+        let handoff = Handoff {
+            reader: Arc::new(Mutex::new(Some(reader))),
+            writer: Arc::new(Mutex::new(Some(writer))),
+            body_read: Arc::new(AtomicUsize::new(0)),
+        };
+        let reader = SlotReader {
+            slot: handoff.reader.clone(),
+            body_read: handoff.body_read.clone(),
+            buffer: Vec::new(),
+        };
+        let writer = SlotWriter {
+            slot: handoff.writer.clone(),
+        };
+        (handoff, reader, writer)
+    }
+
+    /// Number of bytes read through the `SlotReader`.
+    pub(crate) fn body_read(&self) -> usize {
+        self.body_read.load(Relaxed)
+    }
+
+    /// Takes the connection away from the server.
+    ///
+    /// Returns the socket and the bytes that were buffered but not read yet. Nothing is taken
+    /// if the error is `HandoffError::PipelinedRequests`.
+    pub(crate) fn take(&self) -> Result<(TcpStream, Vec<u8>), HandoffError> {
+        let mut writer_slot = self.writer.lock().unwrap();
+        let mut reader_slot = self.reader.lock().unwrap();
+
+        let writer = writer_slot
+            .as_mut()
+            .ok_or(HandoffError::PipelinedRequests)?;
+        if !writer.previous_finished() {
+            return Err(HandoffError::PipelinedRequests);
+        }
+        let reader = match reader_slot.take() {
+            Some(reader) => reader,
+            None => return Err(HandoffError::PipelinedRequests),
+        };
+        let mut reader = match reader.try_into_inner() {
+            Ok(reader) => reader,
+            Err(reader) => {
+                *reader_slot = Some(reader);
+                return Err(HandoffError::PipelinedRequests);
+            }
+        };
+
+        // from now on, the server won't read or write anything on this connection
+        let writer = writer_slot.take().unwrap();
+        {
+            let mut shared = writer.shared_writer().lock().unwrap();
+            shared.flush().map_err(HandoffError::Io)?;
+            shared.get_mut().disarm();
+        }
+        reader.get_mut().disarm();
+
+        let stream = reader
+            .get_ref()
+            .try_clone_tcp()
+            .map_err(HandoffError::Io)?
+            .ok_or(HandoffError::Unsupported)?;
+        Ok((stream, reader.buffer().to_vec()))
+    }
+}
+
+/// Reads from the connection as long as it hasn't been handed off.
+pub(crate) struct SlotReader {
+    slot: Arc<Mutex<Option<SocketReader>>>,
+    body_read: Arc<AtomicUsize>,
+    // copy of the start of the buffer of the reader in the slot, returned by `fill_buf()`;
+    // the bytes stay in the reader until they are consumed, so that a handoff returns them
+    buffer: Vec<u8>,
+}
+
+impl Read for SlotReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let mut slot = self.slot.lock().unwrap();
+        let reader = match *slot {
+            Some(ref mut reader) => reader,
+            None => {
+                self.buffer.clear();
+                return Ok(0);
+            }
+        };
+        let read = if self.buffer.is_empty() {
+            reader.read(buf)?
+        } else {
+            let read = buf.len().min(self.buffer.len());
+            buf[..read].copy_from_slice(&self.buffer[..read]);
+            self.buffer.drain(..read);
+            reader.consume(read);
+            read
+        };
+        self.body_read.fetch_add(read, Relaxed);
+        Ok(read)
+    }
+}
+
+impl BufRead for SlotReader {
+    fn fill_buf(&mut self) -> IoResult<&[u8]> {
+        match *self.slot.lock().unwrap() {
+            Some(ref mut reader) if self.buffer.is_empty() => {
+                self.buffer.extend_from_slice(reader.fill_buf()?);
+            }
+            Some(_) => (),
+            None => self.buffer.clear(),
+        }
+        Ok(&self.buffer)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Some(ref mut reader) = *self.slot.lock().unwrap() {
+            reader.consume(amt);
+        }
+        self.buffer.drain(..amt);
+        self.body_read.fetch_add(amt, Relaxed);
+    }
+}
+
+/// Writes to the connection as long as it hasn't been handed off.
+pub(crate) struct SlotWriter {
+    slot: Arc<Mutex<Option<SocketWriter>>>,
+}
+
+impl Write for SlotWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        match *self.slot.lock().unwrap() {
+            Some(ref mut writer) => writer.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match *self.slot.lock().unwrap() {
+            Some(ref mut writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
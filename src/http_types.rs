@@ -0,0 +1,359 @@
+//! Conversions between the types of this crate and the types of the `http` crate, to use
+//! tiny-http with libraries that are built around `http`.
+//!
+//! Requires the `http-types` feature.
+//!
+//! Header names and values don't have the same rules in both crates: tiny-http only accepts
+//! ASCII values, while `http` rejects some names and control characters that tiny-http lets
+//! through. With `HeaderPolicy::Lenient`, which is what the `TryFrom` implementations and
+//! [`to_tiny_response`] use, such headers are dropped with a warning and the rest of the
+//! conversion goes on. With `HeaderPolicy::Strict`, the conversion fails instead.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+
+use crate::log;
+use crate::{HTTPVersion, Header, Method, Request, Response, StatusCode};
+
+/// What to do with a header that can't be represented by the other crate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeaderPolicy {
+    /// The header is dropped and a warning is logged.
+    Lenient,
+    /// The whole conversion fails with `HttpConversionError::Header`.
+    Strict,
+}
+
+/// Error returned by the conversions of this module.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum HttpConversionError {
+    /// The method isn't a valid token for the `http` crate.
+    Method(http::method::InvalidMethod),
+    /// The URL of the request, combined with its `Host` header, isn't a valid URI.
+    Uri(http::uri::InvalidUri),
+    /// The HTTP version has no equivalent in the other crate.
+    Version,
+    /// A header can't be converted, contains the name of the header.
+    Header(String),
+}
+
+impl fmt::Display for HttpConversionError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpConversionError::Method(err) => write!(formatter, "Invalid method: {}", err),
+            HttpConversionError::Uri(err) => write!(formatter, "Invalid URI: {}", err),
+            HttpConversionError::Version => write!(formatter, "Unsupported HTTP version"),
+            HttpConversionError::Header(name) => write!(formatter, "Invalid header: {}", name),
+        }
+    }
+}
+
+impl Error for HttpConversionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HttpConversionError::Method(err) => Some(err),
+            HttpConversionError::Uri(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<HTTPVersion> for http::Version {
+    type Error = HttpConversionError;
+
+    fn try_from(version: HTTPVersion) -> Result<http::Version, HttpConversionError> {
+        match (version.0, version.1) {
+            (0, 9) => Ok(http::Version::HTTP_09),
+            (1, 0) => Ok(http::Version::HTTP_10),
+            (1, 1) => Ok(http::Version::HTTP_11),
+            (2, 0) => Ok(http::Version::HTTP_2),
+            (3, 0) => Ok(http::Version::HTTP_3),
+            _ => Err(HttpConversionError::Version),
+        }
+    }
+}
+
+impl TryFrom<http::Version> for HTTPVersion {
+    type Error = HttpConversionError;
+
+    fn try_from(version: http::Version) -> Result<HTTPVersion, HttpConversionError> {
+        match version {
+            http::Version::HTTP_09 => Ok(HTTPVersion(0, 9)),
+            http::Version::HTTP_10 => Ok(HTTPVersion(1, 0)),
+            http::Version::HTTP_11 => Ok(HTTPVersion(1, 1)),
+            http::Version::HTTP_2 => Ok(HTTPVersion(2, 0)),
+            http::Version::HTTP_3 => Ok(HTTPVersion(3, 0)),
+            _ => Err(HttpConversionError::Version),
+        }
+    }
+}
+
+impl TryFrom<&Method> for http::Method {
+    type Error = HttpConversionError;
+
+    fn try_from(method: &Method) -> Result<http::Method, HttpConversionError> {
+        http::Method::from_bytes(method.as_str().as_bytes()).map_err(HttpConversionError::Method)
+    }
+}
+
+impl TryFrom<&Header> for (http::HeaderName, http::HeaderValue) {
+    type Error = HttpConversionError;
+
+    fn try_from(
+        header: &Header,
+    ) -> Result<(http::HeaderName, http::HeaderValue), HttpConversionError> {
+        let invalid = || HttpConversionError::Header(header.field.to_string());
+        let name = http::HeaderName::from_bytes(header.field.as_str().as_bytes())
+            .map_err(|_| invalid())?;
+        let value = http::HeaderValue::from_str(header.value.as_str()).map_err(|_| invalid())?;
+        Ok((name, value))
+    }
+}
+
+impl TryFrom<(&http::HeaderName, &http::HeaderValue)> for Header {
+    type Error = HttpConversionError;
+
+    fn try_from(
+        (name, value): (&http::HeaderName, &http::HeaderValue),
+    ) -> Result<Header, HttpConversionError> {
+        Header::from_bytes(name.as_str().as_bytes(), value.as_bytes())
+            .map_err(|_| HttpConversionError::Header(name.to_string()))
+    }
+}
+
+/// Applies the policy to the result of converting a header.
+fn filter_header<T>(
+    converted: Result<T, HttpConversionError>,
+    policy: HeaderPolicy,
+) -> Result<Option<T>, HttpConversionError> {
+    match (converted, policy) {
+        (Ok(header), _) => Ok(Some(header)),
+        (Err(err), HeaderPolicy::Strict) => Err(err),
+        (Err(_err), HeaderPolicy::Lenient) => {
+            log::warn!(
+                "Dropping header that can't be converted: {}",
+                crate::sanitize::sanitize_for_log(
+                    _err.to_string().as_bytes(),
+                    crate::sanitize::LOG_BUDGET
+                )
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Converts tiny-http headers into a `HeaderMap`. Repeated headers, such as `Set-Cookie`,
+/// are all kept.
+pub fn to_header_map(
+    headers: &[Header],
+    policy: HeaderPolicy,
+) -> Result<http::HeaderMap, HttpConversionError> {
+    let mut map = http::HeaderMap::with_capacity(headers.len());
+    for header in headers {
+        if let Some((name, value)) = filter_header(TryFrom::try_from(header), policy)? {
+            map.append(name, value);
+        }
+    }
+    Ok(map)
+}
+
+/// Converts a `HeaderMap` into tiny-http headers. Repeated headers, such as `Set-Cookie`,
+/// are all kept.
+pub fn from_header_map(
+    map: &http::HeaderMap,
+    policy: HeaderPolicy,
+) -> Result<Vec<Header>, HttpConversionError> {
+    let mut headers = Vec::with_capacity(map.len());
+    for entry in map {
+        if let Some(header) = filter_header(Header::try_from(entry), policy)? {
+            headers.push(header);
+        }
+    }
+    Ok(headers)
+}
+
+/// Returns the method, URI, version and headers of a request.
+///
+/// If the URL of the request is a path, the URI is made absolute with the `Host` header and
+/// the scheme of the connection.
+pub fn request_parts(
+    request: &Request,
+    policy: HeaderPolicy,
+) -> Result<http::request::Parts, HttpConversionError> {
+    let url = request.url();
+    let host = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Host"))
+        .map(|h| h.value.as_str());
+    let uri = match host {
+        Some(host) if url.starts_with('/') => {
+            let scheme = if request.secure() { "https" } else { "http" };
+            format!("{}://{}{}", scheme, host, url).parse()
+        }
+        _ => url.parse(),
+    }
+    .map_err(HttpConversionError::Uri)?;
+
+    let (mut parts, ()) = http::Request::new(()).into_parts();
+    parts.method = http::Method::try_from(request.method())?;
+    parts.uri = uri;
+    parts.version = http::Version::try_from(request.http_version().clone())?;
+    parts.headers = to_header_map(request.headers(), policy)?;
+    Ok(parts)
+}
+
+impl TryFrom<&Request> for http::request::Parts {
+    type Error = HttpConversionError;
+
+    /// Same as `request_parts` with `HeaderPolicy::Lenient`.
+    fn try_from(request: &Request) -> Result<http::request::Parts, HttpConversionError> {
+        request_parts(request, HeaderPolicy::Lenient)
+    }
+}
+
+/// Converts a response of the `http` crate, dropping the headers that tiny-http can't
+/// represent.
+///
+/// The length of the body is taken from the `Content-Length` header if there is one.
+/// Otherwise, the response is sent with chunked encoding when the client supports it.
+pub fn to_tiny_response<B>(response: http::Response<B>) -> Response<B>
+where
+    B: Read + Send + 'static,
+{
+    match try_to_tiny_response(response, HeaderPolicy::Lenient) {
+        Ok(response) => response,
+        Err(_) => unreachable!("lenient conversions only fail on headers"),
+    }
+}
+
+/// Same as `to_tiny_response`, but lets choose what to do with invalid headers.
+pub fn try_to_tiny_response<B>(
+    response: http::Response<B>,
+    policy: HeaderPolicy,
+) -> Result<Response<B>, HttpConversionError>
+where
+    B: Read + Send + 'static,
+{
+    let (parts, body) = response.into_parts();
+    let headers = from_header_map(&parts.headers, policy)?;
+
+    // the `Content-Length` header sets the length of the data
+    Ok(Response::new(
+        StatusCode(parts.status.as_u16()),
+        headers,
+        body,
+        None,
+        None,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        from_header_map, request_parts, to_tiny_response, try_to_tiny_response, HeaderPolicy,
+    };
+    use crate::{HTTPVersion, Header, Method, Request, TestRequest};
+    use std::convert::TryFrom;
+    use std::io::Cursor;
+
+    fn written(response: crate::Response<Cursor<Vec<u8>>>) -> String {
+        let mut out = Vec::new();
+        response
+            .raw_print(&mut out, HTTPVersion(1, 1), &[], false, None)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn request_round_trip() {
+        let request: Request = TestRequest::new()
+            .with_method(Method::NonStandard("PURGE".parse().unwrap()))
+            .with_path("/cache/item?all=1")
+            .with_header("Host: example.com".parse().unwrap())
+            .with_header("Cookie: a=1".parse().unwrap())
+            .with_header("Cookie: b=2".parse().unwrap())
+            .into();
+
+        let parts = http::request::Parts::try_from(&request).unwrap();
+        assert_eq!(parts.method.as_str(), "PURGE");
+        assert_eq!(parts.uri, "http://example.com/cache/item?all=1");
+        assert_eq!(parts.version, http::Version::HTTP_11);
+        let cookies: Vec<_> = parts.headers.get_all("cookie").iter().collect();
+        assert_eq!(cookies, ["a=1", "b=2"]);
+
+        let headers = from_header_map(&parts.headers, HeaderPolicy::Strict).unwrap();
+        assert_eq!(headers.len(), request.headers().len());
+        assert_eq!(
+            &HTTPVersion::try_from(parts.version).unwrap(),
+            request.http_version()
+        );
+    }
+
+    #[test]
+    fn invalid_header_policy() {
+        // `http` rejects names with a space, tiny-http doesn't
+        let request: Request = TestRequest::new()
+            .with_header(Header::from_bytes(&b"Bad Name"[..], &b"x"[..]).unwrap())
+            .with_header("X-Good: y".parse().unwrap())
+            .into();
+
+        let parts = request_parts(&request, HeaderPolicy::Lenient).unwrap();
+        assert_eq!(parts.headers.len(), request.headers().len() - 1);
+        assert_eq!(parts.headers["x-good"], "y");
+
+        assert!(request_parts(&request, HeaderPolicy::Strict).is_err());
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let response = http::Response::builder()
+            .status(201)
+            .header("Set-Cookie", "a=1")
+            .header("Set-Cookie", "b=2")
+            .header("Content-Type", "text/plain")
+            .header("Content-Length", "5")
+            .body(Cursor::new(b"hello".to_vec()))
+            .unwrap();
+
+        let response = to_tiny_response(response);
+        assert_eq!(response.status_code(), 201);
+        assert_eq!(response.data_length(), Some(5));
+
+        let written = written(response);
+        assert!(written.starts_with("HTTP/1.1 201"), "{}", written);
+        assert!(written.contains("set-cookie: a=1\r\n"), "{}", written);
+        assert!(written.contains("set-cookie: b=2\r\n"), "{}", written);
+        assert!(written.contains("Content-Length: 5\r\n"), "{}", written);
+        assert!(written.ends_with("\r\n\r\nhello"), "{}", written);
+    }
+
+    #[test]
+    fn non_ascii_response_header() {
+        let response = http::Response::builder()
+            .header("X-Name", http::HeaderValue::from_bytes(b"caf\xe9").unwrap())
+            .header("X-Other", "ok")
+            .body(Cursor::new(Vec::new()))
+            .unwrap();
+        assert!(try_to_tiny_response(response, HeaderPolicy::Strict).is_err());
+
+        let response = http::Response::builder()
+            .header("X-Name", http::HeaderValue::from_bytes(b"caf\xe9").unwrap())
+            .header("X-Other", "ok")
+            .body(Cursor::new(Vec::new()))
+            .unwrap();
+        let response = to_tiny_response(response);
+        assert_eq!(response.headers().len(), 1);
+        assert!(response.headers()[0].field.equiv("X-Other"));
+    }
+
+    #[test]
+    fn response_without_length() {
+        let response = http::Response::new(Cursor::new(b"hello".to_vec()));
+        let response = to_tiny_response(response);
+        assert_eq!(response.data_length(), None);
+    }
+}
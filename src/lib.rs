@@ -99,36 +99,117 @@
 use zeroize::Zeroizing;
 
 use std::error::Error;
-use std::io::Error as IoError;
+use std::io::Cursor;
 use std::io::ErrorKind as IoErrorKind;
 use std::io::Result as IoResult;
-use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::io::Write;
+use std::net::ToSocketAddrs;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::mpsc;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use client::ClientConnection;
 use connection::Connection;
+use shutdown::{AcceptErrorAction, ShutdownState};
 use util::MessagesQueue;
 
-pub use common::{HTTPVersion, Header, HeaderField, Method, StatusCode};
+pub use auth::{Authorization, AuthorizationError};
+pub use builder::ServerBuilder;
+pub use common::{
+    Cookie, HTTPDate, HTTPVersion, Header, HeaderField, Method, SameSite, StatusCode,
+};
+#[cfg(feature = "compression")]
+pub use compression::{choose_content_encoding, CompressedCache, CompressedReader, Encoding};
+pub use conditional::Precondition;
+pub use config::{
+    BufferingMode, ConnectionDecision, ConnectionLimitMode, FrameOptions, LoadShedding,
+    SecurityHeaders, ServerConfigAdvanced, TaskQueueLimitMode, TlsClientAuthMode,
+};
 pub use connection::{ConfigListenAddr, ListenAddr, Listener};
-pub use request::{ReadWrite, Request};
-pub use response::{Response, ResponseBox};
-pub use test::TestRequest;
+pub use event_stream::EventWriter;
+pub use fadvise::FileAccessHint;
+#[cfg(feature = "multipart")]
+pub use form_data::{Multipart, Part};
+pub use framed_writer::FramedWriter;
+pub use handoff::{HandoffError, RawConnectionParts};
+pub use lines::{BodyLines, BodyLinesStr};
+pub use mime::mime_for_extension;
+pub use multipart::MultipartResponse;
+pub use parts::{BodyReader, RequestHead, Responder};
+pub use path::PathError;
+#[cfg(feature = "profiling")]
+pub use profiling::{Percentiles, PhaseProfile, ProfileSnapshot};
+pub use range::{parse_byte_range, ByteRange, RangeError, RangeReader};
+pub use request::{BodyKind, ReadWrite, Request};
+pub use response::{InvalidLocation, RespondError, Response, ResponseBox};
+pub use shutdown::{RecvError, ShutdownHandle, ShutdownReason};
+pub use stats::{AccessLogEntry, InFlightRequest, ServerStats};
+pub use target::RequestTarget;
+#[cfg(feature = "tcp-diagnostics")]
+pub use tcp_diagnostics::TcpDiagnostics;
+pub use test::{ResponseCapture, TestRequest};
+pub use trace::ConnectionTraceFilter;
+pub use urlencoded::FormError;
+#[cfg(feature = "websocket")]
+pub use websocket::WsHandshakeError;
+#[cfg(feature = "websocket")]
+pub use websocket_stream::{WebSocket, WsMessage};
+pub use worker::WorkerToken;
 
+mod auth;
+mod builder;
 mod client;
 mod common;
+#[cfg(feature = "compression")]
+mod compression;
+mod conditional;
+mod config;
 mod connection;
+mod event_stream;
+mod fadvise;
+#[cfg(feature = "multipart")]
+mod form_data;
+mod forwarded;
+mod framed_writer;
+mod handoff;
+#[cfg(feature = "http-types")]
+pub mod http_types;
+mod lines;
 mod log;
+mod mime;
+mod multipart;
+mod negotiation;
+mod parts;
+mod path;
+#[cfg(feature = "profiling")]
+mod profiling;
+pub mod proxy;
+mod proxy_protocol;
+mod range;
 mod request;
 mod response;
+pub mod sanitize;
+mod shutdown;
 mod ssl;
+mod stats;
+mod target;
+#[cfg(feature = "tcp-diagnostics")]
+mod tcp_diagnostics;
 mod test;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod trace;
+mod urlencoded;
 mod util;
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(feature = "websocket")]
+mod websocket_stream;
+mod worker;
 
 /// The main class of this library.
 ///
@@ -143,29 +224,215 @@ pub struct Server {
     // queue for messages received by child threads
     messages: Arc<MessagesQueue<Message>>,
 
-    // result of TcpListener::local_addr()
-    listening_addr: ListenAddr,
+    // result of TcpListener::local_addr() for each listener, never empty
+    listening_addrs: Vec<ListenAddr>,
+
+    // counters updated by the accept thread and the connections
+    stats: Arc<stats::Counters>,
+
+    // settings shared with the connections
+    config: Arc<ServerConfigAdvanced>,
+
+    // last time a warning about the queue latency was logged
+    last_queue_latency_warning: Mutex<Option<Instant>>,
+
+    // true if the accept threads notice by themselves that the server is closed
+    accept_timeout: bool,
+
+    // why the server was shut down, shared with the accept threads
+    shutdown: Arc<ShutdownState>,
+
+    // set by `Replay::record()` to record the requests returned by `recv()`
+    #[cfg(any(test, feature = "testing"))]
+    recorder: Option<Arc<test::Recorder>>,
+
+    // connections to trace, shared with the accept threads
+    tracer: Arc<trace::ConnectionTracer>,
 }
 
+/// Maximum time an accept thread waits for a connection before checking whether the server
+/// is closed.
+const ACCEPT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Bounds of the pause of the accept thread after `accept()` failed for lack of resources,
+/// doubled after each consecutive failure.
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// Response sent to the connections over the limit, see `ConnectionLimitMode`.
+const CONNECTION_LIMIT_RESPONSE: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+
+/// Maximum number of connections closed at the same time after their last response, see
+/// `util::Lingerer`.
+const MAX_LINGERING_CONNECTIONS: usize = 256;
+
+/// Minimum interval between two warnings about the queue latency.
+const QUEUE_LATENCY_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
 enum Message {
-    Error(IoError),
-    NewRequest(Request),
+    // the request and the moment it was pushed to the queue
+    NewRequest(Request, Instant),
 }
 
-impl From<IoError> for Message {
-    fn from(e: IoError) -> Message {
-        Message::Error(e)
+impl From<Request> for Message {
+    fn from(rq: Request) -> Message {
+        Message::NewRequest(rq, Instant::now())
     }
 }
 
-impl From<Request> for Message {
-    fn from(rq: Request) -> Message {
-        Message::NewRequest(rq)
+/// Task that reads the requests of a client and dispatches them to the messages queue.
+///
+/// After handling `inline_fairness_budget` requests in a row, the task yields its thread by
+/// pushing itself back at the end of the tasks queue, so that a client pipelining lots of
+/// requests can't starve the other connections.
+struct ConnectionTask {
+    client: ClientConnection,
+    messages: Arc<MessagesQueue<Message>>,
+    queue: util::TaskQueue,
+    stats: Arc<stats::Counters>,
+    // Synchronization is needed for HTTPS requests to avoid a deadlock
+    sync: Option<(mpsc::Sender<()>, mpsc::Receiver<()>)>,
+    // counts the connection against `ServerConfigAdvanced::with_max_connections` until dropped
+    _slot: Option<util::SemaphorePermit>,
+}
+
+impl ConnectionTask {
+    fn new(
+        client: ClientConnection,
+        messages: Arc<MessagesQueue<Message>>,
+        queue: util::TaskQueue,
+        stats: Arc<stats::Counters>,
+        slot: Option<util::SemaphorePermit>,
+    ) -> ConnectionTask {
+        let sync = if client.secure() {
+            Some(mpsc::channel())
+        } else {
+            None
+        };
+
+        ConnectionTask {
+            client,
+            messages,
+            queue,
+            stats,
+            sync,
+            _slot: slot,
+        }
+    }
+
+    /// Builds the task of a new client, or returns `None` if its streams couldn't be set up.
+    ///
+    /// `proxied_addr` is the address of the client read from a PROXY protocol header, which
+    /// replaces the address of the peer.
+    #[allow(clippy::too_many_arguments)]
+    fn from_streams(
+        streams: IoResult<(util::RefinedTcpStream, util::RefinedTcpStream)>,
+        messages: Arc<MessagesQueue<Message>>,
+        queue: util::TaskQueue,
+        config: Arc<ServerConfigAdvanced>,
+        stats: Arc<stats::Counters>,
+        lingerer: Arc<util::Lingerer>,
+        slot: Option<util::SemaphorePermit>,
+        trace: Option<Arc<trace::ConnectionTrace>>,
+        proxied_addr: Option<std::net::SocketAddr>,
+    ) -> Option<ConnectionTask> {
+        let (mut read_closable, mut write_closable) = match streams {
+            Ok(streams) => streams,
+            Err(_err) => {
+                log::error!("Error setting up new client: {}", _err);
+                return None;
+            }
+        };
+        if let Some(ref trace) = trace {
+            read_closable.set_trace(trace.clone());
+            write_closable.set_trace(trace.clone());
+        }
+
+        let remote_addr = match proxied_addr {
+            Some(addr) => Ok(Some(addr)),
+            None => read_closable.peer_addr(),
+        };
+        let traced_addr = remote_addr.as_ref().ok().and_then(|addr| *addr);
+        let client = ClientConnection::new(
+            write_closable,
+            read_closable,
+            remote_addr,
+            config,
+            &stats,
+            Some(lingerer),
+        );
+        if let Some(trace) = trace {
+            trace.start(client.id(), traced_addr);
+        }
+        Some(ConnectionTask::new(client, messages, queue, stats, slot))
+    }
+
+    fn run(mut self) {
+        let budget = self.client.config().inline_fairness_budget;
+        let mut handled = 0;
+
+        while let Some(rq) = self.client.next() {
+            if let Some(ref allowlist) = self.client.config().path_allowlist {
+                if !allowlist.allows(rq.url()) {
+                    self.stats.rejected_paths.fetch_add(1, Relaxed);
+                    log::debug!(
+                        "Rejected request for {} from {:?}",
+                        sanitize::sanitize_path(rq.url().as_bytes(), sanitize::LOG_BUDGET),
+                        rq.remote_addr()
+                    );
+                    let _ = rq.respond_serialized(allowlist.reject_status, allowlist.rejection());
+                    continue;
+                }
+            }
+
+            if let Some(ref load_shedding) = self.client.config().load_shedding {
+                if load_shedding.should_shed(self.messages.len(), rq.url()) {
+                    self.stats.shed_requests.fetch_add(1, Relaxed);
+                    let _ = rq.respond_serialized(StatusCode(503), load_shedding.rejection());
+                    continue;
+                }
+            }
+
+            #[cfg(any(test, feature = "testing"))]
+            self.stats.queued_requests.fetch_add(1, Relaxed);
+            let guard = self.stats.in_flight.start(InFlightRequest {
+                connection: rq.connection_id(),
+                sequence: rq.request_seq(),
+                method: rq.method().clone(),
+                path: sanitize::sanitize_path(rq.url().as_bytes(), sanitize::LOG_BUDGET)
+                    .into_owned(),
+                remote_addr: rq.remote_addr().copied(),
+                started: Instant::now(),
+            });
+            let rq = rq.with_in_flight(guard);
+            match self.sync {
+                Some((ref sender, ref receiver)) => {
+                    self.messages
+                        .push(rq.with_notify_sender(sender.clone()).into());
+                    receiver.recv().unwrap();
+                }
+                None => self.messages.push(rq.into()),
+            }
+
+            handled += 1;
+            if budget != 0 && handled >= budget {
+                let queue = self.queue.clone();
+                let mut task = Some(self);
+                queue.push_back(Box::new(move || {
+                    if let Some(task) = task.take() {
+                        task.run();
+                    }
+                }));
+                return;
+            }
+        }
     }
 }
 
 // this trait is to make sure that Server implements Share and Send
 #[doc(hidden)]
+#[allow(dead_code)]
 trait MustBeShareDummy: Sync + Send {}
 #[doc(hidden)]
 impl MustBeShareDummy for Server {}
@@ -193,6 +460,27 @@ pub struct SslConfig {
     pub private_key: Vec<u8>,
 }
 
+#[cfg(any(
+    all(feature = "ssl-openssl", feature = "ssl-rustls"),
+    all(feature = "ssl-openssl", feature = "ssl-native-tls"),
+    all(feature = "ssl-native-tls", feature = "ssl-rustls"),
+))]
+compile_error!(
+    "Only one feature from 'ssl-openssl', 'ssl-rustls', 'ssl-native-tls' can be enabled at the same time"
+);
+#[cfg(not(any(
+    feature = "ssl-openssl",
+    feature = "ssl-rustls",
+    feature = "ssl-native-tls"
+)))]
+type SslContext = ();
+#[cfg(any(
+    feature = "ssl-openssl",
+    feature = "ssl-rustls",
+    feature = "ssl-native-tls"
+))]
+type SslContext = crate::ssl::SslContextImpl;
+
 impl Server {
     /// Shortcut for a simple server on a specific address.
     #[inline]
@@ -238,10 +526,13 @@ impl Server {
         })
     }
 
-    /// Builds a new server that listens on the specified address.
+    /// Builds a new server that listens on the specified addresses.
+    ///
+    /// Every address of `config.addr` is bound, each with its own accept thread, and the
+    /// connections of all of them are served by the same server. The addresses that can't be
+    /// bound are skipped with a warning; an error is only returned if none of them could be.
     pub fn new(config: ServerConfig) -> Result<Server, Box<dyn Error + Send + Sync + 'static>> {
-        let listener = config.addr.bind()?;
-        Self::from_listener(listener, config.ssl)
+        Ok(Self::prepare(config)?.start())
     }
 
     /// Builds a new server using the specified TCP listener.
@@ -252,38 +543,301 @@ impl Server {
         listener: L,
         ssl_config: Option<SslConfig>,
     ) -> Result<Server, Box<dyn Error + Send + Sync + 'static>> {
-        let listener = listener.into();
-        // building the "close" variable
-        let close_trigger = Arc::new(AtomicBool::new(false));
+        Self::from_listener_with_config(listener, ssl_config, ServerConfigAdvanced::default())
+    }
 
-        // building the TcpListener
-        let (server, local_addr) = {
-            let local_addr = listener.local_addr()?;
-            log::debug!("Server listening on {}", local_addr);
-            (listener, local_addr)
-        };
+    /// Same as `from_listener()`, but with additional settings.
+    pub fn from_listener_with_config<L: Into<Listener>>(
+        listener: L,
+        ssl_config: Option<SslConfig>,
+        config: ServerConfigAdvanced,
+    ) -> Result<Server, Box<dyn Error + Send + Sync + 'static>> {
+        Ok(PreparedServer::new(vec![listener.into()], ssl_config, config)?.start())
+    }
+
+    /// Binds the server and checks its configuration, but doesn't accept connections yet.
+    ///
+    /// No thread is started before `PreparedServer::start()` is called. This allows binding
+    /// a server in a process, then forking worker processes that each start serving on the
+    /// shared socket.
+    ///
+    /// On Linux and Android, dropping the server of a worker process doesn't disturb the
+    /// others: its accept threads notice by themselves that it is closed. Other systems can't
+    /// time out `accept()`, so the accept threads are woken up by connecting to the listening
+    /// addresses; with a shared socket, that connection may be accepted by another process
+    /// instead, which sees a client that closes its connection without sending a request.
+    pub fn prepare(
+        config: ServerConfig,
+    ) -> Result<PreparedServer, Box<dyn Error + Send + Sync + 'static>> {
+        Self::prepare_with_advanced(config, ServerConfigAdvanced::default())
+    }
+
+    /// Same as `prepare()`, with the settings of `ServerBuilder::with_advanced()`.
+    pub(crate) fn prepare_with_advanced(
+        config: ServerConfig,
+        advanced: ServerConfigAdvanced,
+    ) -> Result<PreparedServer, Box<dyn Error + Send + Sync + 'static>> {
+        let listeners = config.addr.bind(advanced.reuse_port)?;
+        PreparedServer::new(listeners, config.ssl, advanced)
+    }
+
+    /// Returns an iterator for all the incoming requests.
+    ///
+    /// The iterator will return `None` if the server socket is shutdown.
+    #[inline]
+    pub fn incoming_requests(&self) -> IncomingRequests<'_> {
+        IncomingRequests { server: self }
+    }
+
+    /// Returns a snapshot of the counters of this server.
+    #[inline]
+    pub fn stats(&self) -> ServerStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns the distribution of the time spent parsing, queuing and answering requests
+    /// since the server started or `reset_profile()` was last called.
+    #[cfg(feature = "profiling")]
+    pub fn profile_snapshot(&self) -> ProfileSnapshot {
+        self.stats.profile.snapshot()
+    }
+
+    /// Clears the histograms returned by `profile_snapshot()`.
+    #[cfg(feature = "profiling")]
+    pub fn reset_profile(&self) {
+        self.stats.profile.reset();
+    }
+
+    /// Returns the requests that were queued and not answered yet, oldest first, for example
+    /// to find out what a server that seems stuck is working on.
+    ///
+    /// A request leaves the list once it is answered, dropped, or turned into a writer. This
+    /// only locks the registry of the requests briefly, so it can be called regularly.
+    pub fn in_flight(&self) -> Vec<InFlightRequest> {
+        self.stats.in_flight.snapshot()
+    }
+
+    /// Returns `in_flight()` as a plain text table, to answer the requests of a debug
+    /// endpoint. The table includes the request being answered with it.
+    pub fn in_flight_response(&self) -> Response<Cursor<Vec<u8>>> {
+        Response::from_string(stats::in_flight_table(&self.in_flight()))
+    }
+
+    /// Logs a hexdump of the data exchanged with the next connections matching `filter`, for
+    /// example to debug a single client without tracing the others. Replaces the filter of a
+    /// previous call, but not the connections it already matched.
+    ///
+    /// The dumps are logged at the `trace` level, each line tagged with the number of the
+    /// connection and `recv` or `send`. For TLS connections, the decrypted data is dumped.
+    /// Checking whether a new connection must be traced is cheap while no filter is set.
+    pub fn trace_connection(&self, filter: ConnectionTraceFilter) {
+        self.tracer.arm(filter);
+    }
+
+    /// Returns the address the server is listening to. If it listens to several addresses,
+    /// returns the first one, see `server_addrs()`.
+    #[inline]
+    pub fn server_addr(&self) -> ListenAddr {
+        self.listening_addrs[0].clone()
+    }
+
+    /// Returns all the addresses the server is listening to, in the order of
+    /// `ServerConfig::addr`.
+    pub fn server_addrs(&self) -> Vec<ListenAddr> {
+        self.listening_addrs.clone()
+    }
+
+    /// Returns the number of requests waiting to be returned by `recv()`, for example to
+    /// decide when to start more threads calling it.
+    ///
+    /// This locks the queue of the requests briefly.
+    pub fn pending_requests(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Returns the number of clients currently connected to the server.
+    pub fn num_connections(&self) -> usize {
+        unimplemented!()
+        //self.requests_receiver.lock().len()
+    }
+
+    /// Blocks until an HTTP request has been submitted and returns it.
+    ///
+    /// Returns an error once the server is shut down, see `recv_checked()` to find out why.
+    pub fn recv(&self) -> IoResult<Request> {
+        Ok(self.recv_checked()?)
+    }
+
+    /// Same as `recv()`, but the error tells whether the thread was unblocked or the server
+    /// shut down, and why.
+    pub fn recv_checked(&self) -> Result<Request, RecvError> {
+        if let Some(ShutdownReason::Immediate) = self.shutdown.reason() {
+            return Err(RecvError::Shutdown(ShutdownReason::Immediate));
+        }
+        match self.messages.pop() {
+            Some(Message::NewRequest(rq, enqueued)) => Ok(self.dequeued(rq, enqueued)),
+            None => match self.shutdown.reason() {
+                Some(reason) => Err(RecvError::Shutdown(reason)),
+                None => Err(RecvError::Unblocked),
+            },
+        }
+    }
+
+    /// Returns a token for a new thread that receives requests with `recv_with_token()`, so
+    /// that this thread can later be stopped on its own with `WorkerToken::retire()`.
+    pub fn register_worker(&self) -> WorkerToken {
+        WorkerToken::new(self.messages.clone())
+    }
+
+    /// Same as `recv_checked()`, but returns `RecvError::Retired` once `token` is retired,
+    /// without waiting for a request.
+    pub fn recv_with_token(&self, token: &WorkerToken) -> Result<Request, RecvError> {
+        if let Some(ShutdownReason::Immediate) = self.shutdown.reason() {
+            return Err(RecvError::Shutdown(ShutdownReason::Immediate));
+        }
+        match self.messages.pop_unless(token.flag()) {
+            Some(Message::NewRequest(rq, enqueued)) => Ok(self.dequeued(rq, enqueued)),
+            None if token.is_retired() => Err(RecvError::Retired),
+            None => match self.shutdown.reason() {
+                Some(reason) => Err(RecvError::Shutdown(reason)),
+                None => Err(RecvError::Unblocked),
+            },
+        }
+    }
+
+    /// Same as `recv()` but doesn't block longer than timeout
+    pub fn recv_timeout(&self, timeout: Duration) -> IoResult<Option<Request>> {
+        Ok(self.recv_timeout_checked(timeout)?)
+    }
+
+    /// Same as `recv_timeout()`, with the errors of `recv_checked()`.
+    ///
+    /// Returns `Ok(None)` when the timeout expired or the thread was unblocked.
+    pub fn recv_timeout_checked(&self, timeout: Duration) -> Result<Option<Request>, RecvError> {
+        if let Some(ShutdownReason::Immediate) = self.shutdown.reason() {
+            return Err(RecvError::Shutdown(ShutdownReason::Immediate));
+        }
+        match self.messages.pop_timeout(timeout) {
+            Some(Message::NewRequest(rq, enqueued)) => Ok(Some(self.dequeued(rq, enqueued))),
+            None => match self.shutdown.reason() {
+                Some(reason) => Err(RecvError::Shutdown(reason)),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Same as `recv()` but doesn't block.
+    pub fn try_recv(&self) -> IoResult<Option<Request>> {
+        match self.messages.try_pop() {
+            Some(Message::NewRequest(rq, enqueued)) => Ok(Some(self.dequeued(rq, enqueued))),
+            None => match self.shutdown.reason() {
+                Some(reason) => Err(RecvError::Shutdown(reason).into()),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Records how long a request waited in the queue, and warns if it is too long.
+    fn dequeued(&self, mut rq: Request, enqueued: Instant) -> Request {
+        let now = Instant::now();
+        let latency = now.saturating_duration_since(enqueued);
+        rq.set_queue_latency(latency);
+        #[cfg(any(test, feature = "testing"))]
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&rq, enqueued);
+        }
+        #[cfg(feature = "profiling")]
+        self.stats.profile.record_queue(latency);
+
+        match self.config.queue_latency_warning {
+            Some(threshold) if latency > threshold => {
+                let mut last_warning = self.last_queue_latency_warning.lock().unwrap();
+                let due = last_warning.map_or(true, |last| {
+                    now.saturating_duration_since(last) >= QUEUE_LATENCY_WARNING_INTERVAL
+                });
+                if due {
+                    *last_warning = Some(now);
+                    self.stats.queue_latency_warnings.fetch_add(1, Relaxed);
+                    log::warn!(
+                        "Request waited {:?} in the queue before being received, {} requests still pending",
+                        latency,
+                        self.messages.len()
+                    );
+                }
+            }
+            _ => (),
+        }
+
+        rq
+    }
+
+    /// Unblock thread stuck in recv() or incoming_requests().
+    /// If there are several such threads, only one is unblocked.
+    /// This method allows graceful shutdown of server.
+    pub fn unblock(&self) {
+        self.messages.unblock();
+    }
+
+    /// Stops accepting new connections. The requests that are already queued can still be
+    /// received, after which all the threads stuck in `recv()` or `incoming_requests()` are
+    /// unblocked with `RecvError::Shutdown(ShutdownReason::Graceful)`.
+    ///
+    /// Does nothing if the server is already shut down. See `ShutdownHandle::shutdown_gracefully()`
+    /// to also wait for the requests that are being answered.
+    pub fn shutdown_gracefully(&self) {
+        self.initiate_shutdown(ShutdownReason::Graceful);
+    }
+
+    /// Returns why the server was shut down, or `None` if it is still running.
+    pub fn shutdown_reason(&self) -> Option<ShutdownReason> {
+        self.shutdown.reason()
+    }
+
+    /// Returns a handle that can shut the server down from another thread, after draining the
+    /// requests in flight.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            state: self.shutdown.clone(),
+            close: self.close.clone(),
+            messages: self.messages.clone(),
+            in_flight: self.stats.in_flight.clone(),
+            listening_addrs: self.listening_addrs.clone(),
+            accept_timeout: self.accept_timeout,
+        }
+    }
+
+    /// Records the reason of the shutdown, then stops the accept thread and unblocks the
+    /// receivers. The first reason wins.
+    fn initiate_shutdown(&self, reason: ShutdownReason) {
+        self.shutdown_handle().initiate(reason);
+    }
+}
+
+/// A server that is bound to its address but doesn't accept connections yet.
+///
+/// Built with `Server::prepare()`.
+pub struct PreparedServer {
+    listeners: Vec<Listener>,
+    local_addrs: Vec<ListenAddr>,
+    ssl: Option<SslContext>,
+    config: Arc<ServerConfigAdvanced>,
+}
+
+impl PreparedServer {
+    fn new(
+        listeners: Vec<Listener>,
+        ssl_config: Option<SslConfig>,
+        config: ServerConfigAdvanced,
+    ) -> Result<PreparedServer, Box<dyn Error + Send + Sync + 'static>> {
+        let local_addrs = listeners
+            .iter()
+            .map(Listener::local_addr)
+            .collect::<IoResult<Vec<_>>>()?;
+        for _addr in &local_addrs {
+            log::debug!("Server listening on {}", _addr);
+        }
 
         // building the SSL capabilities
-        #[cfg(any(
-            all(feature = "ssl-openssl", feature = "ssl-rustls"),
-            all(feature = "ssl-openssl", feature = "ssl-native-tls"),
-            all(feature = "ssl-native-tls", feature = "ssl-rustls"),
-        ))]
-        compile_error!(
-            "Only one feature from 'ssl-openssl', 'ssl-rustls', 'ssl-native-tls' can be enabled at the same time"
-        );
-        #[cfg(not(any(
-            feature = "ssl-openssl",
-            feature = "ssl-rustls",
-            feature = "ssl-native-tls"
-        )))]
-        type SslContext = ();
-        #[cfg(any(
-            feature = "ssl-openssl",
-            feature = "ssl-rustls",
-            feature = "ssl-native-tls"
-        ))]
-        type SslContext = crate::ssl::SslContextImpl;
         let ssl: Option<SslContext> = {
             match ssl_config {
                 #[cfg(any(
@@ -291,9 +845,10 @@ impl Server {
                     feature = "ssl-rustls",
                     feature = "ssl-native-tls"
                 ))]
-                Some(config) => Some(SslContext::from_pem(
-                    config.certificate,
-                    Zeroizing::new(config.private_key),
+                Some(ssl_config) => Some(SslContext::from_pem(
+                    ssl_config.certificate,
+                    Zeroizing::new(ssl_config.private_key),
+                    config.tls_client_auth.as_ref(),
                 )?),
                 #[cfg(not(any(
                     feature = "ssl-openssl",
@@ -308,144 +863,502 @@ impl Server {
             }
         };
 
-        // creating a task where server.accept() is continuously called
-        // and ClientConnection objects are pushed in the messages queue
+        Ok(PreparedServer {
+            listeners,
+            local_addrs,
+            ssl,
+            config: Arc::new(config),
+        })
+    }
+
+    /// Returns the address the server is listening to. If it listens to several addresses,
+    /// returns the first one, see `server_addrs()`.
+    #[inline]
+    pub fn server_addr(&self) -> ListenAddr {
+        self.local_addrs[0].clone()
+    }
+
+    /// Returns all the addresses the server is listening to.
+    pub fn server_addrs(&self) -> Vec<ListenAddr> {
+        self.local_addrs.clone()
+    }
+
+    /// Starts accepting connections, with one thread for each listener.
+    pub fn start(self) -> Server {
+        let PreparedServer {
+            listeners,
+            local_addrs,
+            ssl,
+            config,
+        } = self;
+
+        // with a timeout, an accept thread regularly checks whether the server is closed,
+        // otherwise it must be woken up by connecting to it
+        let accept_timeouts: Vec<bool> = listeners
+            .iter()
+            .map(|listener| listener.set_accept_timeout(ACCEPT_TIMEOUT).is_ok())
+            .collect();
+
+        // building the "close" variable
+        let close_trigger = Arc::new(AtomicBool::new(false));
+
+        // the accept threads continuously call listener.accept()
+        // and push the ClientConnection objects in the messages queue
         let messages = MessagesQueue::with_capacity(8);
 
-        let inside_close_trigger = close_trigger.clone();
-        let inside_messages = messages.clone();
-        thread::spawn(move || {
-            // a tasks pool is used to dispatch the connections into threads
-            let tasks_pool = util::TaskPool::new();
-
-            log::debug!("Running accept thread");
-            while !inside_close_trigger.load(Relaxed) {
-                let new_client = match server.accept() {
-                    Ok((sock, _)) => {
-                        use util::RefinedTcpStream;
-                        let (read_closable, write_closable) = match ssl {
-                            None => RefinedTcpStream::new(sock),
-                            #[cfg(any(
-                                feature = "ssl-openssl",
-                                feature = "ssl-rustls",
-                                feature = "ssl-native-tls"
-                            ))]
-                            Some(ref ssl) => {
-                                // trying to apply SSL over the connection
-                                // if an error occurs, we just close the socket and resume listening
-                                let sock = match ssl.accept(sock) {
-                                    Ok(s) => s,
-                                    Err(_) => continue,
-                                };
+        let stats = Arc::new(stats::Counters::default());
+        let shutdown = Arc::new(ShutdownState::default());
+        let tracer = Arc::new(trace::ConnectionTracer::default());
+
+        let server = Server {
+            messages: messages.clone(),
+            close: close_trigger.clone(),
+            listening_addrs: local_addrs,
+            stats: stats.clone(),
+            config: config.clone(),
+            last_queue_latency_warning: Mutex::new(None),
+            accept_timeout: accept_timeouts.iter().all(|&timeout| timeout),
+            shutdown,
+            #[cfg(any(test, feature = "testing"))]
+            recorder: None,
+            tracer: tracer.clone(),
+        };
+        // also stops the other accept threads when one of them fails
+        let shutdown = server.shutdown_handle();
 
-                                RefinedTcpStream::new(sock)
+        // a tasks pool, shared by the accept threads, is used to dispatch the connections
+        // into threads
+        let max_queued = config.task_queue_limit.map(|(limit, _)| limit);
+        let tasks_pool = Arc::new(match config.worker_threads {
+            0 => util::TaskPool::new(config.min_idle_threads, max_queued),
+            count => util::TaskPool::with_fixed_size(count, max_queued),
+        });
+
+        // closes the connections after their last response, for the accept threads and the
+        // connections
+        let lingerer = Arc::new(util::Lingerer::new(MAX_LINGERING_CONNECTIONS));
+
+        // the handshakes are done by the tasks pool, at most this many at the same time; the
+        // connections over the limit wait in a queue, whose own thread hands them to the pool
+        #[cfg(any(
+            feature = "ssl-openssl",
+            feature = "ssl-rustls",
+            feature = "ssl-native-tls"
+        ))]
+        let handshakes = match (&ssl, config.max_concurrent_tls_handshakes) {
+            (Some(_), Some(limit)) => {
+                let queue = util::HandshakeQueue::new(
+                    limit,
+                    config.max_queued_tls_handshakes,
+                    config.tls_handshake_queue_timeout,
+                );
+                let dispatcher = queue.dispatcher();
+                let tasks_pool = tasks_pool.clone();
+                let config = config.clone();
+                let stats = stats.clone();
+                thread::spawn(move || {
+                    dispatcher.run(
+                        |task, permit| {
+                            let mut task = Some((task, permit));
+                            let task = Box::new(move || {
+                                if let Some((task, permit)) = task.take() {
+                                    task(permit);
+                                }
+                            });
+                            dispatch_task(&tasks_pool, &config, &stats, task);
+                        },
+                        |_task| {
+                            // dropping the task closes the socket
+                            stats.tls_handshake_queue_timeouts.fetch_add(1, Relaxed);
+                            log::debug!(
+                                "Closing new client after waiting {:?} for a TLS handshake",
+                                config.tls_handshake_queue_timeout
+                            );
+                        },
+                    )
+                });
+                Some(Arc::new(queue))
+            }
+            _ => None,
+        };
+        let ssl = ssl.map(Arc::new);
+
+        // at most this many connections are open at the same time, for all the listeners
+        let connection_slots = config
+            .max_connections
+            .map(|(limit, mode)| (util::Semaphore::new(limit), mode));
+
+        for (listener, accept_timeout) in listeners.into_iter().zip(accept_timeouts) {
+            let close_trigger = close_trigger.clone();
+            let messages = messages.clone();
+            let stats = stats.clone();
+            let config = config.clone();
+            let tracer = tracer.clone();
+            let shutdown = shutdown.clone();
+            let tasks_pool = tasks_pool.clone();
+            let lingerer = lingerer.clone();
+            let ssl = ssl.clone();
+            #[cfg(any(
+                feature = "ssl-openssl",
+                feature = "ssl-rustls",
+                feature = "ssl-native-tls"
+            ))]
+            let handshakes = handshakes.clone();
+            let connection_slots = connection_slots.clone();
+
+            thread::spawn(move || {
+                log::debug!("Running accept thread");
+                let _guard = shutdown::AcceptThreadGuard(shutdown.clone());
+                let mut backoff = ACCEPT_BACKOFF_MIN;
+
+                let dispatch = |task| dispatch_task(&tasks_pool, &config, &stats, task);
+
+                while !close_trigger.load(Relaxed) {
+                    // waiting for a connection to be closed before accepting a new one
+                    let slot = match connection_slots {
+                        Some((ref slots, ConnectionLimitMode::StopAccepting)) => {
+                            match slots.acquire_timeout(ACCEPT_TIMEOUT) {
+                                Some(slot) => Some(slot),
+                                // checking whether the server is closed
+                                None => continue,
                             }
-                            #[cfg(not(any(
-                                feature = "ssl-openssl",
-                                feature = "ssl-rustls",
-                                feature = "ssl-native-tls"
-                            )))]
-                            Some(ref _ssl) => unreachable!(),
-                        };
-
-                        Ok(ClientConnection::new(write_closable, read_closable))
-                    }
-                    Err(e) => Err(e),
-                };
-
-                match new_client {
-                    Ok(client) => {
-                        let messages = inside_messages.clone();
-                        let mut client = Some(client);
-                        tasks_pool.spawn(Box::new(move || {
-                            if let Some(client) = client.take() {
-                                // Synchronization is needed for HTTPS requests to avoid a deadlock
-                                if client.secure() {
-                                    let (sender, receiver) = mpsc::channel();
-                                    for rq in client {
-                                        messages.push(rq.with_notify_sender(sender.clone()).into());
-                                        receiver.recv().unwrap();
-                                    }
+                        }
+                        _ => None,
+                    };
+
+                    let (mut sock, peer_addr) = match listener.accept() {
+                        Ok(accepted) => {
+                            backoff = ACCEPT_BACKOFF_MIN;
+                            accepted
+                        }
+
+                        // the accept timeout expired, checking whether the server is closed
+                        Err(ref e)
+                            if accept_timeout
+                                && matches!(
+                                    e.kind(),
+                                    IoErrorKind::WouldBlock | IoErrorKind::TimedOut
+                                ) =>
+                        {
+                            continue
+                        }
+
+                        Err(e) => match AcceptErrorAction::for_error(&e) {
+                            AcceptErrorAction::Stop(reason) => {
+                                if shutdown.initiate(reason) {
+                                    log::error!("Error accepting new client: {}", e);
+                                }
+                                break;
+                            }
+                            action => {
+                                stats.accept_errors.fetch_add(1, Relaxed);
+                                if let Some(ref handler) = config.accept_error_handler {
+                                    handler.call(&e);
+                                }
+                                if action == AcceptErrorAction::Backoff {
+                                    log::warn!(
+                                        "Error accepting new client, retrying in {:?}: {}",
+                                        backoff,
+                                        e
+                                    );
+                                    thread::sleep(backoff);
+                                    backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
                                 } else {
-                                    for rq in client {
-                                        messages.push(rq.into());
-                                    }
+                                    log::debug!("Error accepting new client: {}", e);
                                 }
+                                continue;
+                            }
+                        },
+                    };
+
+                    stats.accepted_connections.fetch_add(1, Relaxed);
+
+                    // the accepted socket inherits the accept timeout of the listener
+                    if accept_timeout {
+                        if let Err(_err) = sock.set_read_timeout(None) {
+                            log::error!("Error setting up new client: {}", _err);
+                            continue;
+                        }
+                    }
+
+                    if let (Some(ref filter), Some(addr)) = (&config.connection_filter, peer_addr) {
+                        let decision = filter.decide(&addr);
+                        if decision != ConnectionDecision::Accept {
+                            log::debug!("Filtered the connection of {}: {:?}", addr, decision);
+                            stats.filtered_connections.fetch_add(1, Relaxed);
+                            if let (ConnectionDecision::RejectWith(status), None) = (decision, &ssl)
+                            {
+                                let _ = write!(
+                                    sock,
+                                    "HTTP/1.1 {} {}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+                                    status.0,
+                                    status.default_reason_phrase()
+                                );
+                                lingerer.close(sock, false);
+                            }
+                            continue;
+                        }
+                    }
+
+                    if let Err(_err) =
+                        sock.set_tcp_options(config.tcp_nodelay, config.tcp_keepalive)
+                    {
+                        log::debug!("Unable to set the options of a new client: {}", _err);
+                    }
+
+                    if let Some(ref setup) = config.connection_setup {
+                        if let (Connection::Tcp(ref stream), Some(addr)) = (&sock, peer_addr) {
+                            if let Err(_err) = setup.call(stream, addr) {
+                                log::debug!("Rejected the connection of {}: {}", addr, _err);
+                                stats.rejected_connections.fetch_add(1, Relaxed);
+                                continue;
                             }
-                        }));
+                        }
                     }
 
-                    Err(e) => {
-                        log::error!("Error accepting new client: {}", e);
-                        inside_messages.push(e.into());
-                        break;
+                    let slot = match connection_slots {
+                        Some((ref slots, ConnectionLimitMode::RespondUnavailable)) => {
+                            match slots.acquire_timeout(Duration::from_secs(0)) {
+                                Some(slot) => Some(slot),
+                                None => {
+                                    log::debug!("Rejected a new client, too many connections");
+                                    stats.connection_limit_rejections.fetch_add(1, Relaxed);
+                                    if ssl.is_none() {
+                                        let _ = sock.write_all(CONNECTION_LIMIT_RESPONSE);
+                                        lingerer.close(sock, false);
+                                    }
+                                    continue;
+                                }
+                            }
+                        }
+                        _ => slot,
+                    };
+                    let trace = tracer.accepted(peer_addr);
+
+                    let messages = messages.clone();
+                    let lingerer = lingerer.clone();
+                    let queue = tasks_pool.queue();
+                    let config = config.clone();
+                    let stats = stats.clone();
+                    match ssl {
+                        // the header is read by the worker thread, since it can take a while
+                        None if config.accept_proxy_protocol => {
+                            let mut sock = Some(sock);
+                            let mut slot = slot;
+                            let mut trace = trace;
+                            dispatch(Box::new(move || {
+                                let mut sock = match sock.take() {
+                                    Some(sock) => sock,
+                                    None => return,
+                                };
+                                let proxied_addr = match read_proxy_header(&mut sock, &config) {
+                                    Ok(addr) => addr,
+                                    Err(()) => return,
+                                };
+                                let streams = util::RefinedTcpStream::new(sock);
+                                if let Some(task) = ConnectionTask::from_streams(
+                                    streams,
+                                    messages.clone(),
+                                    queue.clone(),
+                                    config.clone(),
+                                    stats.clone(),
+                                    lingerer.clone(),
+                                    slot.take(),
+                                    trace.take(),
+                                    proxied_addr,
+                                ) {
+                                    task.run();
+                                }
+                            }));
+                        }
+                        None => {
+                            let streams = util::RefinedTcpStream::new(sock);
+                            if let Some(task) = ConnectionTask::from_streams(
+                                streams, messages, queue, config, stats, lingerer, slot, trace,
+                                None,
+                            ) {
+                                let mut task = Some(task);
+                                dispatch(Box::new(move || {
+                                    if let Some(task) = task.take() {
+                                        task.run();
+                                    }
+                                }));
+                            }
+                        }
+                        #[cfg(any(
+                            feature = "ssl-openssl",
+                            feature = "ssl-rustls",
+                            feature = "ssl-native-tls"
+                        ))]
+                        Some(ref ssl) => {
+                            let handshake = TlsHandshake {
+                                ssl: ssl.clone(),
+                                sock,
+                                messages,
+                                queue,
+                                config: config.clone(),
+                                stats,
+                                lingerer,
+                                slot,
+                                trace,
+                                proxied_addr: None,
+                            };
+                            let handshakes = handshakes.clone();
+                            if config.accept_proxy_protocol {
+                                // the header is read by a worker thread, like for plain
+                                // connections, before waiting for the handshake
+                                let mut handshake = Some(handshake);
+                                dispatch(Box::new(move || {
+                                    let mut handshake = match handshake.take() {
+                                        Some(handshake) => handshake,
+                                        None => return,
+                                    };
+                                    handshake.proxied_addr = match read_proxy_header(
+                                        &mut handshake.sock,
+                                        &handshake.config,
+                                    ) {
+                                        Ok(addr) => addr,
+                                        Err(()) => return,
+                                    };
+                                    match handshakes {
+                                        Some(ref handshakes) => handshake.enqueue(handshakes),
+                                        None => handshake.run(None),
+                                    }
+                                }));
+                            } else {
+                                match handshakes {
+                                    Some(ref handshakes) => handshake.enqueue(handshakes),
+                                    None => {
+                                        let mut handshake = Some(handshake);
+                                        dispatch(Box::new(move || {
+                                            if let Some(handshake) = handshake.take() {
+                                                handshake.run(None);
+                                            }
+                                        }));
+                                    }
+                                }
+                            }
+                        }
+                        #[cfg(not(any(
+                            feature = "ssl-openssl",
+                            feature = "ssl-rustls",
+                            feature = "ssl-native-tls"
+                        )))]
+                        Some(ref _ssl) => unreachable!(),
                     }
                 }
-            }
-            log::debug!("Terminating accept thread");
-        });
+                log::debug!(
+                    "Terminating accept thread: {:?}",
+                    shutdown.shutdown_reason()
+                );
+            });
+        }
 
-        // result
-        Ok(Server {
-            messages,
-            close: close_trigger,
-            listening_addr: local_addr,
-        })
+        server
     }
+}
 
-    /// Returns an iterator for all the incoming requests.
-    ///
-    /// The iterator will return `None` if the server socket is shutdown.
-    #[inline]
-    pub fn incoming_requests(&self) -> IncomingRequests<'_> {
-        IncomingRequests { server: self }
+/// Hands a task to the pool, or drops it if too many tasks are waiting and the server is
+/// configured to drop them, see `ServerConfigAdvanced::with_task_queue_limit`.
+fn dispatch_task(
+    tasks_pool: &util::TaskPool,
+    config: &ServerConfigAdvanced,
+    stats: &stats::Counters,
+    task: Box<dyn FnMut() + Send>,
+) {
+    let drop_when_full = matches!(config.task_queue_limit, Some((_, TaskQueueLimitMode::Drop)));
+    if !drop_when_full {
+        tasks_pool.spawn(task);
+    } else if !tasks_pool.try_spawn(task) {
+        log::debug!("Closed a new client, too many clients waiting for a thread");
+        stats.task_queue_rejections.fetch_add(1, Relaxed);
     }
+}
 
-    /// Returns the address the server is listening to.
-    #[inline]
-    pub fn server_addr(&self) -> ListenAddr {
-        self.listening_addr.clone()
-    }
+/// Reads the PROXY protocol header of a new connection, and returns the address of the client
+/// it contains. Fails if the header is invalid or doesn't arrive in time, in which case the
+/// connection must be closed.
+fn read_proxy_header(
+    sock: &mut Connection,
+    config: &ServerConfigAdvanced,
+) -> Result<Option<std::net::SocketAddr>, ()> {
+    proxy_protocol::read_from_connection(sock, config.keep_alive_timeout).map_err(|_err| {
+        log::warn!(
+            "Closing new client, invalid PROXY protocol header: {}",
+            _err
+        );
+    })
+}
 
-    /// Returns the number of clients currently connected to the server.
-    pub fn num_connections(&self) -> usize {
-        unimplemented!()
-        //self.requests_receiver.lock().len()
-    }
+/// A new TLS connection, before its handshake.
+#[cfg(any(
+    feature = "ssl-openssl",
+    feature = "ssl-rustls",
+    feature = "ssl-native-tls"
+))]
+struct TlsHandshake {
+    ssl: Arc<SslContext>,
+    sock: Connection,
+    messages: Arc<MessagesQueue<Message>>,
+    queue: util::TaskQueue,
+    config: Arc<ServerConfigAdvanced>,
+    stats: Arc<stats::Counters>,
+    lingerer: Arc<util::Lingerer>,
+    slot: Option<util::SemaphorePermit>,
+    trace: Option<Arc<trace::ConnectionTrace>>,
+    proxied_addr: Option<std::net::SocketAddr>,
+}
 
-    /// Blocks until an HTTP request has been submitted and returns it.
-    pub fn recv(&self) -> IoResult<Request> {
-        match self.messages.pop() {
-            Some(Message::Error(err)) => Err(err),
-            Some(Message::NewRequest(rq)) => Ok(rq),
-            None => Err(IoError::new(IoErrorKind::Other, "thread unblocked")),
+#[cfg(any(
+    feature = "ssl-openssl",
+    feature = "ssl-rustls",
+    feature = "ssl-native-tls"
+))]
+impl TlsHandshake {
+    /// Waits in `handshakes` for the handshake to start, or closes the connection if too many
+    /// connections are already waiting.
+    fn enqueue(self, handshakes: &util::HandshakeQueue) {
+        let stats = self.stats.clone();
+        if handshakes
+            .push(Box::new(move |permit| self.run(Some(permit))))
+            .is_err()
+        {
+            stats.tls_handshake_queue_rejections.fetch_add(1, Relaxed);
+            log::debug!("Closing new client, too many clients waiting for a TLS handshake");
         }
     }
 
-    /// Same as `recv()` but doesn't block longer than timeout
-    pub fn recv_timeout(&self, timeout: Duration) -> IoResult<Option<Request>> {
-        match self.messages.pop_timeout(timeout) {
-            Some(Message::Error(err)) => Err(err),
-            Some(Message::NewRequest(rq)) => Ok(Some(rq)),
-            None => Ok(None),
-        }
-    }
+    /// Does the handshake, then serves the connection from the current thread.
+    fn run(self, permit: Option<util::HandshakePermit>) {
+        // if an error occurs, the socket is just closed
+        self.stats.tls_handshakes_in_progress.fetch_add(1, Relaxed);
+        let sock = self
+            .ssl
+            .accept(self.sock, self.config.tls_handshake_timeout);
+        self.stats.tls_handshakes_in_progress.fetch_sub(1, Relaxed);
+        drop(permit);
 
-    /// Same as `recv()` but doesn't block.
-    pub fn try_recv(&self) -> IoResult<Option<Request>> {
-        match self.messages.try_pop() {
-            Some(Message::Error(err)) => Err(err),
-            Some(Message::NewRequest(rq)) => Ok(Some(rq)),
-            None => Ok(None),
+        let streams = match sock {
+            Ok(sock) => util::RefinedTcpStream::new(sock),
+            Err(_err) => {
+                log::debug!("TLS handshake failed: {}", _err);
+                return;
+            }
+        };
+        if let Some(task) = ConnectionTask::from_streams(
+            streams,
+            self.messages,
+            self.queue,
+            self.config,
+            self.stats,
+            self.lingerer,
+            self.slot,
+            self.trace,
+            self.proxied_addr,
+        ) {
+            task.run();
         }
     }
-
-    /// Unblock thread stuck in recv() or incoming_requests().
-    /// If there are several such threads, only one is unblocked.
-    /// This method allows graceful shutdown of server.
-    pub fn unblock(&self) {
-        self.messages.unblock();
-    }
 }
 
 impl Iterator for IncomingRequests<'_> {
@@ -457,25 +1370,14 @@ impl Iterator for IncomingRequests<'_> {
 
 impl Drop for Server {
     fn drop(&mut self) {
-        self.close.store(true, Relaxed);
-        // Connect briefly to ourselves to unblock the accept thread
-        let maybe_stream = match &self.listening_addr {
-            ListenAddr::IP(addr) => TcpStream::connect(addr).map(Connection::from),
-            #[cfg(unix)]
-            ListenAddr::Unix(addr) => {
-                // TODO: use connect_addr when its stabilized.
-                let path = addr.as_pathname().unwrap();
-                std::os::unix::net::UnixStream::connect(path).map(Connection::from)
-            }
-        };
-        if let Ok(stream) = maybe_stream {
-            let _ = stream.shutdown(Shutdown::Both);
-        }
+        self.initiate_shutdown(ShutdownReason::Immediate);
 
         #[cfg(unix)]
-        if let ListenAddr::Unix(addr) = &self.listening_addr {
-            if let Some(path) = addr.as_pathname() {
-                let _ = std::fs::remove_file(path);
+        for listening_addr in &self.listening_addrs {
+            if let ListenAddr::Unix(addr) = listening_addr {
+                if let Some(path) = addr.as_pathname() {
+                    let _ = std::fs::remove_file(path);
+                }
             }
         }
     }
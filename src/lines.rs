@@ -0,0 +1,211 @@
+//! Line-by-line iteration over request bodies.
+
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+
+use crate::Request;
+
+/// Default maximum length of a line returned by [`BodyLines`], in bytes.
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Number of bytes read from the body at once.
+const READ_SIZE: usize = 8 * 1024;
+
+/// Iterator over the lines of the body of a request, returned by
+/// [`Request::body_lines`](crate::Request::body_lines).
+///
+/// Each line is returned without its delimiter. A last line that doesn't end with a delimiter
+/// is still returned. Iteration stops after the first error.
+///
+/// The body is read in blocks, so the iterator usually holds bytes past the last line it
+/// returned. They are given back to the request when the iterator is dropped, and
+/// [`Request::as_reader`](crate::Request::as_reader) then reads the body from where the
+/// iteration stopped.
+pub struct BodyLines<'a> {
+    request: &'a mut Request,
+    // `as_reader()` isn't called before the first line is requested, so that the
+    // `100 Continue` is only sent when the body is actually read
+    started: bool,
+    done: bool,
+    // bytes read from the body, of which the ones before `consumed` were returned
+    buffer: Vec<u8>,
+    consumed: usize,
+    delimiter: u8,
+    strip_cr: bool,
+    max_line_length: usize,
+}
+
+impl<'a> BodyLines<'a> {
+    pub(crate) fn new(request: &'a mut Request) -> BodyLines<'a> {
+        BodyLines {
+            request,
+            started: false,
+            done: false,
+            buffer: Vec::new(),
+            consumed: 0,
+            delimiter: b'\n',
+            strip_cr: false,
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+        }
+    }
+
+    /// Sets the byte that separates lines. Defaults to `\n`.
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// If true, a `\r` found right before the delimiter is removed as well. Defaults to false.
+    pub fn with_strip_cr(mut self, strip_cr: bool) -> Self {
+        self.strip_cr = strip_cr;
+        self
+    }
+
+    /// Sets the maximum length of a line, delimiter excluded.
+    ///
+    /// Reading a longer line returns an error of kind `InvalidData` instead of truncating it.
+    /// Defaults to [`DEFAULT_MAX_LINE_LENGTH`].
+    pub fn with_max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length;
+        self
+    }
+
+    /// Returns the bytes read from the body and not returned yet, reading more if there are
+    /// none. Returns an empty slice at the end of the body.
+    fn fill_buf(&mut self) -> IoResult<&[u8]> {
+        if self.consumed == self.buffer.len() {
+            self.buffer.resize(READ_SIZE, 0);
+            self.consumed = 0;
+            let read = loop {
+                match self.request.as_reader().read(&mut self.buffer) {
+                    Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        self.buffer.clear();
+                        return Err(err);
+                    }
+                    Ok(read) => break read,
+                }
+            };
+            self.buffer.truncate(read);
+        }
+        Ok(&self.buffer[self.consumed..])
+    }
+
+    fn read_line(&mut self) -> IoResult<Option<Vec<u8>>> {
+        if self.done {
+            return Ok(None);
+        }
+        self.started = true;
+        let delimiter = self.delimiter;
+        // a `\r` at the end of the line doesn't count in the limit when it is stripped
+        let allowed = self
+            .max_line_length
+            .saturating_add(usize::from(self.strip_cr));
+
+        let mut line = Vec::new();
+        loop {
+            let available = self.fill_buf()?;
+            if available.is_empty() {
+                if line.is_empty() {
+                    return Ok(None);
+                }
+                break;
+            }
+
+            let (chunk, found) = match available.iter().position(|&b| b == delimiter) {
+                Some(pos) => (&available[..pos], true),
+                None => (available, false),
+            };
+            if line.len() + chunk.len() > allowed {
+                return Err(line_too_long());
+            }
+
+            line.extend_from_slice(chunk);
+            self.consumed += chunk.len() + usize::from(found);
+            if found {
+                break;
+            }
+        }
+
+        if self.strip_cr && line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if line.len() > self.max_line_length {
+            return Err(line_too_long());
+        }
+
+        Ok(Some(line))
+    }
+}
+
+fn line_too_long() -> IoError {
+    IoError::new(ErrorKind::InvalidData, "Line exceeds the maximum length")
+}
+
+impl<'a> Iterator for BodyLines<'a> {
+    type Item = IoResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<IoResult<Vec<u8>>> {
+        match self.read_line() {
+            Ok(Some(line)) => Some(Ok(line)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl<'a> Drop for BodyLines<'a> {
+    fn drop(&mut self) {
+        if self.started && self.consumed < self.buffer.len() {
+            let remaining = self.buffer.split_off(self.consumed);
+            self.request.unread_body(remaining);
+        }
+    }
+}
+
+/// Iterator over the lines of the body of a request as strings, returned by
+/// [`Request::body_lines_str`](crate::Request::body_lines_str).
+///
+/// Works like [`BodyLines`], but each line must be valid UTF-8 or an error of kind
+/// `InvalidData` is returned.
+pub struct BodyLinesStr<'a>(BodyLines<'a>);
+
+impl<'a> BodyLinesStr<'a> {
+    pub(crate) fn new(request: &'a mut Request) -> BodyLinesStr<'a> {
+        BodyLinesStr(BodyLines::new(request))
+    }
+
+    /// See [`BodyLines::with_delimiter`].
+    pub fn with_delimiter(self, delimiter: u8) -> Self {
+        BodyLinesStr(self.0.with_delimiter(delimiter))
+    }
+
+    /// See [`BodyLines::with_strip_cr`].
+    pub fn with_strip_cr(self, strip_cr: bool) -> Self {
+        BodyLinesStr(self.0.with_strip_cr(strip_cr))
+    }
+
+    /// See [`BodyLines::with_max_line_length`].
+    pub fn with_max_line_length(self, max_line_length: usize) -> Self {
+        BodyLinesStr(self.0.with_max_line_length(max_line_length))
+    }
+}
+
+impl<'a> Iterator for BodyLinesStr<'a> {
+    type Item = IoResult<String>;
+
+    fn next(&mut self) -> Option<IoResult<String>> {
+        let line = self.0.next()?;
+        Some(line.and_then(|line| {
+            String::from_utf8(line).map_err(|err| {
+                self.0.done = true;
+                IoError::new(ErrorKind::InvalidData, err)
+            })
+        }))
+    }
+}
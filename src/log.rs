@@ -1,5 +1,5 @@
 #[cfg(feature = "log")]
-pub(crate) use log::{debug, error};
+pub(crate) use log::{debug, error, trace, warn};
 
 #[cfg(not(feature = "log"))]
 macro_rules! _debug {
@@ -14,4 +14,16 @@ macro_rules! _error {
 }
 
 #[cfg(not(feature = "log"))]
-pub(crate) use {_debug as debug, _error as error};
+macro_rules! _warn {
+    (target: $target:expr, $($arg:tt)+) => {};
+    ($($arg:tt)+) => {};
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! _trace {
+    (target: $target:expr, $($arg:tt)+) => {};
+    ($($arg:tt)+) => {};
+}
+
+#[cfg(not(feature = "log"))]
+pub(crate) use {_debug as debug, _error as error, _trace as trace, _warn as warn};
@@ -0,0 +1,62 @@
+//! Media types of the files served with `Response::from_path()`.
+
+/// Returns the media type usually served for files with this extension, such as
+/// `text/html; charset=utf-8` for `html`, or `None` if the extension isn't known.
+///
+/// The extension is given without its dot, and its case is ignored. The text types are
+/// assumed to be encoded with UTF-8.
+pub fn mime_for_extension(extension: &str) -> Option<&'static str> {
+    let mime = match extension.to_ascii_lowercase().as_str() {
+        // text
+        "htm" | "html" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "xml" => "application/xml",
+        "json" | "map" => "application/json",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        // images
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "gif" => "image/gif",
+        "ico" => "image/vnd.microsoft.icon",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        // fonts
+        "otf" => "font/otf",
+        "ttf" => "font/ttf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        // audio and video
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        // archives
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "zip" => "application/zip",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mime_for_extension;
+
+    #[test]
+    fn extensions() {
+        assert_eq!(mime_for_extension("html"), Some("text/html; charset=utf-8"));
+        assert_eq!(mime_for_extension("PNG"), Some("image/png"));
+        assert_eq!(mime_for_extension("tar.gz"), None);
+        assert_eq!(mime_for_extension(""), None);
+        assert_eq!(mime_for_extension("unknown"), None);
+    }
+}
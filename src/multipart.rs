@@ -0,0 +1,123 @@
+//! Responses made of several parts, such as `multipart/mixed` or `multipart/related`.
+
+use std::collections::VecDeque;
+use std::io::{Cursor, Read, Result as IoResult};
+
+use crate::util::random_f64;
+use crate::{Header, Response, ResponseBox};
+
+/// Builder of a `multipart/*` response, whose parts are read one after the other while the
+/// response is sent.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Read;
+/// use tiny_http::{Header, MultipartResponse};
+///
+/// let mut multipart = MultipartResponse::new("related; type=\"application/json\"");
+/// let json = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+/// multipart.add_part(vec![json], &b"{\"files\": 1}"[..], Some(12));
+/// multipart.add_part(vec![], std::io::repeat(0).take(1024), None);
+/// let response = multipart.into_response();
+/// ```
+pub struct MultipartResponse {
+    subtype: String,
+    boundary: String,
+    // framing and bodies of the parts, in the order they are sent
+    segments: VecDeque<Box<dyn Read + Send>>,
+    // `None` once a part of unknown length was added
+    length: Option<usize>,
+}
+
+impl MultipartResponse {
+    /// Starts a response of type `multipart/<subtype>`, with a random boundary.
+    ///
+    /// The subtype may be followed by parameters, such as the `type` parameter of
+    /// `multipart/related`. The `boundary` parameter is added automatically.
+    pub fn new(subtype: &str) -> MultipartResponse {
+        let random = || (random_f64() * (1u64 << 53) as f64) as u64;
+        MultipartResponse {
+            subtype: subtype.to_owned(),
+            boundary: format!("tiny-http-{:014x}{:014x}", random(), random()),
+            segments: VecDeque::new(),
+            length: Some(0),
+        }
+    }
+
+    /// Returns the boundary that separates the parts.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Adds a part with these headers. The body is only read when the part is sent.
+    ///
+    /// If `len` is `Some`, at most `len` bytes are read from `body`. The response has a
+    /// `Content-Length` only if the lengths of all its parts are known.
+    pub fn add_part<R>(&mut self, headers: Vec<Header>, body: R, len: Option<usize>)
+    where
+        R: Read + Send + 'static,
+    {
+        let mut head = format!("--{}\r\n", self.boundary);
+        for header in headers {
+            head.push_str(&format!("{}\r\n", header));
+        }
+        head.push_str("\r\n");
+
+        self.length = match (self.length, len) {
+            (Some(total), Some(len)) => Some(total + head.len() + len + 2),
+            _ => None,
+        };
+        self.segments
+            .push_back(Box::new(Cursor::new(head.into_bytes())));
+        match len {
+            Some(len) => self.segments.push_back(Box::new(body.take(len as u64))),
+            None => self.segments.push_back(Box::new(body)),
+        }
+        self.segments.push_back(Box::new(&b"\r\n"[..]));
+    }
+
+    /// Builds the response, with a `Content-Type` header that can't be replaced since it
+    /// carries the boundary.
+    pub fn into_response(mut self) -> ResponseBox {
+        let closing = format!("--{}--\r\n", self.boundary);
+        let length = self.length.map(|length| length + closing.len());
+        self.segments
+            .push_back(Box::new(Cursor::new(closing.into_bytes())));
+
+        let content_type = format!("multipart/{}; boundary={}", self.subtype, self.boundary);
+        let mut response = Response::new(
+            200.into(),
+            vec![Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap()],
+            Box::new(MultipartReader {
+                segments: self.segments,
+            }) as Box<dyn Read + Send>,
+            length,
+            None,
+        );
+        response.lock_content_type();
+        response
+    }
+}
+
+/// Reads the segments of a multipart body in turn.
+struct MultipartReader {
+    segments: VecDeque<Box<dyn Read + Send>>,
+}
+
+impl Read for MultipartReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while let Some(segment) = self.segments.front_mut() {
+            match segment.read(buf)? {
+                0 => {
+                    self.segments.pop_front();
+                }
+                len => return Ok(len),
+            }
+        }
+        Ok(0)
+    }
+}
@@ -0,0 +1,261 @@
+//! Choice of the media type of a response from the `Accept` headers of the request, see
+//! `Request::negotiate_content_type`.
+
+use crate::Header;
+
+/// Media range of an `Accept` header, such as `text/*;q=0.5`.
+struct MediaRange<'a> {
+    media_type: &'a str,
+    subtype: &'a str,
+    params: Vec<(&'a str, &'a str)>,
+    quality: f32,
+}
+
+impl MediaRange<'_> {
+    /// Returns how specific the range is if it matches `offered`, more specific ranges giving
+    /// larger values.
+    fn matches(&self, offered: &MediaType<'_>) -> Option<(u8, usize)> {
+        if self.media_type == "*" {
+            return Some((0, 0));
+        }
+        if !self.media_type.eq_ignore_ascii_case(offered.media_type) {
+            return None;
+        }
+        if self.subtype == "*" {
+            return Some((1, 0));
+        }
+        if !self.subtype.eq_ignore_ascii_case(offered.subtype) {
+            return None;
+        }
+        let params_match = self.params.iter().all(|(name, value)| {
+            offered
+                .params
+                .iter()
+                .any(|(n, v)| n.eq_ignore_ascii_case(name) && v == value)
+        });
+        if params_match {
+            Some((2, self.params.len()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Media type offered by the server, such as `text/html;level=1`.
+struct MediaType<'a> {
+    media_type: &'a str,
+    subtype: &'a str,
+    params: Vec<(&'a str, &'a str)>,
+}
+
+/// Returns the element of `offered` that the client prefers, given the `Accept` headers of
+/// the request, or `None` if the client accepts none of them.
+///
+/// Each offered type gets the weight of the most specific media range matching it. Types
+/// with the same weight are preferred in the order of `offered`. Without a valid `Accept`
+/// header, the client accepts anything.
+pub(crate) fn negotiate<'a>(headers: &[Header], offered: &[&'a str]) -> Option<&'a str> {
+    let ranges: Vec<MediaRange<'_>> = headers
+        .iter()
+        .filter(|h| h.field.equiv("Accept"))
+        .flat_map(|h| h.value.as_str().split(','))
+        .filter_map(parse_media_range)
+        .collect();
+    if ranges.is_empty() {
+        return offered.first().copied();
+    }
+
+    let mut best: Option<(&'a str, f32)> = None;
+    for &candidate in offered {
+        let media_type = match parse_media_type(candidate) {
+            Some(media_type) => media_type,
+            None => continue,
+        };
+        let quality = ranges
+            .iter()
+            .filter_map(|range| Some((range.matches(&media_type)?, range.quality)))
+            .max_by_key(|&(specificity, _)| specificity)
+            .map_or(0.0, |(_, quality)| quality);
+        if quality > 0.0 && best.map_or(true, |(_, best)| quality > best) {
+            best = Some((candidate, quality));
+        }
+    }
+    best.map(|(candidate, _)| candidate)
+}
+
+/// Parses a media range, returning `None` if it is malformed.
+fn parse_media_range(input: &str) -> Option<MediaRange<'_>> {
+    let mut parts = input.split(';');
+    let (media_type, subtype) = parse_type(parts.next()?)?;
+    if media_type == "*" && subtype != "*" {
+        return None;
+    }
+
+    let mut params = Vec::new();
+    let mut quality = 1.0;
+    for part in parts {
+        let (name, value) = parse_param(part)?;
+        // the parameters after the weight are extensions of the range
+        if name.eq_ignore_ascii_case("q") {
+            quality = parse_quality(value)?;
+            break;
+        }
+        params.push((name, value));
+    }
+    Some(MediaRange {
+        media_type,
+        subtype,
+        params,
+        quality,
+    })
+}
+
+fn parse_media_type(input: &str) -> Option<MediaType<'_>> {
+    let mut parts = input.split(';');
+    let (media_type, subtype) = parse_type(parts.next()?)?;
+    let params = parts.map(parse_param).collect::<Option<Vec<_>>>()?;
+    Some(MediaType {
+        media_type,
+        subtype,
+        params,
+    })
+}
+
+fn parse_type(input: &str) -> Option<(&str, &str)> {
+    let (media_type, subtype) = input.trim().split_once('/')?;
+    if media_type.is_empty() || subtype.is_empty() {
+        return None;
+    }
+    Some((media_type, subtype))
+}
+
+fn parse_param(input: &str) -> Option<(&str, &str)> {
+    let (name, value) = input.trim().split_once('=')?;
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    Some((name.trim(), value))
+}
+
+/// Parses a weight, between 0 and 1 with at most three decimals (RFC 7231 §5.3.1).
+fn parse_quality(input: &str) -> Option<f32> {
+    let (integer, decimals) = input.split_once('.').unwrap_or((input, ""));
+    let valid = match integer {
+        "0" => decimals.len() <= 3 && decimals.bytes().all(|b| b.is_ascii_digit()),
+        "1" => decimals.len() <= 3 && decimals.bytes().all(|b| b == b'0'),
+        _ => false,
+    };
+    if valid {
+        input.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::negotiate;
+    use crate::Header;
+    use std::str::FromStr;
+
+    fn choose<'a>(accept: &[&str], offered: &[&'a str]) -> Option<&'a str> {
+        let headers: Vec<Header> = accept
+            .iter()
+            .map(|value| Header::from_str(&format!("Accept: {}", value)).unwrap())
+            .collect();
+        negotiate(&headers, offered)
+    }
+
+    #[test]
+    fn missing_header_accepts_anything() {
+        assert_eq!(choose(&[], &["text/html", "text/plain"]), Some("text/html"));
+        assert_eq!(choose(&[], &[]), None);
+        assert_eq!(choose(&["garbage"], &["text/plain"]), Some("text/plain"));
+    }
+
+    #[test]
+    fn weights() {
+        // RFC 7231 §5.3.2
+        let accept = ["audio/*; q=0.2, audio/basic"];
+        assert_eq!(
+            choose(&accept, &["audio/ogg", "audio/basic"]),
+            Some("audio/basic")
+        );
+        assert_eq!(choose(&accept, &["audio/ogg"]), Some("audio/ogg"));
+        assert_eq!(choose(&accept, &["video/mp4"]), None);
+
+        let accept = ["text/plain; q=0.5, text/html, text/x-dvi; q=0.8, text/x-c"];
+        let offered = ["text/plain", "text/x-dvi", "text/x-c", "text/html"];
+        assert_eq!(choose(&accept, &offered), Some("text/x-c"));
+        assert_eq!(choose(&accept, &offered[..2]), Some("text/x-dvi"));
+        assert_eq!(choose(&accept, &offered[..1]), Some("text/plain"));
+
+        // a weight of 0 refuses the type
+        assert_eq!(choose(&["text/html;q=0, */*"], &["text/html"]), None);
+        assert_eq!(
+            choose(&["text/html;q=0, */*;q=0.1"], &["text/html", "image/png"]),
+            Some("image/png")
+        );
+    }
+
+    #[test]
+    fn specificity() {
+        // RFC 7231 §5.3.2
+        let accept = ["text/*;q=0.3, text/html;q=0.7, text/html;level=1,\
+                       text/html;level=2;q=0.4, */*;q=0.5"];
+        let cases = [
+            (["text/html;level=1", "image/jpeg"], "text/html;level=1"),
+            (["text/plain", "text/html"], "text/html"),
+            (["text/plain", "image/jpeg"], "image/jpeg"),
+            (["text/html;level=2", "text/plain"], "text/html;level=2"),
+            (
+                ["text/html;level=2", "text/html;level=3"],
+                "text/html;level=3",
+            ),
+        ];
+        for (offered, expected) in cases.iter() {
+            assert_eq!(choose(&accept, offered), Some(*expected), "{:?}", offered);
+        }
+
+        let accept = ["text/*, text/plain, text/plain;format=flowed, */*"];
+        let offered = ["text/plain;format=fixed", "text/plain;format=flowed"];
+        assert_eq!(choose(&accept, &offered), Some("text/plain;format=fixed"));
+    }
+
+    #[test]
+    fn ties_keep_the_offered_order() {
+        let accept = ["text/html, application/json"];
+        assert_eq!(
+            choose(&accept, &["application/json", "text/html"]),
+            Some("application/json")
+        );
+        assert_eq!(
+            choose(&["*/*"], &["text/html", "application/json"]),
+            Some("text/html")
+        );
+    }
+
+    #[test]
+    fn multiple_headers_and_case() {
+        let accept = ["text/html;q=0.5", "Application/JSON"];
+        assert_eq!(
+            choose(&accept, &["text/html", "application/json"]),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn malformed_ranges_are_ignored() {
+        let accept = ["text/html;q=2, application/json;q=abc, */html, text/plain;q=0.5"];
+        assert_eq!(
+            choose(&accept, &["text/html", "application/json", "text/plain"]),
+            Some("text/plain")
+        );
+        assert_eq!(
+            choose(&["text/plain;q=0.1234"], &["text/plain"]),
+            Some("text/plain")
+        );
+    }
+}
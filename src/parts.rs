@@ -0,0 +1,64 @@
+//! The owned parts of a request, see `Request::into_parts()`.
+
+use std::fmt;
+use std::io::{Error as IoError, Read};
+use std::net::SocketAddr;
+
+use crate::{HTTPVersion, Header, Method, Request, Response};
+
+/// The method, target, version and headers of a request, returned by
+/// [`Request::into_parts`](crate::Request::into_parts).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct RequestHead {
+    /// Method of the request.
+    pub method: Method,
+    /// Target of the request, as returned by `Request::url()`.
+    pub url: String,
+    /// HTTP version of the request.
+    pub http_version: HTTPVersion,
+    /// Headers of the request.
+    pub headers: Vec<Header>,
+    /// Address of the client, `None` for Unix sockets and test requests.
+    pub remote_addr: Option<SocketAddr>,
+}
+
+/// Reader of the body of a request, returned by
+/// [`Request::into_parts`](crate::Request::into_parts).
+pub type BodyReader = Box<dyn Read + Send + 'static>;
+
+/// Handle answering a request whose head and body were taken away by
+/// [`Request::into_parts`](crate::Request::into_parts).
+///
+/// The responses are sent in the order of the requests of the connection, as with
+/// `Request::respond`. If the `Responder` is dropped without answering, an empty response with
+/// the status 500 is sent.
+pub struct Responder {
+    // the request without its head and body
+    request: Request,
+}
+
+impl Responder {
+    pub(crate) fn new(request: Request) -> Responder {
+        Responder { request }
+    }
+
+    /// Sends a response to the request, see `Request::respond`.
+    pub fn respond<R>(self, response: Response<R>) -> Result<(), IoError>
+    where
+        R: Read,
+    {
+        self.request.respond(response)
+    }
+}
+
+impl fmt::Debug for Responder {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "Responder(connection {} #{})",
+            self.request.connection_id(),
+            self.request.request_seq()
+        )
+    }
+}
@@ -0,0 +1,152 @@
+//! Decoding and normalization of the path of a request, see `Request::decoded_path`.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::urlencoded::percent_decode;
+
+/// Error returned by `Request::decoded_path()` for paths that can't be decoded safely. The
+/// request should usually be answered with `400 Bad Request`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PathError {
+    /// The path contains `%00`.
+    EncodedNul,
+    /// The path contains `%2F`, which would be confused with a separator of segments once
+    /// decoded.
+    EncodedSlash,
+    /// The decoded path isn't valid UTF-8, for example because of an overlong sequence such
+    /// as `%C0%AE`.
+    InvalidUtf8,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::EncodedNul => write!(formatter, "Encoded NUL in request path"),
+            PathError::EncodedSlash => write!(formatter, "Encoded slash in request path"),
+            PathError::InvalidUtf8 => write!(formatter, "Invalid UTF-8 in request path"),
+        }
+    }
+}
+
+impl Error for PathError {}
+
+/// Percent-decodes the path of `url`, without its query string, and removes its dot
+/// segments if `normalize` is true.
+pub(crate) fn decode_path(url: &str, normalize: bool) -> Result<String, PathError> {
+    let path = url.split(|c| c == '?' || c == '#').next().unwrap_or("");
+    let bytes = path.as_bytes();
+    for window in bytes.windows(3).filter(|window| window[0] == b'%') {
+        match (window[1], window[2].to_ascii_uppercase()) {
+            (b'0', b'0') => return Err(PathError::EncodedNul),
+            (b'2', b'F') => return Err(PathError::EncodedSlash),
+            _ => (),
+        }
+    }
+
+    let decoded = String::from_utf8(percent_decode(bytes)).map_err(|_| PathError::InvalidUtf8)?;
+    if normalize {
+        Ok(remove_dot_segments(&decoded))
+    } else {
+        Ok(decoded)
+    }
+}
+
+/// Removes the `.` and `..` segments of a path (RFC 3986 §5.2.4).
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output = String::with_capacity(path.len());
+    while !input.is_empty() {
+        if let Some(rest) = input
+            .strip_prefix("../")
+            .or_else(|| input.strip_prefix("./"))
+        {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") || input == "/.." {
+            input = if input == "/.." { "/" } else { &input[3..] };
+            let last = output.rfind('/').unwrap_or(0);
+            output.truncate(last);
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            let start = usize::from(input.starts_with('/'));
+            let end = input[start..]
+                .find('/')
+                .map_or(input.len(), |end| end + start);
+            output.push_str(&input[..end]);
+            input = &input[end..];
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_path, remove_dot_segments, PathError};
+
+    #[test]
+    fn decoding() {
+        assert_eq!(decode_path("/a%20b/c?d=%20", false).unwrap(), "/a b/c");
+        assert_eq!(decode_path("/caf%C3%A9", false).unwrap(), "/caf\u{e9}");
+        // mixed-case hex
+        assert_eq!(decode_path("/caf%c3%A9%7e", false).unwrap(), "/caf\u{e9}~");
+        assert_eq!(decode_path("/100%", false).unwrap(), "/100%");
+        assert_eq!(decode_path("/%zz", false).unwrap(), "/%zz");
+        assert_eq!(decode_path("*", false).unwrap(), "*");
+    }
+
+    #[test]
+    fn rejected_paths() {
+        assert_eq!(decode_path("/a%00b", false), Err(PathError::EncodedNul));
+        assert_eq!(decode_path("/a%2Fb", false), Err(PathError::EncodedSlash));
+        assert_eq!(decode_path("/a%2fb", true), Err(PathError::EncodedSlash));
+        // overlong encodings of `.` and `/`
+        assert_eq!(
+            decode_path("/%C0%AE%C0%AE/", true),
+            Err(PathError::InvalidUtf8)
+        );
+        assert_eq!(decode_path("/%c0%af", false), Err(PathError::InvalidUtf8));
+        assert_eq!(
+            decode_path("/%E0%80%AF", false),
+            Err(PathError::InvalidUtf8)
+        );
+        assert_eq!(decode_path("/%FF", false), Err(PathError::InvalidUtf8));
+        // only in the query string
+        assert_eq!(decode_path("/a?b=%2F%00", false).unwrap(), "/a");
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!(
+            decode_path("/files/%2e%2e/secret", true).unwrap(),
+            "/secret"
+        );
+        assert_eq!(
+            decode_path("/files/%2e%2e/secret", false).unwrap(),
+            "/files/../secret"
+        );
+        assert_eq!(decode_path("/a//b/./c", true).unwrap(), "/a//b/c");
+        assert_eq!(
+            decode_path("/../../etc/passwd", true).unwrap(),
+            "/etc/passwd"
+        );
+        assert_eq!(decode_path("/a/b/..", true).unwrap(), "/a/");
+    }
+
+    #[test]
+    fn rfc_3986_examples() {
+        assert_eq!(remove_dot_segments("/a/b/c/./../../g"), "/a/g");
+        assert_eq!(remove_dot_segments("mid/content=5/../6"), "mid/6");
+        assert_eq!(remove_dot_segments("/b/c/."), "/b/c/");
+        assert_eq!(remove_dot_segments("/b/c/g/.."), "/b/c/");
+        assert_eq!(remove_dot_segments("/b/c/..g"), "/b/c/..g");
+        assert_eq!(remove_dot_segments("/b/c/g;x=1/./y"), "/b/c/g;x=1/y");
+        assert_eq!(remove_dot_segments("/./.."), "/");
+        assert_eq!(remove_dot_segments("."), "");
+    }
+}
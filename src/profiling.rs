@@ -0,0 +1,253 @@
+//! Measures of the time spent by the library on each request, enabled by the `profiling`
+//! feature.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+
+/// Number of buckets of a histogram. Bucket `i` counts the durations between `2^(i-1)` and
+/// `2^i` nanoseconds, the last one counts everything above.
+const BUCKETS: usize = 48;
+
+/// Histogram of durations with fixed log-scale buckets.
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            buckets: [(); BUCKETS].map(|_| AtomicU64::new(0)),
+        }
+    }
+
+    fn record(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        let bucket = (64 - nanos.leading_zeros() as usize).min(BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Relaxed);
+    }
+
+    fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Relaxed);
+        }
+    }
+
+    fn snapshot(&self) -> (u64, Percentiles) {
+        let counts: Vec<u64> = self.buckets.iter().map(|b| b.load(Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+
+        // upper bound of the bucket that contains the given fraction of the durations
+        let percentile = |fraction: f64| {
+            let rank = ((total as f64) * fraction).ceil() as u64;
+            let mut seen = 0;
+            for (bucket, count) in counts.iter().enumerate() {
+                seen += count;
+                if seen >= rank.max(1) {
+                    return match bucket {
+                        0 => Duration::from_nanos(0),
+                        _ => Duration::from_nanos(1 << bucket),
+                    };
+                }
+            }
+            Duration::from_nanos(0)
+        };
+
+        (
+            total,
+            Percentiles {
+                p50: percentile(0.50),
+                p95: percentile(0.95),
+                p99: percentile(0.99),
+            },
+        )
+    }
+}
+
+/// Percentiles of the durations of a phase.
+///
+/// The durations are grouped in buckets whose bounds grow by powers of two, each percentile
+/// is the upper bound of its bucket.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Percentiles {
+    /// Median.
+    pub p50: Duration,
+    /// 95th percentile.
+    pub p95: Duration,
+    /// 99th percentile.
+    pub p99: Duration,
+}
+
+/// Profile of one phase of the handling of requests.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PhaseProfile {
+    /// Number of requests that went through this phase.
+    pub count: u64,
+    /// Wall-clock durations.
+    pub wall: Percentiles,
+    /// CPU time of the thread that ran the phase. `None` if it can't be measured on this
+    /// platform or doesn't apply to the phase.
+    pub cpu: Option<Percentiles>,
+}
+
+/// Time spent by the library on requests, returned by `Server::profile_snapshot()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ProfileSnapshot {
+    /// Parsing the head of the request, from the moment its request line is received to the
+    /// creation of the `Request`.
+    pub parse: PhaseProfile,
+    /// Time spent in the queue of the server before being received.
+    pub queue: PhaseProfile,
+    /// Writing the response, body included.
+    pub serialize: PhaseProfile,
+}
+
+struct Phase {
+    wall: Histogram,
+    cpu: Histogram,
+}
+
+impl Phase {
+    fn new() -> Phase {
+        Phase {
+            wall: Histogram::new(),
+            cpu: Histogram::new(),
+        }
+    }
+
+    fn snapshot(&self, with_cpu: bool) -> PhaseProfile {
+        let (count, wall) = self.wall.snapshot();
+        let cpu = if with_cpu && thread_cpu_time().is_some() {
+            Some(self.cpu.snapshot().1)
+        } else {
+            None
+        };
+        PhaseProfile { count, wall, cpu }
+    }
+
+    fn reset(&self) {
+        self.wall.reset();
+        self.cpu.reset();
+    }
+}
+
+/// Histograms of a server, shared with its connections.
+pub(crate) struct Profile {
+    parse: Phase,
+    queue: Phase,
+    serialize: Phase,
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile {
+            parse: Phase::new(),
+            queue: Phase::new(),
+            serialize: Phase::new(),
+        }
+    }
+}
+
+impl fmt::Debug for Profile {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.snapshot(), formatter)
+    }
+}
+
+impl Profile {
+    pub(crate) fn record_parse(&self, timer: PhaseTimer) {
+        timer.finish(&self.parse);
+    }
+
+    pub(crate) fn record_queue(&self, latency: Duration) {
+        self.queue.wall.record(latency);
+    }
+
+    pub(crate) fn record_serialize(&self, timer: PhaseTimer) {
+        timer.finish(&self.serialize);
+    }
+
+    pub(crate) fn snapshot(&self) -> ProfileSnapshot {
+        ProfileSnapshot {
+            parse: self.parse.snapshot(true),
+            queue: self.queue.snapshot(false),
+            serialize: self.serialize.snapshot(true),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.parse.reset();
+        self.queue.reset();
+        self.serialize.reset();
+    }
+}
+
+/// Start of a phase, the phase ends when the timer is given to `Profile`.
+pub(crate) struct PhaseTimer {
+    wall: Instant,
+    cpu: Option<Duration>,
+}
+
+impl PhaseTimer {
+    pub(crate) fn start() -> PhaseTimer {
+        PhaseTimer {
+            wall: Instant::now(),
+            cpu: thread_cpu_time(),
+        }
+    }
+
+    fn finish(self, phase: &Phase) {
+        // reading the CPU time first, so that it doesn't exceed the wall-clock time
+        if let (Some(start), Some(end)) = (self.cpu, thread_cpu_time()) {
+            phase.cpu.record(end.saturating_sub(start));
+        }
+        phase.wall.record(self.wall.elapsed());
+    }
+}
+
+/// Returns the CPU time consumed by the current thread.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn thread_cpu_time() -> Option<Duration> {
+    use nix::time::{clock_gettime, ClockId};
+
+    let time = clock_gettime(ClockId::CLOCK_THREAD_CPUTIME_ID).ok()?;
+    Some(Duration::new(time.tv_sec() as u64, time.tv_nsec() as u32))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn thread_cpu_time() -> Option<Duration> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Histogram;
+    use std::time::Duration;
+
+    #[test]
+    fn histogram_percentiles() {
+        let histogram = Histogram::new();
+        for _ in 0..90 {
+            histogram.record(Duration::from_micros(10));
+        }
+        for _ in 0..10 {
+            histogram.record(Duration::from_millis(10));
+        }
+
+        let (count, percentiles) = histogram.snapshot();
+        assert_eq!(count, 100);
+        // 10µs is in the bucket that ends at 2^14 ns
+        assert_eq!(percentiles.p50, Duration::from_nanos(1 << 14));
+        // 10ms is in the bucket that ends at 2^24 ns
+        assert_eq!(percentiles.p95, Duration::from_nanos(1 << 24));
+        assert_eq!(percentiles.p99, Duration::from_nanos(1 << 24));
+
+        histogram.reset();
+        let (count, percentiles) = histogram.snapshot();
+        assert_eq!(count, 0);
+        assert_eq!(percentiles.p99, Duration::from_nanos(0));
+    }
+}
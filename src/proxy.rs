@@ -0,0 +1,577 @@
+//! Forwarding requests to an upstream HTTP server.
+//!
+//! [`proxy_request`] opens a new connection to the upstream for each request, while
+//! [`proxy_request_pooled`] reuses the connections of an [`UpstreamPool`].
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::io::{Error as IoError, ErrorKind as IoErrorKind, Result as IoResult};
+use std::net::{SocketAddr, TcpStream};
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chunked_transfer::Encoder;
+
+use crate::log;
+use crate::{BodyKind, Header, Method, Request, Response, StatusCode};
+
+/// Headers that only apply to a single connection, and are never forwarded.
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Settings of an [`UpstreamPool`].
+#[derive(Debug, Clone)]
+pub struct PoolOptions {
+    /// Maximum number of idle connections kept in the pool. Defaults to 8.
+    pub max_idle: usize,
+    /// Idle connections older than this are closed instead of being reused. Defaults to
+    /// 30 seconds.
+    pub idle_timeout: Duration,
+    /// Connections are closed once they have been open for this long, even if they are still
+    /// in use. Defaults to 5 minutes.
+    pub max_lifetime: Duration,
+}
+
+impl Default for PoolOptions {
+    fn default() -> PoolOptions {
+        PoolOptions {
+            max_idle: 8,
+            idle_timeout: Duration::from_secs(30),
+            max_lifetime: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Counters of an [`UpstreamPool`], returned by `UpstreamPool::stats()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PoolStats {
+    /// Number of connections opened to the upstream.
+    pub opened: usize,
+    /// Number of checkouts that reused an idle connection.
+    pub reused: usize,
+    /// Number of idle connections that were closed because they expired or the upstream
+    /// closed them.
+    pub discarded: usize,
+    /// Number of connections currently idle in the pool.
+    pub idle: usize,
+}
+
+struct IdleConn {
+    stream: TcpStream,
+    opened_at: Instant,
+    idle_since: Instant,
+}
+
+/// Source of the current time of a pool, replaceable for tests.
+type Clock = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// Keep-alive connections to an upstream server, shared between the threads that handle
+/// requests.
+pub struct UpstreamPool {
+    addr: SocketAddr,
+    options: PoolOptions,
+    clock: Clock,
+    idle: Mutex<VecDeque<IdleConn>>,
+    opened: AtomicUsize,
+    reused: AtomicUsize,
+    discarded: AtomicUsize,
+}
+
+impl UpstreamPool {
+    /// Builds an empty pool of connections to `addr`.
+    pub fn new(addr: SocketAddr, options: PoolOptions) -> UpstreamPool {
+        UpstreamPool::with_clock(addr, options, Arc::new(Instant::now))
+    }
+
+    /// Same as `new()`, but reads the current time from `clock` to decide when connections
+    /// expire.
+    pub fn with_clock(
+        addr: SocketAddr,
+        options: PoolOptions,
+        clock: Arc<dyn Fn() -> Instant + Send + Sync>,
+    ) -> UpstreamPool {
+        UpstreamPool {
+            addr,
+            options,
+            clock,
+            idle: Mutex::new(VecDeque::new()),
+            opened: AtomicUsize::new(0),
+            reused: AtomicUsize::new(0),
+            discarded: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the address of the upstream.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns a snapshot of the counters of the pool.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            opened: self.opened.load(Relaxed),
+            reused: self.reused.load(Relaxed),
+            discarded: self.discarded.load(Relaxed),
+            idle: self.idle.lock().unwrap().len(),
+        }
+    }
+
+    /// Returns an idle connection that is still usable, or opens a new one.
+    ///
+    /// The connection goes back to the pool when the `PooledConn` is dropped, unless
+    /// `PooledConn::discard()` was called or an I/O error happened on it.
+    pub fn checkout(&self) -> IoResult<PooledConn<'_>> {
+        let now = (self.clock)();
+
+        loop {
+            // the lock isn't held while checking the connection
+            let conn = self.idle.lock().unwrap().pop_back();
+            let conn = match conn {
+                Some(conn) => conn,
+                None => break,
+            };
+
+            if self.is_expired(&conn, now) || !is_still_open(&conn.stream) {
+                self.discarded.fetch_add(1, Relaxed);
+                continue;
+            }
+
+            self.reused.fetch_add(1, Relaxed);
+            return Ok(PooledConn {
+                pool: self,
+                stream: Some(conn.stream),
+                opened_at: conn.opened_at,
+                reusable: true,
+            });
+        }
+
+        let stream = TcpStream::connect(self.addr)?;
+        self.opened.fetch_add(1, Relaxed);
+        Ok(PooledConn {
+            pool: self,
+            stream: Some(stream),
+            opened_at: now,
+            reusable: true,
+        })
+    }
+
+    fn is_expired(&self, conn: &IdleConn, now: Instant) -> bool {
+        now.saturating_duration_since(conn.idle_since) >= self.options.idle_timeout
+            || now.saturating_duration_since(conn.opened_at) >= self.options.max_lifetime
+    }
+
+    fn check_in(&self, stream: TcpStream, opened_at: Instant) {
+        let now = (self.clock)();
+        if now.saturating_duration_since(opened_at) >= self.options.max_lifetime {
+            return;
+        }
+
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() >= self.options.max_idle {
+            return;
+        }
+        idle.push_back(IdleConn {
+            stream,
+            opened_at,
+            idle_since: now,
+        });
+    }
+}
+
+impl fmt::Debug for UpstreamPool {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("UpstreamPool")
+            .field("addr", &self.addr)
+            .field("options", &self.options)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// Returns false if the upstream closed the connection while it was idle.
+///
+/// An idle connection must not have anything to read: either the upstream closed it, or it
+/// sent data that doesn't belong to any response.
+fn is_still_open(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let open = match stream.peek(&mut [0]) {
+        Err(ref err) => err.kind() == IoErrorKind::WouldBlock,
+        Ok(_) => false,
+    };
+    stream.set_nonblocking(false).is_ok() && open
+}
+
+/// A connection of an [`UpstreamPool`], returned to the pool when dropped.
+pub struct PooledConn<'a> {
+    pool: &'a UpstreamPool,
+    stream: Option<TcpStream>,
+    opened_at: Instant,
+    reusable: bool,
+}
+
+impl PooledConn<'_> {
+    /// Closes the connection when it is dropped instead of returning it to the pool, for
+    /// example when the upstream answered with `Connection: close`.
+    pub fn discard(&mut self) {
+        self.reusable = false;
+    }
+
+    /// Returns false if the connection won't go back to the pool.
+    pub fn is_reusable(&self) -> bool {
+        self.reusable
+    }
+}
+
+impl Deref for PooledConn<'_> {
+    type Target = TcpStream;
+
+    fn deref(&self) -> &TcpStream {
+        self.stream.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConn<'_> {
+    fn deref_mut(&mut self) -> &mut TcpStream {
+        self.stream.as_mut().unwrap()
+    }
+}
+
+impl Read for PooledConn<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let result = self.deref_mut().read(buf);
+        // the state of the connection is unknown after an error
+        if result.is_err() {
+            self.reusable = false;
+        }
+        result
+    }
+}
+
+impl Write for PooledConn<'_> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let result = self.deref_mut().write(buf);
+        if result.is_err() {
+            self.reusable = false;
+        }
+        result
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        let result = self.deref_mut().flush();
+        if result.is_err() {
+            self.reusable = false;
+        }
+        result
+    }
+}
+
+impl Drop for PooledConn<'_> {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            if self.reusable {
+                self.pool.check_in(stream, self.opened_at);
+            }
+        }
+    }
+}
+
+/// Forwards a request to `upstream` over a new connection, then sends the response of the
+/// upstream back to the client.
+///
+/// Hop-by-hop headers such as `Connection` aren't forwarded in either direction. The body of
+/// the response is streamed to the client as it is received.
+pub fn proxy_request(request: Request, upstream: SocketAddr) -> IoResult<()> {
+    let mut stream = TcpStream::connect(upstream)?;
+    forward(request, &mut stream, false).map(|_| ())
+}
+
+/// Same as `proxy_request`, but uses a connection of `pool`.
+///
+/// The connection goes back to the pool if the upstream keeps it alive and the whole response
+/// was read.
+pub fn proxy_request_pooled(request: Request, pool: &UpstreamPool) -> IoResult<()> {
+    let mut conn = pool.checkout()?;
+    match forward(request, &mut conn, true) {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            conn.discard();
+            Ok(())
+        }
+        Err(err) => {
+            conn.discard();
+            Err(err)
+        }
+    }
+}
+
+/// Sends the request over `stream` and answers it with the response of the upstream.
+///
+/// Returns true if the connection can be used for another request.
+fn forward<S: Read + Write>(
+    mut request: Request,
+    stream: &mut S,
+    keep_alive: bool,
+) -> IoResult<bool> {
+    let no_response_body = *request.method() == Method::Head;
+    write_upstream_request(&mut request, &mut *stream, keep_alive)?;
+
+    let mut reader = BufReader::new(stream);
+    // the interim responses, eg. `100 Continue`, are for the proxy and not forwarded; the
+    // request is sent whole anyway
+    let head = loop {
+        let head = read_response_head(&mut reader)?;
+        match head.status.0 {
+            100 | 102..=199 => {
+                log::debug!("Skipping interim upstream response {}", head.status.0);
+            }
+            _ => break head,
+        }
+    };
+    let mut reusable = keep_alive && head.keep_alive;
+
+    let no_body = no_response_body || matches!(head.status.0, 100..=199 | 204 | 304);
+    let headers: Vec<Header> = head
+        .headers
+        .iter()
+        .filter(|h| !HOP_BY_HOP_HEADERS.iter().any(|name| h.field.equiv(name)))
+        .cloned()
+        .collect();
+
+    if no_body {
+        request.respond(Response::new(head.status, headers, io::empty(), None, None))?;
+    } else if head.chunked {
+        let mut body = ChunkedBody::new(&mut reader);
+        let response = Response::new(head.status, headers, body.by_ref(), None, None);
+        request.respond(response)?;
+        reusable = reusable && body.is_done();
+    } else if let Some(length) = head.content_length {
+        let mut body = reader.by_ref().take(length as u64);
+        let response = Response::new(head.status, headers, body.by_ref(), Some(length), None);
+        request.respond(response)?;
+        reusable = reusable && body.limit() == 0;
+    } else {
+        // the body ends when the upstream closes the connection
+        request.respond(Response::new(
+            head.status,
+            headers,
+            reader.by_ref(),
+            None,
+            None,
+        ))?;
+        reusable = false;
+    }
+
+    // anything sent after the response doesn't belong to any request
+    Ok(reusable && reader.buffer().is_empty())
+}
+
+fn write_upstream_request<W: Write>(
+    request: &mut Request,
+    stream: W,
+    keep_alive: bool,
+) -> IoResult<()> {
+    let mut writer = BufWriter::new(stream);
+    write!(
+        writer,
+        "{} {} HTTP/1.1\r\n",
+        request.method(),
+        request.url()
+    )?;
+    for header in request.headers() {
+        if header.field.equiv("Content-Length")
+            || HOP_BY_HOP_HEADERS
+                .iter()
+                .any(|name| header.field.equiv(name))
+        {
+            continue;
+        }
+        write!(writer, "{}\r\n", header)?;
+    }
+    let connection = if keep_alive { "keep-alive" } else { "close" };
+    write!(writer, "Connection: {}\r\n", connection)?;
+
+    let length = match request.body_kind() {
+        BodyKind::Fixed(length) => Some(length),
+        // only the declared body is forwarded, not the rest of the upgraded stream
+        BodyKind::UpgradeRaw => request.body_length().map(|length| length as u64),
+        BodyKind::Chunked | BodyKind::None => None,
+    };
+    if let Some(length) = length {
+        write!(writer, "Content-Length: {}\r\n\r\n", length)?;
+        io::copy(&mut request.as_reader().take(length), &mut writer)?;
+    } else if request.body_kind() == BodyKind::Chunked {
+        write!(writer, "Transfer-Encoding: chunked\r\n\r\n")?;
+        let mut encoder = Encoder::new(&mut writer);
+        io::copy(request.as_reader(), &mut encoder)?;
+        encoder.flush()?;
+    } else {
+        write!(writer, "\r\n")?;
+    }
+
+    writer.flush()
+}
+
+struct ResponseHead {
+    status: StatusCode,
+    headers: Vec<Header>,
+    content_length: Option<usize>,
+    chunked: bool,
+    keep_alive: bool,
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> IoResult<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(IoError::new(
+            IoErrorKind::UnexpectedEof,
+            "Upstream closed the connection",
+        ));
+    }
+    let trimmed = line.trim_end_matches(|c| c == '\r' || c == '\n').len();
+    line.truncate(trimmed);
+    Ok(line)
+}
+
+fn read_response_head<R: BufRead>(reader: &mut R) -> IoResult<ResponseHead> {
+    let invalid = |what: &str| IoError::new(IoErrorKind::InvalidData, format!("Invalid {}", what));
+
+    // eg. HTTP/1.1 200 OK
+    let status_line = read_line(reader)?;
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or("");
+    let status = parts
+        .next()
+        .and_then(|code| u16::from_str(code).ok())
+        .ok_or_else(|| invalid("upstream status line"))?;
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            break;
+        }
+        headers.push(Header::from_str(&line).map_err(|_| invalid("upstream header"))?);
+    }
+
+    let find = |name: &'static str| {
+        headers
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str().to_ascii_lowercase())
+    };
+    let chunked = find("Transfer-Encoding").map_or(false, |v| v.contains("chunked"));
+    let content_length = find("Content-Length").and_then(|v| v.parse().ok());
+    let keep_alive = match find("Connection") {
+        Some(ref v) if v.contains("close") => false,
+        Some(ref v) if v.contains("keep-alive") => true,
+        _ => version == "HTTP/1.1",
+    };
+
+    if !keep_alive {
+        log::debug!("Upstream doesn't keep the connection alive");
+    }
+
+    Ok(ResponseHead {
+        status: StatusCode(status),
+        headers,
+        content_length,
+        chunked,
+        keep_alive,
+    })
+}
+
+/// Body of an upstream response with the `chunked` transfer coding.
+///
+/// Unlike `chunked_transfer::Decoder`, the trailers and the line break that end the body are
+/// consumed as well, so that the next response can be read from the same connection once
+/// `is_done()` returns true. The trailers are dropped, since the response to the client is
+/// sent before they are read.
+struct ChunkedBody<R> {
+    reader: R,
+    // remaining size of the chunk being read, `None` between two chunks
+    remaining: Option<u64>,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedBody<R> {
+    fn new(reader: R) -> ChunkedBody<R> {
+        ChunkedBody {
+            reader,
+            remaining: None,
+            done: false,
+        }
+    }
+
+    /// Returns true once the whole body, trailers included, was read.
+    fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn read_chunk_size(&mut self) -> IoResult<u64> {
+        let line = read_line(&mut self.reader)?;
+        // eg. `1a;name=value`, the extensions are ignored
+        let size = line.split(';').next().unwrap_or("").trim();
+        u64::from_str_radix(size, 16)
+            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Invalid upstream chunk size"))
+    }
+}
+
+impl<R: BufRead> Read for ChunkedBody<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                let size = self.read_chunk_size()?;
+                if size == 0 {
+                    // the trailers end with an empty line
+                    while !read_line(&mut self.reader)?.is_empty() {}
+                    self.done = true;
+                    return Ok(0);
+                }
+                size
+            }
+        };
+
+        let len = remaining.min(buf.len() as u64) as usize;
+        let read = self.reader.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(IoError::new(
+                IoErrorKind::UnexpectedEof,
+                "Upstream closed the connection in a chunk",
+            ));
+        }
+
+        let remaining = remaining - read as u64;
+        if remaining == 0 {
+            if !read_line(&mut self.reader)?.is_empty() {
+                return Err(IoError::new(
+                    IoErrorKind::InvalidData,
+                    "Missing line break after an upstream chunk",
+                ));
+            }
+            self.remaining = None;
+        } else {
+            self.remaining = Some(remaining);
+        }
+        Ok(read)
+    }
+}
@@ -0,0 +1,283 @@
+//! Header of the PROXY protocol sent by load balancers before the data of the client, see
+//! `ServerConfigAdvanced::with_accept_proxy_protocol`.
+//!
+//! Both versions of the protocol are supported: the text header of version 1 and the binary
+//! header of version 2. The header is read without reading any byte past its end, so that
+//! the rest of the stream can be handed to a TLS handshake as it is: the end of a version 1
+//! header is found by looking at the received bytes before reading them.
+
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use crate::connection::Connection;
+
+/// Time to wait for the header when no keep-alive timeout is configured.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Start of a version 1 header.
+const V1_PREFIX: &[u8] = b"PROXY ";
+
+/// Maximum length of a version 1 header, including the final CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+/// Signature starting a version 2 header.
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Stream that a header is read from.
+pub(crate) trait Peek: Read {
+    /// Copies the next bytes of the stream into `buf` without consuming them, waiting for at
+    /// least one, and returns their number. Returns `None` if the stream can't do that, in
+    /// which case a version 1 header is read one byte at a time.
+    fn peek(&mut self, buf: &mut [u8]) -> Option<IoResult<usize>>;
+}
+
+impl Peek for Connection {
+    fn peek(&mut self, buf: &mut [u8]) -> Option<IoResult<usize>> {
+        match self {
+            Connection::Tcp(stream) => Some(stream.peek(buf)),
+            #[cfg(unix)]
+            Connection::Unix(_) => None,
+        }
+    }
+}
+
+impl Peek for &[u8] {
+    fn peek(&mut self, buf: &mut [u8]) -> Option<IoResult<usize>> {
+        let len = buf.len().min(self.len());
+        buf[..len].copy_from_slice(&self[..len]);
+        Some(Ok(len))
+    }
+}
+
+/// Reads the PROXY protocol header at the start of `reader`, and returns the address of the
+/// client it contains.
+///
+/// Returns `None` for the headers that don't carry the address of a TCP client, such as the
+/// health checks of the load balancer, in which case the address of the peer must be kept.
+/// Fails with `ErrorKind::InvalidData` if the stream doesn't start with a valid header.
+pub(crate) fn read_header<R: Peek>(reader: &mut R) -> IoResult<Option<SocketAddr>> {
+    let mut start = [0; 6];
+    reader.read_exact(&mut start)?;
+    if start == V1_PREFIX {
+        read_v1(reader)
+    } else if start == V2_SIGNATURE[..6] {
+        read_v2(reader)
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+/// Reads the header at the start of a new connection, waiting at most `timeout` for it, or
+/// `DEFAULT_TIMEOUT` if there is none, so that a silent client doesn't hold a thread forever.
+pub(crate) fn read_from_connection(
+    sock: &mut Connection,
+    timeout: Option<Duration>,
+) -> IoResult<Option<SocketAddr>> {
+    sock.set_read_timeout(Some(timeout.unwrap_or(DEFAULT_TIMEOUT)))?;
+    let addr = read_header(sock)?;
+    sock.set_read_timeout(None)?;
+    Ok(addr)
+}
+
+/// Reads the rest of a version 1 header, after its prefix.
+fn read_v1<R: Peek>(reader: &mut R) -> IoResult<Option<SocketAddr>> {
+    let mut line = [0; V1_MAX_LENGTH - V1_PREFIX.len()];
+    let mut len = 0;
+    while len == 0 || line[len - 1] != b'\n' {
+        if len == line.len() {
+            return Err(invalid("PROXY protocol header too long"));
+        }
+        // reading the received bytes up to the end of the line, but not past it
+        let count = match reader.peek(&mut line[len..]) {
+            Some(peeked) => match peeked? {
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                peeked => line[len..len + peeked]
+                    .iter()
+                    .position(|&byte| byte == b'\n')
+                    .map_or(peeked, |end| end + 1),
+            },
+            None => 1,
+        };
+        reader.read_exact(&mut line[len..len + count])?;
+        len += count;
+    }
+    let line = match line[..len].strip_suffix(b"\r\n") {
+        Some(line) => line,
+        None => return Err(invalid("invalid PROXY protocol header")),
+    };
+
+    let line = std::str::from_utf8(line).map_err(|_| invalid("invalid PROXY protocol header"))?;
+    parse_v1(line).ok_or_else(|| invalid("invalid PROXY protocol header"))
+}
+
+/// Parses the fields of a version 1 header, such as `TCP4 192.0.2.1 192.0.2.2 5000 80`.
+fn parse_v1(line: &str) -> Option<Option<SocketAddr>> {
+    let mut fields = line.split(' ');
+    let ip = match fields.next()? {
+        // the rest of the line is meaningless
+        "UNKNOWN" => return Some(None),
+        "TCP4" => fields.next()?.parse::<Ipv4Addr>().ok().map(IpAddr::V4)?,
+        "TCP6" => fields.next()?.parse::<Ipv6Addr>().ok().map(IpAddr::V6)?,
+        _ => return None,
+    };
+    let _destination = fields.next()?;
+    let port = fields.next()?.parse::<u16>().ok()?;
+    let _destination_port = fields.next()?.parse::<u16>().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(Some(SocketAddr::new(ip, port)))
+}
+
+/// Reads the rest of a version 2 header, after the beginning of its signature.
+fn read_v2<R: Read>(reader: &mut R) -> IoResult<Option<SocketAddr>> {
+    let mut head = [0; 10];
+    reader.read_exact(&mut head)?;
+    if head[..6] != V2_SIGNATURE[6..] {
+        return Err(invalid("missing PROXY protocol header"));
+    }
+    let (version_command, family, length) = (head[6], head[7], [head[8], head[9]]);
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    // the addresses, followed by extensions that are skipped
+    let mut payload = vec![0; u16::from_be_bytes(length) as usize];
+    reader.read_exact(&mut payload)?;
+
+    match version_command & 0x0f {
+        // sent by the load balancer itself, for example for health checks
+        0 => return Ok(None),
+        1 => (),
+        _ => return Err(invalid("unsupported PROXY protocol command")),
+    }
+    let port = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+    match family {
+        // TCP over IPv4
+        0x11 if payload.len() >= 12 => {
+            let mut ip = [0; 4];
+            ip.copy_from_slice(&payload[..4]);
+            Ok(Some(SocketAddr::new(ip.into(), port(&payload[8..]))))
+        }
+        // TCP over IPv6
+        0x21 if payload.len() >= 36 => {
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&payload[..16]);
+            Ok(Some(SocketAddr::new(ip.into(), port(&payload[32..]))))
+        }
+        0x11 | 0x21 => Err(invalid("truncated PROXY protocol addresses")),
+        // unspecified, UDP or Unix sockets
+        _ => Ok(None),
+    }
+}
+
+fn invalid(message: &'static str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_header, Peek};
+    use std::io::{ErrorKind, Read, Result as IoResult};
+
+    /// Stream receiving its data a few bytes at a time.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Peek for Trickle<'_> {
+        fn peek(&mut self, buf: &mut [u8]) -> Option<IoResult<usize>> {
+            let len = buf.len().min(5);
+            self.0.peek(&mut buf[..len])
+        }
+    }
+
+    fn v2(command: u8, family: u8, payload: &[u8]) -> Vec<u8> {
+        let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        header.extend_from_slice(payload);
+        header
+    }
+
+    #[test]
+    fn v1_headers() {
+        let mut data = &b"PROXY TCP4 192.0.2.1 192.0.2.2 5000 80\r\nGET /"[..];
+        let addr = read_header(&mut data).unwrap();
+        assert_eq!(addr, Some("192.0.2.1:5000".parse().unwrap()));
+        assert_eq!(data, b"GET /");
+
+        let mut data = &b"PROXY TCP6 2001:db8::1 2001:db8::2 5000 443\r\n"[..];
+        let addr = read_header(&mut data).unwrap();
+        assert_eq!(addr, Some("[2001:db8::1]:5000".parse().unwrap()));
+
+        let mut data = &b"PROXY UNKNOWN whatever\r\nGET /"[..];
+        assert_eq!(read_header(&mut data).unwrap(), None);
+        assert_eq!(data, b"GET /");
+
+        let mut data = Trickle(b"PROXY TCP4 192.0.2.1 192.0.2.2 5000 80\r\nGET /");
+        let addr = read_header(&mut data).unwrap();
+        assert_eq!(addr, Some("192.0.2.1:5000".parse().unwrap()));
+        assert_eq!(data.0, b"GET /");
+    }
+
+    #[test]
+    fn v2_headers() {
+        let payload = [192, 0, 2, 1, 192, 0, 2, 2, 0x13, 0x88, 0, 80, 0x03, 0, 1, 0];
+        let mut data = v2(1, 0x11, &payload);
+        data.extend_from_slice(b"GET /");
+        let mut reader = &data[..];
+        let addr = read_header(&mut reader).unwrap();
+        assert_eq!(addr, Some("192.0.2.1:5000".parse().unwrap()));
+        assert_eq!(reader, b"GET /");
+
+        let mut payload = vec![0; 36];
+        payload[..16].copy_from_slice(
+            &"2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        payload[32..34].copy_from_slice(&5000u16.to_be_bytes());
+        let addr = read_header(&mut &v2(1, 0x21, &payload)[..]).unwrap();
+        assert_eq!(addr, Some("[2001:db8::1]:5000".parse().unwrap()));
+
+        // health check of the load balancer
+        assert_eq!(read_header(&mut &v2(0, 0, &[])[..]).unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_headers() {
+        let cases: &[&[u8]] = &[
+            b"GET / HTTP/1.1\r\n\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 5000\r\n",
+            b"PROXY TCP4 2001:db8::1 192.0.2.2 5000 80\r\n",
+            b"PROXY UDP4 192.0.2.1 192.0.2.2 5000 80\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 5000 80\nGET / HTTP/1.1\r\n\r\n",
+            &[b'P'; 200],
+            b"\r\n\r\n\0\r\nQUIT\n\x31\x11\0\0",
+            b"\r\n\r\n\0\r\nQUIT\n\x21\x11\0\x04\xc0\0\x02\x01",
+        ];
+        for case in cases {
+            let mut reader = &case[..];
+            let err = read_header(&mut reader).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{:?}", case);
+        }
+        let v1_line = b"PROXY TCP4 192.0.2.1 192.0.2.2 5000 80\r\n";
+        let mut long = &[&v1_line[..6], &[b'x'; 200][..]].concat()[..];
+        assert!(read_header(&mut long).is_err());
+        let mut rest = Vec::new();
+        long.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest.len(), 200 - 101);
+
+        // the client went away
+        let err = read_header(&mut &b"PROXY TCP4"[..]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
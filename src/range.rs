@@ -0,0 +1,314 @@
+//! Parsing of the `Range` header, and bodies limited to a range of bytes.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Error as IoError, Read, Result as IoResult};
+
+use crate::{Header, Response};
+
+/// A range of bytes of a body, with both ends inclusive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ByteRange {
+    /// Position of the first byte of the range.
+    pub start: u64,
+    /// Position of the last byte of the range.
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Returns the number of bytes in the range.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Returns the value of the `Content-Range` header of a response containing this range.
+    pub fn content_range(&self, total_len: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total_len)
+    }
+}
+
+/// Error returned when a response can't be limited to the range requested by the client.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RangeError {
+    /// The `Range` header isn't a single range of bytes. The header should be ignored, and the
+    /// whole body sent instead.
+    Invalid,
+    /// The range starts after the end of the body. The request should be answered with
+    /// `RangeError::response()`.
+    Unsatisfiable {
+        /// Length of the body.
+        total_len: u64,
+    },
+    /// Moving the body to the start of the range failed.
+    Io(IoError),
+}
+
+impl RangeError {
+    /// Returns the `416 Range Not Satisfiable` response to send when the range is
+    /// unsatisfiable, or `None` for the other errors.
+    pub fn response(&self) -> Option<Response<io::Empty>> {
+        match self {
+            RangeError::Unsatisfiable { total_len } => Some(
+                Response::empty(416).with_header(
+                    Header::from_bytes(
+                        &b"Content-Range"[..],
+                        format!("bytes */{}", total_len).as_bytes(),
+                    )
+                    .unwrap(),
+                ),
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RangeError::Invalid => write!(formatter, "Invalid Range header"),
+            RangeError::Unsatisfiable { total_len } => {
+                write!(formatter, "Range not satisfiable for {} bytes", total_len)
+            }
+            RangeError::Io(err) => write!(formatter, "Could not seek to the range: {}", err),
+        }
+    }
+}
+
+impl Error for RangeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RangeError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<IoError> for RangeError {
+    fn from(err: IoError) -> RangeError {
+        RangeError::Io(err)
+    }
+}
+
+/// Parses the value of a `Range` header containing a single range of bytes, for a body of
+/// `total_len` bytes.
+///
+/// Open-ended (`bytes=100-`) and suffix (`bytes=-100`) ranges are supported, and ranges ending
+/// after the body are shortened to its end, as described in RFC 7233.
+pub fn parse_byte_range(value: &str, total_len: u64) -> Result<ByteRange, RangeError> {
+    let spec = value
+        .trim()
+        .strip_prefix("bytes=")
+        .ok_or(RangeError::Invalid)?
+        .trim();
+    if spec.contains(',') {
+        return Err(RangeError::Invalid);
+    }
+
+    let (start, end) = {
+        let mut parts = spec.splitn(2, '-');
+        let start = parts.next().unwrap().trim();
+        let end = parts.next().ok_or(RangeError::Invalid)?.trim();
+        (start, end)
+    };
+    let parse = |s: &str| s.parse::<u64>().map_err(|_| RangeError::Invalid);
+    let unsatisfiable = RangeError::Unsatisfiable { total_len };
+
+    if start.is_empty() {
+        // eg. bytes=-100 for the last 100 bytes
+        let suffix = parse(end)?;
+        if suffix == 0 || total_len == 0 {
+            return Err(unsatisfiable);
+        }
+        return Ok(ByteRange {
+            start: total_len.saturating_sub(suffix),
+            end: total_len - 1,
+        });
+    }
+
+    let start = parse(start)?;
+    let end = if end.is_empty() {
+        None
+    } else {
+        Some(parse(end)?)
+    };
+    if end.map_or(false, |end| end < start) {
+        return Err(RangeError::Invalid);
+    }
+    if start >= total_len {
+        return Err(unsatisfiable);
+    }
+
+    Ok(ByteRange {
+        start,
+        end: end.map_or(total_len - 1, |end| end.min(total_len - 1)),
+    })
+}
+
+/// A `Read` that returns at most the length of a range from a sub-reader.
+pub struct RangeReader<R> {
+    reader: R,
+    remaining: u64,
+}
+
+impl<R> RangeReader<R>
+where
+    R: Read,
+{
+    /// Builds a reader returning the next `len` bytes of `reader`.
+    pub(crate) fn new(reader: R, len: u64) -> RangeReader<R> {
+        RangeReader {
+            reader,
+            remaining: len,
+        }
+    }
+
+    /// Returns the sub-reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R> Read for RangeReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+
+        let buf = if (buf.len() as u64) < self.remaining {
+            buf
+        } else {
+            &mut buf[..self.remaining as usize]
+        };
+
+        let len = self.reader.read(buf)?;
+        self.remaining -= len as u64;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_byte_range, ByteRange, RangeError};
+    use crate::{Header, Response};
+    use std::io::{Cursor, Read};
+    use std::str::FromStr;
+
+    const BLOB: &[u8] = b"0123456789abcdefghij";
+
+    fn range(value: &str) -> Header {
+        Header::from_str(&format!("Range: {}", value)).unwrap()
+    }
+
+    fn body<R: Read>(response: Response<R>) -> Vec<u8> {
+        let mut body = Vec::new();
+        response.into_reader().read_to_end(&mut body).unwrap();
+        body
+    }
+
+    fn header<R: Read>(response: &Response<R>, name: &'static str) -> Option<String> {
+        response
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.to_string())
+    }
+
+    #[test]
+    fn parse_ranges() {
+        let parse = |value| parse_byte_range(value, 20);
+        assert_eq!(parse("bytes=0-4").unwrap(), ByteRange { start: 0, end: 4 });
+        assert_eq!(
+            parse("bytes=15-").unwrap(),
+            ByteRange { start: 15, end: 19 }
+        );
+        assert_eq!(parse("bytes=-5").unwrap(), ByteRange { start: 15, end: 19 });
+        assert_eq!(parse("bytes=-50").unwrap(), ByteRange { start: 0, end: 19 });
+        assert_eq!(
+            parse("bytes=10-100").unwrap(),
+            ByteRange { start: 10, end: 19 }
+        );
+
+        assert!(matches!(parse("bytes=5-2"), Err(RangeError::Invalid)));
+        assert!(matches!(parse("bytes=0-1,5-6"), Err(RangeError::Invalid)));
+        assert!(matches!(parse("items=0-1"), Err(RangeError::Invalid)));
+        assert!(matches!(parse("bytes=a-"), Err(RangeError::Invalid)));
+        assert!(matches!(
+            parse("bytes=20-"),
+            Err(RangeError::Unsatisfiable { total_len: 20 })
+        ));
+        assert!(matches!(
+            parse("bytes=-0"),
+            Err(RangeError::Unsatisfiable { total_len: 20 })
+        ));
+    }
+
+    #[test]
+    fn open_ended_range() {
+        let response = Response::from_data(BLOB)
+            .with_byte_range(&range("bytes=12-"), 20)
+            .unwrap();
+        assert_eq!(response.status_code().0, 206);
+        assert_eq!(response.data_length(), Some(8));
+        assert_eq!(
+            header(&response, "Content-Range").as_deref(),
+            Some("bytes 12-19/20")
+        );
+        assert_eq!(body(response), &BLOB[12..]);
+    }
+
+    #[test]
+    fn suffix_range() {
+        let response = Response::from_data(BLOB)
+            .with_byte_range(&range("bytes=-3"), 20)
+            .unwrap();
+        assert_eq!(
+            header(&response, "Content-Range").as_deref(),
+            Some("bytes 17-19/20")
+        );
+        assert_eq!(body(response), &BLOB[17..]);
+    }
+
+    #[test]
+    fn exact_end_range() {
+        let response = Response::from_data(BLOB)
+            .with_byte_range(&range("bytes=5-19"), 20)
+            .unwrap();
+        assert_eq!(response.data_length(), Some(15));
+        assert_eq!(body(response), &BLOB[5..]);
+
+        let response = Response::from_data(BLOB)
+            .with_byte_range(&range("bytes=19-19"), 20)
+            .unwrap();
+        assert_eq!(body(response), b"j");
+    }
+
+    #[test]
+    fn unsatisfiable_range() {
+        let err = match Response::from_data(BLOB).with_byte_range(&range("bytes=20-"), 20) {
+            Err(err) => err,
+            Ok(_) => panic!("range should be unsatisfiable"),
+        };
+        let response = err.response().unwrap();
+        assert_eq!(response.status_code().0, 416);
+        assert_eq!(
+            header(&response, "Content-Range").as_deref(),
+            Some("bytes */20")
+        );
+    }
+
+    #[test]
+    fn range_by_skip() {
+        // a reader that can't seek
+        let reader = Cursor::new(BLOB).chain(&b""[..]);
+        let response = Response::new(200.into(), vec![], reader, Some(20), None)
+            .with_byte_range_by_skip(&range("bytes=4-7"), 20)
+            .unwrap();
+        assert_eq!(response.data_length(), Some(4));
+        assert_eq!(body(response), b"4567");
+    }
+}
@@ -1,15 +1,26 @@
-use crate::common::{HTTPVersion, Header, StatusCode};
-use httpdate::HttpDate;
+use crate::auth;
+use crate::common::{self, Cookie, HTTPDate, HTTPVersion, Header, HeaderField, StatusCode};
+use crate::conditional;
+use crate::config::ServerConfigAdvanced;
+use crate::fadvise::{FileAccessHint, FileHints};
+use crate::mime::mime_for_extension;
+use crate::range::{parse_byte_range, ByteRange, RangeError, RangeReader};
+use crate::{Method, Request};
 use std::cmp::Ordering;
-use std::sync::mpsc::Receiver;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender};
 
 use std::io::Result as IoResult;
-use std::io::{self, Cursor, Read, Write};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
 
-use std::fs::File;
+use std::fs::{self, File};
+use std::path::Path;
 
 use std::str::FromStr;
-use std::time::SystemTime;
+use std::time::{Duration, Instant};
 
 /// Object representing an HTTP response whose purpose is to be given to a `Request`.
 ///
@@ -24,18 +35,23 @@ use std::time::SystemTime;
 /// Some headers have special behaviors:
 ///
 ///  - `Content-Encoding`: If you define this header, the library
-///     will assume that the data from the `Read` object has the specified encoding
-///     and will just pass-through.
+///    will assume that the data from the `Read` object has the specified encoding
+///    and will just pass-through.
 ///
 ///  - `Content-Length`: The length of the data should be set manually
-///     using the `Reponse` object's API. Attempting to set the value of this
-///     header will be equivalent to modifying the size of the data but the header
-///     itself may not be present in the final result.
+///    using the `Reponse` object's API. Attempting to set the value of this
+///    header will be equivalent to modifying the size of the data but the header
+///    itself may not be present in the final result.
 ///
-///  - `Content-Type`: You may only set this header to one value at a time. If you
-///     try to set it more than once, the existing value will be overwritten. This
-///     behavior differs from the default for most headers, which is to allow them to
-///     be set multiple times in the same response.
+///  - `Content-Type`, `Date` and `Location`: You may only set these headers to one value at a
+///    time. If you try to set one of them more than once, the existing value will be
+///    overwritten. This behavior differs from the default for most headers, which is to allow
+///    them to be set multiple times in the same response; use `set_header` to replace them.
+///    The `Content-Type` of a response built by `MultipartResponse` can't be changed, as it
+///    carries the boundary of the parts.
+///
+///  - `Vary`: The values of all the `Vary` headers are merged with the fields added with
+///    `add_vary`, and sent as a single header.
 ///
 pub struct Response<R> {
     reader: R,
@@ -43,15 +59,27 @@ pub struct Response<R> {
     headers: Vec<Header>,
     data_length: Option<usize>,
     chunked_threshold: Option<usize>,
+    // set by `with_identity_transfer` and `with_forced_chunked`
+    transfer_encoding: Option<TransferEncoding>,
+    file_hints: Option<FileHints>,
+    vary: Vec<String>,
+    // true if `Content-Type` can't be changed anymore
+    content_type_locked: bool,
+    // false if set by `without_default_headers`
+    default_headers: bool,
+    trailers: Trailers,
 }
 
 /// A `Response` without a template parameter.
 pub type ResponseBox = Response<Box<dyn Read + Send>>;
 
+/// Headers replacing the previous header with the same name when added to a response.
+const SINGLE_VALUED_HEADERS: [&str; 3] = ["Content-Type", "Date", "Location"];
+
 /// Transfer encoding to use when sending the message.
 /// Note that only *supported* encoding are listed here.
 #[derive(Copy, Clone)]
-enum TransferEncoding {
+pub(crate) enum TransferEncoding {
     Identity,
     Chunked,
 }
@@ -70,24 +98,62 @@ impl FromStr for TransferEncoding {
     }
 }
 
+/// Informations about the request being answered and the server answering it, used to decide
+/// how a response is written.
+pub(crate) struct PrintContext<'a> {
+    pub(crate) http_version: HTTPVersion,
+    pub(crate) request_headers: &'a [Header],
+    pub(crate) do_not_send_body: bool,
+    pub(crate) upgrade: Option<&'a str>,
+    // true if the response must be framed as for HTTP 1.0 and close the connection
+    pub(crate) legacy_client: bool,
+    // true if the connection is closed after the response
+    pub(crate) last_on_connection: bool,
+    // true if the request was made through HTTPS
+    pub(crate) secure: bool,
+    pub(crate) config: &'a ServerConfigAdvanced,
+}
+
+impl PrintContext<'_> {
+    /// Returns the version deciding how the body is framed.
+    fn framing_version(&self) -> HTTPVersion {
+        if self.legacy_client {
+            HTTPVersion(1, 0)
+        } else {
+            self.http_version.clone()
+        }
+    }
+}
+
 /// Builds a Date: header with the current date.
 fn build_date_header() -> Header {
-    let d = HttpDate::from(SystemTime::now());
+    let d = HTTPDate::now();
     Header::from_bytes(&b"Date"[..], &d.to_string().into_bytes()[..]).unwrap()
 }
 
+/// Response sent instead of a response whose head is too large, see `RespondError`.
+const HEAD_TOO_LARGE_RESPONSE: &[u8] =
+    b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n";
+
+/// Writes the status line and the headers, and returns their size.
+///
+/// The head is checked against the limits of `config` before anything is written. If it
+/// exceeds them, an empty 500 response is written instead and a `RespondError` is returned.
 fn write_message_header<W>(
     mut writer: W,
     http_version: &HTTPVersion,
     status_code: &StatusCode,
     headers: &[Header],
-) -> IoResult<()>
+    config: &ServerConfigAdvanced,
+) -> IoResult<usize>
 where
     W: Write,
 {
+    let mut head = Vec::with_capacity(256);
+
     // writing status line
     write!(
-        &mut writer,
+        &mut head,
         "HTTP/{}.{} {} {}\r\n",
         http_version.0,
         http_version.1,
@@ -97,18 +163,125 @@ where
 
     // writing headers
     for header in headers.iter() {
-        writer.write_all(header.field.as_str().as_ref())?;
-        write!(&mut writer, ": ")?;
-        writer.write_all(header.value.as_str().as_ref())?;
-        write!(&mut writer, "\r\n")?;
+        if header.value.len() > config.max_response_header_value_len {
+            writer.write_all(HEAD_TOO_LARGE_RESPONSE)?;
+            return Err(RespondError::HeaderValueTooLong {
+                name: header.field.to_string(),
+                len: header.value.len(),
+            }
+            .into());
+        }
+        head.extend_from_slice(header.field.as_str().as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(header.value.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    if let Some(ref static_headers) = config.static_response_headers {
+        static_headers.write_to(&mut head, headers)?;
     }
 
     // separator between header and data
-    write!(&mut writer, "\r\n")?;
+    head.extend_from_slice(b"\r\n");
 
-    Ok(())
+    if head.len() > config.max_response_header_size {
+        writer.write_all(HEAD_TOO_LARGE_RESPONSE)?;
+        return Err(RespondError::HeadersTooLarge { size: head.len() }.into());
+    }
+    writer.write_all(&head)?;
+    Ok(head.len())
+}
+
+/// Error sending a response whose head is too large, returned in an `io::Error` of kind
+/// `InvalidData` by `Request::respond()` and the other functions sending a response.
+///
+/// Nothing of the response is sent; the client receives an empty `500 Internal Server Error`
+/// response instead. The limits are set with
+/// `ServerConfigAdvanced::with_max_response_header_size` and
+/// `ServerConfigAdvanced::with_max_response_header_value_len`, and include the headers added
+/// by tiny-http, such as `Date` and `Content-Length`.
+///
+/// ```no_run
+/// # let server = tiny_http::Server::http("0.0.0.0:0").unwrap();
+/// # let request = server.recv().unwrap();
+/// # let response = tiny_http::Response::empty(200);
+/// if let Err(err) = request.respond(response) {
+///     if let Some(tiny_http::RespondError::HeadersTooLarge { size }) =
+///         tiny_http::RespondError::from_io_error(&err)
+///     {
+///         eprintln!("generated {} bytes of headers", size);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RespondError {
+    /// The status line and the headers are `size` bytes long, separator included.
+    HeadersTooLarge {
+        /// Size of the head in bytes.
+        size: usize,
+    },
+    /// The value of the header `name` is `len` bytes long.
+    HeaderValueTooLong {
+        /// Name of the header.
+        name: String,
+        /// Length of the value in bytes.
+        len: usize,
+    },
+    /// A response was already sent for this request, or its writer was taken away.
+    AlreadyResponded,
+}
+
+impl RespondError {
+    /// Returns the `RespondError` carried by an error returned when sending a response, if
+    /// there is one.
+    pub fn from_io_error(err: &io::Error) -> Option<&RespondError> {
+        err.get_ref()?.downcast_ref()
+    }
 }
 
+impl fmt::Display for RespondError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RespondError::HeadersTooLarge { size } => {
+                write!(
+                    f,
+                    "The headers of the response are too large ({} bytes)",
+                    size
+                )
+            }
+            RespondError::HeaderValueTooLong { name, len } => write!(
+                f,
+                "The value of the {} header of the response is too long ({} bytes)",
+                name, len
+            ),
+            RespondError::AlreadyResponded => {
+                write!(f, "A response was already sent for this request")
+            }
+        }
+    }
+}
+
+impl Error for RespondError {}
+
+impl From<RespondError> for io::Error {
+    fn from(err: RespondError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Error returned by `Response::redirect()` and `Response::redirect_with()` when the location
+/// contains a line break.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidLocation;
+
+impl fmt::Display for InvalidLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The redirection location contains a line break")
+    }
+}
+
+impl Error for InvalidLocation {}
+
 fn choose_transfer_encoding(
     status_code: StatusCode,
     request_headers: &[Header],
@@ -116,6 +289,7 @@ fn choose_transfer_encoding(
     entity_length: &Option<usize>,
     has_additional_headers: bool,
     chunked_threshold: usize,
+    forced: Option<TransferEncoding>,
 ) -> TransferEncoding {
     use crate::util;
 
@@ -131,6 +305,11 @@ fn choose_transfer_encoding(
         return TransferEncoding::Identity;
     }
 
+    // the choice of the application overrides everything else
+    if let Some(forced) = forced {
+        return forced;
+    }
+
     // parsing the request's TE header
     let user_request = request_headers
         .iter()
@@ -183,14 +362,399 @@ fn choose_transfer_encoding(
     TransferEncoding::Identity
 }
 
+/// Returns true for the status codes 1xx, 204 and 304, which MUST not include a body.
+fn status_forbids_body(status_code: StatusCode) -> bool {
+    matches!(status_code.0, 100..=199 | 204 | 304)
+}
+
+/// Adds the headers that the server sends with every response, unless the response already
+/// defines them.
+fn add_server_headers(
+    headers: &mut Vec<Header>,
+    vary: &[String],
+    ctx: &PrintContext<'_>,
+    has_body: bool,
+    default_headers: bool,
+) {
+    // add `Date` if not in the headers
+    if !headers.iter().any(|h| h.field.equiv("Date")) {
+        headers.insert(0, build_date_header());
+    }
+
+    let static_headers = ctx.config.static_response_headers.as_deref();
+
+    // add `Server` if not in the headers
+    if !headers.iter().any(|h| h.field.equiv("Server"))
+        && !static_headers.map_or(false, |s| s.contains("Server"))
+    {
+        headers.insert(
+            0,
+            Header::from_bytes(&b"Server"[..], &b"tiny-http (Rust)"[..]).unwrap(),
+        );
+    }
+
+    if !vary.is_empty() {
+        if let Ok(vary) = Header::from_bytes(&b"Vary"[..], vary.join(", ").as_bytes()) {
+            headers.push(vary);
+        }
+    }
+
+    // add the security headers that the response doesn't define
+    if let (true, Some(security_headers)) = (default_headers, &ctx.config.security_headers) {
+        for header in security_headers.headers(ctx.secure) {
+            if !headers.iter().any(|h| h.field == header.field) {
+                headers.push(header.clone());
+            }
+        }
+    }
+
+    // add the default `Content-Type` if the response has a body but no type
+    if let (true, Some(content_type)) = (default_headers, &ctx.config.default_content_type) {
+        if has_body && !headers.iter().any(|h| h.field.equiv("Content-Type")) {
+            headers.push(content_type.clone());
+        }
+    }
+
+    // the connection of a legacy client is closed after each response, and any connection
+    // after its last request
+    let closes = headers.iter().any(|h| {
+        h.field.equiv("Connection")
+            && h.value
+                .as_str()
+                .split(',')
+                .any(|option| option.trim().eq_ignore_ascii_case("close"))
+    });
+    if (ctx.legacy_client || ctx.last_on_connection) && ctx.upgrade.is_none() && !closes {
+        headers.push(Header::from_bytes(&b"Connection"[..], &b"close"[..]).unwrap());
+    }
+}
+
+/// Headers sent after the last chunk of the body, see `Response::trailer_sender()`.
+#[derive(Default)]
+pub(crate) struct Trailers {
+    // channels passed to `Response::new` or created by `Response::trailer_sender`
+    receivers: Vec<Receiver<Header>>,
+    // kept to hand out more senders, dropped before waiting for the trailers
+    sender: Option<Sender<Header>>,
+    // how long to wait for the trailers after the last chunk
+    grace: Duration,
+    // names sent in the `Trailer` header, see `Response::with_announced_trailer`
+    announced: Vec<HeaderField>,
+}
+
+impl Trailers {
+    /// Returns a sender of the channel created by the first call.
+    fn sender(&mut self) -> Sender<Header> {
+        if let Some(ref sender) = self.sender {
+            return sender.clone();
+        }
+        let (sender, receiver) = mpsc::channel();
+        self.receivers.push(receiver);
+        self.sender = Some(sender.clone());
+        sender
+    }
+
+    fn are_expected(&self) -> bool {
+        !self.receivers.is_empty()
+    }
+
+    /// Returns the `Trailer` header announcing the trailers, if any.
+    fn announcement(&self) -> Option<Header> {
+        if self.announced.is_empty() {
+            return None;
+        }
+        let names: Vec<_> = self
+            .announced
+            .iter()
+            .map(|name| name.as_str().as_str())
+            .collect();
+        Some(Header::from_bytes(&b"Trailer"[..], names.join(", ").as_bytes()).unwrap())
+    }
+
+    /// Takes the trailers that are already queued, without waiting for the others, to send
+    /// them as headers when the body isn't chunked.
+    fn take_queued(&self) -> Vec<Header> {
+        self.receivers
+            .iter()
+            .flat_map(|receiver| receiver.try_iter())
+            .filter(|trailer| !is_forbidden_trailer(trailer))
+            .collect()
+    }
+
+    /// Takes the trailers that are already queued, then waits for the others until the
+    /// grace period expires or all the senders are dropped. The channels are closed
+    /// afterwards, so that sending fails instead of blocking.
+    fn collect(self) -> Vec<Header> {
+        let Trailers {
+            receivers,
+            sender,
+            grace,
+            ..
+        } = self;
+        drop(sender);
+
+        let deadline = Instant::now() + grace;
+        let mut trailers = Vec::new();
+        for receiver in receivers {
+            loop {
+                let trailer = match deadline.checked_duration_since(Instant::now()) {
+                    Some(left) if left > Duration::from_secs(0) => receiver.recv_timeout(left).ok(),
+                    _ => receiver.try_recv().ok(),
+                };
+                match trailer {
+                    Some(trailer) if is_forbidden_trailer(&trailer) => {}
+                    Some(trailer) => trailers.push(trailer),
+                    None => break,
+                }
+            }
+        }
+        trailers
+    }
+}
+
+/// Returns true if `trailer` would change the framing of a message that is already sent.
+fn is_forbidden_trailer(trailer: &Header) -> bool {
+    trailer.field.equiv("Connection")
+        || trailer.field.equiv("Content-Length")
+        || trailer.field.equiv("Trailer")
+        || trailer.field.equiv("Transfer-Encoding")
+        || trailer.field.equiv("Upgrade")
+}
+
+/// Writes the data of `reader` as chunks, without the last chunk, and returns its length.
+fn write_chunks<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> IoResult<u64> {
+    let mut buf = vec![0; 8192];
+    let mut written = 0;
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => return Ok(written),
+            Ok(len) => len,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        write!(writer, "{:x}\r\n", len)?;
+        writer.write_all(&buf[..len])?;
+        writer.write_all(b"\r\n")?;
+        written += len as u64;
+    }
+}
+
+/// Writes the last chunk, followed by `trailers`.
+fn write_last_chunk<W: Write>(writer: &mut W, trailers: &[Header]) -> IoResult<()> {
+    let mut last = Vec::with_capacity(64);
+    last.extend_from_slice(b"0\r\n");
+    for trailer in trailers {
+        last.extend_from_slice(trailer.field.as_str().as_bytes());
+        last.extend_from_slice(b": ");
+        last.extend_from_slice(trailer.value.as_bytes());
+        last.extend_from_slice(b"\r\n");
+    }
+    last.extend_from_slice(b"\r\n");
+    writer.write_all(&last)
+}
+
+/// How the body following a head must be framed, see `ResponsePlan` and `Response::write_head`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum BodyFraming {
+    /// The body must not be sent.
+    Discard,
+    /// Exactly this many bytes must be sent.
+    Length(u64),
+    /// The body must be sent with the chunked encoding.
+    Chunked,
+    /// The body ends when the connection is closed.
+    UntilClose,
+}
+
+/// The parts of a response deciding how it is sent, see `plan_response`.
+pub(crate) struct ResponseSpec<'a> {
+    pub(crate) status_code: StatusCode,
+    pub(crate) headers: Vec<Header>,
+    pub(crate) vary: &'a [String],
+    pub(crate) data_length: Option<usize>,
+    pub(crate) chunked_threshold: usize,
+    // the transfer encoding chosen by the application, if any
+    pub(crate) transfer_encoding: Option<TransferEncoding>,
+    // true if trailers may follow the body
+    pub(crate) has_trailers: bool,
+    // the `Trailer` header sent if the trailers follow the body
+    pub(crate) trailer_announcement: Option<Header>,
+    // false if the default `Content-Type` and the security headers must not be added
+    pub(crate) default_headers: bool,
+}
+
+/// Everything decided about how a response is sent, written by `write_head` and `write_body`.
+#[derive(Debug)]
+pub(crate) struct ResponsePlan<'a> {
+    pub(crate) http_version: HTTPVersion,
+    pub(crate) status_code: StatusCode,
+    /// The headers to send, including the ones added by the server and the framing headers.
+    pub(crate) headers: Vec<Header>,
+    pub(crate) body: BodyFraming,
+    // true if the trailers are sent after the last chunk
+    pub(crate) trailers: bool,
+    pub(crate) config: &'a ServerConfigAdvanced,
+}
+
+/// Returns the transfer encoding of a response, or `None` if its body isn't framed because
+/// the connection is upgraded.
+fn response_transfer_encoding(
+    spec: &ResponseSpec<'_>,
+    ctx: &PrintContext<'_>,
+) -> Option<TransferEncoding> {
+    if ctx.upgrade.is_some() {
+        return None;
+    }
+    Some(choose_transfer_encoding(
+        spec.status_code,
+        ctx.request_headers,
+        &ctx.framing_version(),
+        &spec.data_length,
+        spec.has_trailers,
+        spec.chunked_threshold,
+        spec.transfer_encoding,
+    ))
+}
+
+/// Returns true if the body must be read to learn its length before the response is planned.
+///
+/// This is the case when the body is sent without framing, which never depends on the length
+/// itself, so the plan made with the length of the buffered body is the same.
+pub(crate) fn needs_buffered_length(spec: &ResponseSpec<'_>, ctx: &PrintContext<'_>) -> bool {
+    spec.data_length.is_none()
+        && matches!(
+            response_transfer_encoding(spec, ctx),
+            Some(TransferEncoding::Identity)
+        )
+}
+
+/// Decides the final headers of a response and how its body is framed, without any IO.
+///
+/// # Panics
+///
+/// Panics if the length of the body is unknown while `needs_buffered_length()` is true.
+pub(crate) fn plan_response<'a>(
+    spec: ResponseSpec<'_>,
+    ctx: &PrintContext<'a>,
+) -> ResponsePlan<'a> {
+    let transfer_encoding = response_transfer_encoding(&spec, ctx);
+    let ResponseSpec {
+        status_code,
+        mut headers,
+        vary,
+        data_length,
+        has_trailers,
+        trailer_announcement,
+        default_headers,
+        ..
+    } = spec;
+
+    let status_forbids_body = status_forbids_body(status_code);
+    add_server_headers(
+        &mut headers,
+        vary,
+        ctx,
+        !status_forbids_body && data_length != Some(0),
+        default_headers,
+    );
+
+    if let Some(upgrade) = ctx.upgrade {
+        headers.insert(
+            0,
+            Header::from_bytes(&b"Upgrade"[..], upgrade.as_bytes()).unwrap(),
+        );
+        headers.insert(
+            0,
+            Header::from_bytes(&b"Connection"[..], &b"upgrade"[..]).unwrap(),
+        );
+    }
+
+    // preparing headers for transfer
+    let body = match transfer_encoding {
+        Some(TransferEncoding::Chunked) => {
+            headers.push(Header::from_bytes(&b"Transfer-Encoding"[..], &b"chunked"[..]).unwrap());
+            BodyFraming::Chunked
+        }
+        Some(TransferEncoding::Identity) => {
+            let data_length = data_length.expect("The length of the body must be known");
+            headers.push(
+                Header::from_bytes(
+                    &b"Content-Length"[..],
+                    format!("{}", data_length).as_bytes(),
+                )
+                .unwrap(),
+            );
+            BodyFraming::Length(data_length as u64)
+        }
+        None => BodyFraming::Discard,
+    };
+
+    let trailers = has_trailers && body == BodyFraming::Chunked;
+    if let (true, Some(announcement)) = (trailers, trailer_announcement) {
+        headers.push(announcement);
+    }
+
+    // checking whether to ignore the body of the response
+    let body = if ctx.do_not_send_body || status_forbids_body {
+        BodyFraming::Discard
+    } else {
+        body
+    };
+
+    ResponsePlan {
+        http_version: ctx.http_version.clone(),
+        status_code,
+        headers,
+        body,
+        trailers: trailers && body == BodyFraming::Chunked,
+        config: ctx.config,
+    }
+}
+
+/// Writes the status line and the headers of `plan`, and returns the size of the head.
+pub(crate) fn write_head<W: Write>(writer: W, plan: &ResponsePlan<'_>) -> IoResult<usize> {
+    write_message_header(
+        writer,
+        &plan.http_version,
+        &plan.status_code,
+        &plan.headers,
+        plan.config,
+    )
+}
+
+/// Writes the body read from `reader` as planned, followed by `trailers` if `plan` expects
+/// them, and returns the number of bytes of the body.
+pub(crate) fn write_body<R: Read, W: Write>(
+    mut writer: W,
+    plan: &ResponsePlan<'_>,
+    mut reader: R,
+    trailers: Trailers,
+) -> IoResult<u64> {
+    match plan.body {
+        BodyFraming::Discard | BodyFraming::Length(0) => Ok(0),
+        BodyFraming::Chunked if plan.trailers => {
+            let written = write_chunks(&mut reader, &mut writer)?;
+            write_last_chunk(&mut writer, &trailers.collect())?;
+            Ok(written)
+        }
+        BodyFraming::Chunked => {
+            use chunked_transfer::Encoder;
+
+            let mut writer = Encoder::new(writer);
+            io::copy(&mut reader, &mut writer)
+        }
+        BodyFraming::Length(_) | BodyFraming::UntilClose => io::copy(&mut reader, &mut writer),
+    }
+}
+
 impl<R> Response<R>
 where
     R: Read,
 {
     /// Creates a new Response object.
     ///
-    /// The `additional_headers` argument is a receiver that
-    ///  may provide headers even after the response has been sent.
+    /// The `additional_headers` argument is a receiver of headers sent as trailers after the
+    /// body, or as headers if the body isn't chunked, see `trailer_sender()`.
     ///
     /// All the other arguments are straight-forward.
     pub fn new(
@@ -206,17 +770,20 @@ where
             headers: Vec::with_capacity(16),
             data_length,
             chunked_threshold: None,
+            transfer_encoding: None,
+            file_hints: None,
+            vary: Vec::new(),
+            content_type_locked: false,
+            default_headers: true,
+            trailers: Trailers::default(),
         };
 
         for h in headers {
             response.add_header(h)
         }
 
-        // dummy implementation
         if let Some(additional_headers) = additional_headers {
-            for h in additional_headers.iter() {
-                response.add_header(h)
-            }
+            response.trailers.receivers.push(additional_headers);
         }
 
         response
@@ -226,11 +793,85 @@ where
     /// transfer. Notice that chunked transfer might happen regardless of
     /// this threshold, for instance when the request headers indicate
     /// it is wanted or when there is no `Content-Length`.
+    ///
+    /// A body of unknown length is always sent with the chunked transfer encoding to HTTP/1.1
+    /// clients, unless `with_identity_transfer()` is used. The threshold is ignored once
+    /// `with_identity_transfer()` or `with_forced_chunked()` is used.
     pub fn with_chunked_threshold(mut self, length: usize) -> Response<R> {
         self.chunked_threshold = Some(length);
         self
     }
 
+    /// Sends the body with a `Content-Length` header, whatever its length, for the clients
+    /// that require one. This overrides `with_forced_chunked()`.
+    ///
+    /// A body of unknown length is read entirely into memory before the response is sent, to
+    /// learn its length. The trailers are discarded, as they can only follow a chunked body.
+    pub fn with_identity_transfer(mut self) -> Response<R> {
+        self.transfer_encoding = Some(TransferEncoding::Identity);
+        self
+    }
+
+    /// Sends the body with the chunked transfer encoding, even if its length is known. This
+    /// overrides `with_identity_transfer()`.
+    ///
+    /// HTTP/1.0 clients don't support it: their responses are still sent with a
+    /// `Content-Length`, as when the body is of unknown length. The responses whose status code
+    /// forbids a `Transfer-Encoding`, `1xx` and `204`, are sent without one either.
+    pub fn with_forced_chunked(mut self) -> Response<R> {
+        self.transfer_encoding = Some(TransferEncoding::Chunked);
+        self
+    }
+
+    /// Sends the response without the default `Content-Type` and the security headers
+    /// configured on the server, for example for a body whose type must stay unknown.
+    pub fn without_default_headers(mut self) -> Response<R> {
+        self.default_headers = false;
+        self
+    }
+
+    /// Returns a sender of trailers, headers sent after the last chunk of the body. The
+    /// channel is created by the first call, the next calls return senders of the same channel.
+    ///
+    /// A response expecting trailers is sent with the chunked encoding, unless the client
+    /// doesn't support it or `with_identity_transfer()` is used. The trailers already queued
+    /// when the head of such a response is sent are then added to its headers, and the
+    /// others are discarded. Otherwise, the trailers queued
+    /// before the last chunk are always sent. The server then waits for the others during
+    /// the grace period set with `with_trailer_grace()`, and closes the channel: sending
+    /// fails once the response is complete, so that a sender never blocks the response.
+    ///
+    /// `Connection`, `Content-Length`, `Trailer`, `Transfer-Encoding` and `Upgrade` trailers
+    /// are ignored.
+    pub fn trailer_sender(&mut self) -> Sender<Header> {
+        self.trailers.sender()
+    }
+
+    /// Announces the trailer `name` in a `Trailer` header sent before the body, so that the
+    /// client can tell which trailers to expect. The header is only sent if the response is
+    /// sent with trailers, see `trailer_sender()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid header name.
+    pub fn with_announced_trailer(mut self, name: &str) -> Response<R> {
+        let name = HeaderField::from_str(name)
+            .ok()
+            .filter(|_| !name.is_empty())
+            .expect("invalid trailer name");
+        if !self.trailers.announced.contains(&name) {
+            self.trailers.announced.push(name);
+        }
+        self
+    }
+
+    /// Sets how long the server waits for trailers after the last chunk of the body, see
+    /// `trailer_sender()`. The default is zero, sending only the trailers already queued.
+    pub fn with_trailer_grace(mut self, grace: Duration) -> Response<R> {
+        self.trailers.grace = grace;
+        self
+    }
+
     /// Convert the response into the underlying `Read` type.
     ///
     /// This is mainly useful for testing as it must consume the `Response`.
@@ -270,14 +911,22 @@ where
             }
 
             return;
-        // if the header is Content-Type and it's already set, overwrite it
-        } else if header.field.equiv("Content-Type") {
-            if let Some(content_type_header) = self
-                .headers
-                .iter_mut()
-                .find(|h| h.field.equiv("Content-Type"))
-            {
-                content_type_header.value = header.value;
+        // if the header is Vary, merging its fields with the other ones
+        } else if header.field.equiv("Vary") {
+            for field in header.value.as_str().split(',') {
+                self.add_vary(field);
+            }
+            return;
+        // if the header can only be set once and it's already set, overwrite it
+        } else if SINGLE_VALUED_HEADERS
+            .iter()
+            .any(|name| header.field.equiv(name))
+        {
+            if self.content_type_locked && header.field.equiv("Content-Type") {
+                return;
+            }
+            if let Some(existing) = self.headers.iter_mut().find(|h| h.field == header.field) {
+                existing.value = header.value;
                 return;
             }
         }
@@ -285,6 +934,72 @@ where
         self.headers.push(header);
     }
 
+    /// Adds a header, replacing all the headers with the same name, with the same checks as
+    /// `add_header`.
+    pub fn set_header<H>(&mut self, header: H)
+    where
+        H: Into<Header>,
+    {
+        let header = header.into();
+        self.remove_header(header.field.as_str().as_str());
+        self.add_header(header);
+    }
+
+    /// Returns the same response, with `header` replacing all the headers with the same
+    /// name, see `set_header`.
+    pub fn with_header_replaced<H>(mut self, header: H) -> Response<R>
+    where
+        H: Into<Header>,
+    {
+        self.set_header(header);
+        self
+    }
+
+    /// Removes all the headers named `field`, for example to remove the `Content-Type` set by
+    /// `from_string`. Removing `Vary` also removes the fields added with `add_vary`.
+    ///
+    /// The length of the body is kept when removing `Content-Length`, and the `Content-Type`
+    /// of a `MultipartResponse` can't be removed.
+    pub fn remove_header(&mut self, field: &str) {
+        if field.eq_ignore_ascii_case("Vary") {
+            self.vary.clear();
+        } else if !(self.content_type_locked && field.eq_ignore_ascii_case("Content-Type")) {
+            self.headers
+                .retain(|h| !h.field.as_str().as_str().eq_ignore_ascii_case(field));
+        }
+    }
+
+    /// Ignores the `Content-Type` headers added from now on.
+    pub(crate) fn lock_content_type(&mut self) {
+        self.content_type_locked = true;
+    }
+
+    /// Adds a field to the `Vary` header, telling caches that the response depends on this
+    /// header of the request.
+    ///
+    /// The field is ignored if it is already present, regardless of its case. Adding `*`
+    /// replaces all the other fields.
+    pub fn add_vary(&mut self, field: &str) {
+        let field = field.trim();
+        if field.is_empty() || self.vary.iter().any(|f| f == "*") {
+            return;
+        }
+
+        if field == "*" {
+            self.vary.clear();
+        } else if self.vary.iter().any(|f| f.eq_ignore_ascii_case(field)) {
+            return;
+        }
+        self.vary.push(field.to_owned());
+    }
+
+    /// Returns the fields of the `Vary` header that will be sent with the response. The `Vary`
+    /// headers added with `add_header` are merged into these fields and aren't returned by
+    /// `headers()`.
+    pub fn vary_fields(&self) -> &[String] {
+        &self.vary
+    }
+
     /// Returns the same request, but with an additional header.
     ///
     /// Some headers cannot be modified and some other have a
@@ -298,6 +1013,68 @@ where
         self
     }
 
+    /// Adds a `Set-Cookie` header for `cookie`. Each cookie gets its own header.
+    pub fn add_cookie(&mut self, cookie: Cookie) {
+        self.add_header(cookie);
+    }
+
+    /// Returns the same response, but with an additional `Set-Cookie` header for `cookie`.
+    #[inline]
+    pub fn with_cookie(mut self, cookie: Cookie) -> Response<R> {
+        self.add_cookie(cookie);
+        self
+    }
+
+    /// Returns the same response with the status `401 Unauthorized` and a `WWW-Authenticate`
+    /// header asking for `Basic` credentials for `realm`, which is quoted as needed. The
+    /// control characters and non-ASCII characters of `realm` are percent-encoded.
+    pub fn with_www_authenticate(self, realm: &str) -> Response<R> {
+        self.with_status_code(401)
+            .with_header(auth::basic_challenge(realm))
+    }
+
+    /// Returns the same response with an `ETag` header, replacing any other one. Quotes are
+    /// added around `etag` unless it already is a quoted or weak (`W/"..."`) entity tag.
+    ///
+    /// The characters that can't be part of an entity tag, such as control characters,
+    /// spaces or non-ASCII characters, are percent-encoded, the same way as by
+    /// `Request::evaluate_preconditions()`.
+    pub fn with_etag(mut self, etag: &str) -> Response<R> {
+        let etag = conditional::quote_etag(etag);
+        // the entity tag is printable ASCII
+        self.set_header(Header::from_bytes(&b"ETag"[..], etag.as_bytes()).unwrap());
+        self
+    }
+
+    /// Returns the same response with a `Last-Modified` header, replacing any other one.
+    pub fn with_last_modified(mut self, date: HTTPDate) -> Response<R> {
+        self.set_header(
+            Header::from_bytes(&b"Last-Modified"[..], date.to_string().as_bytes()).unwrap(),
+        );
+        self
+    }
+
+    /// Returns a `304 Not Modified` response for the same resource, without a body, and with
+    /// the headers of this response that RFC 7232 §4.1 requires or allows to send with it:
+    /// `Cache-Control`, `Content-Location`, `Date`, `ETag`, `Expires`, `Last-Modified` and
+    /// `Vary`.
+    pub fn not_modified(self) -> Response<io::Empty> {
+        const KEPT: [&str; 7] = [
+            "Cache-Control",
+            "Content-Location",
+            "Date",
+            "ETag",
+            "Expires",
+            "Last-Modified",
+            "Vary",
+        ];
+        let mut response = self.with_data(io::empty(), None).with_status_code(304);
+        response
+            .headers
+            .retain(|h| KEPT.iter().any(|name| h.field.equiv(name)));
+        response
+    }
+
     /// Returns the same request, but with a different status code.
     #[inline]
     pub fn with_status_code<S>(mut self, code: S) -> Response<R>
@@ -308,6 +1085,17 @@ where
         self
     }
 
+    /// Returns the same response without its body, keeping its status code, its headers and
+    /// the length of the body, to answer a `HEAD` request for the same resource without
+    /// building the response twice.
+    ///
+    /// The response still announces the length of the original body, so it must only be used
+    /// to answer `HEAD` requests, for which tiny-http never sends a body.
+    pub fn head_only(self) -> Response<io::Empty> {
+        let data_length = self.data_length;
+        self.with_data(io::empty(), data_length)
+    }
+
     /// Returns the same request, but with different data.
     pub fn with_data<S>(self, reader: S, data_length: Option<usize>) -> Response<S>
     where
@@ -319,6 +1107,67 @@ where
             status_code: self.status_code,
             data_length,
             chunked_threshold: self.chunked_threshold,
+            transfer_encoding: self.transfer_encoding,
+            file_hints: None,
+            vary: self.vary,
+            content_type_locked: self.content_type_locked,
+            default_headers: self.default_headers,
+            trailers: self.trailers,
+        }
+    }
+
+    /// Same as `with_byte_range`, for readers that can't seek. The bytes before the range are
+    /// read and thrown away, which takes a time proportional to the start of the range.
+    pub fn with_byte_range_by_skip(
+        mut self,
+        range_header: &Header,
+        total_len: u64,
+    ) -> Result<Response<RangeReader<R>>, RangeError> {
+        let range = parse_byte_range(range_header.value.as_str(), total_len)?;
+        let skipped = io::copy(&mut self.reader.by_ref().take(range.start), &mut io::sink())?;
+        if skipped < range.start {
+            return Err(RangeError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(self.into_range(range, total_len))
+    }
+
+    fn into_range(self, range: ByteRange, total_len: u64) -> Response<RangeReader<R>> {
+        let mut response = self
+            .into_limited(range.len(), usize::try_from(range.len()).ok())
+            .with_status_code(206);
+        response.add_header(
+            Header::from_bytes(
+                &b"Content-Range"[..],
+                range.content_range(total_len).as_bytes(),
+            )
+            .unwrap(),
+        );
+        response
+    }
+
+    /// Returns the same response, with a body limited to its next `len` bytes.
+    fn into_limited(self, len: u64, data_length: Option<usize>) -> Response<RangeReader<R>> {
+        self.map_reader(|reader| RangeReader::new(reader, len), data_length)
+    }
+
+    /// Returns the same response, with its reader wrapped by `f`. Unlike `with_data`, the
+    /// file hints are kept, as the body still comes from the same file.
+    pub(crate) fn map_reader<S, F>(self, f: F, data_length: Option<usize>) -> Response<S>
+    where
+        F: FnOnce(R) -> S,
+    {
+        Response {
+            reader: f(self.reader),
+            status_code: self.status_code,
+            headers: self.headers,
+            data_length,
+            chunked_threshold: self.chunked_threshold,
+            transfer_encoding: self.transfer_encoding,
+            file_hints: self.file_hints,
+            vary: self.vary,
+            content_type_locked: self.content_type_locked,
+            default_headers: self.default_headers,
+            trailers: self.trailers,
         }
     }
 
@@ -332,125 +1181,132 @@ where
     ///
     /// Note: does not flush the writer.
     pub fn raw_print<W: Write>(
-        mut self,
-        mut writer: W,
+        self,
+        writer: W,
         http_version: HTTPVersion,
         request_headers: &[Header],
         do_not_send_body: bool,
         upgrade: Option<&str>,
     ) -> IoResult<()> {
-        let mut transfer_encoding = Some(choose_transfer_encoding(
-            self.status_code,
-            request_headers,
-            &http_version,
-            &self.data_length,
-            false, /* TODO */
-            self.chunked_threshold(),
-        ));
+        self.print(
+            writer,
+            &PrintContext {
+                http_version,
+                request_headers,
+                do_not_send_body,
+                upgrade,
+                legacy_client: false,
+                last_on_connection: false,
+                secure: false,
+                config: &ServerConfigAdvanced::default(),
+            },
+        )
+        .map(|_| ())
+    }
 
-        // add `Date` if not in the headers
-        if !self.headers.iter().any(|h| h.field.equiv("Date")) {
-            self.headers.insert(0, build_date_header());
-        }
+    /// Same as `raw_print`, but also applies the settings of the server, and returns the
+    /// number of bytes of the body that were written.
+    pub(crate) fn print<W: Write>(
+        mut self,
+        mut writer: W,
+        ctx: &PrintContext<'_>,
+    ) -> IoResult<u64> {
+        let trailers = mem::take(&mut self.trailers);
+        let mut spec = ResponseSpec {
+            status_code: self.status_code,
+            chunked_threshold: self.chunked_threshold(),
+            transfer_encoding: self.transfer_encoding,
+            headers: self.headers,
+            vary: &self.vary,
+            data_length: self.data_length,
+            has_trailers: trailers.are_expected(),
+            trailer_announcement: trailers.announcement(),
+            default_headers: self.default_headers,
+        };
 
-        // add `Server` if not in the headers
-        if !self.headers.iter().any(|h| h.field.equiv("Server")) {
-            self.headers.insert(
-                0,
-                Header::from_bytes(&b"Server"[..], &b"tiny-http (Rust)"[..]).unwrap(),
-            );
+        // if the transfer encoding is identity, the content length must be known ; therefore if
+        // we don't know it, we buffer the entire response first here
+        // while this is an expensive operation, it is only ever needed for clients using HTTP 1.0
+        let reader: Box<dyn Read> = if needs_buffered_length(&spec, ctx) {
+            let mut buf = Vec::new();
+            self.reader.read_to_end(&mut buf)?;
+            spec.data_length = Some(buf.len());
+            Box::new(Cursor::new(buf))
+        } else {
+            Box::new(self.reader)
+        };
+
+        let mut plan = plan_response(spec, ctx);
+        // without chunks, the trailers already queued are sent as headers instead
+        if trailers.are_expected()
+            && matches!(plan.body, BodyFraming::Length(_) | BodyFraming::UntilClose)
+        {
+            plan.headers.extend(trailers.take_queued());
         }
+        write_head(writer.by_ref(), &plan)?;
 
-        // handling upgrade
-        if let Some(upgrade) = upgrade {
-            self.headers.insert(
-                0,
-                Header::from_bytes(&b"Upgrade"[..], upgrade.as_bytes()).unwrap(),
-            );
-            self.headers.insert(
-                0,
-                Header::from_bytes(&b"Connection"[..], &b"upgrade"[..]).unwrap(),
-            );
-            transfer_encoding = None;
+        let mut body_bytes = 0;
+        if plan.body != BodyFraming::Discard {
+            if let Some(ref file_hints) = self.file_hints {
+                file_hints.before_send();
+            }
+            body_bytes = write_body(writer, &plan, reader, trailers)?;
+            if let Some(ref file_hints) = self.file_hints {
+                file_hints.after_send();
+            }
         }
 
-        // if the transfer encoding is identity, the content length must be known ; therefore if
-        // we don't know it, we buffer the entire response first here
-        // while this is an expensive operation, it is only ever needed for clients using HTTP 1.0
-        let (mut reader, data_length): (Box<dyn Read>, _) =
-            match (self.data_length, transfer_encoding) {
-                (Some(l), _) => (Box::new(self.reader), Some(l)),
-                (None, Some(TransferEncoding::Identity)) => {
-                    let mut buf = Vec::new();
-                    self.reader.read_to_end(&mut buf)?;
-                    let l = buf.len();
-                    (Box::new(Cursor::new(buf)), Some(l))
-                }
-                _ => (Box::new(self.reader), None),
-            };
-
-        // checking whether to ignore the body of the response
-        let do_not_send_body = do_not_send_body
-            || match self.status_code.0 {
-                // status code 1xx, 204 and 304 MUST not include a body
-                100..=199 | 204 | 304 => true,
-                _ => false,
-            };
-
-        // preparing headers for transfer
-        match transfer_encoding {
-            Some(TransferEncoding::Chunked) => self
-                .headers
-                .push(Header::from_bytes(&b"Transfer-Encoding"[..], &b"chunked"[..]).unwrap()),
-
-            Some(TransferEncoding::Identity) => {
-                assert!(data_length.is_some());
-                let data_length = data_length.unwrap();
+        Ok(body_bytes)
+    }
 
+    /// Writes the status line and the headers only, for a body of `body_length` bytes (or of
+    /// unknown length) that the caller writes with the returned framing.
+    pub(crate) fn write_head<W: Write>(
+        mut self,
+        writer: W,
+        ctx: &PrintContext<'_>,
+        body_length: Option<u64>,
+    ) -> IoResult<BodyFraming> {
+        let status_forbids_body = status_forbids_body(self.status_code);
+        add_server_headers(
+            &mut self.headers,
+            &self.vary,
+            ctx,
+            !status_forbids_body && body_length != Some(0),
+            self.default_headers,
+        );
+
+        let framing = match body_length {
+            _ if status_forbids_body => BodyFraming::Discard,
+            Some(length) => {
                 self.headers.push(
-                    Header::from_bytes(
-                        &b"Content-Length"[..],
-                        format!("{}", data_length).as_bytes(),
-                    )
-                    .unwrap(),
-                )
+                    Header::from_bytes(&b"Content-Length"[..], length.to_string().as_bytes())
+                        .unwrap(),
+                );
+                BodyFraming::Length(length)
+            }
+            // HTTP 1.0 doesn't support other encoding
+            None if ctx.framing_version() <= (1, 0) => BodyFraming::UntilClose,
+            None => {
+                self.headers
+                    .push(Header::from_bytes(&b"Transfer-Encoding"[..], &b"chunked"[..]).unwrap());
+                BodyFraming::Chunked
             }
-
-            _ => (),
         };
 
-        // sending headers
         write_message_header(
-            writer.by_ref(),
-            &http_version,
+            writer,
+            &ctx.http_version,
             &self.status_code,
             &self.headers,
+            ctx.config,
         )?;
 
-        // sending the body
-        if !do_not_send_body {
-            match transfer_encoding {
-                Some(TransferEncoding::Chunked) => {
-                    use chunked_transfer::Encoder;
-
-                    let mut writer = Encoder::new(writer);
-                    io::copy(&mut reader, &mut writer)?;
-                }
-
-                Some(TransferEncoding::Identity) => {
-                    assert!(data_length.is_some());
-                    let data_length = data_length.unwrap();
-
-                    if data_length >= 1 {
-                        io::copy(&mut reader, &mut writer)?;
-                    }
-                }
-
-                _ => (),
-            }
+        if ctx.do_not_send_body {
+            Ok(BodyFraming::Discard)
+        } else {
+            Ok(framing)
         }
-
-        Ok(())
     }
 
     /// Retrieves the current value of the `Response` status code
@@ -469,6 +1325,85 @@ where
     }
 }
 
+impl<R> Response<R>
+where
+    R: Read + Seek,
+{
+    /// Limits the body to the single range of bytes requested by the `Range` header of a
+    /// request, `total_len` being the length of the whole body.
+    ///
+    /// The reader is moved to the start of the range, and the response gets the status code
+    /// `206 Partial Content` and the matching `Content-Range` and `Content-Length` headers.
+    /// When the range starts after the end of the body, a `416 Range Not Satisfiable`
+    /// response can be built with `RangeError::response()`.
+    pub fn with_byte_range(
+        mut self,
+        range_header: &Header,
+        total_len: u64,
+    ) -> Result<Response<RangeReader<R>>, RangeError> {
+        let range = parse_byte_range(range_header.value.as_str(), total_len)?;
+        self.reader.seek(SeekFrom::Start(range.start))?;
+        Ok(self.into_range(range, total_len))
+    }
+
+    /// Answers the `Range` header of `request`, which this response is the whole resource
+    /// for.
+    ///
+    /// A `200 OK` response with a known length gets an `Accept-Ranges: bytes` header, and:
+    ///
+    ///  - If `request` is a `GET` or `HEAD` request asking for a single range of bytes, the
+    ///    body is limited to it as with `with_byte_range`.
+    ///  - If that range starts after the end of the body, the response is replaced with an empty
+    ///    `416 Range Not Satisfiable` response, with a `Content-Range: bytes */<length>`
+    ///    header.
+    ///  - Otherwise, such as for a request without `Range` header or asking for several ranges,
+    ///    the whole body is sent.
+    ///
+    /// The other responses are sent unchanged. Fails if the reader can't be moved to the start
+    /// of the range.
+    ///
+    /// ```no_run
+    /// # let server = tiny_http::Server::http("0.0.0.0:0").unwrap();
+    /// let request = server.recv().unwrap();
+    /// let response = tiny_http::Response::from_path("video.mp4").unwrap();
+    /// let response = response.with_range(&request).unwrap();
+    /// request.respond(response).unwrap();
+    /// ```
+    pub fn with_range(mut self, request: &Request) -> IoResult<Response<RangeReader<R>>> {
+        let total_len = match self.data_length {
+            Some(len) if self.status_code == StatusCode(200) => len as u64,
+            data_length => return Ok(self.into_limited(u64::MAX, data_length)),
+        };
+        self.add_header(Header::from_bytes(&b"Accept-Ranges"[..], &b"bytes"[..]).unwrap());
+
+        let range_header = match *request.method() {
+            Method::Get | Method::Head => request.headers().iter().find(|h| h.field.equiv("Range")),
+            _ => None,
+        };
+        let range = match range_header.map(|h| parse_byte_range(h.value.as_str(), total_len)) {
+            Some(Ok(range)) => range,
+            Some(Err(RangeError::Unsatisfiable { .. })) => {
+                let mut response = self.into_limited(0, Some(0)).with_status_code(416);
+                response.add_header(
+                    Header::from_bytes(
+                        &b"Content-Range"[..],
+                        format!("bytes */{}", total_len).as_bytes(),
+                    )
+                    .unwrap(),
+                );
+                return Ok(response);
+            }
+            _ => {
+                let data_length = self.data_length;
+                return Ok(self.into_limited(total_len, data_length));
+            }
+        };
+
+        self.reader.seek(SeekFrom::Start(range.start))?;
+        Ok(self.into_range(range, total_len))
+    }
+}
+
 impl<R> Response<R>
 where
     R: Read + Send + 'static,
@@ -481,15 +1416,33 @@ where
             headers: self.headers,
             data_length: self.data_length,
             chunked_threshold: self.chunked_threshold,
+            transfer_encoding: self.transfer_encoding,
+            file_hints: self.file_hints,
+            vary: self.vary,
+            content_type_locked: self.content_type_locked,
+            default_headers: self.default_headers,
+            trailers: self.trailers,
         }
     }
 }
 
+/// Whether the metadata of an open file and of a path describe the same file.
+#[cfg(unix)]
+fn same_file(file: &fs::Metadata, path: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    file.dev() == path.dev() && file.ino() == path.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    true
+}
+
 impl Response<File> {
     /// Builds a new `Response` from a `File`.
     ///
     /// The `Content-Type` will **not** be automatically detected,
-    ///  you must set it yourself.
+    ///  you must set it yourself, or use `from_path`.
     pub fn from_file(file: File) -> Response<File> {
         let file_size = file.metadata().ok().map(|v| v.len() as usize);
 
@@ -501,10 +1454,82 @@ impl Response<File> {
             None,
         )
     }
+
+    /// Opens the file at `path` and builds a `Response` sending it.
+    ///
+    /// The `Content-Type` is given by `mime_for_extension` from the extension of the path,
+    /// `application/octet-stream` if it isn't known.
+    ///
+    /// Fails with `ErrorKind::InvalidInput` if the path is a symbolic link or a directory, and
+    /// with the error of the file system if it can't be opened.
+    ///
+    /// The path is checked once the file is opened. On Unix, a path that was replaced by a
+    /// symbolic link in the meantime doesn't lead to the same file and is rejected as well;
+    /// elsewhere, a link created right before the file is opened may still be followed.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> IoResult<Response<File>> {
+        let path = path.as_ref();
+        let invalid = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is {}", path.display(), what),
+            )
+        };
+
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let link_metadata = fs::symlink_metadata(path)?;
+        if link_metadata.file_type().is_symlink() {
+            return Err(invalid("a symbolic link"));
+        } else if !same_file(&metadata, &link_metadata) {
+            return Err(invalid("not the file that was opened"));
+        }
+        if metadata.is_dir() {
+            return Err(invalid("a directory"));
+        } else if !metadata.is_file() {
+            return Err(invalid("not a regular file"));
+        }
+
+        let content_type = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(mime_for_extension)
+            .unwrap_or("application/octet-stream");
+        Ok(Response::new(
+            StatusCode(200),
+            vec![Header::from_bytes(&b"Content-Type"[..], content_type).unwrap()],
+            file,
+            Some(metadata.len() as usize),
+            None,
+        ))
+    }
+
+    /// Tells the kernel how the file is going to be accessed while it is sent. Can be called
+    /// several times to combine hints.
+    ///
+    /// Only has an effect on Linux and Android with the `fadvise` feature enabled; the hints
+    /// are ignored elsewhere and failing to apply them never makes the response fail.
+    pub fn with_file_access_hint(mut self, hint: FileAccessHint) -> Response<File> {
+        if self.file_hints.is_none() {
+            self.file_hints = FileHints::new(&self.reader);
+        }
+        if let Some(ref mut file_hints) = self.file_hints {
+            file_hints.add(hint);
+        }
+        self
+    }
 }
 
 impl Response<Cursor<Vec<u8>>> {
     pub fn from_data<D>(data: D) -> Response<Cursor<Vec<u8>>>
+    where
+        D: Into<Vec<u8>>,
+    {
+        Response::from_data_with_headers(data, Vec::with_capacity(0))
+    }
+
+    /// Same as `from_data(data)` followed by `with_header()` for each header, but checks the
+    /// headers in a single pass.
+    pub fn from_data_with_headers<D>(data: D, headers: Vec<Header>) -> Response<Cursor<Vec<u8>>>
     where
         D: Into<Vec<u8>>,
     {
@@ -513,7 +1538,7 @@ impl Response<Cursor<Vec<u8>>> {
 
         Response::new(
             StatusCode(200),
-            Vec::with_capacity(0),
+            headers,
             Cursor::new(data),
             Some(data_len),
             None,
@@ -538,6 +1563,125 @@ impl Response<Cursor<Vec<u8>>> {
             None,
         )
     }
+
+    /// Builds a response with a JSON body, already serialized, and the `Content-Type:
+    /// application/json` header.
+    pub fn json<D>(data: D) -> Response<Cursor<Vec<u8>>>
+    where
+        D: Into<Vec<u8>>,
+    {
+        Response::with_content_type(data.into(), "application/json")
+    }
+
+    /// Builds a response with the JSON serialization of `value` as body, and the
+    /// `Content-Type: application/json` header. Only available with the `serde` feature.
+    ///
+    /// Fails if `value` can't be serialized, for example a map whose keys aren't strings.
+    #[cfg(feature = "serde")]
+    pub fn json_from<T>(value: &T) -> serde_json::Result<Response<Cursor<Vec<u8>>>>
+    where
+        T: serde_crate::Serialize + ?Sized,
+    {
+        serde_json::to_vec(value).map(Response::json)
+    }
+
+    /// Builds a response with an HTML body and the `Content-Type: text/html; charset=utf-8`
+    /// header.
+    pub fn html<S>(data: S) -> Response<Cursor<Vec<u8>>>
+    where
+        S: Into<String>,
+    {
+        Response::with_content_type(data.into().into_bytes(), "text/html; charset=utf-8")
+    }
+
+    /// Builds a response with a binary body and the `Content-Type: application/octet-stream`
+    /// header.
+    pub fn octet_stream<D>(data: D) -> Response<Cursor<Vec<u8>>>
+    where
+        D: Into<Vec<u8>>,
+    {
+        Response::with_content_type(data.into(), "application/octet-stream")
+    }
+
+    fn with_content_type(data: Vec<u8>, content_type: &'static str) -> Response<Cursor<Vec<u8>>> {
+        Response::from_data_with_headers(
+            data,
+            vec![Header::from_bytes(&b"Content-Type"[..], content_type).unwrap()],
+        )
+    }
+
+    /// Builds a `302 Found` response redirecting to `location`, see `redirect_with()`.
+    pub fn redirect<L>(location: L) -> Result<Response<Cursor<Vec<u8>>>, InvalidLocation>
+    where
+        L: AsRef<str>,
+    {
+        Response::redirect_with(302, location)
+    }
+
+    /// Builds a response redirecting to `location`, which can be absolute or relative to the
+    /// URL of the request, with a `Location` header and a short HTML page linking to it.
+    ///
+    /// The status code tells how the client follows the redirection:
+    ///
+    ///  - `301 Moved Permanently` and `302 Found`: clients usually turn a `POST` into a `GET`.
+    ///  - `303 See Other`: always with a `GET`, for example after a form is submitted.
+    ///  - `307 Temporary Redirect` and `308 Permanent Redirect`: with the same method and body.
+    ///
+    /// The non-ASCII characters, spaces and control characters of `location` are
+    /// percent-encoded, except for line breaks, which could end the `Location` header and
+    /// make the error `InvalidLocation`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `status_code` isn't a `3xx` status code.
+    pub fn redirect_with<S, L>(
+        status_code: S,
+        location: L,
+    ) -> Result<Response<Cursor<Vec<u8>>>, InvalidLocation>
+    where
+        S: Into<StatusCode>,
+        L: AsRef<str>,
+    {
+        let status_code = status_code.into();
+        assert!(
+            (300..400).contains(&status_code.0),
+            "not a redirection status code: {}",
+            status_code.0
+        );
+        let location = location.as_ref();
+        if location.contains(|c| c == '\r' || c == '\n') {
+            return Err(InvalidLocation);
+        }
+        let location = common::percent_encode(location, |b| (0x21..0x7f).contains(&b));
+        // the location is printable ASCII
+        let header = Header::from_bytes(&b"Location"[..], location.as_bytes()).unwrap();
+
+        let mut escaped = String::with_capacity(location.len());
+        for c in location.chars() {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&#39;"),
+                c => escaped.push(c),
+            }
+        }
+        let body = format!(
+            "<!DOCTYPE html>\n<title>{0}</title>\n<p>Redirecting to <a href=\"{1}\">{1}</a>.</p>\n",
+            status_code.default_reason_phrase(),
+            escaped
+        );
+
+        Ok(Response::from_data_with_headers(
+            body,
+            vec![
+                header,
+                Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..]).unwrap(),
+            ],
+        )
+        .with_status_code(status_code))
+    }
 }
 
 impl Response<io::Empty> {
@@ -569,6 +1713,659 @@ impl Clone for Response<io::Empty> {
             headers: self.headers.clone(),
             data_length: self.data_length,
             chunked_threshold: self.chunked_threshold,
+            transfer_encoding: self.transfer_encoding,
+            file_hints: None,
+            vary: self.vary.clone(),
+            content_type_locked: self.content_type_locked,
+            default_headers: self.default_headers,
+            // the channels can't be shared
+            trailers: Trailers {
+                grace: self.trailers.grace,
+                announced: self.trailers.announced.clone(),
+                ..Trailers::default()
+            },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        needs_buffered_length, plan_response, BodyFraming, InvalidLocation, PrintContext,
+        RespondError, Response, ResponsePlan, ResponseSpec, TransferEncoding,
+    };
+    use crate::config::{SecurityHeaders, ServerConfigAdvanced};
+    use crate::{Cookie, HTTPVersion, Header, SameSite, StatusCode};
+    use std::io::Read;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn print<R: Read>(
+        response: Response<R>,
+        config: &ServerConfigAdvanced,
+        secure: bool,
+    ) -> String {
+        let mut output = Vec::new();
+        let ctx = PrintContext {
+            http_version: HTTPVersion(1, 1),
+            request_headers: &[],
+            do_not_send_body: false,
+            upgrade: None,
+            legacy_client: false,
+            last_on_connection: false,
+            secure,
+            config,
+        };
+        response.print(&mut output, &ctx).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn default_content_type_and_nosniff() {
+        let config = ServerConfigAdvanced::default()
+            .with_default_content_type(Some("application/octet-stream"))
+            .with_security_headers(SecurityHeaders::new());
+
+        let output = print(Response::from_data(vec![1, 2, 3]), &config, false);
+        assert!(output.contains("\r\nContent-Type: application/octet-stream\r\n"));
+        assert!(output.contains("\r\nX-Content-Type-Options: nosniff\r\n"));
+
+        // no body, no content type
+        let output = print(Response::empty(200), &config, false);
+        assert!(!output.contains("\r\nContent-Type"));
+        assert!(output.contains("\r\nX-Content-Type-Options: nosniff\r\n"));
+    }
+
+    #[test]
+    fn default_headers_opt_out() {
+        let config = ServerConfigAdvanced::default()
+            .with_default_content_type(Some("application/octet-stream"))
+            .with_security_headers(SecurityHeaders::new());
+
+        let response = Response::from_data(vec![1, 2, 3]).without_default_headers();
+        let output = print(response, &config, true);
+        assert!(!output.contains("\r\nContent-Type"));
+        assert!(!output.contains("X-Content-Type-Options"));
+        assert!(output.contains("\r\nServer: tiny-http (Rust)\r\n"));
+    }
+
+    #[test]
+    fn explicit_content_type_not_overridden() {
+        let config = ServerConfigAdvanced::default()
+            .with_default_content_type(Some("application/octet-stream"));
+
+        let response = Response::from_data(vec![1, 2, 3])
+            .with_header(Header::from_bytes(&b"Content-Type"[..], &b"image/png"[..]).unwrap());
+        let output = print(response, &config, false);
+        assert!(output.contains("\r\nContent-Type: image/png\r\n"));
+        assert!(!output.contains("application/octet-stream"));
+    }
+
+    #[test]
+    fn one_header_per_cookie() {
+        let response = Response::empty(204)
+            .with_cookie(Cookie::new("a", "1").with_path("/"))
+            .with_cookie(Cookie::new("b", "2").with_same_site(SameSite::Lax));
+        let output = print(response, &ServerConfigAdvanced::default(), false);
+        assert!(output.contains("\r\nSet-Cookie: a=1; Path=/\r\nSet-Cookie: b=2; SameSite=Lax\r\n"));
+    }
+
+    #[test]
+    fn www_authenticate() {
+        let response = Response::from_string("denied").with_www_authenticate("Admin \"area\"");
+        let output = print(response, &ServerConfigAdvanced::default(), false);
+        assert!(output.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(output.contains("\r\nWWW-Authenticate: Basic realm=\"Admin \\\"area\\\"\"\r\n"));
+    }
+
+    #[test]
+    fn hsts_only_on_secure_requests() {
+        let config = ServerConfigAdvanced::default().with_security_headers(
+            SecurityHeaders::new().with_strict_transport_security(Duration::from_secs(60), true),
+        );
+
+        let output = print(Response::from_string("hello"), &config, true);
+        assert!(output.contains("\r\nStrict-Transport-Security: max-age=60; includeSubDomains\r\n"));
+
+        let output = print(Response::from_string("hello"), &config, false);
+        assert!(!output.contains("Strict-Transport-Security"));
+        assert!(output.contains("\r\nX-Content-Type-Options: nosniff\r\n"));
+    }
+
+    #[test]
+    fn static_response_headers() {
+        let config = ServerConfigAdvanced::default().with_static_response_headers(vec![
+            Header::from_bytes(&b"X-Served-By"[..], &b"edge-fra-3"[..]).unwrap(),
+            Header::from_bytes(&b"Server"[..], &b"edge"[..]).unwrap(),
+        ]);
+
+        let output = print(Response::from_string("hello"), &config, false);
+        assert!(output.contains("\r\nX-Served-By: edge-fra-3\r\n"));
+        assert!(output.contains("\r\nServer: edge\r\n"));
+        assert!(!output.contains("tiny-http"));
+
+        let response = Response::from_string("hello")
+            .with_header(Header::from_bytes(&b"x-served-by"[..], &b"origin"[..]).unwrap());
+        let output = print(response, &config, false);
+        assert!(output.contains("\r\nx-served-by: origin\r\n"));
+        assert!(!output.contains("edge-fra-3"));
+        assert!(output.contains("\r\nServer: edge\r\n"));
+    }
+
+    #[test]
+    fn vary_fields_are_merged() {
+        let config = ServerConfigAdvanced::default();
+
+        let mut response = Response::from_string("hello");
+        response.add_vary("Accept-Encoding");
+        response.add_vary("accept-encoding");
+        assert_eq!(response.vary_fields(), ["Accept-Encoding"]);
+        let output = print(response, &config, false);
+        assert_eq!(output.matches("Accept-Encoding").count(), 1);
+        assert!(output.contains("\r\nVary: Accept-Encoding\r\n"));
+
+        let mut response = Response::from_string("hello").with_header(
+            Header::from_bytes(&b"Vary"[..], &b"Cookie, Accept-Language"[..]).unwrap(),
+        );
+        response.add_vary("Accept-Encoding");
+        response.add_vary("cookie");
+        let output = print(response, &config, false);
+        assert_eq!(output.matches("Vary").count(), 1);
+        assert!(output.contains("\r\nVary: Cookie, Accept-Language, Accept-Encoding\r\n"));
+    }
+
+    #[test]
+    fn vary_star_wins() {
+        let mut response = Response::from_string("hello");
+        response.add_vary("Accept-Encoding");
+        response.add_vary("*");
+        response.add_vary("Cookie");
+        assert_eq!(response.vary_fields(), ["*"]);
+
+        let response = Response::from_string("hello")
+            .with_header(Header::from_bytes(&b"Vary"[..], &b"Accept, *"[..]).unwrap());
+        let output = print(response, &ServerConfigAdvanced::default(), false);
+        assert!(output.contains("\r\nVary: *\r\n"));
+    }
+
+    #[test]
+    fn replaced_headers() {
+        let config = ServerConfigAdvanced::default();
+        let header = |s: &str| s.parse::<Header>().unwrap();
+
+        let response = Response::from_string("{}")
+            .with_header_replaced(header("Content-Type: application/json"))
+            .with_header(header("Set-Cookie: a=1"))
+            .with_header(header("Set-Cookie: b=2"))
+            .with_header(header("X-Tag: one"))
+            .with_header(header("x-tag: two"))
+            .with_header_replaced(header("X-TAG: three"))
+            .with_header(header("Location: /old"))
+            .with_header(header("Location: /new"));
+        let output = print(response, &config, false);
+        assert_eq!(output.matches("Content-Type").count(), 1, "{}", output);
+        assert!(output.contains("\r\nContent-Type: application/json\r\n"));
+        assert!(output.contains("\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n"));
+        assert_eq!(output.matches("one").count(), 0, "{}", output);
+        assert!(output.contains("\r\nX-TAG: three\r\n"));
+        assert_eq!(output.matches("Location").count(), 1, "{}", output);
+        assert!(output.contains("\r\nLocation: /new\r\n"));
+
+        let mut response = Response::from_string("hello")
+            .with_header(header("Set-Cookie: a=1"))
+            .with_header(header("Vary: Accept"));
+        response.remove_header("content-type");
+        response.remove_header("Set-Cookie");
+        response.remove_header("Vary");
+        response.remove_header("Content-Length");
+        assert!(response.headers().is_empty());
+        assert!(response.vary_fields().is_empty());
+        assert_eq!(response.data_length(), Some(5));
+    }
+
+    #[test]
+    fn typed_constructors() {
+        let config = ServerConfigAdvanced::default();
+        let content_type = |response: &Response<_>| {
+            let types: Vec<_> = response
+                .headers()
+                .iter()
+                .filter(|h| h.field.equiv("Content-Type"))
+                .map(|h| h.value.to_string())
+                .collect();
+            types.join(", ")
+        };
+
+        let response = Response::json(r#"{"widgets": []}"#);
+        assert_eq!(content_type(&response), "application/json");
+        assert_eq!(response.data_length(), Some(15));
+        let output = print(response, &config, false);
+        assert!(output.contains("\r\nContent-Length: 15\r\n"), "{}", output);
+        assert!(output.ends_with("\r\n\r\n{\"widgets\": []}"));
+
+        let response = Response::html("<p>hello</p>");
+        assert_eq!(content_type(&response), "text/html; charset=utf-8");
+        assert_eq!(response.data_length(), Some(12));
+
+        let response = Response::octet_stream(vec![0, 1, 2]);
+        assert_eq!(content_type(&response), "application/octet-stream");
+        assert_eq!(response.data_length(), Some(3));
+
+        // a Content-Type added later wins
+        let response = Response::json("[]").with_header(
+            Header::from_bytes(&b"Content-Type"[..], &b"application/ld+json"[..]).unwrap(),
+        );
+        assert_eq!(content_type(&response), "application/ld+json");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_from_value() {
+        let mut map = std::collections::BTreeMap::new();
+        map.insert("widgets", vec![1, 2]);
+        let response = Response::json_from(&map).unwrap();
+        assert_eq!(response.data_length(), Some(17));
+        let mut body = String::new();
+        response.into_reader().read_to_string(&mut body).unwrap();
+        assert_eq!(body, r#"{"widgets":[1,2]}"#);
+    }
+
+    #[test]
+    fn redirects() {
+        let config = ServerConfigAdvanced::default();
+        let output = print(
+            Response::redirect("/login?next=a&b").unwrap(),
+            &config,
+            false,
+        );
+        assert!(output.starts_with("HTTP/1.1 302 Found\r\n"), "{}", output);
+        assert!(output.contains("\r\nLocation: /login?next=a&b\r\n"));
+        assert!(output.contains("\r\nContent-Type: text/html; charset=utf-8\r\n"));
+        assert!(
+            output.contains("<a href=\"/login?next=a&amp;b\">"),
+            "{}",
+            output
+        );
+
+        for &code in [301, 303, 307, 308].iter() {
+            let response = Response::redirect_with(code, "https://example.com/").unwrap();
+            assert_eq!(response.status_code(), StatusCode(code));
+            assert!(response
+                .headers()
+                .iter()
+                .any(|h| h.field.equiv("Location") && h.value == "https://example.com/"));
+        }
+    }
+
+    #[test]
+    fn redirect_location_encoded() {
+        let response = Response::redirect("/caf\u{e9} menu\t").unwrap();
+        let location = response
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Location"))
+            .unwrap();
+        assert_eq!(location.value, "/caf%C3%A9%20menu%09");
+
+        assert_eq!(
+            Response::redirect("/\r\nSet-Cookie: session=stolen").err(),
+            Some(InvalidLocation)
+        );
+        assert!(Response::redirect("/\n").is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "not a redirection status code")]
+    fn redirect_with_other_status_rejected() {
+        let _ = Response::redirect_with(200, "/");
+    }
+
+    #[test]
+    #[should_panic]
+    fn static_content_length_rejected() {
+        let _ =
+            ServerConfigAdvanced::default().with_static_response_headers(vec![Header::from_bytes(
+                &b"Content-Length"[..],
+                &b"5"[..],
+            )
+            .unwrap()]);
+    }
+
+    #[test]
+    fn head_size_includes_server_headers() {
+        let response = || {
+            Response::from_string("hello")
+                .with_header(Header::from_bytes(&b"Link"[..], &b"</a>; rel=preload"[..]).unwrap())
+        };
+        let output = print(response(), &ServerConfigAdvanced::default(), false);
+        let size = output.find("\r\n\r\n").unwrap() + 4;
+
+        let config = ServerConfigAdvanced::default().with_max_response_header_size(size);
+        assert_eq!(print(response(), &config, false), output);
+
+        let config = ServerConfigAdvanced::default().with_max_response_header_size(size - 1);
+        let ctx = PrintContext {
+            http_version: HTTPVersion(1, 1),
+            request_headers: &[],
+            do_not_send_body: false,
+            upgrade: None,
+            legacy_client: false,
+            last_on_connection: false,
+            secure: false,
+            config: &config,
+        };
+        let mut output = Vec::new();
+        let err = response().print(&mut output, &ctx).unwrap_err();
+        assert_eq!(
+            RespondError::from_io_error(&err),
+            Some(&RespondError::HeadersTooLarge { size })
+        );
+        assert_eq!(
+            output,
+            b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn header_value_too_long() {
+        // long enough for the `Date` header
+        let config = ServerConfigAdvanced::default().with_max_response_header_value_len(29);
+        let ctx = PrintContext {
+            http_version: HTTPVersion(1, 1),
+            request_headers: &[],
+            do_not_send_body: false,
+            upgrade: None,
+            legacy_client: false,
+            last_on_connection: false,
+            secure: false,
+            config: &config,
+        };
+        let response = Response::empty(200)
+            .with_header(Header::from_bytes(&b"X-Short"[..], vec![b'a'; 29]).unwrap())
+            .with_header(Header::from_bytes(&b"X-Long"[..], vec![b'a'; 30]).unwrap());
+        let err = response.print(&mut Vec::new(), &ctx).unwrap_err();
+        assert_eq!(
+            RespondError::from_io_error(&err),
+            Some(&RespondError::HeaderValueTooLong {
+                name: "X-Long".to_owned(),
+                len: 30
+            })
+        );
+    }
+
+    #[test]
+    fn from_data_with_headers_matches_builder() {
+        let headers = || {
+            vec![
+                Header::from_bytes(&b"Date"[..], &b"Thu, 01 Jan 1970 00:00:00 GMT"[..]).unwrap(),
+                Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap(),
+                Header::from_bytes(&b"Content-Type"[..], &b"text/html"[..]).unwrap(),
+                Header::from_bytes(&b"Vary"[..], &b"Accept"[..]).unwrap(),
+                Header::from_bytes(&b"Connection"[..], &b"close"[..]).unwrap(),
+                Header::from_bytes(&b"X-Custom"[..], &b"1"[..]).unwrap(),
+            ]
+        };
+        let config = ServerConfigAdvanced::default();
+
+        let built = headers()
+            .into_iter()
+            .fold(Response::from_data("hello"), Response::with_header);
+        let direct = Response::from_data_with_headers("hello", headers());
+        assert_eq!(print(direct, &config, false), print(built, &config, false));
+    }
+
+    #[test]
+    fn queued_trailers_are_sent() {
+        let config = ServerConfigAdvanced::default();
+        let mut response = Response::from_data("hello");
+        let sender = response.trailer_sender();
+        sender
+            .send(Header::from_bytes(&b"X-Checksum"[..], &b"abc"[..]).unwrap())
+            .unwrap();
+        sender
+            .send(Header::from_bytes(&b"Content-Length"[..], &b"3"[..]).unwrap())
+            .unwrap();
+
+        // chunked although the length is known, to send the trailers
+        let output = print(response, &config, false);
+        assert!(output.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(!output.contains("Content-Length"));
+        assert!(output.ends_with("\r\n\r\n5\r\nhello\r\n0\r\nX-Checksum: abc\r\n\r\n"));
+
+        // the channel is closed once the response is sent
+        assert!(sender
+            .send(Header::from_bytes(&b"X-Late"[..], &b"1"[..]).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn announced_trailers() {
+        let config = ServerConfigAdvanced::default();
+        let mut response = Response::from_data("hello")
+            .with_announced_trailer("X-Checksum")
+            .with_announced_trailer("X-Count")
+            .with_announced_trailer("x-checksum");
+        let sender = response.trailer_sender();
+        sender
+            .send(Header::from_bytes(&b"X-Checksum"[..], &b"abc"[..]).unwrap())
+            .unwrap();
+
+        let output = print(response, &config, false);
+        assert!(
+            output.contains("\r\nTrailer: X-Checksum, X-Count\r\n"),
+            "{}",
+            output
+        );
+        assert!(output.ends_with("\r\n0\r\nX-Checksum: abc\r\n\r\n"));
+
+        // nothing to announce without trailers
+        let response = Response::from_data("hello").with_announced_trailer("X-Checksum");
+        assert!(!print(response, &config, false).contains("Trailer"));
+    }
+
+    #[test]
+    fn queued_trailers_without_chunks() {
+        let config = ServerConfigAdvanced::default();
+        let mut response = Response::from_data("hello")
+            .with_identity_transfer()
+            .with_announced_trailer("X-Checksum");
+        let sender = response.trailer_sender();
+        sender
+            .send(Header::from_bytes(&b"X-Checksum"[..], &b"abc"[..]).unwrap())
+            .unwrap();
+        sender
+            .send(Header::from_bytes(&b"Transfer-Encoding"[..], &b"gzip"[..]).unwrap())
+            .unwrap();
+
+        // the queued trailers become headers
+        let output = print(response, &config, false);
+        assert!(output.contains("\r\nContent-Length: 5\r\n"), "{}", output);
+        assert!(output.contains("\r\nX-Checksum: abc\r\n"), "{}", output);
+        assert!(!output.contains("Trailer"), "{}", output);
+        assert!(!output.contains("gzip"), "{}", output);
+        assert!(output.ends_with("\r\n\r\nhello"));
+        assert!(sender
+            .send(Header::from_bytes(&b"X-Late"[..], &b"1"[..]).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn hanging_trailer_sender() {
+        let config = ServerConfigAdvanced::default();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let response = Response::new(
+            StatusCode(200),
+            Vec::new(),
+            &b"hello"[..],
+            Some(5),
+            Some(receiver),
+        )
+        .with_trailer_grace(Duration::from_millis(50));
+
+        // sends one trailer, then waits for the response to be complete before sending another
+        let (complete, wait_for_complete) = std::sync::mpsc::channel::<()>();
+        let sender = thread::spawn(move || {
+            sender
+                .send(Header::from_bytes(&b"X-Early"[..], &b"1"[..]).unwrap())
+                .unwrap();
+            let _ = wait_for_complete.recv_timeout(Duration::from_secs(10));
+            sender.send(Header::from_bytes(&b"X-Late"[..], &b"1"[..]).unwrap())
+        });
+
+        let start = Instant::now();
+        let output = print(response, &config, false);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        complete.send(()).unwrap();
+
+        assert!(
+            output.ends_with("\r\n0\r\nX-Early: 1\r\n\r\n"),
+            "{}",
+            output
+        );
+        assert!(sender.join().unwrap().is_err());
+    }
+
+    fn spec(status_code: u16, data_length: Option<usize>) -> ResponseSpec<'static> {
+        ResponseSpec {
+            status_code: StatusCode(status_code),
+            headers: Vec::new(),
+            vary: &[],
+            data_length,
+            chunked_threshold: 32768,
+            transfer_encoding: None,
+            has_trailers: false,
+            trailer_announcement: None,
+            default_headers: true,
+        }
+    }
+
+    #[test]
+    fn plan_framing() {
+        let config = ServerConfigAdvanced::default();
+        let ctx = |http_version, do_not_send_body| PrintContext {
+            http_version,
+            request_headers: &[],
+            do_not_send_body,
+            upgrade: None,
+            legacy_client: false,
+            last_on_connection: false,
+            secure: false,
+            config: &config,
+        };
+        let http11 = ctx(HTTPVersion(1, 1), false);
+        let http10 = ctx(HTTPVersion(1, 0), false);
+        let head = ctx(HTTPVersion(1, 1), true);
+
+        assert!(!needs_buffered_length(&spec(200, None), &http11));
+        assert!(needs_buffered_length(&spec(200, None), &http10));
+        assert_eq!(
+            plan_response(spec(200, None), &http11).body,
+            BodyFraming::Chunked
+        );
+        assert_eq!(
+            plan_response(spec(200, Some(5)), &http11).body,
+            BodyFraming::Length(5)
+        );
+        assert_eq!(
+            plan_response(spec(200, Some(40000)), &http11).body,
+            BodyFraming::Chunked
+        );
+
+        // the transfer encoding chosen by the application
+        let forced = |data_length, transfer_encoding| ResponseSpec {
+            transfer_encoding: Some(transfer_encoding),
+            ..spec(200, data_length)
+        };
+        assert_eq!(
+            plan_response(forced(Some(40000), TransferEncoding::Identity), &http11).body,
+            BodyFraming::Length(40000)
+        );
+        assert!(needs_buffered_length(
+            &forced(None, TransferEncoding::Identity),
+            &http11
+        ));
+        assert_eq!(
+            plan_response(forced(Some(5), TransferEncoding::Chunked), &http11).body,
+            BodyFraming::Chunked
+        );
+        assert_eq!(
+            plan_response(forced(Some(5), TransferEncoding::Chunked), &http10).body,
+            BodyFraming::Length(5)
+        );
+        assert_eq!(
+            plan_response(
+                ResponseSpec {
+                    transfer_encoding: Some(TransferEncoding::Chunked),
+                    ..spec(204, Some(0))
+                },
+                &http11
+            )
+            .body,
+            BodyFraming::Discard
+        );
+
+        // the framing headers are sent even when the body isn't
+        let plan = plan_response(spec(200, Some(5)), &head);
+        assert_eq!(plan.body, BodyFraming::Discard);
+        assert!(plan
+            .headers
+            .iter()
+            .any(|h| h.field.equiv("Content-Length") && h.value == "5"));
+        assert_eq!(
+            plan_response(spec(304, None), &http11).body,
+            BodyFraming::Discard
+        );
+    }
+
+    #[test]
+    fn plan_upgrade() {
+        let config = ServerConfigAdvanced::default();
+        let ctx = PrintContext {
+            http_version: HTTPVersion(1, 1),
+            request_headers: &[],
+            do_not_send_body: false,
+            upgrade: Some("websocket"),
+            legacy_client: false,
+            last_on_connection: false,
+            secure: false,
+            config: &config,
+        };
+
+        assert!(!needs_buffered_length(&spec(101, None), &ctx));
+        let plan = plan_response(spec(101, None), &ctx);
+        assert_eq!(plan.body, BodyFraming::Discard);
+        let fields: Vec<_> = plan.headers.iter().map(|h| h.field.to_string()).collect();
+        assert_eq!(fields, ["Connection", "Upgrade", "Server", "Date"]);
+    }
+
+    #[test]
+    fn plan_last_on_connection() {
+        let config = ServerConfigAdvanced::default();
+        let ctx = PrintContext {
+            http_version: HTTPVersion(1, 1),
+            request_headers: &[],
+            do_not_send_body: false,
+            upgrade: None,
+            legacy_client: false,
+            last_on_connection: true,
+            secure: false,
+            config: &config,
+        };
+        let closes = |plan: ResponsePlan<'_>| {
+            plan.headers
+                .iter()
+                .filter(|h| h.field.equiv("Connection"))
+                .map(|h| h.value.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(closes(plan_response(spec(200, Some(0)), &ctx)), ["close"]);
+
+        // a response that already tells it doesn't repeat it
+        let mut closing = spec(200, Some(0));
+        closing
+            .headers
+            .push(Header::from_bytes(&b"Connection"[..], &b"Close"[..]).unwrap());
+        assert_eq!(closes(plan_response(closing, &ctx)), ["Close"]);
+    }
+}
@@ -0,0 +1,249 @@
+//! Making request data safe to write to logs and error bodies.
+//!
+//! The method, path and headers of a request are chosen by the client. Written as they are, a
+//! path containing `%0d%0a` decoded to a line break could forge log lines, and a huge header
+//! could fill a log file. The functions of this module replace the characters that can't be
+//! written safely with `U+FFFD`, decode invalid UTF-8 lossily, and truncate the result to a
+//! budget of bytes, ending it with `…(+N bytes)` where `N` is the number of bytes of the input
+//! that were left out.
+//!
+//! The input is borrowed as it is when it is already clean and fits in the budget.
+//!
+//! ```
+//! use tiny_http::sanitize::sanitize_for_log;
+//!
+//! assert_eq!(sanitize_for_log(b"/index.html", 64), "/index.html");
+//! assert_eq!(sanitize_for_log(b"/a\r\nforged", 64), "/a\u{fffd}\u{fffd}forged");
+//! assert_eq!(sanitize_for_log(b"/0123456789abcdefghij", 16), "/0…(+19 bytes)");
+//! ```
+
+use std::borrow::Cow;
+use std::str;
+
+/// Character replacing the ones that can't be written.
+const REPLACEMENT: char = '\u{fffd}';
+
+/// Budget used by the library when it logs request data.
+#[cfg_attr(not(feature = "log"), allow(dead_code))]
+pub(crate) const LOG_BUDGET: usize = 256;
+
+/// Makes arbitrary bytes safe to write to a log line.
+///
+/// The C0 and C1 control characters and `DEL` are replaced, tabs and line breaks included.
+pub fn sanitize_for_log(input: &[u8], max_len: usize) -> Cow<'_, str> {
+    sanitize(input, max_len, is_control)
+}
+
+/// Makes the value of a header safe to write to a log line or to a header.
+///
+/// Same as `sanitize_for_log()`, except that tabs are kept since header values may contain
+/// them.
+pub fn sanitize_header_value(input: &[u8], max_len: usize) -> Cow<'_, str> {
+    sanitize(input, max_len, |c| c != '\t' && is_control(c))
+}
+
+/// Makes a request target safe to write to a log line or an error body.
+///
+/// Same as `sanitize_for_log()`, except that whitespace and the characters changing the
+/// direction of the text are also replaced, so that the path can't be mistaken for
+/// something else when displayed.
+pub fn sanitize_path(input: &[u8], max_len: usize) -> Cow<'_, str> {
+    sanitize(input, max_len, |c| {
+        is_control(c) || c.is_whitespace() || is_bidi_control(c)
+    })
+}
+
+fn is_control(c: char) -> bool {
+    // `char::is_control` covers C0, DEL and C1
+    c.is_control()
+}
+
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+fn sanitize(input: &[u8], max_len: usize, replace: impl Fn(char) -> bool) -> Cow<'_, str> {
+    if input.len() <= max_len {
+        if let Ok(clean) = str::from_utf8(input) {
+            if !clean.chars().any(&replace) {
+                return Cow::Borrowed(clean);
+            }
+        }
+    }
+
+    let mut output = String::with_capacity(input.len().min(max_len));
+    let mut rest = input;
+    // number of bytes of the input written to the output so far
+    let mut consumed = 0;
+    while !rest.is_empty() {
+        let (valid, invalid) = match str::from_utf8(rest) {
+            Ok(valid) => (valid, 0),
+            Err(err) => {
+                let valid = str::from_utf8(&rest[..err.valid_up_to()]).unwrap();
+                let invalid = err.error_len().unwrap_or(rest.len() - valid.len());
+                (valid, invalid)
+            }
+        };
+
+        for c in valid.chars() {
+            let c_out = if replace(c) { REPLACEMENT } else { c };
+            if !push(
+                &mut output,
+                c_out,
+                input.len() - consumed - c.len_utf8(),
+                max_len,
+            ) {
+                return Cow::Owned(truncated(output, input.len() - consumed, max_len));
+            }
+            consumed += c.len_utf8();
+        }
+        if invalid != 0 {
+            if !push(
+                &mut output,
+                REPLACEMENT,
+                input.len() - consumed - invalid,
+                max_len,
+            ) {
+                return Cow::Owned(truncated(output, input.len() - consumed, max_len));
+            }
+            consumed += invalid;
+        }
+        rest = &rest[valid.len() + invalid..];
+    }
+    Cow::Owned(output)
+}
+
+/// Pushes `c` if it fits in the budget along with the marker for the `left` bytes after it,
+/// or without a marker if nothing is left.
+fn push(output: &mut String, c: char, left: usize, max_len: usize) -> bool {
+    let marker_len = if left == 0 { 0 } else { marker(left).len() };
+    if output.len() + c.len_utf8() + marker_len > max_len {
+        return false;
+    }
+    output.push(c);
+    true
+}
+
+/// Ends a truncated output with its marker, itself truncated if the budget is too small.
+fn truncated(mut output: String, omitted: usize, max_len: usize) -> String {
+    for c in marker(omitted).chars() {
+        if output.len() + c.len_utf8() > max_len {
+            break;
+        }
+        output.push(c);
+    }
+    output
+}
+
+fn marker(omitted: usize) -> String {
+    format!("…(+{} bytes)", omitted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sanitize_for_log, sanitize_header_value, sanitize_path};
+    use crate::util::XorShift;
+    use std::borrow::Cow;
+
+    #[test]
+    fn clean_inputs_are_borrowed() {
+        for input in [
+            "",
+            "/index.html",
+            "GET",
+            "text/html;charset=utf-8",
+            "/caf\u{e9}",
+        ] {
+            for sanitized in [
+                sanitize_for_log(input.as_bytes(), 64),
+                sanitize_header_value(input.as_bytes(), 64),
+                sanitize_path(input.as_bytes(), 64),
+            ] {
+                assert!(
+                    matches!(sanitized, Cow::Borrowed(s) if s == input),
+                    "{}",
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn policies() {
+        assert_eq!(
+            sanitize_for_log(b"a\tb\r\nc\x7f\x00", 64),
+            "a\u{fffd}b\u{fffd}\u{fffd}c\u{fffd}\u{fffd}"
+        );
+        assert_eq!(sanitize_for_log("a\u{85}b".as_bytes(), 64), "a\u{fffd}b");
+        assert_eq!(
+            sanitize_header_value(b"a\tb\r\n", 64),
+            "a\tb\u{fffd}\u{fffd}"
+        );
+        assert_eq!(sanitize_path(b"/a b\t", 64), "/a\u{fffd}b\u{fffd}");
+        assert_eq!(
+            sanitize_path("/\u{202e}gpj.exe".as_bytes(), 64),
+            "/\u{fffd}gpj.exe"
+        );
+        assert_eq!(
+            sanitize_for_log(b"a\xffb\xe2\x82", 64),
+            "a\u{fffd}b\u{fffd}"
+        );
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(sanitize_for_log(b"0123456789", 10), "0123456789");
+        assert_eq!(
+            sanitize_for_log(b"0123456789abcdefghij", 16),
+            "01…(+18 bytes)"
+        );
+        // multi-byte characters are never split
+        assert_eq!(
+            sanitize_for_log(
+                "\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}\u{e9}".as_bytes(),
+                16
+            ),
+            "\u{e9}…(+16 bytes)"
+        );
+        // the marker itself is cut when the budget is tiny
+        assert_eq!(sanitize_for_log(b"0123456789", 5), "…(+");
+        assert_eq!(sanitize_for_log(b"0123456789", 0), "");
+    }
+
+    #[test]
+    fn random_inputs() {
+        let mut rng = XorShift::new(0x5eed);
+        for _ in 0..2000 {
+            let len = (rng.next_f64() * 80.0) as usize;
+            let input: Vec<u8> = (0..len)
+                .map(|_| {
+                    // mostly ASCII, with controls and bytes of multi-byte characters
+                    let x = rng.next_f64();
+                    if x < 0.6 {
+                        b' ' + (rng.next_f64() * 95.0) as u8
+                    } else {
+                        (rng.next_f64() * 256.0) as u8
+                    }
+                })
+                .collect();
+            let max_len = (rng.next_f64() * 100.0) as usize;
+
+            for sanitized in [
+                sanitize_for_log(&input, max_len),
+                sanitize_header_value(&input, max_len),
+                sanitize_path(&input, max_len),
+            ] {
+                assert!(sanitized.len() <= max_len, "{:?} {}", input, max_len);
+                assert!(
+                    !sanitized.chars().any(|c| c.is_control() && c != '\t'),
+                    "{:?}",
+                    sanitized
+                );
+                if let Cow::Borrowed(s) = sanitized {
+                    assert_eq!(s.as_bytes(), &input[..]);
+                }
+            }
+            assert!(!sanitize_for_log(&input, max_len).contains('\t'));
+        }
+    }
+}
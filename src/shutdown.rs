@@ -0,0 +1,464 @@
+//! Reasons why a server stops serving requests.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::connection::Connection;
+use crate::log;
+use crate::stats::InFlightRequest;
+use crate::util::MessagesQueue;
+use crate::{ListenAddr, Message};
+
+/// Why a server was shut down, returned by `Server::shutdown_reason()` and carried by
+/// `RecvError::Shutdown`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// `Server::shutdown_gracefully()` was called. The requests that were already queued are
+    /// still returned before receiving fails.
+    Graceful,
+    /// The server was dropped.
+    Immediate,
+    /// Accepting connections failed with an error of this kind, that the server can't recover
+    /// from.
+    AcceptThreadFailed(IoErrorKind),
+    /// The listening socket was closed from outside of the server.
+    ListenerClosed,
+}
+
+impl ShutdownReason {
+    /// Returns the reason for shutting down after `accept()` failed with `err`.
+    pub(crate) fn from_accept_error(err: &IoError) -> ShutdownReason {
+        match err.kind() {
+            // what `accept()` returns on Linux once the socket is shut down
+            IoErrorKind::InvalidInput | IoErrorKind::NotConnected => ShutdownReason::ListenerClosed,
+            kind => ShutdownReason::AcceptThreadFailed(kind),
+        }
+    }
+}
+
+/// What the accept thread does after `accept()` failed with an error.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum AcceptErrorAction {
+    /// The error only concerns the connection being accepted, accepting again right away.
+    Retry,
+    /// The process or the system is out of resources, accepting again after a pause.
+    Backoff,
+    /// The listener can't be used anymore, the server shuts down.
+    Stop(ShutdownReason),
+}
+
+impl AcceptErrorAction {
+    pub(crate) fn for_error(err: &IoError) -> AcceptErrorAction {
+        match err.kind() {
+            IoErrorKind::ConnectionAborted
+            | IoErrorKind::ConnectionReset
+            | IoErrorKind::Interrupted => AcceptErrorAction::Retry,
+            IoErrorKind::OutOfMemory => AcceptErrorAction::Backoff,
+            _ if err
+                .raw_os_error()
+                .map_or(false, |code| EXHAUSTION_ERRORS.contains(&code)) =>
+            {
+                AcceptErrorAction::Backoff
+            }
+            _ => AcceptErrorAction::Stop(ShutdownReason::from_accept_error(err)),
+        }
+    }
+}
+
+// codes of the errors telling that there are no file descriptors or buffers left: ENFILE,
+// EMFILE and ENOBUFS, or WSAEMFILE and WSAENOBUFS on Windows
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const EXHAUSTION_ERRORS: &[i32] = &[23, 24, 105];
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+const EXHAUSTION_ERRORS: &[i32] = &[23, 24, 55];
+#[cfg(windows)]
+const EXHAUSTION_ERRORS: &[i32] = &[10024, 10055];
+#[cfg(not(any(unix, windows)))]
+const EXHAUSTION_ERRORS: &[i32] = &[];
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShutdownReason::Graceful => write!(formatter, "Server was shut down gracefully"),
+            ShutdownReason::Immediate => write!(formatter, "Server was dropped"),
+            ShutdownReason::AcceptThreadFailed(kind) => {
+                write!(formatter, "Accepting connections failed: {:?}", kind)
+            }
+            ShutdownReason::ListenerClosed => write!(formatter, "Listening socket was closed"),
+        }
+    }
+}
+
+/// Error returned by `Server::recv_checked()`, `Server::recv_timeout_checked()` and
+/// `Server::recv_with_token()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RecvError {
+    /// `Server::unblock()` was called.
+    Unblocked,
+    /// The server is shut down and no request is left in the queue.
+    Shutdown(ShutdownReason),
+    /// `WorkerToken::retire()` was called on the token passed to `Server::recv_with_token()`.
+    Retired,
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Unblocked => write!(formatter, "thread unblocked"),
+            RecvError::Shutdown(reason) => write!(formatter, "{}", reason),
+            RecvError::Retired => write!(formatter, "worker retired"),
+        }
+    }
+}
+
+impl Error for RecvError {}
+
+impl From<RecvError> for IoError {
+    fn from(err: RecvError) -> IoError {
+        let kind = match err {
+            RecvError::Shutdown(ShutdownReason::AcceptThreadFailed(kind)) => kind,
+            _ => IoErrorKind::Other,
+        };
+        IoError::new(kind, err)
+    }
+}
+
+/// Reason of the shutdown, shared between the server and its accept thread.
+#[derive(Default)]
+pub(crate) struct ShutdownState {
+    reason: Mutex<Option<ShutdownReason>>,
+}
+
+impl ShutdownState {
+    /// Records the reason of the shutdown. Returns false if the shutdown was already
+    /// initiated, in which case the first reason is kept.
+    pub(crate) fn initiate(&self, reason: ShutdownReason) -> bool {
+        let mut current = self.reason.lock().unwrap();
+        if current.is_some() {
+            return false;
+        }
+        *current = Some(reason);
+        true
+    }
+
+    pub(crate) fn reason(&self) -> Option<ShutdownReason> {
+        *self.reason.lock().unwrap()
+    }
+}
+
+/// Number of shards of the registry of the requests in flight, so that the connections
+/// rarely wait for each other.
+const IN_FLIGHT_SHARDS: usize = 8;
+
+/// Requests that were queued by the connections and not answered yet.
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    count: Mutex<usize>,
+    drained: Condvar,
+    // the requests by id, each in the shard `id % IN_FLIGHT_SHARDS`
+    shards: [Mutex<HashMap<u64, InFlightRequest>>; IN_FLIGHT_SHARDS],
+    next_id: AtomicU64,
+}
+
+impl InFlight {
+    /// Registers a new request until the returned guard is dropped.
+    pub(crate) fn start(self: &Arc<Self>, request: InFlightRequest) -> InFlightGuard {
+        let id = self.next_id.fetch_add(1, Relaxed);
+        self.shard(id).insert(id, request);
+        *self.count.lock().unwrap() += 1;
+        InFlightGuard {
+            in_flight: self.clone(),
+            id,
+        }
+    }
+
+    fn shard(&self, id: u64) -> MutexGuard<'_, HashMap<u64, InFlightRequest>> {
+        self.shards[(id % IN_FLIGHT_SHARDS as u64) as usize]
+            .lock()
+            .unwrap()
+    }
+
+    /// Returns the requests in flight, oldest first.
+    pub(crate) fn snapshot(&self) -> Vec<InFlightRequest> {
+        let mut requests = Vec::new();
+        for shard in &self.shards {
+            requests.extend(shard.lock().unwrap().values().cloned());
+        }
+        requests.sort_by_key(|request| request.started);
+        requests
+    }
+
+    /// Blocks until no request is in flight, or until the timeout expires. Returns false if
+    /// the timeout expired first.
+    pub(crate) fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut count = self.count.lock().unwrap();
+        while *count != 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            count = self.drained.wait_timeout(count, deadline - now).unwrap().0;
+        }
+        true
+    }
+}
+
+/// Held by a request while it is in flight.
+#[derive(Debug)]
+pub(crate) struct InFlightGuard {
+    in_flight: Arc<InFlight>,
+    id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.shard(self.id).remove(&self.id);
+        let mut count = self.in_flight.count.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.in_flight.drained.notify_all();
+        }
+    }
+}
+
+/// Handle to shut a server down from another thread, such as a thread waiting for signals.
+///
+/// Obtained with `Server::shutdown_handle()`. The handle doesn't keep the server alive.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    pub(crate) state: Arc<ShutdownState>,
+    pub(crate) close: Arc<AtomicBool>,
+    pub(crate) messages: Arc<MessagesQueue<Message>>,
+    pub(crate) in_flight: Arc<InFlight>,
+    pub(crate) listening_addrs: Vec<ListenAddr>,
+    pub(crate) accept_timeout: bool,
+}
+
+impl ShutdownHandle {
+    /// Stops accepting new connections, then waits until the requests that were already
+    /// queued, including the ones received later on the open connections, are answered or
+    /// dropped. A request turned into a writer with `Request::into_writer()` counts as
+    /// answered.
+    ///
+    /// Once the drain completes or the timeout expires, all the threads stuck in `recv()` or
+    /// `incoming_requests()` are unblocked with `RecvError::Shutdown(ShutdownReason::Graceful)`.
+    /// Until then, they keep receiving requests.
+    ///
+    /// Returns false if the timeout expired before the drain completed.
+    pub fn shutdown_gracefully(&self, timeout: Duration) -> bool {
+        self.stop_accepting();
+        let drained = self.in_flight.wait(timeout);
+        if !drained {
+            log::warn!(
+                "Requests still in flight after {:?}, shutting down",
+                timeout
+            );
+        }
+        self.initiate(ShutdownReason::Graceful);
+        drained
+    }
+
+    /// Returns why the server was shut down, or `None` if it is still running or draining.
+    pub fn shutdown_reason(&self) -> Option<ShutdownReason> {
+        self.state.reason()
+    }
+
+    /// Records the reason of the shutdown, then stops the accept thread and unblocks the
+    /// receivers. The first reason wins: returns false if the shutdown was already initiated.
+    pub(crate) fn initiate(&self, reason: ShutdownReason) -> bool {
+        if !self.state.initiate(reason) {
+            return false;
+        }
+        log::debug!("Shutting down server: {}", reason);
+
+        self.stop_accepting();
+        self.messages.close();
+        true
+    }
+
+    /// Stops the accept threads, once.
+    fn stop_accepting(&self) {
+        if self.close.swap(true, Relaxed) {
+            return;
+        }
+
+        // Connect briefly to ourselves to unblock the accept threads, unless they wake up by
+        // themselves. Connecting may wake up any server sharing the same socket instead, such
+        // as the servers of other processes in a pre-fork model, which then only see an empty
+        // connection; this is documented in `Server::prepare()`.
+        if !self.accept_timeout {
+            for listening_addr in &self.listening_addrs {
+                let maybe_stream = match listening_addr {
+                    ListenAddr::IP(addr) => TcpStream::connect(addr).map(Connection::from),
+                    #[cfg(unix)]
+                    ListenAddr::Unix(addr) => {
+                        // TODO: use connect_addr when its stabilized.
+                        let path = addr.as_pathname().unwrap();
+                        std::os::unix::net::UnixStream::connect(path).map(Connection::from)
+                    }
+                };
+                if let Ok(stream) = maybe_stream {
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+            }
+        }
+    }
+}
+
+/// Shuts the server down if the accept thread holding it panics, so that the receivers don't
+/// wait for requests forever.
+pub(crate) struct AcceptThreadGuard(pub(crate) ShutdownHandle);
+
+impl Drop for AcceptThreadGuard {
+    fn drop(&mut self) {
+        if std::thread::panicking()
+            && self
+                .0
+                .initiate(ShutdownReason::AcceptThreadFailed(IoErrorKind::Other))
+        {
+            log::error!("Accept thread stopped unexpectedly");
+        }
+    }
+}
+
+impl fmt::Debug for ShutdownHandle {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("ShutdownHandle")
+            .field("listening_addrs", &self.listening_addrs)
+            .field("reason", &self.state.reason())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AcceptErrorAction, AcceptThreadGuard, InFlight, ShutdownHandle, ShutdownReason,
+        ShutdownState,
+    };
+    use crate::stats::InFlightRequest;
+    use crate::util::MessagesQueue;
+    use crate::Method;
+    use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn request(sequence: u64) -> InFlightRequest {
+        InFlightRequest {
+            connection: 1,
+            sequence,
+            method: Method::Get,
+            path: "/".to_owned(),
+            remote_addr: None,
+            started: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn first_reason_wins() {
+        let state = ShutdownState::default();
+        assert_eq!(state.reason(), None);
+        assert!(state.initiate(ShutdownReason::Graceful));
+        assert!(!state.initiate(ShutdownReason::Immediate));
+        assert_eq!(state.reason(), Some(ShutdownReason::Graceful));
+    }
+
+    #[test]
+    fn accept_errors() {
+        let err = IoError::new(IoErrorKind::PermissionDenied, "denied");
+        assert_eq!(
+            ShutdownReason::from_accept_error(&err),
+            ShutdownReason::AcceptThreadFailed(IoErrorKind::PermissionDenied)
+        );
+        let err = IoError::new(IoErrorKind::InvalidInput, "not listening");
+        assert_eq!(
+            ShutdownReason::from_accept_error(&err),
+            ShutdownReason::ListenerClosed
+        );
+    }
+
+    #[test]
+    fn transient_accept_errors() {
+        let err = IoError::new(IoErrorKind::ConnectionAborted, "aborted");
+        assert_eq!(AcceptErrorAction::for_error(&err), AcceptErrorAction::Retry);
+        let err = IoError::new(IoErrorKind::Interrupted, "interrupted");
+        assert_eq!(AcceptErrorAction::for_error(&err), AcceptErrorAction::Retry);
+        #[cfg(unix)]
+        assert_eq!(
+            AcceptErrorAction::for_error(&IoError::from_raw_os_error(24)),
+            AcceptErrorAction::Backoff
+        );
+        let err = IoError::new(IoErrorKind::PermissionDenied, "denied");
+        assert_eq!(
+            AcceptErrorAction::for_error(&err),
+            AcceptErrorAction::Stop(ShutdownReason::AcceptThreadFailed(
+                IoErrorKind::PermissionDenied
+            ))
+        );
+    }
+
+    #[test]
+    fn panicking_accept_thread() {
+        let handle = ShutdownHandle {
+            state: Arc::new(ShutdownState::default()),
+            close: Arc::new(AtomicBool::new(false)),
+            messages: MessagesQueue::with_capacity(0),
+            in_flight: Arc::new(InFlight::default()),
+            listening_addrs: Vec::new(),
+            accept_timeout: true,
+        };
+
+        // a thread stopping normally leaves the reason to whoever stopped it
+        drop(AcceptThreadGuard(handle.clone()));
+        assert_eq!(handle.state.reason(), None);
+
+        let guard = AcceptThreadGuard(handle.clone());
+        let receiver = {
+            let messages = handle.messages.clone();
+            thread::spawn(move || messages.pop().is_none())
+        };
+        assert!(thread::spawn(move || {
+            let _guard = guard;
+            panic!("accept thread");
+        })
+        .join()
+        .is_err());
+        assert!(receiver.join().unwrap());
+        assert_eq!(
+            handle.state.reason(),
+            Some(ShutdownReason::AcceptThreadFailed(IoErrorKind::Other))
+        );
+    }
+
+    #[test]
+    fn in_flight_drain() {
+        let in_flight = Arc::new(InFlight::default());
+        assert!(in_flight.wait(Duration::from_secs(0)));
+
+        let first = in_flight.start(request(1));
+        let second = in_flight.start(request(2));
+        drop(first);
+        let remaining = in_flight.snapshot();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].sequence, 2);
+        assert!(!in_flight.wait(Duration::from_millis(10)));
+
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(second);
+        });
+        assert!(in_flight.wait(Duration::from_secs(5)));
+        releaser.join().unwrap();
+    }
+}
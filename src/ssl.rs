@@ -25,3 +25,13 @@ pub(crate) mod native_tls;
 pub(crate) use self::native_tls::NativeTlsContext as SslContextImpl;
 #[cfg(feature = "ssl-native-tls")]
 pub(crate) use self::native_tls::NativeTlsStream as SslStream;
+
+/// What the TLS handshake of a connection tells about the client, shared by the requests of
+/// the connection.
+#[derive(Debug, Default)]
+pub(crate) struct TlsSession {
+    /// The DER certificates sent by the client, its own certificate first, if it sent some.
+    pub(crate) peer_certificates: Option<Vec<Vec<u8>>>,
+    /// The server name that the client asked for with SNI.
+    pub(crate) sni_hostname: Option<String>,
+}
@@ -1,9 +1,12 @@
 use crate::connection::Connection;
+use crate::ssl::TlsSession;
 use crate::util::refined_tcp_stream::Stream as RefinedStream;
+use crate::TlsClientAuthMode;
 use std::error::Error;
 use std::io::{Read, Write};
 use std::net::{Shutdown, SocketAddr};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use zeroize::Zeroizing;
 
 /// A wrapper around a `native_tls` stream.
@@ -29,6 +32,31 @@ impl NativeTlsStream {
             .get_mut()
             .shutdown(how)
     }
+
+    /// Returns a new handle to the socket under the TLS layer.
+    pub(crate) fn try_clone_socket(&self) -> std::io::Result<Connection> {
+        self.0
+            .lock()
+            .expect("Failed to lock SSL stream mutex")
+            .get_ref()
+            .try_clone()
+    }
+
+    pub(crate) fn session(&self) -> TlsSession {
+        let stream = self.0.lock().expect("Failed to lock SSL stream mutex");
+        // native-tls only gives the certificate of the client, without its chain
+        let peer_certificates = stream
+            .peer_certificate()
+            .ok()
+            .flatten()
+            .and_then(|cert| cert.to_der().ok())
+            .map(|der| vec![der]);
+        // nor the server name that the client asked for
+        TlsSession {
+            peer_certificates,
+            sni_hostname: None,
+        }
+    }
 }
 
 impl Read for NativeTlsStream {
@@ -62,7 +90,11 @@ impl NativeTlsContext {
     pub fn from_pem(
         certificates: Vec<u8>,
         private_key: Zeroizing<Vec<u8>>,
+        client_auth: Option<&(Vec<u8>, TlsClientAuthMode)>,
     ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        if client_auth.is_some() {
+            return Err("The native-tls backend doesn't support client certificates.".into());
+        }
         let identity = native_tls::Identity::from_pkcs8(&certificates, &private_key)?;
         let acceptor = native_tls::TlsAcceptor::new(identity)?;
         Ok(Self(acceptor))
@@ -71,8 +103,13 @@ impl NativeTlsContext {
     pub fn accept(
         &self,
         stream: Connection,
+        timeout: Duration,
     ) -> Result<NativeTlsStream, Box<dyn Error + Send + Sync + 'static>> {
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
         let stream = self.0.accept(stream)?;
+        stream.get_ref().set_read_timeout(None)?;
+        stream.get_ref().set_write_timeout(None)?;
         Ok(NativeTlsStream(Arc::new(Mutex::new(stream))))
     }
 }
@@ -1,9 +1,12 @@
 use crate::connection::Connection;
+use crate::ssl::TlsSession;
 use crate::util::refined_tcp_stream::Stream as RefinedStream;
+use crate::TlsClientAuthMode;
 use std::error::Error;
 use std::io::{Read, Write};
 use std::net::{Shutdown, SocketAddr};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use zeroize::Zeroizing;
 
 pub(crate) struct OpenSslStream {
@@ -22,6 +25,29 @@ impl SplitOpenSslStream {
     pub(crate) fn shutdown(&mut self, how: Shutdown) -> std::io::Result<()> {
         self.0.lock().unwrap().inner.get_mut().shutdown(how)
     }
+
+    /// Returns a new handle to the socket under the TLS layer.
+    pub(crate) fn try_clone_socket(&self) -> std::io::Result<Connection> {
+        self.0.lock().unwrap().inner.get_ref().try_clone()
+    }
+
+    pub(crate) fn session(&self) -> TlsSession {
+        let stream = self.0.lock().unwrap();
+        let ssl = stream.inner.ssl();
+        // on the server side, the chain doesn't include the certificate of the client
+        let peer_certificates = ssl.peer_certificate().map(|cert| {
+            std::iter::once(cert.as_ref())
+                .chain(ssl.peer_cert_chain().into_iter().flatten())
+                .filter_map(|cert| cert.to_der().ok())
+                .collect()
+        });
+        TlsSession {
+            peer_certificates,
+            sni_hostname: ssl
+                .servername(openssl::ssl::NameType::HOST_NAME)
+                .map(str::to_owned),
+        }
+    }
 }
 
 impl Clone for SplitOpenSslStream {
@@ -68,6 +94,7 @@ impl OpenSslContext {
     pub fn from_pem(
         certificates: Vec<u8>,
         private_key: Zeroizing<Vec<u8>>,
+        client_auth: Option<&(Vec<u8>, TlsClientAuthMode)>,
     ) -> Result<Self, Box<dyn Error + Send + Sync>> {
         use openssl::pkey::PKey;
         use openssl::ssl::{self, SslVerifyMode};
@@ -86,7 +113,25 @@ impl OpenSslContext {
         }
         let key = PKey::private_key_from_pem(&private_key)?;
         ctx.set_private_key(&key)?;
-        ctx.set_verify(SslVerifyMode::NONE);
+        match client_auth {
+            Some((ca_certificates, mode)) => {
+                let authorities = X509::stack_from_pem(ca_certificates)?;
+                if authorities.is_empty() {
+                    return Err("Couldn't extract the client CA certificates from config.".into());
+                }
+                for authority in authorities {
+                    ctx.add_client_ca(&authority)?;
+                    ctx.cert_store_mut().add_cert(authority)?;
+                }
+                ctx.set_verify(match mode {
+                    TlsClientAuthMode::Optional => SslVerifyMode::PEER,
+                    TlsClientAuthMode::Required => {
+                        SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT
+                    }
+                });
+            }
+            None => ctx.set_verify(SslVerifyMode::NONE),
+        }
         ctx.check_private_key()?;
 
         Ok(Self(ctx.build()))
@@ -95,10 +140,15 @@ impl OpenSslContext {
     pub fn accept(
         &self,
         stream: Connection,
+        timeout: Duration,
     ) -> Result<OpenSslStream, Box<dyn Error + Send + Sync + 'static>> {
         use openssl::ssl::Ssl;
         let session = Ssl::new(&self.0).expect("Failed to create new OpenSSL session");
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
         let stream = session.accept(stream)?;
+        stream.get_ref().set_read_timeout(None)?;
+        stream.get_ref().set_write_timeout(None)?;
         Ok(OpenSslStream { inner: stream })
     }
 }
@@ -1,9 +1,12 @@
 use crate::connection::Connection;
+use crate::ssl::TlsSession;
 use crate::util::refined_tcp_stream::Stream as RefinedStream;
+use crate::TlsClientAuthMode;
 use std::error::Error;
 use std::io::{Read, Write};
 use std::net::{Shutdown, SocketAddr};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use zeroize::Zeroizing;
 
 /// A wrapper around an owned Rustls connection and corresponding stream.
@@ -29,6 +32,26 @@ impl RustlsStream {
             .sock
             .shutdown(how)
     }
+
+    /// Returns a new handle to the socket under the TLS layer.
+    pub(crate) fn try_clone_socket(&self) -> std::io::Result<Connection> {
+        self.0
+            .lock()
+            .expect("Failed to lock SSL stream mutex")
+            .sock
+            .try_clone()
+    }
+
+    pub(crate) fn session(&self) -> TlsSession {
+        let stream = self.0.lock().expect("Failed to lock SSL stream mutex");
+        TlsSession {
+            peer_certificates: stream
+                .conn
+                .peer_certificates()
+                .map(|chain| chain.iter().map(|cert| cert.0.clone()).collect()),
+            sni_hostname: stream.conn.sni_hostname().map(str::to_owned),
+        }
+    }
 }
 
 impl Clone for RustlsStream {
@@ -68,6 +91,7 @@ impl RustlsContext {
     pub(crate) fn from_pem(
         certificates: Vec<u8>,
         private_key: Zeroizing<Vec<u8>>,
+        client_auth: Option<&(Vec<u8>, TlsClientAuthMode)>,
     ) -> Result<Self, Box<dyn Error + Send + Sync>> {
         let certificate_chain: Vec<rustls::Certificate> =
             rustls_pemfile::certs(&mut certificates.as_slice())?
@@ -94,10 +118,28 @@ impl RustlsContext {
             }
         });
 
-        let tls_conf = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(certificate_chain, private_key)?;
+        let tls_conf = rustls::ServerConfig::builder().with_safe_defaults();
+        let tls_conf = match client_auth {
+            Some((ca_certificates, mode)) => {
+                let mut roots = rustls::RootCertStore::empty();
+                for certificate in rustls_pemfile::certs(&mut ca_certificates.as_slice())? {
+                    roots.add(&rustls::Certificate(certificate))?;
+                }
+                if roots.is_empty() {
+                    return Err("Couldn't extract the client CA certificates from config.".into());
+                }
+                tls_conf.with_client_cert_verifier(match mode {
+                    TlsClientAuthMode::Optional => {
+                        rustls::server::AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+                    }
+                    TlsClientAuthMode::Required => {
+                        rustls::server::AllowAnyAuthenticatedClient::new(roots)
+                    }
+                })
+            }
+            None => tls_conf.with_no_client_auth(),
+        };
+        let tls_conf = tls_conf.with_single_cert(certificate_chain, private_key)?;
 
         Ok(Self(Arc::new(tls_conf)))
     }
@@ -105,11 +147,20 @@ impl RustlsContext {
     pub(crate) fn accept(
         &self,
         stream: Connection,
+        timeout: Duration,
     ) -> Result<RustlsStream, Box<dyn Error + Send + Sync + 'static>> {
         let connection = rustls::ServerConnection::new(self.0.clone())?;
-        Ok(RustlsStream(Arc::new(Mutex::new(
-            rustls::StreamOwned::new(connection, stream),
-        ))))
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut stream = rustls::StreamOwned::new(connection, stream);
+        // rustls would do the handshake on the first read, completing it now makes it count
+        // in the limit of concurrent handshakes
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        stream.sock.set_read_timeout(None)?;
+        stream.sock.set_write_timeout(None)?;
+        Ok(RustlsStream(Arc::new(Mutex::new(stream))))
     }
 }
 
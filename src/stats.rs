@@ -0,0 +1,251 @@
+//! Counters describing the activity of a server.
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::common::{HTTPVersion, Method, StatusCode};
+#[cfg(feature = "profiling")]
+use crate::profiling::Profile;
+use crate::shutdown::InFlight;
+
+/// Snapshot of the counters of a server, returned by `Server::stats()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServerStats {
+    /// Number of connections whose peer address couldn't be determined.
+    ///
+    /// Requests of these connections are still served, but `Request::remote_addr()` returns
+    /// `None` for them.
+    pub unknown_peer_connections: usize,
+
+    /// Number of warnings logged because a request waited too long in the queue.
+    ///
+    /// See `ServerConfigAdvanced::with_queue_latency_warning`.
+    pub queue_latency_warnings: usize,
+
+    /// Number of requests answered with a 503 by the load shedding.
+    ///
+    /// See `ServerConfigAdvanced::with_load_shedding`.
+    pub shed_requests: usize,
+
+    /// Number of requests rejected because their path isn't in the allowlist.
+    ///
+    /// See `ServerConfigAdvanced::with_path_prefix_allowlist`.
+    pub rejected_paths: usize,
+
+    /// Total size in bytes of the connection buffers freed because they grew above the
+    /// threshold.
+    ///
+    /// See `ServerConfigAdvanced::with_buffer_shrink_threshold`.
+    pub reclaimed_buffer_bytes: usize,
+    /// Total size in bytes of the data that clients sent after a request that closed the
+    /// connection, and that was discarded without being parsed.
+    pub surplus_bytes_after_close: usize,
+    /// Number of TLS handshakes in progress.
+    pub tls_handshakes_in_progress: usize,
+    /// Number of new connections closed because they waited too long for their TLS
+    /// handshake.
+    ///
+    /// See `ServerConfigAdvanced::with_max_concurrent_tls_handshakes`.
+    pub tls_handshake_queue_timeouts: usize,
+    /// Number of new connections closed because too many connections were already waiting
+    /// for their TLS handshake.
+    ///
+    /// See `ServerConfigAdvanced::with_max_queued_tls_handshakes`.
+    pub tls_handshake_queue_rejections: usize,
+    /// Number of connections closed because the client didn't send a request in time.
+    ///
+    /// See `ServerConfigAdvanced::with_keep_alive_timeout`.
+    pub keep_alive_timeouts: usize,
+    /// Number of new connections closed because the connection setup callback failed.
+    ///
+    /// See `ServerConfigAdvanced::with_connection_setup`.
+    pub rejected_connections: usize,
+    /// Number of new connections dropped or rejected by the connection filter.
+    ///
+    /// See `ServerConfigAdvanced::with_connection_filter`.
+    pub filtered_connections: usize,
+    /// Number of new connections closed because too many connections were open.
+    ///
+    /// See `ConnectionLimitMode::RespondUnavailable`.
+    pub connection_limit_rejections: usize,
+    /// Number of new connections closed because too many connections were waiting for a
+    /// worker thread.
+    ///
+    /// See `TaskQueueLimitMode::Drop`.
+    pub task_queue_rejections: usize,
+    /// Number of times accepting a connection failed with an error that the server recovered
+    /// from.
+    ///
+    /// See `ServerConfigAdvanced::with_accept_error_handler`.
+    pub accept_errors: usize,
+    /// Number of connections accepted by the server, including the ones closed right away
+    /// because of a limit or a filter.
+    pub accepted_connections: usize,
+    /// Number of requests read from the clients, including the ones answered by the server
+    /// itself and never returned by `Server::recv()`.
+    pub parsed_requests: usize,
+    /// Number of responses written with `Request::respond()`. The responses that couldn't be
+    /// written entirely, for example because the client went away, don't count.
+    ///
+    /// See `Server::pending_requests` for the requests waiting to be received.
+    pub written_responses: usize,
+}
+
+/// Counters shared between the server and its connections.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    pub(crate) unknown_peer_connections: AtomicUsize,
+    pub(crate) queue_latency_warnings: AtomicUsize,
+    pub(crate) shed_requests: AtomicUsize,
+    pub(crate) rejected_paths: AtomicUsize,
+    pub(crate) reclaimed_buffer_bytes: AtomicUsize,
+    pub(crate) surplus_bytes_after_close: AtomicUsize,
+    pub(crate) tls_handshakes_in_progress: AtomicUsize,
+    pub(crate) tls_handshake_queue_timeouts: AtomicUsize,
+    pub(crate) tls_handshake_queue_rejections: AtomicUsize,
+    pub(crate) keep_alive_timeouts: AtomicUsize,
+    pub(crate) rejected_connections: AtomicUsize,
+    pub(crate) filtered_connections: AtomicUsize,
+    pub(crate) connection_limit_rejections: AtomicUsize,
+    pub(crate) task_queue_rejections: AtomicUsize,
+    pub(crate) accept_errors: AtomicUsize,
+    pub(crate) accepted_connections: AtomicUsize,
+    pub(crate) parsed_requests: AtomicUsize,
+    pub(crate) written_responses: AtomicUsize,
+    // requests pushed to the queue of the server, used by `Replay::run()`
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) queued_requests: AtomicUsize,
+    // requests queued and not answered yet, waited for by `ShutdownHandle`
+    pub(crate) in_flight: Arc<InFlight>,
+    // connections accepted so far, numbering the connections of `Request::connection_id`
+    pub(crate) connections: AtomicU64,
+    #[cfg(feature = "profiling")]
+    pub(crate) profile: Arc<Profile>,
+}
+
+impl Counters {
+    pub(crate) fn snapshot(&self) -> ServerStats {
+        ServerStats {
+            unknown_peer_connections: self.unknown_peer_connections.load(Relaxed),
+            queue_latency_warnings: self.queue_latency_warnings.load(Relaxed),
+            shed_requests: self.shed_requests.load(Relaxed),
+            rejected_paths: self.rejected_paths.load(Relaxed),
+            reclaimed_buffer_bytes: self.reclaimed_buffer_bytes.load(Relaxed),
+            surplus_bytes_after_close: self.surplus_bytes_after_close.load(Relaxed),
+            tls_handshakes_in_progress: self.tls_handshakes_in_progress.load(Relaxed),
+            tls_handshake_queue_timeouts: self.tls_handshake_queue_timeouts.load(Relaxed),
+            tls_handshake_queue_rejections: self.tls_handshake_queue_rejections.load(Relaxed),
+            keep_alive_timeouts: self.keep_alive_timeouts.load(Relaxed),
+            rejected_connections: self.rejected_connections.load(Relaxed),
+            filtered_connections: self.filtered_connections.load(Relaxed),
+            connection_limit_rejections: self.connection_limit_rejections.load(Relaxed),
+            task_queue_rejections: self.task_queue_rejections.load(Relaxed),
+            accept_errors: self.accept_errors.load(Relaxed),
+            accepted_connections: self.accepted_connections.load(Relaxed),
+            parsed_requests: self.parsed_requests.load(Relaxed),
+            written_responses: self.written_responses.load(Relaxed),
+        }
+    }
+}
+
+/// A request that was queued and not answered yet, returned by `Server::in_flight()`.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct InFlightRequest {
+    /// Number of the connection of the request, the first connection accepted by the server
+    /// being 1, see `Request::connection_id()`.
+    pub connection: u64,
+
+    /// Number of the request on its connection, the first request being 1, see
+    /// `Request::request_seq()`.
+    pub sequence: u64,
+
+    /// Method of the request, see `Request::method()`.
+    pub method: Method,
+
+    /// Path of the request, sanitized and truncated with `sanitize::sanitize_path()`.
+    pub path: String,
+
+    /// Address of the client, or of the proxy in front of the server, see
+    /// `Request::remote_addr()`. `None` for UNIX sockets.
+    pub remote_addr: Option<SocketAddr>,
+
+    /// When the request was pushed to the queue of the server.
+    pub started: Instant,
+}
+
+impl InFlightRequest {
+    /// Returns how long the request has been in flight.
+    pub fn age(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// Summary of an answered request, passed to the hook set with
+/// `ServerConfigAdvanced::with_access_log`.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct AccessLogEntry {
+    /// Address of the client, or of the proxy in front of the server, see
+    /// `Request::remote_addr()`. `None` for UNIX sockets.
+    pub remote_addr: Option<SocketAddr>,
+
+    /// Method of the request, see `Request::method()`.
+    pub method: Method,
+
+    /// Path of the request as sent by the client, including the query string. It isn't
+    /// sanitized, so it must be escaped before being written to a log.
+    pub path: String,
+
+    /// HTTP version of the request, see `Request::http_version()`.
+    pub http_version: HTTPVersion,
+
+    /// Status code of the final response, even if it couldn't be written.
+    pub status_code: StatusCode,
+
+    /// Number of bytes of the body that were written, without the headers and the chunked
+    /// framing. 0 for the responses to `HEAD` requests, and for responses that couldn't be
+    /// written.
+    pub body_bytes: u64,
+
+    /// Time between reading the request line, see `Request::received_at()`, and writing the
+    /// end of the response. This includes reading the headers, waiting in the queue of the
+    /// server and handling the request, unlike `Request::queue_latency()`.
+    pub elapsed: Duration,
+
+    /// Informational (`1xx`) responses sent before the final one, and when they were sent,
+    /// see `Request::interim_responses()`.
+    pub interim_responses: Vec<(StatusCode, Instant)>,
+
+    /// Number of bytes of the request body read by the application. A request that got a
+    /// `100 Continue` but whose body never came has 0 here.
+    pub body_read_bytes: u64,
+}
+
+/// Renders `requests` as a plain text table, one request per line.
+pub(crate) fn in_flight_table(requests: &[InFlightRequest]) -> String {
+    let mut table = format!(
+        "{:>10}  {:>6}  {:>5}  {:<7}  {:<40}  {}\n",
+        "AGE", "CONN", "SEQ", "METHOD", "REMOTE", "PATH"
+    );
+    for request in requests {
+        let remote = request
+            .remote_addr
+            .map_or_else(|| "-".to_owned(), |addr| addr.to_string());
+        let _ = writeln!(
+            table,
+            "{:>9.3}s  {:>6}  {:>5}  {:<7}  {:<40}  {}",
+            request.age().as_secs_f64(),
+            request.connection,
+            request.sequence,
+            request.method.to_string(),
+            remote,
+            request.path
+        );
+    }
+    table
+}
@@ -0,0 +1,288 @@
+//! Parsing of the request-target of the request line, see `Request::target`.
+
+use crate::{Header, Method};
+
+/// The request-target of a request, in one of the four forms of RFC 7230 §5.3.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestTarget {
+    /// `/path?query`, used by most requests.
+    Origin(String),
+    /// `http://host:port/path?query`, used by requests made to proxies.
+    Absolute {
+        /// The scheme, in lowercase.
+        scheme: String,
+        /// The host, with the brackets of an IPv6 address.
+        host: String,
+        /// The port, if any.
+        port: Option<u16>,
+        /// The path and query string, `/` if the target has no path.
+        path: String,
+    },
+    /// `host:port`, only used by `CONNECT` requests, as sent by the client. The port is
+    /// always present, and the host keeps the brackets of an IPv6 address.
+    Authority(String),
+    /// `*`, only used by `OPTIONS` requests that are about the server as a whole.
+    Asterisk,
+}
+
+impl RequestTarget {
+    /// Returns the host of an absolute-form or authority-form target.
+    pub fn host(&self) -> Option<&str> {
+        match self {
+            RequestTarget::Absolute { host, .. } => Some(host),
+            RequestTarget::Authority(authority) => parse_authority(authority).map(|(host, _)| host),
+            _ => None,
+        }
+    }
+
+    /// Returns the port of an absolute-form or authority-form target, if it has one.
+    pub fn port(&self) -> Option<u16> {
+        match self {
+            RequestTarget::Absolute { port, .. } => *port,
+            RequestTarget::Authority(authority) => parse_authority(authority)?.1,
+            _ => None,
+        }
+    }
+
+    /// Returns the path and query string of an origin-form or absolute-form target.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            RequestTarget::Origin(path) | RequestTarget::Absolute { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Returns the target as given by `Request::url()`.
+    pub(crate) fn url(&self) -> &str {
+        match self {
+            RequestTarget::Origin(path) | RequestTarget::Absolute { path, .. } => path,
+            RequestTarget::Authority(authority) => authority,
+            RequestTarget::Asterisk => "*",
+        }
+    }
+
+    /// Same as `url()`, but takes the string instead of copying it.
+    pub(crate) fn into_url(self) -> String {
+        match self {
+            RequestTarget::Origin(path) | RequestTarget::Absolute { path, .. } => path,
+            RequestTarget::Authority(authority) => authority,
+            RequestTarget::Asterisk => "*".to_owned(),
+        }
+    }
+}
+
+/// Parses the request-target of a request with the given method, returning `None` if it is
+/// malformed or if its form can't be used with this method.
+///
+/// Non-ASCII characters are accepted in the path and query string, as tiny-http always did,
+/// although RFC 3986 requires them to be percent-encoded.
+pub(crate) fn parse(method: &Method, target: &str) -> Option<RequestTarget> {
+    if target.is_empty()
+        || !target
+            .bytes()
+            .all(|b| b.is_ascii_graphic() || !b.is_ascii())
+    {
+        return None;
+    }
+
+    if *method == Method::Connect {
+        parse_authority(target)?.1?;
+        return Some(RequestTarget::Authority(target.to_owned()));
+    }
+    if target == "*" {
+        return if *method == Method::Options {
+            Some(RequestTarget::Asterisk)
+        } else {
+            None
+        };
+    }
+    if target.starts_with('/') {
+        return Some(RequestTarget::Origin(target.to_owned()));
+    }
+
+    let (scheme, rest) = target.split_once("://")?;
+    let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b));
+    if !valid_scheme {
+        return None;
+    }
+    let end = rest.find(|c| c == '/' || c == '?').unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+    // the fragment is never sent by a client
+    if path.contains('#') {
+        return None;
+    }
+    let (host, port) = parse_authority(authority)?;
+    let path = if path.starts_with('/') {
+        path.to_owned()
+    } else {
+        format!("/{}", path)
+    };
+    Some(RequestTarget::Absolute {
+        scheme: scheme.to_ascii_lowercase(),
+        host: host.to_owned(),
+        port,
+        path,
+    })
+}
+
+/// Parses `host[:port]`, where the host is a name, an IPv4 address or an IPv6 address
+/// between brackets. User information isn't allowed.
+///
+/// This is also the syntax of the `Host` header.
+pub(crate) fn parse_authority(authority: &str) -> Option<(&str, Option<u16>)> {
+    let (host, port) = if authority.starts_with('[') {
+        let end = authority.find(']')? + 1;
+        let address = &authority[1..end - 1];
+        if address.is_empty()
+            || !address
+                .bytes()
+                .all(|b| b.is_ascii_hexdigit() || b":.".contains(&b))
+        {
+            return None;
+        }
+        (&authority[..end], &authority[end..])
+    } else {
+        let end = authority.find(':').unwrap_or(authority.len());
+        let host = &authority[..end];
+        let valid = !host.is_empty()
+            && host
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-._~%!$&'()*+,;=".contains(&b));
+        if !valid {
+            return None;
+        }
+        (host, &authority[end..])
+    };
+
+    let port = match port.strip_prefix(':') {
+        Some(port) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+            Some(port.parse().ok()?)
+        }
+        Some(_) => return None,
+        None if port.is_empty() => None,
+        None => return None,
+    };
+    Some((host, port))
+}
+
+/// Returns false if an HTTP/1.1 request has no `Host` header, or several ones with different
+/// values (RFC 7230 §5.4).
+pub(crate) fn has_valid_host(headers: &[Header]) -> bool {
+    let mut hosts = headers
+        .iter()
+        .filter(|h| h.field.equiv("Host"))
+        .map(|h| h.value.as_str());
+    match hosts.next() {
+        Some(first) => hosts.all(|host| host.eq_ignore_ascii_case(first)),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{has_valid_host, parse, parse_authority, RequestTarget};
+    use crate::Header;
+    use crate::Method;
+
+    #[test]
+    fn origin_form() {
+        let target = parse(&Method::Get, "/a/b?c=d").unwrap();
+        assert_eq!(target, RequestTarget::Origin("/a/b?c=d".to_owned()));
+        assert_eq!(target.path(), Some("/a/b?c=d"));
+        assert_eq!(target.host(), None);
+        assert_eq!(target.port(), None);
+
+        // as before the request-target was parsed
+        let target = parse(&Method::Get, "/caf\u{e9}?q=\u{e9}t\u{e9}").unwrap();
+        assert_eq!(target.path(), Some("/caf\u{e9}?q=\u{e9}t\u{e9}"));
+    }
+
+    #[test]
+    fn absolute_form() {
+        let target = parse(&Method::Get, "HTTP://example.com:8080/a?b").unwrap();
+        assert_eq!(
+            target,
+            RequestTarget::Absolute {
+                scheme: "http".to_owned(),
+                host: "example.com".to_owned(),
+                port: Some(8080),
+                path: "/a?b".to_owned(),
+            }
+        );
+        assert_eq!(target.host(), Some("example.com"));
+        assert_eq!(target.port(), Some(8080));
+
+        let target = parse(&Method::Get, "http://[::1]?q").unwrap();
+        assert_eq!(target.host(), Some("[::1]"));
+        assert_eq!(target.port(), None);
+        assert_eq!(target.path(), Some("/?q"));
+        assert_eq!(
+            parse(&Method::Post, "https://10.0.0.1").unwrap().path(),
+            Some("/")
+        );
+    }
+
+    #[test]
+    fn authority_form() {
+        let target = parse(&Method::Connect, "example.com:443").unwrap();
+        assert_eq!(
+            target,
+            RequestTarget::Authority("example.com:443".to_owned())
+        );
+        assert_eq!(target.host(), Some("example.com"));
+        assert_eq!(target.port(), Some(443));
+        assert_eq!(target.path(), None);
+        assert_eq!(
+            parse(&Method::Connect, "[2001:db8::1]:8443")
+                .unwrap()
+                .port(),
+            Some(8443)
+        );
+    }
+
+    #[test]
+    fn asterisk_form() {
+        assert_eq!(parse(&Method::Options, "*"), Some(RequestTarget::Asterisk));
+        assert_eq!(parse(&Method::Get, "*"), None);
+    }
+
+    #[test]
+    fn host_headers() {
+        assert_eq!(parse_authority("example.com"), Some(("example.com", None)));
+        assert_eq!(parse_authority("[::1]:8080"), Some(("[::1]", Some(8080))));
+        assert_eq!(parse_authority(""), None);
+
+        let host = |value: &str| Header::from_bytes(&b"Host"[..], value).unwrap();
+        assert!(has_valid_host(&[host("a.com")]));
+        assert!(has_valid_host(&[host("a.com"), host("A.com")]));
+        assert!(!has_valid_host(&[host("a.com"), host("b.com")]));
+        assert!(!has_valid_host(&[]));
+    }
+
+    #[test]
+    fn malformed_targets() {
+        let cases = [
+            (Method::Get, ""),
+            (Method::Get, "hello"),
+            (Method::Get, "/caf\u{e9} menu"),
+            (Method::Get, "http://caf\u{e9}.com/"),
+            (Method::Get, "http://"),
+            (Method::Get, "http:///path"),
+            (Method::Get, "1http://example.com/"),
+            (Method::Get, "http://user@example.com/"),
+            (Method::Get, "http://example.com:/"),
+            (Method::Get, "http://example.com:99999/"),
+            (Method::Get, "http://[::1/"),
+            (Method::Get, "http://example.com/#top"),
+            (Method::Connect, "example.com"),
+            (Method::Connect, "/path"),
+            (Method::Connect, "example.com:443/path"),
+        ];
+        for (method, target) in cases.iter() {
+            assert_eq!(parse(method, target), None, "{}", target);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! State of the TCP connection of a request, enabled by the `tcp-diagnostics` feature.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Snapshot of the kernel's `TCP_INFO` for the connection of a request, returned by
+/// `Request::tcp_diagnostics()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize),
+    serde(crate = "serde_crate")
+)]
+#[non_exhaustive]
+pub struct TcpDiagnostics {
+    /// Smoothed round-trip time.
+    pub rtt: Duration,
+    /// Variation of the round-trip time.
+    pub rtt_var: Duration,
+    /// Maximum segment size used to send data.
+    pub snd_mss: u32,
+    /// Number of segments retransmitted since the connection was opened.
+    pub total_retrans: u32,
+    /// Number of bytes written to the socket but not acknowledged by the client yet.
+    pub send_queue: u32,
+    /// Number of bytes received from the client but not read by the server yet.
+    pub recv_queue: u32,
+}
+
+/// Local and remote addresses of a plain TCP connection, to find its socket.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct TcpAddrs {
+    pub(crate) local: SocketAddr,
+    pub(crate) peer: SocketAddr,
+}
+
+impl TcpAddrs {
+    /// Queries the kernel for the current state of the connection.
+    ///
+    /// Returns `None` if the connection is closed or if the kernel can't be queried.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn diagnostics(&self) -> Option<TcpDiagnostics> {
+        sock_diag::query(self)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn diagnostics(&self) -> Option<TcpDiagnostics> {
+        None
+    }
+}
+
+/// Reads `TCP_INFO` through the `sock_diag` netlink interface, which returns the same data as
+/// `getsockopt` without having to go through the file descriptor.
+#[cfg(target_os = "linux")]
+mod sock_diag {
+    use std::convert::TryInto;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use netlink_packet_core::{NetlinkHeader, NetlinkMessage, NetlinkPayload, NLM_F_REQUEST};
+    use netlink_packet_sock_diag::inet::nlas::Nla;
+    use netlink_packet_sock_diag::inet::{ExtensionFlags, InetRequest, SocketId, StateFlags};
+    use netlink_packet_sock_diag::{SockDiagMessage, AF_INET, AF_INET6, IPPROTO_TCP};
+    use netlink_sys::{protocols::NETLINK_SOCK_DIAG, Socket, SocketAddr as NetlinkAddr};
+
+    use super::{TcpAddrs, TcpDiagnostics};
+
+    pub(super) fn query(addrs: &TcpAddrs) -> Option<TcpDiagnostics> {
+        let family = match addrs.local {
+            SocketAddr::V4(_) => AF_INET,
+            SocketAddr::V6(_) => AF_INET6,
+        };
+        let mut header = NetlinkHeader::default();
+        header.flags = NLM_F_REQUEST;
+        let mut packet = NetlinkMessage::new(
+            header,
+            SockDiagMessage::InetRequest(InetRequest {
+                family,
+                protocol: IPPROTO_TCP,
+                extensions: ExtensionFlags::INFO,
+                states: StateFlags::all(),
+                socket_id: SocketId {
+                    source_port: addrs.local.port(),
+                    destination_port: addrs.peer.port(),
+                    source_address: addrs.local.ip(),
+                    destination_address: addrs.peer.ip(),
+                    interface_id: 0,
+                    // INET_DIAG_NOCOOKIE, to look the socket up by its addresses only
+                    cookie: [0xff; 8],
+                },
+            })
+            .into(),
+        );
+        packet.finalize();
+        let mut request = vec![0; packet.buffer_len()];
+        packet.serialize(&mut request);
+
+        let mut socket = Socket::new(NETLINK_SOCK_DIAG).ok()?;
+        socket.bind_auto().ok()?;
+        socket.connect(&NetlinkAddr::new(0, 0)).ok()?;
+        socket.send(&request, 0).ok()?;
+
+        let mut response = vec![0; 4096];
+        let len = socket.recv(&mut &mut response[..], 0).ok()?;
+        let message = NetlinkMessage::<SockDiagMessage>::deserialize(&response[..len]).ok()?;
+        let response = match message.payload {
+            NetlinkPayload::InnerMessage(SockDiagMessage::InetResponse(response)) => response,
+            // the connection is already closed
+            _ => return None,
+        };
+
+        let info = response.nlas.iter().find_map(|nla| match nla {
+            Nla::TcpInfo(info) => Some(info),
+            _ => None,
+        })?;
+        // offsets of the fields in `struct tcp_info`, which only grows at its end
+        let field = |offset: usize| -> Option<u32> {
+            let bytes = info.get(offset..offset + 4)?;
+            Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+        };
+        Some(TcpDiagnostics {
+            rtt: Duration::from_micros(field(68)?.into()),
+            rtt_var: Duration::from_micros(field(72)?.into()),
+            snd_mss: field(16)?,
+            total_retrans: field(100)?,
+            send_queue: response.header.send_queue,
+            recv_queue: response.header.recv_queue,
+        })
+    }
+}
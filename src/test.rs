@@ -1,7 +1,25 @@
+use crate::target::{self, RequestTarget};
 use crate::{request::new_request, HTTPVersion, Header, HeaderField, Method, Request};
 use ascii::AsciiString;
+use std::io::Write;
 use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+mod capture;
+#[cfg(any(test, feature = "testing"))]
+mod faulty;
+#[cfg(any(test, feature = "testing"))]
+mod replay;
+
+pub use self::capture::ResponseCapture;
+#[cfg(any(test, feature = "testing"))]
+pub use self::faulty::{FaultyReader, FaultyWriter};
+#[cfg(any(test, feature = "testing"))]
+pub(crate) use self::replay::Recorder;
+#[cfg(any(test, feature = "testing"))]
+pub use self::replay::{RecorderHandle, Replay, ReplayStep};
 
 /// A simpler version of [`Request`] that is useful for testing. No data actually goes anywhere.
 ///
@@ -51,33 +69,47 @@ pub struct TestRequest {
 }
 
 impl From<TestRequest> for Request {
-    fn from(mut mock: TestRequest) -> Request {
-        // if the user didn't set the Content-Length header, then set it for them
-        // otherwise, leave it alone (it may be under test)
-        if !mock
-            .headers
-            .iter_mut()
-            .any(|h| h.field.equiv("Content-Length"))
-        {
-            mock.headers.push(Header {
-                field: HeaderField::from_str("Content-Length").unwrap(),
-                value: AsciiString::from_ascii(mock.body.len().to_string()).unwrap(),
-            });
-        }
-        new_request(
-            mock.secure,
-            mock.method,
-            mock.path,
-            mock.http_version,
-            mock.headers,
-            Some(mock.remote_addr),
-            mock.body.as_bytes(),
-            std::io::sink(),
-        )
-        .unwrap()
+    fn from(mock: TestRequest) -> Request {
+        build_request(mock, std::io::sink())
     }
 }
 
+/// Builds the request, writing its response to `writer`.
+fn build_request<W: Write + Send + 'static>(mut mock: TestRequest, writer: W) -> Request {
+    // if the user didn't set the Content-Length or Transfer-Encoding header, then set
+    // Content-Length for them, otherwise, leave it alone (it may be under test)
+    if !mock
+        .headers
+        .iter_mut()
+        .any(|h| h.field.equiv("Content-Length") || h.field.equiv("Transfer-Encoding"))
+    {
+        mock.headers.push(Header {
+            field: HeaderField::from_str("Content-Length").unwrap(),
+            value: AsciiString::from_ascii(mock.body.len().to_string()).unwrap(),
+        });
+    }
+    // the request line of a real request is checked by the client, but test requests can have
+    // any path
+    let target = match target::parse(&mock.method, &mock.path) {
+        Some(target) => target,
+        None => RequestTarget::Origin(mock.path),
+    };
+    new_request(
+        mock.secure,
+        mock.method,
+        target,
+        mock.http_version,
+        mock.headers,
+        Some(mock.remote_addr),
+        mock.body.as_bytes(),
+        writer,
+        Arc::default(),
+        None,
+        (Instant::now(), SystemTime::now()),
+    )
+    .unwrap()
+}
+
 impl Default for TestRequest {
     fn default() -> Self {
         TestRequest {
@@ -124,4 +156,76 @@ impl TestRequest {
         self.headers.push(header);
         self
     }
+
+    /// Converts the `TestRequest` into a `Request` whose response is kept, instead of being
+    /// discarded, so that the test can check what the code under test answered.
+    ///
+    /// ```
+    /// # use tiny_http::{Response, StatusCode, TestRequest};
+    /// let (request, captured) = TestRequest::new().into_request_with_capture();
+    /// request.respond(Response::from_string("hello")).unwrap();
+    /// assert_eq!(captured.status_code(), Some(StatusCode(200)));
+    /// assert_eq!(captured.body_bytes(), b"hello");
+    /// ```
+    pub fn into_request_with_capture(self) -> (Request, ResponseCapture) {
+        let (capture, writer) = ResponseCapture::new();
+        (build_request(self, writer), capture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TestRequest;
+    use crate::{Header, Response, StatusCode};
+    use std::io::{self, ErrorKind, Read, Write};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn capture_response() {
+        let (request, captured) = TestRequest::new().into_request_with_capture();
+        assert_eq!(captured.status_code(), None);
+        let header = Header::from_bytes(&b"X-Test"[..], &b"yes"[..]).unwrap();
+        request
+            .respond(
+                Response::from_string("hello")
+                    .with_status_code(201)
+                    .with_header(header),
+            )
+            .unwrap();
+
+        assert_eq!(captured.status_code(), Some(StatusCode(201)));
+        let headers = captured.headers();
+        assert!(headers
+            .iter()
+            .any(|h| h.field.equiv("X-Test") && h.value.as_str() == "yes"));
+        assert!(headers
+            .iter()
+            .any(|h| h.field.equiv("Content-Length") && h.value.as_str() == "5"));
+        assert_eq!(captured.body_bytes(), b"hello");
+        assert!(captured.raw().starts_with(b"HTTP/1.1 201 Created\r\n"));
+    }
+
+    #[test]
+    fn capture_error_on_drop() {
+        let (request, captured) = TestRequest::new().into_request_with_capture();
+        drop(request);
+        assert_eq!(captured.status_code(), Some(StatusCode(500)));
+        assert!(captured.body_bytes().is_empty());
+    }
+
+    #[test]
+    fn capture_chunked_response() {
+        let (request, captured) = TestRequest::new().into_request_with_capture();
+        let body = "0123456789".repeat(1000);
+        request
+            .respond(Response::from_string(body.clone()).with_chunked_threshold(1))
+            .unwrap();
+
+        assert!(captured
+            .headers()
+            .iter()
+            .any(|h| h.field.equiv("Transfer-Encoding") && h.value.as_str() == "chunked"));
+        assert_eq!(captured.body_bytes(), body.as_bytes());
+        assert_ne!(captured.raw().len(), body.len());
+    }
 }
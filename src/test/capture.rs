@@ -0,0 +1,116 @@
+//! Capturing the response written to a `TestRequest`.
+
+use std::io::{Read, Result as IoResult, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::client::parse_header_line;
+use crate::util::ChunksDecoder;
+use crate::{Header, StatusCode};
+
+/// The response written to a request built by
+/// [`TestRequest::into_request_with_capture`](crate::TestRequest::into_request_with_capture).
+///
+/// The methods parse what was written so far, so they are meant to be called once the request
+/// has been answered or dropped. `100 Continue` responses sent before the final one are
+/// skipped.
+#[derive(Debug, Clone)]
+pub struct ResponseCapture {
+    buffer: Arc<Mutex<Vec<u8>>>,
+}
+
+/// A parsed response: status, headers and decoded body.
+type Parsed = (StatusCode, Vec<Header>, Vec<u8>);
+
+impl ResponseCapture {
+    /// Returns a new capture, and the writer that fills it.
+    pub(crate) fn new() -> (ResponseCapture, SharedBuffer) {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        (
+            ResponseCapture {
+                buffer: buffer.clone(),
+            },
+            SharedBuffer(buffer),
+        )
+    }
+
+    /// Returns everything written to the request, as sent on the connection.
+    pub fn raw(&self) -> Vec<u8> {
+        self.buffer.lock().unwrap().clone()
+    }
+
+    /// Returns the status of the response, or `None` if no complete head was written.
+    pub fn status_code(&self) -> Option<StatusCode> {
+        self.parse().map(|(status, _, _)| status)
+    }
+
+    /// Returns the headers of the response, in the order they were written.
+    pub fn headers(&self) -> Vec<Header> {
+        self.parse()
+            .map(|(_, headers, _)| headers)
+            .unwrap_or_default()
+    }
+
+    /// Returns the body of the response, without the chunked encoding if it was used.
+    pub fn body_bytes(&self) -> Vec<u8> {
+        self.parse().map(|(_, _, body)| body).unwrap_or_default()
+    }
+
+    fn parse(&self) -> Option<Parsed> {
+        let buffer = self.buffer.lock().unwrap();
+        let mut data = &buffer[..];
+        loop {
+            let (status, headers, rest) = parse_head(data)?;
+            if (100..200).contains(&status.0) && status.0 != 101 {
+                data = rest;
+                continue;
+            }
+            let body = parse_body(&headers, rest);
+            return Some((status, headers, body));
+        }
+    }
+}
+
+/// Splits the status line and the headers of a response from what follows them.
+fn parse_head(data: &[u8]) -> Option<(StatusCode, Vec<Header>, &[u8])> {
+    let end = data.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&data[..end]).ok()?;
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+    let headers = lines.map(parse_header_line).collect::<Option<Vec<_>>>()?;
+    Some((StatusCode(status), headers, &data[end + 4..]))
+}
+
+fn parse_body(headers: &[Header], data: &[u8]) -> Vec<u8> {
+    let header = |name: &'static str| {
+        headers
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str())
+    };
+
+    let mut body = Vec::new();
+    if header("Transfer-Encoding").map_or(false, |te| te.eq_ignore_ascii_case("chunked")) {
+        // a body cut in the middle keeps what could be decoded
+        let _ = ChunksDecoder::new(data, None, None).read_to_end(&mut body);
+    } else {
+        let length = header("Content-Length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or(data.len());
+        body.extend_from_slice(&data[..length.min(data.len())]);
+    }
+    body
+}
+
+/// Writer appending to a buffer shared with a `ResponseCapture`.
+pub(crate) struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
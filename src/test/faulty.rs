@@ -0,0 +1,345 @@
+//! Slowing down or breaking readers and writers.
+
+use std::collections::VecDeque;
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use std::thread;
+use std::time::Duration;
+
+use crate::util::XorShift;
+
+/// Delay added before each operation of a `FaultyReader` or `FaultyWriter`.
+#[derive(Debug, Clone, Default)]
+struct Latency {
+    fixed: Duration,
+    // maximum of the random delay added to `fixed`, and its generator
+    jitter: Option<(Duration, XorShift)>,
+}
+
+impl Latency {
+    fn wait(&mut self) {
+        let mut delay = self.fixed;
+        if let Some((max, ref mut rng)) = self.jitter {
+            delay += max.mul_f64(rng.next_f64());
+        }
+        if delay > Duration::from_secs(0) {
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Error returned once a given number of bytes went through.
+#[derive(Debug, Clone, Copy)]
+struct ErrorAfter {
+    position: u64,
+    kind: ErrorKind,
+}
+
+impl ErrorAfter {
+    /// Returns the number of bytes that can still go through before the error, or the error.
+    fn check(error: Option<ErrorAfter>, position: u64, len: usize) -> IoResult<usize> {
+        match error {
+            Some(error) if position >= error.position => {
+                Err(IoError::new(error.kind, "injected error"))
+            }
+            Some(error) => Ok(len.min((error.position - position) as usize)),
+            None => Ok(len),
+        }
+    }
+}
+
+/// A `Read` that slows down or breaks a sub-reader, to test how code copes with slow or
+/// failing clients and backends.
+///
+/// It can be used as the body of a `Response`, or to feed a handler that reads a body:
+///
+/// ```
+/// # use std::io::Read;
+/// # use std::time::{Duration, Instant};
+/// use tiny_http::testing::FaultyReader;
+///
+/// // a client sending two bytes every 20ms
+/// let mut body = FaultyReader::new(&b"hello world"[..])
+///     .with_delay_per_read(Duration::from_millis(20))
+///     .with_short_reads(2);
+///
+/// // a handler that gives up on bodies that take more than 50ms
+/// let start = Instant::now();
+/// let mut received = Vec::new();
+/// let mut buf = [0; 64];
+/// while start.elapsed() < Duration::from_millis(50) {
+///     match body.read(&mut buf).unwrap() {
+///         0 => break,
+///         n => received.extend_from_slice(&buf[..n]),
+///     }
+/// }
+/// assert!(received.len() < 11);
+/// ```
+#[derive(Debug)]
+pub struct FaultyReader<R> {
+    inner: R,
+    latency: Latency,
+    error_after: Option<ErrorAfter>,
+    max_chunk: Option<usize>,
+    position: u64,
+}
+
+impl<R> FaultyReader<R>
+where
+    R: Read,
+{
+    /// Builds a reader that behaves like `inner` until faults are added.
+    pub fn new(inner: R) -> FaultyReader<R> {
+        FaultyReader {
+            inner,
+            latency: Latency::default(),
+            error_after: None,
+            max_chunk: None,
+            position: 0,
+        }
+    }
+
+    /// Sleeps for `delay` before each read.
+    pub fn with_delay_per_read(mut self, delay: Duration) -> FaultyReader<R> {
+        self.latency.fixed = delay;
+        self
+    }
+
+    /// Adds a random delay of up to `max` before each read. The same `seed` always gives the
+    /// same sequence of delays.
+    pub fn with_jitter(mut self, max: Duration, seed: u64) -> FaultyReader<R> {
+        self.latency.jitter = Some((max, XorShift::new(seed)));
+        self
+    }
+
+    /// Returns exactly `bytes` bytes, then fails every read with an error of kind `kind`.
+    pub fn with_error_after(mut self, bytes: u64, kind: ErrorKind) -> FaultyReader<R> {
+        self.error_after = Some(ErrorAfter {
+            position: bytes,
+            kind,
+        });
+        self
+    }
+
+    /// Returns at most `max_chunk` bytes per read.
+    pub fn with_short_reads(mut self, max_chunk: usize) -> FaultyReader<R> {
+        self.max_chunk = Some(max_chunk.max(1));
+        self
+    }
+
+    /// Returns the number of bytes read so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the sub-reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R> Read for FaultyReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.latency.wait();
+
+        let len = ErrorAfter::check(self.error_after, self.position, buf.len())?;
+        let len = self.max_chunk.map_or(len, |max| len.min(max));
+        let read = self.inner.read(&mut buf[..len])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+/// A `Write` that slows down or breaks a sub-writer, to test how code copes with slow or
+/// failing clients and backends.
+///
+/// ```
+/// # use std::io::{ErrorKind, Write};
+/// use tiny_http::testing::FaultyWriter;
+///
+/// let mut writer = FaultyWriter::new(Vec::new())
+///     .with_transient_errors(vec![ErrorKind::WouldBlock, ErrorKind::Interrupted])
+///     .with_error_after(4, ErrorKind::BrokenPipe);
+///
+/// assert_eq!(writer.write(b"hello").unwrap_err().kind(), ErrorKind::WouldBlock);
+/// assert_eq!(writer.write(b"hello").unwrap_err().kind(), ErrorKind::Interrupted);
+/// assert_eq!(writer.write(b"hello").unwrap(), 4);
+/// assert_eq!(writer.write(b"o").unwrap_err().kind(), ErrorKind::BrokenPipe);
+/// assert_eq!(writer.into_inner(), b"hell");
+/// ```
+#[derive(Debug)]
+pub struct FaultyWriter<W> {
+    inner: W,
+    latency: Latency,
+    transient_errors: VecDeque<ErrorKind>,
+    error_after: Option<ErrorAfter>,
+    max_chunk: Option<usize>,
+    position: u64,
+}
+
+impl<W> FaultyWriter<W>
+where
+    W: Write,
+{
+    /// Builds a writer that behaves like `inner` until faults are added.
+    pub fn new(inner: W) -> FaultyWriter<W> {
+        FaultyWriter {
+            inner,
+            latency: Latency::default(),
+            transient_errors: VecDeque::new(),
+            error_after: None,
+            max_chunk: None,
+            position: 0,
+        }
+    }
+
+    /// Sleeps for `delay` before each write.
+    pub fn with_delay_per_write(mut self, delay: Duration) -> FaultyWriter<W> {
+        self.latency.fixed = delay;
+        self
+    }
+
+    /// Adds a random delay of up to `max` before each write. The same `seed` always gives the
+    /// same sequence of delays.
+    pub fn with_jitter(mut self, max: Duration, seed: u64) -> FaultyWriter<W> {
+        self.latency.jitter = Some((max, XorShift::new(seed)));
+        self
+    }
+
+    /// Fails the next writes with errors of these kinds, one per write, typically
+    /// `WouldBlock` or `Interrupted`. Nothing is written by the failed writes.
+    pub fn with_transient_errors<I>(mut self, kinds: I) -> FaultyWriter<W>
+    where
+        I: IntoIterator<Item = ErrorKind>,
+    {
+        self.transient_errors.extend(kinds);
+        self
+    }
+
+    /// Accepts exactly `bytes` bytes, then fails every write with an error of kind `kind`.
+    pub fn with_error_after(mut self, bytes: u64, kind: ErrorKind) -> FaultyWriter<W> {
+        self.error_after = Some(ErrorAfter {
+            position: bytes,
+            kind,
+        });
+        self
+    }
+
+    /// Accepts at most `max_chunk` bytes per write.
+    pub fn with_short_writes(mut self, max_chunk: usize) -> FaultyWriter<W> {
+        self.max_chunk = Some(max_chunk.max(1));
+        self
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the sub-writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W> Write for FaultyWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.latency.wait();
+
+        if let Some(kind) = self.transient_errors.pop_front() {
+            return Err(IoError::new(kind, "injected error"));
+        }
+        let len = ErrorAfter::check(self.error_after, self.position, buf.len())?;
+        let len = self.max_chunk.map_or(len, |max| len.min(max));
+        let written = self.inner.write(&buf[..len])?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FaultyReader, FaultyWriter};
+    use std::io::{self, ErrorKind, Read, Write};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn reader_error_after() {
+        let mut reader =
+            FaultyReader::new(&b"0123456789"[..]).with_error_after(7, ErrorKind::ConnectionReset);
+        let mut buf = [0; 5];
+        assert_eq!(reader.read(&mut buf).unwrap(), 5);
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"56");
+        assert_eq!(reader.position(), 7);
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), err.kind());
+    }
+
+    #[test]
+    fn reader_short_reads() {
+        let mut reader = FaultyReader::new(&b"0123456789"[..]).with_short_reads(3);
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"345");
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"6789");
+    }
+
+    #[test]
+    fn reader_delay() {
+        let mut reader = FaultyReader::new(io::repeat(1))
+            .with_delay_per_read(Duration::from_millis(20))
+            .with_jitter(Duration::from_millis(10), 42);
+        let start = Instant::now();
+        for _ in 0..3 {
+            reader.read_exact(&mut [0; 4]).unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn writer_error_after() {
+        let mut writer = FaultyWriter::new(Vec::new())
+            .with_short_writes(4)
+            .with_error_after(6, ErrorKind::BrokenPipe);
+        assert_eq!(writer.write(b"0123456789").unwrap(), 4);
+        assert_eq!(writer.write(b"456789").unwrap(), 2);
+        assert_eq!(writer.position(), 6);
+        let err = writer.write_all(b"6789").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+        assert_eq!(writer.into_inner(), b"012345");
+    }
+
+    #[test]
+    fn writer_transient_errors() {
+        let mut writer = FaultyWriter::new(Vec::new())
+            .with_transient_errors(vec![ErrorKind::Interrupted, ErrorKind::WouldBlock]);
+        // `write_all` retries after `Interrupted`, but not after `WouldBlock`
+        let err = writer.write_all(b"hello").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        assert_eq!(writer.position(), 0);
+        writer.write_all(b"hello").unwrap();
+        assert_eq!(writer.into_inner(), b"hello");
+    }
+
+    #[test]
+    fn faulty_body_is_send() {
+        fn assert_send<T: Send + 'static>(_: T) {}
+        assert_send(FaultyReader::new(io::empty()));
+        assert_send(FaultyWriter::new(io::sink()));
+    }
+}
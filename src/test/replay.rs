@@ -0,0 +1,447 @@
+//! Recording the order in which a server receives requests, and sending them again in the
+//! same order.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::panic;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Header, Method, Request, Server, ServerBuilder, StatusCode};
+
+/// How long to wait for a request to be queued by the server before sending the next one.
+const QUEUE_WAIT: Duration = Duration::from_secs(1);
+
+/// A request of a `Replay` script.
+#[derive(Debug, Clone)]
+pub struct ReplayStep {
+    connection: usize,
+    gap: Duration,
+    method: Method,
+    url: String,
+    headers: Vec<Header>,
+    body: Vec<u8>,
+    expected_status: Option<StatusCode>,
+}
+
+impl ReplayStep {
+    /// Builds a request sent on the simulated connection with this index. The requests of a
+    /// connection are pipelined in the order of the script.
+    ///
+    /// The request is sent with HTTP/1.1, which requires a `Host` header: `Host: localhost` is
+    /// added unless one is set with `with_header()`.
+    pub fn new(connection: usize, method: Method, url: &str) -> ReplayStep {
+        ReplayStep {
+            connection,
+            gap: Duration::from_secs(0),
+            method,
+            url: url.to_owned(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            expected_status: None,
+        }
+    }
+
+    /// Adds a header to the request. A `Host` header replaces the default `Host: localhost`.
+    pub fn with_header(mut self, header: Header) -> ReplayStep {
+        self.headers.push(header);
+        self
+    }
+
+    /// Sets the body of the request. A `Content-Length` header is added if there isn't one.
+    pub fn with_body(mut self, body: Vec<u8>) -> ReplayStep {
+        self.body = body;
+        self
+    }
+
+    /// Sets the time between the previous request of the script and this one.
+    pub fn with_gap(mut self, gap: Duration) -> ReplayStep {
+        self.gap = gap;
+        self
+    }
+
+    /// Makes `Replay::run()` fail if the response to this request doesn't have this status.
+    pub fn expecting(mut self, status: StatusCode) -> ReplayStep {
+        self.expected_status = Some(status);
+        self
+    }
+
+    /// Returns the index of the connection the request is sent on.
+    pub fn connection(&self) -> usize {
+        self.connection
+    }
+
+    /// Returns the time between the previous request of the script and this one.
+    pub fn gap(&self) -> Duration {
+        self.gap
+    }
+
+    /// Returns the method of the request.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// Returns the target of the request.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = format!("{} {} HTTP/1.1\r\n", self.method, self.url);
+        for header in &self.headers {
+            data.push_str(&format!("{}\r\n", header));
+        }
+        if !self.headers.iter().any(|h| h.field.equiv("Host")) {
+            data.push_str("Host: localhost\r\n");
+        }
+        let has_length = self
+            .headers
+            .iter()
+            .any(|h| h.field.equiv("Content-Length") || h.field.equiv("Transfer-Encoding"));
+        if !self.body.is_empty() && !has_length {
+            data.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        data.push_str("\r\n");
+
+        let mut data = data.into_bytes();
+        data.extend_from_slice(&self.body);
+        data
+    }
+}
+
+/// A sequence of requests spread over several connections, which can be recorded from a
+/// server and sent again in the same order to reproduce bugs that depend on it.
+///
+/// # Example
+///
+/// ```
+/// use tiny_http::testing::{Replay, ReplayStep};
+/// use tiny_http::{Method, Response, StatusCode};
+///
+/// let script = Replay::new()
+///     .with_step(ReplayStep::new(0, Method::Get, "/first").expecting(StatusCode(200)))
+///     .with_step(ReplayStep::new(1, Method::Get, "/second").expecting(StatusCode(404)));
+///
+/// script.run(|server| {
+///     for _ in 0..2 {
+///         let rq = server.recv().unwrap();
+///         let status = if rq.url() == "/first" { 200 } else { 404 };
+///         rq.respond(Response::empty(status)).unwrap();
+///     }
+/// });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Replay {
+    steps: Vec<ReplayStep>,
+    honor_gaps: bool,
+}
+
+impl Replay {
+    /// Builds an empty script.
+    pub fn new() -> Replay {
+        Replay::default()
+    }
+
+    /// Builds a server that records the requests it returns from `recv()` and its variants,
+    /// in the order they were queued.
+    ///
+    /// Only the method, target and headers of the requests are recorded. Bodies aren't, since
+    /// they are read by the code under test; they can be added to the steps of the script.
+    pub fn record(
+        builder: ServerBuilder,
+    ) -> Result<(Server, RecorderHandle), Box<dyn Error + Send + Sync + 'static>> {
+        let mut server = builder.build()?;
+        let recorder = Arc::new(Recorder::default());
+        server.recorder = Some(recorder.clone());
+        Ok((server, RecorderHandle { recorder }))
+    }
+
+    /// Adds a request at the end of the script.
+    pub fn with_step(mut self, step: ReplayStep) -> Replay {
+        self.steps.push(step);
+        self
+    }
+
+    /// Waits for the gap of each step before sending it, instead of sending them as fast as
+    /// possible.
+    pub fn with_gaps_honored(mut self) -> Replay {
+        self.honor_gaps = true;
+        self
+    }
+
+    /// Returns the requests of the script.
+    pub fn steps(&self) -> &[ReplayStep] {
+        &self.steps
+    }
+
+    /// Returns the requests of the script, to set the statuses they must get.
+    pub fn steps_mut(&mut self) -> &mut [ReplayStep] {
+        &mut self.steps
+    }
+
+    /// Sends the requests of the script to a new server on the loopback interface, and panics
+    /// if a response doesn't have the status its step expects.
+    ///
+    /// `handler` runs on its own thread and is given the server; it returns once it has
+    /// answered the requests it wants to, or once receiving fails. Each request is sent once
+    /// the previous one has been queued by the server, so that the server receives them in
+    /// the order of the script even when they come from different connections.
+    pub fn run<F>(&self, handler: F)
+    where
+        F: FnOnce(&Server) + Send + 'static,
+    {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let addr = server.server_addr().to_ip().unwrap();
+        let handler = {
+            let server = server.clone();
+            thread::spawn(move || handler(&server))
+        };
+
+        let mut connections: Vec<Option<TcpStream>> = Vec::new();
+        for (index, step) in self.steps.iter().enumerate() {
+            if self.honor_gaps {
+                thread::sleep(step.gap);
+            }
+            if connections.len() <= step.connection {
+                connections.resize_with(step.connection + 1, || None);
+            }
+            let stream = connections[step.connection]
+                .get_or_insert_with(|| TcpStream::connect(addr).unwrap());
+            stream.write_all(&step.to_bytes()).unwrap();
+
+            let deadline = Instant::now() + QUEUE_WAIT;
+            while server.stats.queued_requests.load(Relaxed) <= index && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        for (connection, stream) in connections.into_iter().enumerate() {
+            let stream = match stream {
+                Some(stream) => stream,
+                None => continue,
+            };
+            stream
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            let mut reader = BufReader::new(stream);
+            let steps = self.steps.iter().filter(|s| s.connection == connection);
+            for step in steps {
+                let status = read_response(&mut reader);
+                if let Some(expected) = step.expected_status {
+                    assert_eq!(
+                        status, expected,
+                        "unexpected status for {} {} on connection {}",
+                        step.method, step.url, connection
+                    );
+                }
+            }
+        }
+
+        server.shutdown_gracefully();
+        if let Err(err) = handler.join() {
+            panic::resume_unwind(err);
+        }
+    }
+}
+
+/// Requests recorded by a server built with `Replay::record()`.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    // the remote address of the request identifies its connection
+    requests: Mutex<Vec<(Option<SocketAddr>, Instant, ReplayStep)>>,
+}
+
+impl Recorder {
+    /// Records a request that was queued at `enqueued`.
+    pub(crate) fn record(&self, request: &Request, enqueued: Instant) {
+        let mut step = ReplayStep::new(0, request.method().clone(), request.url());
+        step.headers = request.headers().to_vec();
+        self.requests
+            .lock()
+            .unwrap()
+            .push((request.remote_addr().copied(), enqueued, step));
+    }
+}
+
+/// Handle to the requests recorded by a server built with `Replay::record()`.
+pub struct RecorderHandle {
+    recorder: Arc<Recorder>,
+}
+
+impl RecorderHandle {
+    /// Returns the number of requests recorded so far.
+    pub fn len(&self) -> usize {
+        self.recorder.requests.lock().unwrap().len()
+    }
+
+    /// Returns true if no request was recorded yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Turns the requests recorded so far into a script, ordered by the moment they were
+    /// queued. Connections are numbered in the order of their first request.
+    pub fn into_script(self) -> Replay {
+        let mut requests = self.recorder.requests.lock().unwrap().clone();
+        requests.sort_by_key(|(_, enqueued, _)| *enqueued);
+
+        let mut connections = HashMap::new();
+        let mut previous = None;
+        let mut script = Replay::new();
+        for (addr, enqueued, mut step) in requests {
+            let next = connections.len();
+            step.connection = match addr {
+                Some(addr) => *connections.entry(addr).or_insert(next),
+                // without an address, the connection can't be told apart from the other ones
+                None => {
+                    connections.insert(([0, 0, 0, 0], next as u16).into(), next);
+                    next
+                }
+            };
+            step.gap = previous.map_or(Duration::from_secs(0), |previous: Instant| {
+                enqueued.saturating_duration_since(previous)
+            });
+            previous = Some(enqueued);
+            script = script.with_step(step);
+        }
+        script
+    }
+}
+
+/// Reads a response and returns its status.
+fn read_response<R: BufRead>(reader: &mut R) -> StatusCode {
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let status: u16 = line
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .unwrap_or_else(|| panic!("invalid status line: {:?}", line));
+
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':').unwrap();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = value.trim().parse::<u64>().ok();
+        } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            chunked = value.to_ascii_lowercase().contains("chunked");
+        }
+    }
+
+    let mut body = Vec::new();
+    if status < 200 || status == 204 || status == 304 {
+        // no body
+    } else if chunked {
+        chunked_transfer::Decoder::new(&mut *reader)
+            .read_to_end(&mut body)
+            .unwrap();
+    } else if let Some(length) = content_length {
+        reader.take(length).read_to_end(&mut body).unwrap();
+    } else {
+        reader.read_to_end(&mut body).unwrap();
+    }
+    StatusCode(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Replay, ReplayStep};
+    use crate::{Method, Response, ServerBuilder, StatusCode};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn pipelined_out_of_order_responses() {
+        let script = Replay::new()
+            .with_step(ReplayStep::new(0, Method::Get, "/slow").expecting(StatusCode(200)))
+            .with_step(ReplayStep::new(0, Method::Get, "/fast").expecting(StatusCode(201)))
+            .with_step(ReplayStep::new(1, Method::Get, "/other").expecting(StatusCode(202)));
+
+        script.run(|server| {
+            let slow = server.recv().unwrap();
+            let fast = server.recv().unwrap();
+            let other = server.recv().unwrap();
+            assert_eq!(
+                [slow.url(), fast.url(), other.url()],
+                ["/slow", "/fast", "/other"]
+            );
+
+            // answering in the reverse order doesn't change the order on the connection; the
+            // second response waits for the first one to be sent
+            other.respond(Response::empty(202)).unwrap();
+            let fast = thread::spawn(move || fast.respond(Response::empty(201)).unwrap());
+            thread::sleep(Duration::from_millis(50));
+            slow.respond(Response::empty(200)).unwrap();
+            fast.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn default_host_header() {
+        let step = ReplayStep::new(0, Method::Get, "/");
+        let data = String::from_utf8(step.to_bytes()).unwrap();
+        assert_eq!(data, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+
+        let step = step.with_header("host: example.com".parse().unwrap());
+        let data = String::from_utf8(step.to_bytes()).unwrap();
+        assert_eq!(data, "GET / HTTP/1.1\r\nhost: example.com\r\n\r\n");
+    }
+
+    #[test]
+    fn record_then_replay() {
+        let builder = ServerBuilder::new()
+            .with_addr(crate::ConfigListenAddr::from_socket_addrs("127.0.0.1:0").unwrap());
+        let (server, recorder) = Replay::record(builder).unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+
+        let mut clients = [
+            TcpStream::connect(addr).unwrap(),
+            TcpStream::connect(addr).unwrap(),
+        ];
+        for (client, url) in [(0, "/a"), (1, "/b"), (0, "/c")] {
+            let client = &mut clients[client];
+            write!(client, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", url).unwrap();
+            let rq = server.recv().unwrap();
+            rq.respond(Response::empty(204)).unwrap();
+            let mut head = [0; 12];
+            client.read_exact(&mut head).unwrap();
+        }
+
+        let mut script = recorder.into_script();
+        let steps: Vec<_> = script
+            .steps()
+            .iter()
+            .map(|s| (s.connection(), s.url().to_owned()))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                (0, "/a".to_owned()),
+                (1, "/b".to_owned()),
+                (0, "/c".to_owned())
+            ]
+        );
+
+        for step in script.steps_mut() {
+            *step = step.clone().expecting(StatusCode(204));
+        }
+        script.run(|server| {
+            for rq in server.incoming_requests() {
+                rq.respond(Response::empty(204)).unwrap();
+            }
+        });
+    }
+}
@@ -0,0 +1,128 @@
+//! Running the parsing and response path of a connection without sockets.
+//!
+//! This is meant for protocol tests and fuzzing: the bytes a client would send are handed to
+//! the same code that reads requests from a socket, and everything the server writes back is
+//! returned.
+//!
+//! `Replay` records the order in which a server receives requests, and sends them again in
+//! the same order. `FaultyReader` and `FaultyWriter` slow down or break the bodies of requests
+//! and responses.
+//!
+//! This module requires the `testing` feature.
+
+use std::io::{Cursor, Result as IoResult, Write};
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::client::ClientConnection;
+use crate::stats::Counters;
+use crate::util::RefinedTcpStream;
+use crate::Request;
+
+pub use crate::test::{FaultyReader, FaultyWriter, RecorderHandle, Replay, ReplayStep};
+
+/// Reads the requests of `request_bytes` as if they were sent on a single connection, calls
+/// `handler` for each of them in order, and returns everything that was written back.
+///
+/// The connection ends when the input does, or when a request makes the server close it.
+/// The responses include the ones that the server sends itself, such as a `400 Bad Request`
+/// for a malformed request.
+///
+/// # Panics
+///
+/// Panics if `handler` returns without answering or dropping its request, for example after
+/// storing it for later: the next request can't be read before the previous one is done,
+/// and the connection would otherwise wait forever.
+///
+/// # Example
+///
+/// ```
+/// use tiny_http::Response;
+///
+/// let output = tiny_http::testing::run_raw(
+///     b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n",
+///     |request| request.respond(Response::from_string("world")).unwrap(),
+/// );
+/// assert!(output.starts_with(b"HTTP/1.1 200 OK\r\n"));
+/// assert!(output.ends_with(b"world"));
+/// ```
+pub fn run_raw(request_bytes: &[u8], mut handler: impl FnMut(Request)) -> Vec<u8> {
+    let output = SharedOutput::default();
+    let (read, write) = RefinedTcpStream::from_halves(
+        Box::new(Cursor::new(request_bytes.to_vec())),
+        Box::new(output.clone()),
+    );
+    let stats = Arc::new(Counters::default());
+    let connection = ClientConnection::new(write, read, Ok(None), Arc::default(), &stats, None);
+    for request in connection {
+        let (sender, done) = mpsc::channel();
+        handler(request.with_notify_sender(sender));
+        assert!(
+            done.try_recv().is_ok(),
+            "The handler of run_raw() must answer or drop each request before returning"
+        );
+    }
+
+    let output = output.0.lock().unwrap();
+    output.clone()
+}
+
+/// Buffer that the connection writes to, kept by `run_raw()` after the connection is gone.
+#[derive(Clone, Default)]
+struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_raw;
+    use crate::Response;
+
+    #[test]
+    fn pipelined_requests() {
+        let mut urls = Vec::new();
+        let output = run_raw(
+            b"GET /first HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              POST /second HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\n\r\nabc",
+            |mut request| {
+                urls.push(request.url().to_owned());
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                let text = format!("{}:{}", request.url(), body);
+                request.respond(Response::from_string(text)).unwrap();
+            },
+        );
+
+        assert_eq!(urls, ["/first", "/second"]);
+        let output = String::from_utf8(output).unwrap();
+        let first = output.find("\r\n\r\n/first:").unwrap();
+        let second = output.find("\r\n\r\n/second:abc").unwrap();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{}", output);
+        assert!(first < second, "{}", output);
+        assert_eq!(output.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "must answer or drop each request")]
+    fn kept_request() {
+        let mut kept = Vec::new();
+        run_raw(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            |request| kept.push(request),
+        );
+    }
+
+    #[test]
+    fn malformed_request() {
+        let output = run_raw(b"NOT A REQUEST\r\n\r\n", |_| panic!("no request expected"));
+        assert!(output.starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
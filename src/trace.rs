@@ -0,0 +1,248 @@
+//! Hexdumps of the data exchanged with some connections, see `Server::trace_connection()`.
+
+use std::fmt::Write as _;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::log;
+
+/// Number of bytes shown on each line of a hexdump.
+const BYTES_PER_LINE: usize = 16;
+
+/// Selects the connections traced by `Server::trace_connection()`.
+///
+/// ```
+/// # use std::time::Duration;
+/// # use tiny_http::ConnectionTraceFilter;
+/// // the next 2 connections from this client, if they arrive within a minute
+/// let filter = ConnectionTraceFilter::new(2)
+///     .with_peer_ip("192.0.2.7".parse().unwrap())
+///     .with_ttl(Duration::from_secs(60));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnectionTraceFilter {
+    connections: usize,
+    peer_ip: Option<IpAddr>,
+    ttl: Option<Duration>,
+    max_bytes: usize,
+}
+
+impl ConnectionTraceFilter {
+    /// Traces the next `connections` connections accepted by the server.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `connections` is 0.
+    pub fn new(connections: usize) -> ConnectionTraceFilter {
+        assert!(connections != 0, "At least one connection must be traced");
+        ConnectionTraceFilter {
+            connections,
+            peer_ip: None,
+            ttl: None,
+            max_bytes: 4096,
+        }
+    }
+
+    /// Only traces the connections from this address. Connections through Unix sockets never
+    /// match.
+    pub fn with_peer_ip(mut self, ip: IpAddr) -> ConnectionTraceFilter {
+        self.peer_ip = Some(ip);
+        self
+    }
+
+    /// Stops matching new connections after `ttl`, even if fewer connections than requested
+    /// were traced. The connections that are already traced keep being traced.
+    pub fn with_ttl(mut self, ttl: Duration) -> ConnectionTraceFilter {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Sets the number of bytes dumped for each connection, both directions included.
+    /// Defaults to 4096.
+    pub fn with_max_bytes(mut self, bytes: usize) -> ConnectionTraceFilter {
+        self.max_bytes = bytes;
+        self
+    }
+
+    fn matches(&self, peer_addr: Option<SocketAddr>) -> bool {
+        match self.peer_ip {
+            Some(ip) => peer_addr.map_or(false, |addr| addr.ip() == ip),
+            None => true,
+        }
+    }
+}
+
+/// Filter set with `Server::trace_connection()`, shared with the accept thread.
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionTracer {
+    // true while `filter` is set, so that the accept thread doesn't lock it otherwise
+    armed: AtomicBool,
+    filter: Mutex<Option<ArmedFilter>>,
+}
+
+#[derive(Debug)]
+struct ArmedFilter {
+    filter: ConnectionTraceFilter,
+    expires: Option<Instant>,
+    remaining: usize,
+}
+
+impl ConnectionTracer {
+    /// Replaces the current filter.
+    pub(crate) fn arm(&self, filter: ConnectionTraceFilter) {
+        let mut armed = self.filter.lock().unwrap();
+        *armed = Some(ArmedFilter {
+            expires: filter.ttl.map(|ttl| Instant::now() + ttl),
+            remaining: filter.connections,
+            filter,
+        });
+        self.armed.store(true, Relaxed);
+    }
+
+    /// Returns the trace of a new connection, if it must be traced. The trace starts once the
+    /// connection is numbered, see `ConnectionTrace::start()`.
+    pub(crate) fn accepted(&self, peer_addr: Option<SocketAddr>) -> Option<Arc<ConnectionTrace>> {
+        if !self.armed.load(Relaxed) {
+            return None;
+        }
+
+        let mut armed = self.filter.lock().unwrap();
+        let current = armed.as_mut()?;
+        if current
+            .expires
+            .map_or(false, |expires| Instant::now() >= expires)
+        {
+            log::debug!("Connection trace expired");
+            self.disarm(&mut armed);
+            return None;
+        }
+        if !current.filter.matches(peer_addr) {
+            return None;
+        }
+
+        let max_bytes = current.filter.max_bytes;
+        current.remaining -= 1;
+        if current.remaining == 0 {
+            self.disarm(&mut armed);
+        }
+        drop(armed);
+
+        Some(Arc::new(ConnectionTrace {
+            id: AtomicU64::new(0),
+            state: Mutex::new(TraceState {
+                received: 0,
+                sent: 0,
+                remaining: max_bytes,
+                truncated: false,
+            }),
+        }))
+    }
+
+    fn disarm(&self, armed: &mut Option<ArmedFilter>) {
+        *armed = None;
+        self.armed.store(false, Relaxed);
+    }
+}
+
+/// Direction of the data of a traced connection.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Direction {
+    Received,
+    Sent,
+}
+
+/// Dumps the data of one connection, shared by its read and write halves.
+#[derive(Debug)]
+pub(crate) struct ConnectionTrace {
+    // number of the connection, see `Request::connection_id()`
+    #[cfg_attr(not(feature = "log"), allow(dead_code))]
+    id: AtomicU64,
+    state: Mutex<TraceState>,
+}
+
+#[derive(Debug)]
+struct TraceState {
+    // offsets of the next bytes in each direction
+    received: usize,
+    sent: usize,
+    // bytes that can still be dumped
+    remaining: usize,
+    // true once some data couldn't be dumped
+    truncated: bool,
+}
+
+impl ConnectionTrace {
+    /// Tags the dumps with `id`, the number of the connection, before any data is exchanged.
+    #[cfg_attr(not(feature = "log"), allow(unused_variables))]
+    pub(crate) fn start(&self, id: u64, peer_addr: Option<SocketAddr>) {
+        self.id.store(id, Relaxed);
+        log::trace!("conn#{}: tracing the connection of {:?}", id, peer_addr);
+    }
+
+    /// Logs `data`, which was just received or sent.
+    #[cfg_attr(not(feature = "log"), allow(unused_variables))]
+    pub(crate) fn record(&self, direction: Direction, data: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        if state.truncated || data.is_empty() {
+            return;
+        }
+
+        let shown = data.len().min(state.remaining);
+        state.remaining -= shown;
+        let (offset, tag) = match direction {
+            Direction::Received => (&mut state.received, "recv"),
+            Direction::Sent => (&mut state.sent, "send"),
+        };
+        for line in data[..shown].chunks(BYTES_PER_LINE) {
+            let mut hex = String::with_capacity(3 * BYTES_PER_LINE);
+            for byte in line {
+                let _ = write!(hex, "{:02x} ", byte);
+            }
+            log::trace!(
+                "conn#{} {} {:06x}  {:<width$} |{}|",
+                self.id.load(Relaxed),
+                tag,
+                *offset,
+                hex,
+                crate::sanitize::sanitize_for_log(line, 3 * BYTES_PER_LINE),
+                width = 3 * BYTES_PER_LINE
+            );
+            *offset += line.len();
+        }
+
+        if shown < data.len() {
+            log::trace!("conn#{}: trace truncated", self.id.load(Relaxed));
+            state.truncated = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionTraceFilter, ConnectionTracer};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn filter_disarms() {
+        let tracer = ConnectionTracer::default();
+        assert!(tracer.accepted(None).is_none());
+
+        let client = "127.0.0.1:1234".parse().ok();
+        let other = "127.0.0.2:1234".parse().ok();
+        tracer.arm(ConnectionTraceFilter::new(2).with_peer_ip("127.0.0.1".parse().unwrap()));
+        assert!(tracer.accepted(other).is_none());
+        assert!(tracer.accepted(None).is_none());
+        assert!(tracer.accepted(client).is_some());
+        assert!(tracer.accepted(client).is_some());
+        assert!(tracer.accepted(client).is_none());
+
+        tracer.arm(ConnectionTraceFilter::new(5).with_ttl(Duration::from_millis(20)));
+        assert!(tracer.accepted(other).is_some());
+        thread::sleep(Duration::from_millis(30));
+        assert!(tracer.accepted(other).is_none());
+        assert!(tracer.filter.lock().unwrap().is_none());
+    }
+}
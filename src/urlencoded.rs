@@ -0,0 +1,174 @@
+//! Decoding of `application/x-www-form-urlencoded` data, such as query strings and the bodies
+//! of HTML forms, see `Request::form_urlencoded`.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{Error as IoError, Read};
+
+use crate::Request;
+
+/// Error returned by `Request::form_urlencoded()`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum FormError {
+    /// The `Content-Type` of the request isn't `application/x-www-form-urlencoded`. The
+    /// request should usually be answered with `415 Unsupported Media Type`.
+    WrongContentType,
+    /// The body is larger than the limit. The request should usually be answered with
+    /// `413 Payload Too Large`.
+    TooLarge,
+    /// Reading the body failed.
+    Io(IoError),
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::WrongContentType => write!(formatter, "Not an urlencoded form"),
+            FormError::TooLarge => write!(formatter, "Form body too large"),
+            FormError::Io(err) => write!(formatter, "Could not read the form body: {}", err),
+        }
+    }
+}
+
+impl Error for FormError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FormError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the body of `request` as an urlencoded form of at most `max_len` bytes.
+pub(crate) fn read_form(
+    request: &mut Request,
+    max_len: usize,
+) -> Result<Vec<(String, String)>, FormError> {
+    let is_form = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Content-Type"))
+        .map_or(false, |h| {
+            let media_type = h.value.as_str().split(';').next().unwrap_or("");
+            media_type
+                .trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        });
+    if !is_form {
+        return Err(FormError::WrongContentType);
+    }
+    if request.body_length().map_or(false, |len| len > max_len) {
+        return Err(FormError::TooLarge);
+    }
+
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(max_len as u64 + 1)
+        .read_to_end(&mut body)
+        .map_err(FormError::Io)?;
+    if body.len() > max_len {
+        return Err(FormError::TooLarge);
+    }
+    Ok(parse_pairs(&body))
+}
+
+/// Splits `input` into decoded name and value pairs. A pair without `=` has an empty value,
+/// and the empty pairs are skipped.
+pub(crate) fn parse_pairs(input: &[u8]) -> Vec<(String, String)> {
+    input
+        .split(|&b| b == b'&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = match pair.iter().position(|&b| b == b'=') {
+                Some(index) => (&pair[..index], &pair[index + 1..]),
+                None => (pair, &[][..]),
+            };
+            (decode_component(name), decode_component(value))
+        })
+        .collect()
+}
+
+/// Decodes a name or a value, where `+` stands for a space.
+fn decode_component(input: &[u8]) -> String {
+    let input: Vec<u8> = input
+        .iter()
+        .map(|&b| if b == b'+' { b' ' } else { b })
+        .collect();
+    String::from_utf8_lossy(&percent_decode(&input)).into_owned()
+}
+
+/// Decodes the `%XX` sequences of `input`. Invalid sequences are kept as they are.
+pub(crate) fn percent_decode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len());
+    let mut index = 0;
+    while index < input.len() {
+        let decoded = match input[index] {
+            b'%' if index + 2 < input.len() => hex_value(input[index + 1])
+                .and_then(|high| Some(high * 16 + hex_value(input[index + 2])?)),
+            _ => None,
+        };
+        match decoded {
+            Some(byte) => {
+                output.push(byte);
+                index += 3;
+            }
+            None => {
+                output.push(input[index]);
+                index += 1;
+            }
+        }
+    }
+    output
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_pairs, percent_decode};
+
+    fn pairs(input: &str) -> Vec<(String, String)> {
+        parse_pairs(input.as_bytes())
+    }
+
+    fn owned(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn decoding() {
+        assert_eq!(
+            pairs("name=John+Doe&city=S%C3%A3o%20Paulo"),
+            owned(&[("name", "John Doe"), ("city", "S\u{e3}o Paulo")])
+        );
+        assert_eq!(
+            pairs("a%2Bb=1%2B1%3D2&%26=%3d"),
+            owned(&[("a+b", "1+1=2"), ("&", "=")])
+        );
+    }
+
+    #[test]
+    fn empty_values_and_duplicates() {
+        assert_eq!(
+            pairs("a=&b&=c&&tag=x&tag=y"),
+            owned(&[("a", ""), ("b", ""), ("", "c"), ("tag", "x"), ("tag", "y")])
+        );
+        assert_eq!(pairs(""), owned(&[]));
+        assert_eq!(pairs("a==b"), owned(&[("a", "=b")]));
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        assert_eq!(percent_decode(b"100%"), b"100%");
+        assert_eq!(percent_decode(b"%4"), b"%4");
+        assert_eq!(percent_decode(b"%zz%41"), b"%zzA");
+        assert_eq!(pairs("bad=%FF"), owned(&[("bad", "\u{fffd}")]));
+    }
+}
@@ -0,0 +1,374 @@
+use std::io::{BufRead, Error as IoError, ErrorKind, Read, Result as IoResult};
+use std::sync::{Arc, Mutex};
+
+use crate::client::{parse_header_line, ConnectionCloser};
+use crate::Header;
+
+/// Maximum size of the line of a chunk size, extensions included.
+const MAX_CHUNK_LINE_LENGTH: usize = 4 * 1024;
+
+/// Maximum size of the trailer section of a chunked body, line breaks included.
+const MAX_TRAILERS_LENGTH: usize = 16 * 1024;
+
+/// Reads a body with the `chunked` transfer coding (RFC 7230 §4.1) and returns the decoded
+/// data.
+///
+/// The trailer headers that follow the last chunk are stored in a list shared with the
+/// `Request`, once the end of the body is reached. Chunk extensions are checked and ignored.
+///
+/// A malformed body, or a chunk over `max_chunk_size`, fails with `ErrorKind::InvalidData`,
+/// and closes the connection since the rest of the body would be mistaken for the next
+/// request.
+pub struct ChunksDecoder<R> {
+    source: R,
+    // remaining size of the chunk being read, `None` between two chunks
+    remaining: Option<usize>,
+    done: bool,
+    trailers: Arc<Mutex<Vec<Header>>>,
+    max_chunk_size: Option<u64>,
+    closer: Option<Arc<ConnectionCloser>>,
+}
+
+impl<R: BufRead> ChunksDecoder<R> {
+    pub fn new(
+        source: R,
+        max_chunk_size: Option<u64>,
+        closer: Option<Arc<ConnectionCloser>>,
+    ) -> ChunksDecoder<R> {
+        ChunksDecoder {
+            source,
+            remaining: None,
+            done: false,
+            trailers: Arc::default(),
+            max_chunk_size,
+            closer,
+        }
+    }
+
+    /// Returns the list that receives the trailers of the body.
+    pub fn trailers(&self) -> Arc<Mutex<Vec<Header>>> {
+        self.trailers.clone()
+    }
+
+    /// Reads a line ending with CRLF, and returns it without the line break.
+    ///
+    /// Only the buffered bytes up to the line break are consumed, so that nothing after the
+    /// body is.
+    fn read_line(&mut self, max_len: usize) -> IoResult<Vec<u8>> {
+        let mut line = Vec::new();
+        loop {
+            let available = self.source.fill_buf()?;
+            if available.is_empty() {
+                return Err(IoError::new(
+                    ErrorKind::UnexpectedEof,
+                    "chunked body ended before its last chunk",
+                ));
+            }
+            let (chunk, found) = match available.iter().position(|&b| b == b'\n') {
+                Some(pos) => (&available[..pos], true),
+                None => (available, false),
+            };
+            // the carriage return doesn't count
+            if line.len() + chunk.len() > max_len + 1 {
+                return Err(invalid("line too long in chunked body"));
+            }
+            line.extend_from_slice(chunk);
+            let consumed = chunk.len() + usize::from(found);
+            self.source.consume(consumed);
+
+            if found {
+                if line.pop() != Some(b'\r') || line.contains(&b'\r') {
+                    return Err(invalid("invalid line break in chunked body"));
+                }
+                return Ok(line);
+            }
+        }
+    }
+
+    fn read_chunk_size(&mut self) -> IoResult<usize> {
+        let line = self.read_line(MAX_CHUNK_LINE_LENGTH)?;
+        let digits = line.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+        if digits == 0 || !is_chunk_ext(&line[digits..]) {
+            return Err(invalid("invalid chunk size"));
+        }
+        // the digits are ASCII
+        let size = std::str::from_utf8(&line[..digits]).unwrap();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid("chunk too large"))?;
+        if self.max_chunk_size.map_or(false, |max| size as u64 > max) {
+            return Err(invalid("chunk too large"));
+        }
+        Ok(size)
+    }
+
+    fn read_trailers(&mut self) -> IoResult<()> {
+        let mut trailers = Vec::new();
+        let mut budget = MAX_TRAILERS_LENGTH;
+        loop {
+            let line = self.read_line(budget)?;
+            if line.is_empty() {
+                break;
+            }
+            budget = budget
+                .checked_sub(line.len() + 2)
+                .ok_or_else(|| invalid("trailers of chunked body too large"))?;
+            let trailer = std::str::from_utf8(&line)
+                .ok()
+                .and_then(parse_header_line)
+                .ok_or_else(|| invalid("invalid trailer in chunked body"))?;
+            trailers.push(trailer);
+        }
+        *self.trailers.lock().unwrap() = trailers;
+        Ok(())
+    }
+
+    fn read_chunks(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                let size = self.read_chunk_size()?;
+                if size == 0 {
+                    self.read_trailers()?;
+                    self.done = true;
+                    return Ok(0);
+                }
+                size
+            }
+        };
+
+        let len = buf.len().min(remaining);
+        let read = self.source.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(IoError::new(
+                ErrorKind::UnexpectedEof,
+                "chunked body ended in the middle of a chunk",
+            ));
+        }
+        if read == remaining {
+            // the data of each chunk is followed by a line break
+            let mut line_break = [0; 2];
+            self.source.read_exact(&mut line_break)?;
+            if line_break != *b"\r\n" {
+                return Err(invalid("missing line break after chunk"));
+            }
+            self.remaining = None;
+        } else {
+            self.remaining = Some(remaining - read);
+        }
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> Read for ChunksDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let result = self.read_chunks(buf);
+        if let (Err(err), Some(closer)) = (&result, &self.closer) {
+            if err.kind() == ErrorKind::InvalidData {
+                closer.close();
+            }
+        }
+        result
+    }
+}
+
+/// Whether `input` is a list of chunk extensions (RFC 7230 §4.1.1), such as
+/// ` ; name=value;flag`.
+fn is_chunk_ext(input: &[u8]) -> bool {
+    let is_tchar = |b: &u8| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(b);
+    let skip_whitespace = |input: &mut &[u8]| {
+        while let [b' ', rest @ ..] | [b'\t', rest @ ..] = *input {
+            *input = rest;
+        }
+    };
+    let token_len = |input: &[u8]| input.iter().take_while(|b| is_tchar(b)).count();
+
+    let mut input = input;
+    loop {
+        skip_whitespace(&mut input);
+        input = match input {
+            [] => return true,
+            [b';', rest @ ..] => rest,
+            _ => return false,
+        };
+        skip_whitespace(&mut input);
+        let name = token_len(input);
+        if name == 0 {
+            return false;
+        }
+        input = &input[name..];
+        skip_whitespace(&mut input);
+        if let [b'=', rest @ ..] = input {
+            input = rest;
+            skip_whitespace(&mut input);
+            let value = match input {
+                [b'"', ..] => quoted_string_len(input),
+                _ => Some(token_len(input)).filter(|&len| len > 0),
+            };
+            match value {
+                Some(len) => input = &input[len..],
+                None => return false,
+            }
+        }
+    }
+}
+
+/// Returns the length of the quoted string at the start of `input`, quotes included.
+fn quoted_string_len(input: &[u8]) -> Option<usize> {
+    let mut escaped = false;
+    for (index, &b) in input.iter().enumerate().skip(1) {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'"' => return Some(index + 1),
+            b'\t' | b' '..=b'~' | 0x80..=0xff => (),
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn invalid(message: &'static str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_chunk_ext, ChunksDecoder};
+    use std::io::{BufReader, ErrorKind, Read};
+
+    fn decode(data: &[u8]) -> std::io::Result<String> {
+        let mut decoded = String::new();
+        ChunksDecoder::new(data, None, None).read_to_string(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn chunks() {
+        assert_eq!(
+            decode(b"3\r\nhel\r\nb\r\nlo world!!!\r\n0\r\n\r\n").unwrap(),
+            "hello world!!!"
+        );
+        assert_eq!(decode(b"0\r\n\r\n").unwrap(), "");
+    }
+
+    #[test]
+    fn trailers() {
+        let data = b"5\r\nhello\r\n0\r\nX-Checksum: abc\r\nX-Other:  1 \r\n\r\nnext";
+        let mut decoder = ChunksDecoder::new(&data[..], None, None);
+        let trailers = decoder.trailers();
+        assert!(trailers.lock().unwrap().is_empty());
+
+        let mut decoded = String::new();
+        decoder.read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "hello");
+        let trailers = trailers.lock().unwrap();
+        assert_eq!(trailers.len(), 2);
+        assert!(trailers[0].field.equiv("X-Checksum"));
+        assert_eq!(trailers[0].value.as_str(), "abc");
+        assert_eq!(trailers[1].value.as_str(), "1");
+
+        // nothing is read after the body
+        let mut rest = String::new();
+        decoder.source.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "next");
+    }
+
+    #[test]
+    fn lines_across_reads() {
+        let data = b"5;a=b\r\nhello\r\n0\r\nX-Checksum: abc\r\n\r\nnext";
+        let mut decoder = ChunksDecoder::new(BufReader::with_capacity(1, &data[..]), None, None);
+        let mut decoded = String::new();
+        decoder.read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "hello");
+        assert_eq!(decoder.trailers().lock().unwrap().len(), 1);
+
+        let mut rest = String::new();
+        decoder.source.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "next");
+    }
+
+    #[test]
+    fn malformed_bodies() {
+        let err = decode(b"5\r\nhel").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let err = decode(b"0\r\nX-Bad\r\n\r\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = decode(b"zz\r\nhello\r\n0\r\n\r\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = decode(b"5\nhello\r\n0\r\n\r\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = decode(b"5\r;a\r\nhello\r\n0\r\n\r\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(
+            decode(b"5;ext=foo\r\nhello\r\n6 ; a ; b = \"c;\\\"d\"\r\n world\r\n0;last\r\n\r\n")
+                .unwrap(),
+            "hello world"
+        );
+
+        assert!(is_chunk_ext(b""));
+        assert!(is_chunk_ext(b";a=1;b"));
+        assert!(is_chunk_ext(b"\t;\ta\t=\t\"\"  "));
+        assert!(!is_chunk_ext(b"x"));
+        assert!(!is_chunk_ext(b";"));
+        assert!(!is_chunk_ext(b";a="));
+        assert!(!is_chunk_ext(b";a=\"b"));
+        assert!(!is_chunk_ext(b";a=b c"));
+        assert!(!is_chunk_ext(b";a\x00"));
+
+        for data in [
+            &b"5;\r\nhello\r\n0\r\n\r\n"[..],
+            b"5 x\r\nhello\r\n0\r\n\r\n",
+        ]
+        .iter()
+        {
+            let err = decode(data).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn chunk_sizes() {
+        // signs and whitespace aren't hexadecimal digits
+        for size in ["+5", " 5", "0x5", "-0"].iter() {
+            let data = format!("{}\r\nhello\r\n0\r\n\r\n", size);
+            let err = decode(data.as_bytes()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{}", size);
+        }
+
+        let err = decode(b"10000000000000000\r\nhello").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let data = b"4\r\nabcd\r\n5\r\nhello\r\n0\r\n\r\n";
+        let mut decoded = Vec::new();
+        let mut decoder = ChunksDecoder::new(&data[..], Some(4), None);
+        let err = decoder.read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(decoded, b"abcd");
+
+        let mut decoded = Vec::new();
+        let mut decoder = ChunksDecoder::new(&data[..], Some(5), None);
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, b"abcdhello");
+    }
+
+    #[test]
+    fn missing_line_break_after_data() {
+        for data in [
+            &b"5\r\nhelloX\r\n0\r\n\r\n"[..],
+            b"5\r\nhello\n0\r\n\r\n",
+            b"5\r\nhello0\r\n",
+        ]
+        .iter()
+        {
+            let err = decode(data).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
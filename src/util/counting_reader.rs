@@ -0,0 +1,26 @@
+use std::io::{Read, Result as IoResult};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+
+/// A `Reader` that adds the number of bytes read from a sub-reader to a shared counter.
+///
+/// The counter stays readable after the reader is moved away, for example by
+/// `Request::into_parts()`.
+pub struct CountingReader<R> {
+    reader: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub fn new(reader: R, count: Arc<AtomicU64>) -> CountingReader<R> {
+        CountingReader { reader, count }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let read = self.reader.read(buf)?;
+        self.count.fetch_add(read as u64, Relaxed);
+        Ok(read)
+    }
+}
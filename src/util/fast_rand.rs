@@ -0,0 +1,47 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+fn seed() -> u64 {
+    // `RandomState` is randomly seeded for each thread
+    RandomState::new().build_hasher().finish()
+}
+
+/// A xorshift generator, which is cheap but must not be used for anything related to security.
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    /// Builds a generator that always returns the same sequence for the same seed.
+    pub fn new(seed: u64) -> XorShift {
+        // the xorshift state must never be zero
+        XorShift { state: seed | 1 }
+    }
+
+    /// Returns a pseudo-random number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        // keeping the 53 bits that fit in the mantissa
+        (x >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Returns a pseudo-random number in `[0, 1)`, using a generator local to the current thread.
+pub fn random_f64() -> f64 {
+    STATE.with(|state| {
+        let mut rng = XorShift::new(state.get());
+        let value = rng.next_f64();
+        state.set(rng.state);
+        value
+    })
+}
@@ -0,0 +1,194 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Work done once a handshake permit is available.
+pub type HandshakeTask = Box<dyn FnOnce(HandshakePermit) + Send>;
+
+/// Connections waiting to start their TLS handshake, see
+/// `ServerConfigAdvanced::with_max_concurrent_tls_handshakes()`.
+///
+/// Unlike with `Semaphore::acquire_timeout()`, the waiting connections don't hold a thread:
+/// they are kept in a bounded FIFO, and a single `HandshakeDispatcher` thread starts them as
+/// the previous handshakes end, or drops them once they waited too long.
+///
+/// The dispatcher stops when the `HandshakeQueue` is dropped.
+pub struct HandshakeQueue {
+    shared: Arc<Shared>,
+}
+
+/// Runs the tasks of a `HandshakeQueue`, see `HandshakeQueue::dispatcher()`.
+pub struct HandshakeDispatcher {
+    shared: Arc<Shared>,
+}
+
+/// Counts a handshake in progress until dropped.
+pub struct HandshakePermit {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    condvar: Condvar,
+    max_waiting: usize,
+    timeout: Duration,
+}
+
+struct State {
+    available: usize,
+    // tasks in arrival order, with the moment their wait expires
+    waiting: VecDeque<(Instant, HandshakeTask)>,
+    closed: bool,
+}
+
+impl HandshakeQueue {
+    /// Allows `permits` handshakes at the same time, and at most `max_waiting` connections
+    /// waiting for `timeout` to start theirs.
+    pub fn new(permits: usize, max_waiting: usize, timeout: Duration) -> HandshakeQueue {
+        HandshakeQueue {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    available: permits,
+                    waiting: VecDeque::new(),
+                    closed: false,
+                }),
+                condvar: Condvar::new(),
+                max_waiting,
+                timeout,
+            }),
+        }
+    }
+
+    /// Returns the handle that starts the queued tasks, meant to be run by its own thread.
+    pub fn dispatcher(&self) -> HandshakeDispatcher {
+        HandshakeDispatcher {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Queues `task`, after the ones already waiting. Gives it back if the queue is full.
+    pub fn push(&self, task: HandshakeTask) -> Result<(), HandshakeTask> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.waiting.len() >= self.shared.max_waiting {
+            return Err(task);
+        }
+        let deadline = Instant::now() + self.shared.timeout;
+        state.waiting.push_back((deadline, task));
+        self.shared.condvar.notify_all();
+        Ok(())
+    }
+}
+
+impl Drop for HandshakeQueue {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.condvar.notify_all();
+    }
+}
+
+impl HandshakeDispatcher {
+    /// Gives each queued task a permit and passes it to `start` as soon as one is available,
+    /// and passes the tasks that waited for too long to `expire`, until the queue is dropped.
+    ///
+    /// The tasks still waiting when the queue is dropped are dropped as well.
+    pub fn run(
+        self,
+        mut start: impl FnMut(HandshakeTask, HandshakePermit),
+        mut expire: impl FnMut(HandshakeTask),
+    ) {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return;
+            }
+
+            let now = Instant::now();
+            if let Some(&(deadline, _)) = state.waiting.front() {
+                if deadline <= now {
+                    let (_, task) = state.waiting.pop_front().unwrap();
+                    drop(state);
+                    expire(task);
+                    state = self.shared.state.lock().unwrap();
+                    continue;
+                }
+
+                if state.available != 0 {
+                    let (_, task) = state.waiting.pop_front().unwrap();
+                    state.available -= 1;
+                    drop(state);
+                    let permit = HandshakePermit {
+                        shared: self.shared.clone(),
+                    };
+                    start(task, permit);
+                    state = self.shared.state.lock().unwrap();
+                    continue;
+                }
+
+                state = self
+                    .shared
+                    .condvar
+                    .wait_timeout(state, deadline - now)
+                    .unwrap()
+                    .0;
+            } else {
+                state = self.shared.condvar.wait(state).unwrap();
+            }
+        }
+    }
+}
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().available += 1;
+        self.shared.condvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HandshakeQueue;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn limit_order_and_timeout() {
+        let queue = HandshakeQueue::new(1, 2, Duration::from_millis(200));
+        let (started_tx, started) = mpsc::channel();
+        let (expired_tx, expired) = mpsc::channel();
+        let dispatcher = queue.dispatcher();
+        let dispatcher = thread::spawn(move || {
+            dispatcher.run(
+                |task, permit| task(permit),
+                |task| expired_tx.send(task).unwrap(),
+            )
+        });
+        let push = |i| {
+            let started_tx = started_tx.clone();
+            queue.push(Box::new(move |permit| {
+                started_tx.send((i, permit)).unwrap()
+            }))
+        };
+
+        // the first task keeps its permit, the others wait in order
+        assert!(push(0).is_ok());
+        let (first, permit) = started.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(first, 0);
+        assert!(push(1).is_ok());
+        assert!(push(2).is_ok());
+
+        // the queue is full
+        assert!(push(3).is_err());
+
+        drop(permit);
+        let (second, _permit) = started.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(second, 1);
+
+        // the last task never gets the permit
+        expired.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(started.try_recv().is_err());
+
+        drop(queue);
+        dispatcher.join().unwrap();
+    }
+}
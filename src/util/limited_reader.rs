@@ -0,0 +1,82 @@
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult};
+use std::sync::Arc;
+
+use crate::client::ConnectionCloser;
+
+/// A `Reader` that fails once more than a given number of bytes are read from a sub-reader.
+///
+/// The bytes over the limit are never returned. When the limit is exceeded, the connection is
+/// closed as well, since the rest of the data would be mistaken for the next request.
+pub struct LimitedReader<R> {
+    reader: R,
+    remaining: u64,
+    exceeded: bool,
+    closer: Option<Arc<ConnectionCloser>>,
+}
+
+impl<R: Read> LimitedReader<R> {
+    pub fn new(reader: R, limit: u64, closer: Option<Arc<ConnectionCloser>>) -> LimitedReader<R> {
+        LimitedReader {
+            reader,
+            remaining: limit,
+            exceeded: false,
+            closer,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.exceeded {
+            return Err(too_large());
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // reading one byte more than allowed tells whether the limit is exceeded
+        let len = (buf.len() as u64).min(self.remaining.saturating_add(1)) as usize;
+        let read = self.reader.read(&mut buf[..len])?;
+        if read as u64 <= self.remaining {
+            self.remaining -= read as u64;
+            return Ok(read);
+        }
+
+        self.exceeded = true;
+        if let Some(ref closer) = self.closer {
+            closer.close();
+        }
+        // the bytes within the limit are returned first
+        match self.remaining {
+            0 => Err(too_large()),
+            allowed => {
+                self.remaining = 0;
+                Ok(allowed as usize)
+            }
+        }
+    }
+}
+
+fn too_large() -> IoError {
+    IoError::new(ErrorKind::InvalidData, "The body exceeds the maximum size")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LimitedReader;
+    use std::io::{ErrorKind, Read};
+
+    #[test]
+    fn limit() {
+        let mut body = String::new();
+        let mut reader = LimitedReader::new(&b"hello"[..], 5, None);
+        reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello");
+
+        let mut body = Vec::new();
+        let mut reader = LimitedReader::new(&b"hello world"[..], 5, None);
+        let err = reader.read_to_end(&mut body).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(body, b"hello");
+    }
+}
@@ -0,0 +1,165 @@
+use std::io::{ErrorKind, Read};
+use std::net::Shutdown;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::connection::Connection;
+
+/// How long a connection being closed keeps discarding what the client sends.
+const LINGER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Bytes that a connection being closed discards at most.
+const LINGER_MAX_BYTES: usize = 64 * 1024;
+
+/// Interval between two reads of the sockets handled by a `Lingerer`.
+const LINGER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Reads once from `socket` and drops the data. Returns false once the client closed its side,
+/// `remaining` bytes were read or the socket failed.
+///
+/// Once the read half of a socket is shut down, reading it returns nothing instead of waiting
+/// for data, which can't be told apart from the client closing its side.
+fn discard_input(socket: &Connection, remaining: &mut usize, read_shut_down: bool) -> bool {
+    let mut buf = [0; 4096];
+    let read = match socket {
+        Connection::Tcp(stream) => (&*stream).read(&mut buf),
+        #[cfg(unix)]
+        Connection::Unix(stream) => (&*stream).read(&mut buf),
+    };
+    match read {
+        Ok(0) => read_shut_down,
+        Ok(read) => {
+            *remaining = remaining.saturating_sub(read);
+            *remaining != 0
+        }
+        Err(ref err)
+            if err.kind() == ErrorKind::WouldBlock
+                || err.kind() == ErrorKind::TimedOut
+                || err.kind() == ErrorKind::Interrupted =>
+        {
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Closes connections after their last response without resetting them, and without making
+/// the threads that wrote the response wait for the clients.
+///
+/// Closing a socket that still has unread data makes the system reset the connection, and
+/// the client may then lose the response before reading it, for example when it sent a
+/// request that the server didn't read. Instead, the socket stops writing, which tells the
+/// client that nothing more comes, and discards what the client sends until it closes its
+/// side, for at most `LINGER_TIMEOUT` and `LINGER_MAX_BYTES`.
+///
+/// A single thread reads from all the sockets without blocking. Once `max_connections` of
+/// them are lingering, the next ones are closed right away.
+///
+/// The thread stops once the `Lingerer` is dropped and the remaining connections are closed.
+#[derive(Debug)]
+pub struct Lingerer {
+    sender: SyncSender<(Connection, bool)>,
+}
+
+impl Lingerer {
+    pub fn new(max_connections: usize) -> Lingerer {
+        let (sender, receiver) = mpsc::sync_channel(max_connections);
+        thread::spawn(move || run(receiver, max_connections));
+        Lingerer { sender }
+    }
+
+    /// Closes `socket` in the background.
+    ///
+    /// If its read half was shut down, the socket isn't closed before `LINGER_TIMEOUT`, since
+    /// it can't tell whether the client closed its side.
+    pub fn close(&self, socket: Connection, read_shut_down: bool) {
+        let _ = socket.shutdown(Shutdown::Write);
+        // dropping the socket if there are too many already
+        let _ = self.sender.try_send((socket, read_shut_down));
+    }
+}
+
+/// Socket closed by a `Lingerer`.
+struct Lingering {
+    socket: Connection,
+    read_shut_down: bool,
+    // moment when the socket is closed anyway
+    deadline: Instant,
+    // bytes that may still be discarded
+    remaining: usize,
+}
+
+fn run(receiver: Receiver<(Connection, bool)>, max_connections: usize) {
+    let mut lingering: Vec<Lingering> = Vec::new();
+    loop {
+        // waiting for a first socket when there is none
+        if lingering.is_empty() {
+            match receiver.recv() {
+                Ok(socket) => add(&mut lingering, socket),
+                Err(_) => return,
+            }
+        }
+        while lingering.len() < max_connections {
+            match receiver.try_recv() {
+                Ok(socket) => add(&mut lingering, socket),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+
+        let now = Instant::now();
+        let mut i = 0;
+        while i < lingering.len() {
+            let socket = &mut lingering[i];
+            if now < socket.deadline
+                && discard_input(&socket.socket, &mut socket.remaining, socket.read_shut_down)
+            {
+                i += 1;
+            } else {
+                lingering.swap_remove(i);
+            }
+        }
+        if !lingering.is_empty() {
+            thread::sleep(LINGER_POLL_INTERVAL);
+        }
+    }
+}
+
+fn add(lingering: &mut Vec<Lingering>, (socket, read_shut_down): (Connection, bool)) {
+    if socket.set_nonblocking(true).is_ok() {
+        lingering.push(Lingering {
+            socket,
+            read_shut_down,
+            deadline: Instant::now() + LINGER_TIMEOUT,
+            remaining: LINGER_MAX_BYTES,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lingerer;
+    use crate::connection::Connection;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+
+    #[test]
+    fn response_survives_unread_data() {
+        let lingerer = Lingerer::new(4);
+        let (mut client, mut server) = pair();
+        client.write_all(&[b'a'; 32 * 1024]).unwrap();
+        server.write_all(b"response").unwrap();
+        lingerer.close(Connection::from(server), false);
+
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"response");
+    }
+}
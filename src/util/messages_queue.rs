@@ -1,4 +1,5 @@
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 use std::time::{Duration, Instant};
 
@@ -11,8 +12,16 @@ pub struct MessagesQueue<T>
 where
     T: Send,
 {
-    queue: Mutex<VecDeque<Control<T>>>,
+    queue: Mutex<Queue<T>>,
     condvar: Condvar,
+    // number of `Control::Elem` in the queue, readable without locking it
+    len: AtomicUsize,
+}
+
+struct Queue<T> {
+    elems: VecDeque<Control<T>>,
+    // once closed, popping from an empty queue returns immediately
+    closed: bool,
 }
 
 impl<T> MessagesQueue<T>
@@ -21,34 +30,83 @@ where
 {
     pub fn with_capacity(capacity: usize) -> Arc<MessagesQueue<T>> {
         Arc::new(MessagesQueue {
-            queue: Mutex::new(VecDeque::with_capacity(capacity)),
+            queue: Mutex::new(Queue {
+                elems: VecDeque::with_capacity(capacity),
+                closed: false,
+            }),
             condvar: Condvar::new(),
+            len: AtomicUsize::new(0),
         })
     }
 
     /// Pushes an element to the queue.
     pub fn push(&self, value: T) {
         let mut queue = self.queue.lock().unwrap();
-        queue.push_back(Control::Elem(value));
+        queue.elems.push_back(Control::Elem(value));
+        self.len.fetch_add(1, Ordering::Relaxed);
         self.condvar.notify_one();
     }
 
+    /// Returns the number of elements waiting in the queue.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
     /// Unblock one thread stuck in pop loop.
     pub fn unblock(&self) {
         let mut queue = self.queue.lock().unwrap();
-        queue.push_back(Control::Unblock);
+        queue.elems.push_back(Control::Unblock);
         self.condvar.notify_one();
     }
 
+    /// Unblocks all the threads stuck in pop loop once the queue is empty, now and in the
+    /// future. Elements that are already queued or pushed later can still be popped.
+    pub fn close(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.closed = true;
+        self.condvar.notify_all();
+    }
+
     /// Pops an element. Blocks until one is available.
-    /// Returns None in case unblock() was issued.
+    /// Returns None in case unblock() was issued or the queue is closed and empty.
     pub fn pop(&self) -> Option<T> {
         let mut queue = self.queue.lock().unwrap();
 
         loop {
-            match queue.pop_front() {
+            match self.pop_front(&mut queue) {
+                Some(Control::Elem(value)) => return Some(value),
+                Some(Control::Unblock) => return None,
+                None if queue.closed => return None,
+                None => (),
+            }
+
+            queue = self.condvar.wait(queue).unwrap();
+        }
+    }
+
+    /// Wakes all the threads stuck in pop loop, so that they check their stop flag.
+    pub fn wake_all(&self) {
+        let _queue = self.queue.lock().unwrap();
+        self.condvar.notify_all();
+    }
+
+    /// Same as `pop()`, but also returns None once `stop` is set, leaving the elements to the
+    /// other threads.
+    pub fn pop_unless(&self, stop: &AtomicBool) -> Option<T> {
+        let mut queue = self.queue.lock().unwrap();
+
+        loop {
+            if stop.load(Ordering::SeqCst) {
+                // this thread may have been woken up by `push()` instead of another one
+                if !queue.elems.is_empty() {
+                    self.condvar.notify_one();
+                }
+                return None;
+            }
+            match self.pop_front(&mut queue) {
                 Some(Control::Elem(value)) => return Some(value),
                 Some(Control::Unblock) => return None,
+                None if queue.closed => return None,
                 None => (),
             }
 
@@ -59,22 +117,32 @@ where
     /// Tries to pop an element without blocking.
     pub fn try_pop(&self) -> Option<T> {
         let mut queue = self.queue.lock().unwrap();
-        match queue.pop_front() {
+        match self.pop_front(&mut queue) {
             Some(Control::Elem(value)) => Some(value),
             Some(Control::Unblock) | None => None,
         }
     }
 
+    /// Takes the first control of the queue, keeping the count of elements up to date.
+    fn pop_front(&self, queue: &mut Queue<T>) -> Option<Control<T>> {
+        let control = queue.elems.pop_front();
+        if let Some(Control::Elem(_)) = control {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        control
+    }
+
     /// Tries to pop an element without blocking
     /// more than the specified timeout duration
-    /// or unblock() was issued
+    /// or unblock() was issued or the queue is closed and empty
     pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
         let mut queue = self.queue.lock().unwrap();
         let mut duration = timeout;
         loop {
-            match queue.pop_front() {
+            match self.pop_front(&mut queue) {
                 Some(Control::Elem(value)) => return Some(value),
                 Some(Control::Unblock) => return None,
+                None if queue.closed => return None,
                 None => (),
             }
             let now = Instant::now();
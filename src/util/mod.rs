@@ -1,21 +1,49 @@
+pub use self::chunks_decoder::ChunksDecoder;
+pub use self::counting_reader::CountingReader;
 pub use self::custom_stream::CustomStream;
 pub use self::equal_reader::EqualReader;
+pub use self::fast_rand::random_f64;
+#[cfg(any(test, feature = "testing"))]
+pub use self::fast_rand::XorShift;
 pub use self::fused_reader::FusedReader;
+#[cfg(any(
+    feature = "ssl-openssl",
+    feature = "ssl-rustls",
+    feature = "ssl-native-tls"
+))]
+pub use self::handshake_queue::{HandshakePermit, HandshakeQueue};
+pub use self::limited_reader::LimitedReader;
+pub use self::linger::Lingerer;
 pub use self::messages_queue::MessagesQueue;
 pub use self::refined_tcp_stream::RefinedTcpStream;
+pub use self::semaphore::{Semaphore, SemaphorePermit};
 pub use self::sequential::{SequentialReader, SequentialReaderBuilder};
 pub use self::sequential::{SequentialWriter, SequentialWriterBuilder};
-pub use self::task_pool::TaskPool;
+pub use self::task_pool::{TaskPool, TaskQueue};
+pub use self::timeout_reader::TimeoutReader;
 
 use std::str::FromStr;
 
+mod chunks_decoder;
+mod counting_reader;
 mod custom_stream;
 mod equal_reader;
+mod fast_rand;
 mod fused_reader;
+#[cfg(any(
+    feature = "ssl-openssl",
+    feature = "ssl-rustls",
+    feature = "ssl-native-tls"
+))]
+mod handshake_queue;
+mod limited_reader;
+mod linger;
 mod messages_queue;
 pub(crate) mod refined_tcp_stream;
+mod semaphore;
 mod sequential;
 mod task_pool;
+mod timeout_reader;
 
 /// Parses a the value of a header.
 /// Suitable for `Accept-*`, `TE`, etc.
@@ -1,6 +1,7 @@
 use std::io::Result as IoResult;
-use std::io::{Read, Write};
-use std::net::{Shutdown, SocketAddr};
+use std::io::{self, Error as IoError, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::Arc;
 
 use crate::connection::Connection;
 #[cfg(any(
@@ -9,6 +10,9 @@ use crate::connection::Connection;
     feature = "ssl-native-tls"
 ))]
 use crate::ssl::SslStream;
+use crate::ssl::TlsSession;
+use crate::trace::{ConnectionTrace, Direction};
+use crate::util::CustomStream;
 
 pub(crate) enum Stream {
     Http(Connection),
@@ -18,29 +22,33 @@ pub(crate) enum Stream {
         feature = "ssl-native-tls"
     ))]
     Https(SslStream),
+    // one half of a connection that isn't a socket, see `RefinedTcpStream::from_halves()`
+    Custom(CustomStream<Box<dyn Read + Send>, Box<dyn Write + Send>>),
 }
 
-impl Clone for Stream {
-    fn clone(&self) -> Self {
+impl From<Connection> for Stream {
+    fn from(tcp_stream: Connection) -> Self {
+        Stream::Http(tcp_stream)
+    }
+}
+
+impl Stream {
+    fn try_clone(&self) -> IoResult<Stream> {
         match self {
-            Stream::Http(tcp_stream) => Stream::Http(tcp_stream.try_clone().unwrap()),
+            Stream::Http(tcp_stream) => tcp_stream.try_clone().map(Stream::Http),
             #[cfg(any(
                 feature = "ssl-openssl",
                 feature = "ssl-rustls",
                 feature = "ssl-native-tls"
             ))]
-            Stream::Https(ssl_stream) => Stream::Https(ssl_stream.clone()),
+            Stream::Https(ssl_stream) => Ok(Stream::Https(ssl_stream.clone())),
+            Stream::Custom(_) => Err(IoError::new(
+                ErrorKind::Other,
+                "the halves of a custom stream can't be cloned",
+            )),
         }
     }
-}
-
-impl From<Connection> for Stream {
-    fn from(tcp_stream: Connection) -> Self {
-        Stream::Http(tcp_stream)
-    }
-}
 
-impl Stream {
     fn secure(&self) -> bool {
         match self {
             Stream::Http(_) => false,
@@ -50,6 +58,20 @@ impl Stream {
                 feature = "ssl-native-tls"
             ))]
             Stream::Https(_) => true,
+            Stream::Custom(_) => false,
+        }
+    }
+
+    fn tls_session(&self) -> Option<TlsSession> {
+        match self {
+            Stream::Http(_) => None,
+            #[cfg(any(
+                feature = "ssl-openssl",
+                feature = "ssl-rustls",
+                feature = "ssl-native-tls"
+            ))]
+            Stream::Https(ssl_stream) => Some(ssl_stream.session()),
+            Stream::Custom(_) => None,
         }
     }
 
@@ -62,6 +84,7 @@ impl Stream {
                 feature = "ssl-native-tls"
             ))]
             Stream::Https(ssl_stream) => ssl_stream.peer_addr(),
+            Stream::Custom(_) => Ok(None),
         }
     }
 
@@ -74,6 +97,7 @@ impl Stream {
                 feature = "ssl-native-tls"
             ))]
             Stream::Https(ssl_stream) => ssl_stream.shutdown(how),
+            Stream::Custom(_) => Ok(()),
         }
     }
 }
@@ -88,6 +112,7 @@ impl Read for Stream {
                 feature = "ssl-native-tls"
             ))]
             Stream::Https(ssl_stream) => ssl_stream.read(buf),
+            Stream::Custom(stream) => stream.read(buf),
         }
     }
 }
@@ -102,6 +127,7 @@ impl Write for Stream {
                 feature = "ssl-native-tls"
             ))]
             Stream::Https(ssl_stream) => ssl_stream.write(buf),
+            Stream::Custom(stream) => stream.write(buf),
         }
     }
 
@@ -114,6 +140,7 @@ impl Write for Stream {
                 feature = "ssl-native-tls"
             ))]
             Stream::Https(ssl_stream) => ssl_stream.flush(),
+            Stream::Custom(stream) => stream.flush(),
         }
     }
 }
@@ -122,30 +149,54 @@ pub struct RefinedTcpStream {
     stream: Stream,
     close_read: bool,
     close_write: bool,
+    // dumps the data read or written, see `Server::trace_connection()`
+    trace: Option<Arc<ConnectionTrace>>,
 }
 
 impl RefinedTcpStream {
-    pub(crate) fn new<S>(stream: S) -> (RefinedTcpStream, RefinedTcpStream)
+    pub(crate) fn new<S>(stream: S) -> IoResult<(RefinedTcpStream, RefinedTcpStream)>
     where
         S: Into<Stream>,
     {
         let stream: Stream = stream.into();
 
-        let (read, write) = (stream.clone(), stream);
+        let (read, write) = (stream.try_clone()?, stream);
 
         let read = RefinedTcpStream {
             stream: read,
             close_read: true,
             close_write: false,
+            trace: None,
         };
 
         let write = RefinedTcpStream {
             stream: write,
             close_read: false,
             close_write: true,
+            trace: None,
         };
 
-        (read, write)
+        Ok((read, write))
+    }
+
+    /// Builds the read and write halves of a connection from a reader and a writer, for
+    /// example in-memory buffers. The read half writes nothing and the write half reads
+    /// nothing.
+    #[cfg_attr(not(any(test, feature = "testing")), allow(dead_code))]
+    pub(crate) fn from_halves(
+        reader: Box<dyn Read + Send>,
+        writer: Box<dyn Write + Send>,
+    ) -> (RefinedTcpStream, RefinedTcpStream) {
+        let half = |stream| RefinedTcpStream {
+            stream: Stream::Custom(stream),
+            close_read: false,
+            close_write: false,
+            trace: None,
+        };
+        (
+            half(CustomStream::new(reader, Box::new(io::sink()))),
+            half(CustomStream::new(Box::new(io::empty()), writer)),
+        )
     }
 
     /// Returns true if this struct wraps around a secure connection.
@@ -157,6 +208,57 @@ impl RefinedTcpStream {
     pub(crate) fn peer_addr(&mut self) -> IoResult<Option<SocketAddr>> {
         self.stream.peer_addr()
     }
+
+    /// Returns what the TLS handshake told about the client, `None` if this isn't a secure
+    /// connection.
+    pub(crate) fn tls_session(&self) -> Option<TlsSession> {
+        self.stream.tls_session()
+    }
+
+    /// Returns the underlying socket if this is a plain TCP connection.
+    pub(crate) fn tcp_stream(&self) -> Option<&TcpStream> {
+        #[allow(unreachable_patterns)]
+        match self.stream {
+            Stream::Http(Connection::Tcp(ref stream)) => Some(stream),
+            _ => None,
+        }
+    }
+
+    /// Returns true if this struct wraps around a plain TCP connection.
+    pub(crate) fn is_tcp(&self) -> bool {
+        self.tcp_stream().is_some()
+    }
+
+    /// Dumps the data that goes through this half of the connection to `trace`.
+    pub(crate) fn set_trace(&mut self, trace: Arc<ConnectionTrace>) {
+        self.trace = Some(trace);
+    }
+
+    /// Prevents the destructor from shutting the connection down.
+    pub(crate) fn disarm(&mut self) {
+        self.close_read = false;
+        self.close_write = false;
+    }
+
+    /// Returns a new handle to the underlying socket, the one under the TLS layer for a TLS
+    /// connection.
+    pub(crate) fn try_clone_socket(&self) -> IoResult<Option<Connection>> {
+        match self.stream {
+            Stream::Http(ref connection) => connection.try_clone().map(Some),
+            #[cfg(any(
+                feature = "ssl-openssl",
+                feature = "ssl-rustls",
+                feature = "ssl-native-tls"
+            ))]
+            Stream::Https(ref stream) => stream.try_clone_socket().map(Some),
+            Stream::Custom(_) => Ok(None),
+        }
+    }
+
+    /// Returns a new handle to the underlying socket if this is a plain TCP connection.
+    pub(crate) fn try_clone_tcp(&self) -> IoResult<Option<TcpStream>> {
+        self.tcp_stream().map(TcpStream::try_clone).transpose()
+    }
 }
 
 impl Drop for RefinedTcpStream {
@@ -173,13 +275,21 @@ impl Drop for RefinedTcpStream {
 
 impl Read for RefinedTcpStream {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
-        self.stream.read(buf)
+        let read = self.stream.read(buf)?;
+        if let Some(ref trace) = self.trace {
+            trace.record(Direction::Received, &buf[..read]);
+        }
+        Ok(read)
     }
 }
 
 impl Write for RefinedTcpStream {
     fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
-        self.stream.write(buf)
+        let written = self.stream.write(buf)?;
+        if let Some(ref trace) = self.trace {
+            trace.record(Direction::Sent, &buf[..written]);
+        }
+        Ok(written)
     }
 
     fn flush(&mut self) -> IoResult<()> {
@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limits the number of threads doing something at the same time. Threads waiting for a
+/// permit get it in the order they asked for it.
+pub struct Semaphore {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+struct State {
+    available: usize,
+    // tickets of the waiting threads, in arrival order
+    waiting: VecDeque<u64>,
+    next_ticket: u64,
+}
+
+/// Permit returned by `Semaphore::acquire_timeout()`, given back when dropped.
+pub struct SemaphorePermit {
+    semaphore: Arc<Semaphore>,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Arc<Semaphore> {
+        Arc::new(Semaphore {
+            state: Mutex::new(State {
+                available: permits,
+                waiting: VecDeque::new(),
+                next_ticket: 0,
+            }),
+            condvar: Condvar::new(),
+        })
+    }
+
+    /// Waits for a permit, after the threads that were already waiting. Returns `None` if
+    /// the timeout expired first.
+    pub fn acquire_timeout(self: &Arc<Self>, timeout: Duration) -> Option<SemaphorePermit> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push_back(ticket);
+
+        loop {
+            if state.available != 0 && state.waiting.front() == Some(&ticket) {
+                state.waiting.pop_front();
+                state.available -= 1;
+                // the next thread may get a permit as well
+                self.condvar.notify_all();
+                return Some(SemaphorePermit {
+                    semaphore: self.clone(),
+                });
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                state.waiting.retain(|&t| t != ticket);
+                // this thread may have been the one the others were waiting for
+                self.condvar.notify_all();
+                return None;
+            }
+            state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+}
+
+impl Drop for SemaphorePermit {
+    fn drop(&mut self) {
+        let mut state = self.semaphore.state.lock().unwrap();
+        state.available += 1;
+        self.semaphore.condvar.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Semaphore;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn limit_and_timeout() {
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.acquire_timeout(Duration::from_secs(0)).unwrap();
+        assert!(semaphore
+            .acquire_timeout(Duration::from_millis(20))
+            .is_none());
+        drop(permit);
+        assert!(semaphore.acquire_timeout(Duration::from_secs(0)).is_some());
+    }
+
+    #[test]
+    fn first_come_first_served() {
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.acquire_timeout(Duration::from_secs(0)).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let waiters: Vec<_> = (0..3)
+            .map(|i| {
+                let semaphore = semaphore.clone();
+                let tx = tx.clone();
+                let waiter = thread::spawn(move || {
+                    let _permit = semaphore.acquire_timeout(Duration::from_secs(10)).unwrap();
+                    tx.send(i).unwrap();
+                    thread::sleep(Duration::from_millis(10));
+                });
+                // letting the waiter take its ticket
+                thread::sleep(Duration::from_millis(50));
+                waiter
+            })
+            .collect();
+
+        drop(permit);
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), [0, 1, 2]);
+    }
+}
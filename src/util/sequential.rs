@@ -1,8 +1,8 @@
 use std::io::Result as IoResult;
-use std::io::{Read, Write};
+use std::io::{BufRead, Error as IoError, ErrorKind, Read, Write};
 
 use std::sync::mpsc::channel;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Mutex};
 
 use std::mem;
@@ -73,40 +73,102 @@ impl<W: Write + Send> SequentialWriterBuilder<W> {
     }
 }
 
-impl<R: Read + Send> Iterator for SequentialReaderBuilder<R> {
-    type Item = SequentialReader<R>;
-
-    fn next(&mut self) -> Option<SequentialReader<R>> {
+impl<R: Read + Send> SequentialReaderBuilder<R> {
+    /// Returns a reader that will start reading once the previously returned one is dropped.
+    pub fn next_reader(&mut self) -> SequentialReader<R> {
         let (tx, rx) = channel();
 
         let inner = mem::replace(&mut self.inner, SequentialReaderBuilderInner::NotFirst(rx));
 
         match inner {
-            SequentialReaderBuilderInner::First(reader) => Some(SequentialReader {
+            SequentialReaderBuilderInner::First(reader) => SequentialReader {
                 inner: SequentialReaderInner::MyTurn(reader),
                 next: tx,
-            }),
+            },
 
-            SequentialReaderBuilderInner::NotFirst(previous) => Some(SequentialReader {
+            SequentialReaderBuilderInner::NotFirst(previous) => SequentialReader {
                 inner: SequentialReaderInner::Waiting(previous),
                 next: tx,
-            }),
+            },
         }
     }
 }
 
-impl<W: Write + Send> Iterator for SequentialWriterBuilder<W> {
-    type Item = SequentialWriter<W>;
-    fn next(&mut self) -> Option<SequentialWriter<W>> {
+impl<W: Write + Send> SequentialWriterBuilder<W> {
+    /// Returns a writer that will start writing once the previously returned one is dropped.
+    pub fn next_writer(&mut self) -> SequentialWriter<W> {
         let (tx, rx) = channel();
         let mut next_next_trigger = Some(rx);
         ::std::mem::swap(&mut next_next_trigger, &mut self.next_trigger);
 
-        Some(SequentialWriter {
+        SequentialWriter {
             trigger: next_next_trigger,
             writer: self.writer.clone(),
             on_finish: tx,
-        })
+        }
+    }
+}
+
+impl<R: Read + Send> SequentialReader<R> {
+    /// Takes the inner reader away if the previous readers are done with it, so that the
+    /// following readers will never get it.
+    ///
+    /// Returns `Err(self)` if a previous reader is still alive.
+    pub fn try_into_inner(mut self) -> Result<R, SequentialReader<R>> {
+        let inner = mem::replace(&mut self.inner, SequentialReaderInner::Empty);
+        match inner {
+            SequentialReaderInner::MyTurn(reader) => Ok(reader),
+            SequentialReaderInner::Waiting(recv) => match recv.try_recv() {
+                Ok(reader) => Ok(reader),
+                Err(_) => {
+                    self.inner = SequentialReaderInner::Waiting(recv);
+                    Err(self)
+                }
+            },
+            SequentialReaderInner::Empty => unreachable!(),
+        }
+    }
+
+    /// Returns the inner reader if the previous readers are done with it, without blocking.
+    pub fn try_inner_mut(&mut self) -> Option<&mut R> {
+        if let SequentialReaderInner::Waiting(ref recv) = self.inner {
+            self.inner = SequentialReaderInner::MyTurn(recv.try_recv().ok()?);
+        }
+        match self.inner {
+            SequentialReaderInner::MyTurn(ref mut reader) => Some(reader),
+            _ => None,
+        }
+    }
+
+    /// Returns the inner reader, blocking until the previous readers are done with it.
+    ///
+    /// Returns `None` if a previous reader took the stream away.
+    pub fn wait_inner_mut(&mut self) -> Option<&mut R> {
+        if let SequentialReaderInner::Waiting(ref recv) = self.inner {
+            self.inner = SequentialReaderInner::MyTurn(recv.recv().ok()?);
+        }
+        match self.inner {
+            SequentialReaderInner::MyTurn(ref mut reader) => Some(reader),
+            _ => None,
+        }
+    }
+}
+
+impl<W: Write + Send> SequentialWriter<W> {
+    /// Returns true if the previous writers have all been dropped.
+    pub fn previous_finished(&mut self) -> bool {
+        if let Some(v) = self.trigger.as_mut() {
+            if let Err(TryRecvError::Empty) = v.try_recv() {
+                return false;
+            }
+        }
+        self.trigger = None;
+        true
+    }
+
+    /// Returns the writer shared by all the `SequentialWriter`s of the builder.
+    pub fn shared_writer(&self) -> &Mutex<W> {
+        &self.writer
     }
 }
 
@@ -114,7 +176,11 @@ impl<R: Read + Send> Read for SequentialReader<R> {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
         let mut reader = match self.inner {
             SequentialReaderInner::MyTurn(ref mut reader) => return reader.read(buf),
-            SequentialReaderInner::Waiting(ref mut recv) => recv.recv().unwrap(),
+            SequentialReaderInner::Waiting(ref mut recv) => match recv.recv() {
+                Ok(reader) => reader,
+                // the previous reader took the stream away
+                Err(_) => return Err(IoError::new(ErrorKind::NotConnected, "Stream released")),
+            },
             SequentialReaderInner::Empty => unreachable!(),
         };
 
@@ -124,6 +190,22 @@ impl<R: Read + Send> Read for SequentialReader<R> {
     }
 }
 
+impl<R: BufRead + Send> BufRead for SequentialReader<R> {
+    fn fill_buf(&mut self) -> IoResult<&[u8]> {
+        match self.wait_inner_mut() {
+            Some(reader) => reader.fill_buf(),
+            // the previous reader took the stream away
+            None => Err(IoError::new(ErrorKind::NotConnected, "Stream released")),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let SequentialReaderInner::MyTurn(ref mut reader) = self.inner {
+            reader.consume(amt);
+        }
+    }
+}
+
 impl<W: Write + Send> Write for SequentialWriter<W> {
     fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
         if let Some(v) = self.trigger.as_mut() {
@@ -156,8 +238,9 @@ where
                 self.next.send(reader).ok();
             }
             SequentialReaderInner::Waiting(recv) => {
-                let reader = recv.recv().unwrap();
-                self.next.send(reader).ok();
+                if let Ok(reader) = recv.recv() {
+                    self.next.send(reader).ok();
+                }
             }
             SequentialReaderInner::Empty => (),
         }
@@ -169,6 +252,200 @@ where
     W: Write + Send,
 {
     fn drop(&mut self) {
+        // a writer that was never used must not let the next one overtake the previous ones
+        if let Some(trigger) = self.trigger.take() {
+            trigger.recv().ok();
+        }
         self.on_finish.send(()).ok();
     }
 }
+
+/// Stress tests running the chains with randomized orders and delays.
+///
+/// `TINY_HTTP_STRESS_ITERATIONS` sets the number of chains tried by each test, 20 by default,
+/// and `TINY_HTTP_STRESS_SEED` replays the chains of a failure.
+#[cfg(test)]
+mod tests {
+    use super::{SequentialReaderBuilder, SequentialWriterBuilder};
+    use crate::util::{random_f64, XorShift};
+    use std::io::{Cursor, Read, Result as IoResult, Write};
+    use std::panic;
+    use std::sync::mpsc::{self, RecvTimeoutError};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Time after which a chain is considered deadlocked.
+    const DEADLOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn env_u64(name: &str) -> Option<u64> {
+        std::env::var(name).ok().map(|value| value.parse().unwrap())
+    }
+
+    /// Calls `chain` with the seed of each iteration, failing if it doesn't return in time.
+    fn stress(chain: fn(&mut XorShift)) {
+        let iterations = env_u64("TINY_HTTP_STRESS_ITERATIONS").unwrap_or(20);
+        let base_seed = env_u64("TINY_HTTP_STRESS_SEED")
+            .unwrap_or_else(|| (random_f64() * u32::MAX as f64) as u64);
+
+        for iteration in 0..iterations {
+            let seed = base_seed + iteration;
+            let (done, wait_for_done) = mpsc::channel();
+            let runner = thread::spawn(move || {
+                chain(&mut XorShift::new(seed));
+                done.send(()).unwrap();
+            });
+            if let Err(RecvTimeoutError::Timeout) = wait_for_done.recv_timeout(DEADLOCK_TIMEOUT) {
+                panic!("deadlock with TINY_HTTP_STRESS_SEED={}", seed);
+            }
+            if let Err(err) = runner.join() {
+                eprintln!("failure with TINY_HTTP_STRESS_SEED={}", seed);
+                panic::resume_unwind(err);
+            }
+        }
+    }
+
+    fn below(rng: &mut XorShift, max: usize) -> usize {
+        (rng.next_f64() * max as f64) as usize
+    }
+
+    fn random_delay(rng: &mut XorShift) -> Duration {
+        Duration::from_micros(below(rng, 500) as u64)
+    }
+
+    /// Returns `0..len` in a random order.
+    fn shuffled(rng: &mut XorShift, len: usize) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..len).collect();
+        for i in (1..len).rev() {
+            order.swap(i, below(rng, i + 1));
+        }
+        order
+    }
+
+    /// Writer whose output can still be read once the `SequentialWriter`s are dropped.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writers_follow_request_order() {
+        stress(|rng| {
+            let output = SharedOutput::default();
+            let mut builder = SequentialWriterBuilder::new(output.clone());
+            let count = 2 + below(rng, 9);
+
+            // some writers are dropped without writing, which must release the next ones
+            let responses: Vec<Option<String>> = (0..count)
+                .map(|i| match below(rng, 4) {
+                    0 => None,
+                    _ => Some(format!("response {};", i).repeat(1 + below(rng, 3))),
+                })
+                .collect();
+            let mut writers: Vec<_> = (0..count).map(|_| Some(builder.next_writer())).collect();
+
+            let mut threads = Vec::new();
+            for i in shuffled(rng, count) {
+                let mut writer = writers[i].take().unwrap();
+                let response = responses[i].clone();
+                let (delay, split) = (random_delay(rng), below(rng, 8));
+                threads.push(thread::spawn(move || {
+                    thread::sleep(delay);
+                    if let Some(response) = response {
+                        // written in two parts, flushed in between
+                        let split = split.min(response.len());
+                        writer.write_all(&response.as_bytes()[..split]).unwrap();
+                        writer.flush().unwrap();
+                        writer.write_all(&response.as_bytes()[split..]).unwrap();
+                    }
+                }));
+            }
+            for thread in threads {
+                thread.join().unwrap();
+            }
+
+            let expected: String = responses.into_iter().flatten().collect();
+            let output = output.0.lock().unwrap();
+            assert_eq!(String::from_utf8_lossy(&output), expected);
+        });
+    }
+
+    #[test]
+    fn unused_writer_releases_successors() {
+        let output = SharedOutput::default();
+        let mut builder = SequentialWriterBuilder::new(output.clone());
+        let mut first = builder.next_writer();
+        let unused = builder.next_writer();
+        let mut third = builder.next_writer();
+
+        let third = thread::spawn(move || third.write_all(b"third").unwrap());
+        let unused = thread::spawn(move || drop(unused));
+        thread::sleep(Duration::from_millis(20));
+        assert!(output.0.lock().unwrap().is_empty());
+
+        first.write_all(b"first ").unwrap();
+        drop(first);
+        unused.join().unwrap();
+        third.join().unwrap();
+        assert_eq!(&output.0.lock().unwrap()[..], b"first third");
+    }
+
+    #[test]
+    fn readers_hand_over_the_stream_intact() {
+        stress(|rng| {
+            let count = 2 + below(rng, 9);
+
+            // each reader reads a part of its request, possibly nothing, then is dropped
+            let lengths: Vec<usize> = (0..count).map(|_| below(rng, 300)).collect();
+            let total = lengths.iter().sum::<usize>() + 10;
+            let data: Vec<u8> = (0..total).map(|i| (i % 251) as u8).collect();
+
+            let mut builder = SequentialReaderBuilder::new(Cursor::new(data.clone()));
+            let mut readers: Vec<_> = (0..count).map(|_| Some(builder.next_reader())).collect();
+            let last = builder.next_reader();
+
+            let (results, received) = mpsc::channel();
+            let mut threads = Vec::new();
+            for i in shuffled(rng, count) {
+                let mut reader = readers[i].take().unwrap();
+                let (delay, len) = (random_delay(rng), lengths[i]);
+                let results = results.clone();
+                threads.push(thread::spawn(move || {
+                    thread::sleep(delay);
+                    let mut buf = vec![0; len];
+                    reader.read_exact(&mut buf).unwrap();
+                    results.send((i, buf)).unwrap();
+                }));
+            }
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            drop(results);
+
+            let mut parts: Vec<(usize, Vec<u8>)> = received.iter().collect();
+            parts.sort();
+            let mut offset = 0;
+            for (i, part) in parts {
+                assert_eq!(part, &data[offset..offset + lengths[i]], "reader {}", i);
+                offset += lengths[i];
+            }
+
+            // the reader of the next request continues where the others stopped
+            let mut rest = Vec::new();
+            last.try_into_inner()
+                .ok()
+                .unwrap()
+                .read_to_end(&mut rest)
+                .unwrap();
+            assert_eq!(rest, &data[offset..]);
+        });
+    }
+}
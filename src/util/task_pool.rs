@@ -1,5 +1,5 @@
 use std::collections::VecDeque;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::Duration;
@@ -7,11 +7,18 @@ use std::time::Duration;
 /// Manages a collection of threads.
 ///
 /// A new thread is created every time all the existing threads are full.
-/// Any idle thread will automatically die after a few seconds.
+/// Any idle thread will automatically die after a few seconds, unless the pool has a fixed
+/// size.
 pub struct TaskPool {
     sharing: Arc<Sharing>,
 }
 
+/// Handle that lets running tasks push more work to the back of the queue of their pool.
+#[derive(Clone)]
+pub struct TaskQueue {
+    sharing: Arc<Sharing>,
+}
+
 struct Sharing {
     // list of the tasks to be done by worker threads
     todo: Mutex<VecDeque<Box<dyn FnMut() + Send>>>,
@@ -19,15 +26,27 @@ struct Sharing {
     // condvar that will be notified whenever a task is added to `todo`
     condvar: Condvar,
 
+    // condvar that will be notified whenever a task is removed from `todo`, if it is bounded
+    space: Condvar,
+
+    // number of tasks that `TaskPool::spawn` lets wait in `todo`
+    max_queued: Option<usize>,
+
     // number of total worker threads running
     active_tasks: AtomicUsize,
 
     // number of idle worker threads
     waiting_tasks: AtomicUsize,
-}
 
-/// Minimum number of active threads.
-static MIN_THREADS: usize = 4;
+    // number of threads that are kept when idle
+    min_threads: usize,
+
+    // true if no thread is ever added after the first `min_threads`
+    fixed: bool,
+
+    // true once the `TaskPool` is dropped
+    closed: AtomicBool,
+}
 
 struct Registration<'a> {
     nb: &'a AtomicUsize,
@@ -46,92 +65,262 @@ impl<'a> Drop for Registration<'a> {
     }
 }
 
+/// Replaces the thread of a fixed size pool if a task makes it panic, so that the pool keeps
+/// its size.
+struct Respawn {
+    sharing: Arc<Sharing>,
+}
+
+impl Drop for Respawn {
+    fn drop(&mut self) {
+        if thread::panicking() && self.sharing.fixed && !self.sharing.closed.load(Ordering::Acquire)
+        {
+            add_thread(self.sharing.clone(), None);
+        }
+    }
+}
+
 impl TaskPool {
-    pub fn new() -> TaskPool {
+    /// Creates a pool keeping `min_threads` threads when idle.
+    ///
+    /// At most `max_queued` tasks wait for a thread, if it is set.
+    pub fn new(min_threads: usize, max_queued: Option<usize>) -> TaskPool {
+        TaskPool::with_threads(min_threads, false, max_queued)
+    }
+
+    /// Creates a pool of exactly `threads` threads, the tasks waiting for one of them to be
+    /// free.
+    pub fn with_fixed_size(threads: usize, max_queued: Option<usize>) -> TaskPool {
+        TaskPool::with_threads(threads, true, max_queued)
+    }
+
+    fn with_threads(min_threads: usize, fixed: bool, max_queued: Option<usize>) -> TaskPool {
         let pool = TaskPool {
             sharing: Arc::new(Sharing {
                 todo: Mutex::new(VecDeque::new()),
                 condvar: Condvar::new(),
+                space: Condvar::new(),
+                max_queued,
                 active_tasks: AtomicUsize::new(0),
                 waiting_tasks: AtomicUsize::new(0),
+                min_threads,
+                fixed,
+                closed: AtomicBool::new(false),
             }),
         };
 
-        for _ in 0..MIN_THREADS {
-            pool.add_thread(None)
+        for _ in 0..min_threads {
+            add_thread(pool.sharing.clone(), None)
         }
 
         pool
     }
 
     /// Executes a function in a thread.
-    /// If no thread is available, spawns a new one.
+    /// If no thread is available, spawns a new one, or waits for one if the pool has a fixed
+    /// size. Blocks while the queue of waiting tasks is full.
     pub fn spawn(&self, code: Box<dyn FnMut() + Send>) {
+        self.spawn_impl(code, true);
+    }
+
+    /// Same as `spawn`, but drops the function and returns false if the queue of waiting tasks
+    /// is full.
+    pub fn try_spawn(&self, code: Box<dyn FnMut() + Send>) -> bool {
+        self.spawn_impl(code, false)
+    }
+
+    fn spawn_impl(&self, code: Box<dyn FnMut() + Send>, block: bool) -> bool {
         let mut queue = self.sharing.todo.lock().unwrap();
 
-        if self.sharing.waiting_tasks.load(Ordering::Acquire) == 0 {
-            self.add_thread(Some(code));
-        } else {
-            queue.push_back(code);
-            self.sharing.condvar.notify_one();
+        loop {
+            if !self.sharing.fixed && self.sharing.waiting_tasks.load(Ordering::Acquire) == 0 {
+                add_thread(self.sharing.clone(), Some(code));
+                return true;
+            }
+            match self.sharing.max_queued {
+                Some(max) if queue.len() >= max && block => {
+                    queue = self.sharing.space.wait(queue).unwrap();
+                }
+                Some(max) if queue.len() >= max => return false,
+                _ => break,
+            }
         }
+
+        queue.push_back(code);
+        self.sharing.condvar.notify_one();
+        true
     }
 
-    fn add_thread(&self, initial_fn: Option<Box<dyn FnMut() + Send>>) {
-        let sharing = self.sharing.clone();
+    /// Returns a handle to the queue of this pool, which can be moved into tasks.
+    pub fn queue(&self) -> TaskQueue {
+        TaskQueue {
+            sharing: self.sharing.clone(),
+        }
+    }
+}
 
-        thread::spawn(move || {
-            let sharing = sharing;
-            let _active_guard = Registration::new(&sharing.active_tasks);
+/// Starts a worker thread of the pool, which runs `initial_fn` first if there is one.
+fn add_thread(sharing: Arc<Sharing>, initial_fn: Option<Box<dyn FnMut() + Send>>) {
+    thread::spawn(move || {
+        // dropped last, once the thread no longer counts as active
+        let _respawn = Respawn {
+            sharing: sharing.clone(),
+        };
+        let _active_guard = Registration::new(&sharing.active_tasks);
 
-            if let Some(mut f) = initial_fn {
-                f();
-            }
+        if let Some(mut f) = initial_fn {
+            f();
+        }
 
-            loop {
-                let mut task: Box<dyn FnMut() + Send> = {
-                    let mut todo = sharing.todo.lock().unwrap();
+        loop {
+            let mut task: Box<dyn FnMut() + Send> = {
+                let mut todo = sharing.todo.lock().unwrap();
 
-                    let task;
-                    loop {
-                        if let Some(poped_task) = todo.pop_front() {
-                            task = poped_task;
-                            break;
-                        }
-                        let _waiting_guard = Registration::new(&sharing.waiting_tasks);
-
-                        let received =
-                            if sharing.active_tasks.load(Ordering::Acquire) <= MIN_THREADS {
-                                todo = sharing.condvar.wait(todo).unwrap();
-                                true
-                            } else {
-                                let (new_lock, waitres) = sharing
-                                    .condvar
-                                    .wait_timeout(todo, Duration::from_millis(5000))
-                                    .unwrap();
-                                todo = new_lock;
-                                !waitres.timed_out()
-                            };
-
-                        if !received && todo.is_empty() {
-                            return;
+                let task;
+                loop {
+                    if let Some(poped_task) = todo.pop_front() {
+                        if sharing.max_queued.is_some() {
+                            sharing.space.notify_one();
                         }
+                        task = poped_task;
+                        break;
                     }
+                    let _waiting_guard = Registration::new(&sharing.waiting_tasks);
 
-                    task
-                };
+                    let received =
+                        if sharing.active_tasks.load(Ordering::Acquire) <= sharing.min_threads {
+                            todo = sharing.condvar.wait(todo).unwrap();
+                            true
+                        } else {
+                            let (new_lock, waitres) = sharing
+                                .condvar
+                                .wait_timeout(todo, Duration::from_millis(5000))
+                                .unwrap();
+                            todo = new_lock;
+                            !waitres.timed_out()
+                        };
 
-                task();
-            }
-        });
+                    if !received && todo.is_empty() {
+                        return;
+                    }
+                }
+
+                task
+            };
+
+            task();
+        }
+    });
+}
+
+impl TaskQueue {
+    /// Pushes a function at the back of the queue.
+    ///
+    /// Contrary to `TaskPool::spawn`, this never starts a new thread and never waits for the
+    /// queue to have space: it is meant to be called by a task that is about to return, so that
+    /// all the tasks queued before the function get their turn first.
+    pub fn push_back(&self, code: Box<dyn FnMut() + Send>) {
+        let mut queue = self.sharing.todo.lock().unwrap();
+        queue.push_back(code);
+        self.sharing.condvar.notify_one();
     }
 }
 
 impl Drop for TaskPool {
     fn drop(&mut self) {
+        self.sharing.closed.store(true, Ordering::Release);
         self.sharing
             .active_tasks
             .store(999_999_999, Ordering::Release);
         self.sharing.condvar.notify_all();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::TaskPool;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn bounded_queue_under_flood() {
+        const MAX_QUEUED: usize = 4;
+        let pool = Arc::new(TaskPool::with_fixed_size(2, Some(MAX_QUEUED)));
+        let done = Arc::new(AtomicUsize::new(0));
+
+        let producers: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = pool.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    for _ in 0..500 {
+                        let done = done.clone();
+                        let sharing = pool.sharing.clone();
+                        pool.spawn(Box::new(move || {
+                            assert!(sharing.todo.lock().unwrap().len() <= MAX_QUEUED);
+                            thread::yield_now();
+                            done.fetch_add(1, Ordering::SeqCst);
+                        }));
+                        assert!(pool.sharing.todo.lock().unwrap().len() <= MAX_QUEUED);
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        for _ in 0..500 {
+            if done.load(Ordering::SeqCst) == 2000 {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("only {} tasks ran", done.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn try_spawn_fails_when_full() {
+        let pool = TaskPool::with_fixed_size(1, Some(1));
+        let (unblock, blocked) = mpsc::channel::<()>();
+        let (started, wait_started) = mpsc::channel();
+        let mut blocked = Some(blocked);
+        pool.spawn(Box::new(move || {
+            started.send(()).unwrap();
+            let _ = blocked.take().unwrap().recv();
+        }));
+        wait_started.recv().unwrap();
+
+        let (ran, wait_ran) = mpsc::channel();
+        let queued = ran.clone();
+        assert!(pool.try_spawn(Box::new(move || queued.send("queued").unwrap())));
+        assert!(!pool.try_spawn(Box::new(move || ran.send("dropped").unwrap())));
+
+        drop(unblock);
+        assert_eq!(
+            wait_ran.recv_timeout(Duration::from_secs(5)).unwrap(),
+            "queued"
+        );
+        assert!(wait_ran.recv_timeout(Duration::from_millis(50)).is_err());
+    }
+    #[test]
+    fn fixed_size_survives_panics() {
+        let pool = TaskPool::with_fixed_size(2, None);
+        for _ in 0..4 {
+            pool.spawn(Box::new(|| panic!("task panicked")));
+        }
+
+        let (ran, wait_ran) = mpsc::channel();
+        for _ in 0..2 {
+            let ran = ran.clone();
+            pool.spawn(Box::new(move || ran.send(()).unwrap()));
+        }
+        for _ in 0..2 {
+            wait_ran.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(pool.sharing.active_tasks.load(Ordering::Acquire), 2);
+    }
+}
@@ -0,0 +1,80 @@
+use std::io::{BufRead, Error as IoError, ErrorKind, Read, Result as IoResult};
+use std::sync::Arc;
+
+use crate::client::ConnectionCloser;
+
+/// A `Reader` for the body of a request, whose socket may have a read timeout.
+///
+/// The expired timeouts, reported as `WouldBlock` on some systems, fail with `TimedOut`. The
+/// connection is closed as well, since the rest of the body would be mistaken for the next
+/// request.
+pub struct TimeoutReader<R> {
+    reader: R,
+    closer: Option<Arc<ConnectionCloser>>,
+}
+
+impl<R: Read> TimeoutReader<R> {
+    pub fn new(reader: R, closer: Option<Arc<ConnectionCloser>>) -> TimeoutReader<R> {
+        TimeoutReader { reader, closer }
+    }
+}
+
+impl<R: Read> Read for TimeoutReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let closer = &self.closer;
+        self.reader
+            .read(buf)
+            .map_err(|err| check_timeout(closer, err))
+    }
+}
+
+impl<R: BufRead> BufRead for TimeoutReader<R> {
+    fn fill_buf(&mut self) -> IoResult<&[u8]> {
+        let closer = &self.closer;
+        self.reader
+            .fill_buf()
+            .map_err(|err| check_timeout(closer, err))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt);
+    }
+}
+
+/// Turns an expired timeout into a `TimedOut` error and closes the connection.
+fn check_timeout(closer: &Option<Arc<ConnectionCloser>>, err: IoError) -> IoError {
+    if err.kind() != ErrorKind::WouldBlock && err.kind() != ErrorKind::TimedOut {
+        return err;
+    }
+    if let Some(ref closer) = closer {
+        closer.close();
+    }
+    IoError::new(ErrorKind::TimedOut, "Body read timeout")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimeoutReader;
+    use std::io::{Error, ErrorKind, Read};
+
+    struct Blocking;
+
+    impl Read for Blocking {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(Error::new(ErrorKind::WouldBlock, "timeout"))
+        }
+    }
+
+    #[test]
+    fn timeouts() {
+        let mut reader = TimeoutReader::new(Blocking, None);
+        let err = reader.read(&mut [0; 4]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+
+        let mut body = String::new();
+        TimeoutReader::new(&b"hello"[..], None)
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, "hello");
+    }
+}
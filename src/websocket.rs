@@ -0,0 +1,374 @@
+//! Server side of the WebSocket opening handshake (RFC 6455 §4.2), see
+//! `Request::upgrade_websocket`.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::request::ReadWrite;
+use crate::{HTTPVersion, Header, Method, Request, Response, StatusCode};
+
+/// Appended to the key of the client to compute the `Sec-WebSocket-Accept` header.
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Error returned by `Request::upgrade_websocket()` when the request isn't a valid WebSocket
+/// handshake, along with the request. `response()` is the usual answer to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WsHandshakeError {
+    /// The request isn't a `GET` request made with HTTP 1.1 or later.
+    WrongRequest,
+    /// The `Upgrade` header doesn't contain `websocket`, or the `Connection` header doesn't
+    /// contain `upgrade`.
+    NotUpgrade,
+    /// The `Sec-WebSocket-Version` header is missing or isn't `13`.
+    UnsupportedVersion,
+    /// The `Sec-WebSocket-Key` header is missing, or isn't 16 bytes encoded in base64.
+    InvalidKey,
+}
+
+impl WsHandshakeError {
+    /// Returns the status of the response sent for this error: `426 Upgrade Required` for an
+    /// unsupported version, along with the supported one, and `400 Bad Request` otherwise.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            WsHandshakeError::UnsupportedVersion => StatusCode(426),
+            _ => StatusCode(400),
+        }
+    }
+
+    /// Returns the response to the invalid handshake, with `status_code()`.
+    pub fn response(&self) -> Response<io::Empty> {
+        let response = Response::empty(self.status_code());
+        match self {
+            WsHandshakeError::UnsupportedVersion => response.with_header(
+                Header::from_bytes(&b"Sec-WebSocket-Version"[..], &b"13"[..]).unwrap(),
+            ),
+            _ => response,
+        }
+    }
+}
+
+impl fmt::Display for WsHandshakeError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WsHandshakeError::WrongRequest => {
+                write!(
+                    formatter,
+                    "WebSocket handshake must be a GET HTTP/1.1 request"
+                )
+            }
+            WsHandshakeError::NotUpgrade => write!(formatter, "Not a WebSocket upgrade request"),
+            WsHandshakeError::UnsupportedVersion => {
+                write!(formatter, "Unsupported WebSocket version")
+            }
+            WsHandshakeError::InvalidKey => write!(formatter, "Invalid Sec-WebSocket-Key header"),
+        }
+    }
+}
+
+impl Error for WsHandshakeError {}
+
+/// Whether the request asks for an upgrade to WebSocket, without checking the rest of the
+/// handshake.
+pub(crate) fn is_upgrade(headers: &[Header]) -> bool {
+    has_token(headers, "Upgrade", "websocket")
+}
+
+/// Checks the handshake of `request`, then answers it with `101 Switching Protocols` and
+/// returns the stream of the connection. Gives the request back unanswered if the handshake
+/// is invalid.
+#[allow(clippy::result_large_err)]
+pub(crate) fn upgrade(
+    request: Request,
+    protocols: &[&str],
+) -> Result<Box<dyn ReadWrite + Send>, (Request, WsHandshakeError)> {
+    let accept = match check_handshake(request.method(), request.http_version(), request.headers())
+    {
+        Ok(accept) => accept,
+        Err(err) => return Err((request, err)),
+    };
+
+    let mut response = Response::empty(101)
+        .with_header(Header::from_bytes(&b"Sec-WebSocket-Accept"[..], accept).unwrap());
+    if let Some(protocol) = select_protocol(request.headers(), protocols) {
+        let header = Header::from_bytes(&b"Sec-WebSocket-Protocol"[..], protocol)
+            .expect("Invalid WebSocket subprotocol");
+        response.add_header(header);
+    }
+    Ok(request.upgrade("websocket", response))
+}
+
+/// Returns the value of the `Sec-WebSocket-Accept` header if the headers are a valid
+/// handshake.
+fn check_handshake(
+    method: &Method,
+    version: &HTTPVersion,
+    headers: &[Header],
+) -> Result<String, WsHandshakeError> {
+    if *method != Method::Get || *version < HTTPVersion(1, 1) {
+        return Err(WsHandshakeError::WrongRequest);
+    }
+    if !is_upgrade(headers) || !has_token(headers, "Connection", "upgrade") {
+        return Err(WsHandshakeError::NotUpgrade);
+    }
+    let version = headers
+        .iter()
+        .find(|h| h.field.equiv("Sec-WebSocket-Version"))
+        .map(|h| h.value.as_str().trim());
+    if version != Some("13") {
+        return Err(WsHandshakeError::UnsupportedVersion);
+    }
+
+    let mut keys = headers
+        .iter()
+        .filter(|h| h.field.equiv("Sec-WebSocket-Key"))
+        .map(|h| h.value.as_str().trim());
+    let key = match (keys.next(), keys.next()) {
+        (Some(key), None) => key,
+        _ => return Err(WsHandshakeError::InvalidKey),
+    };
+    match crate::auth::decode_base64(key) {
+        Some(nonce) if nonce.len() == 16 && key.ends_with("==") => Ok(accept_key(key)),
+        _ => Err(WsHandshakeError::InvalidKey),
+    }
+}
+
+/// Returns the first protocol of `protocols` that the client offered in its
+/// `Sec-WebSocket-Protocol` headers.
+fn select_protocol<'a>(headers: &[Header], protocols: &[&'a str]) -> Option<&'a str> {
+    protocols
+        .iter()
+        .find(|protocol| {
+            headers
+                .iter()
+                .filter(|h| h.field.equiv("Sec-WebSocket-Protocol"))
+                .flat_map(|h| h.value.as_str().split(','))
+                .any(|offered| offered.trim() == **protocol)
+        })
+        .copied()
+}
+
+/// Whether one of the comma-separated lists of the `name` headers contains `token`, compared
+/// case-insensitively.
+fn has_token(headers: &[Header], name: &'static str, token: &str) -> bool {
+    headers
+        .iter()
+        .filter(|h| h.field.equiv(name))
+        .flat_map(|h| h.value.as_str().split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+/// Computes the `Sec-WebSocket-Accept` header that answers `key`.
+fn accept_key(key: &str) -> String {
+    let mut input = key.as_bytes().to_vec();
+    input.extend_from_slice(ACCEPT_GUID.as_bytes());
+    encode_base64(&sha1(&input))
+}
+
+/// SHA-1 digest of `data` (RFC 3174). It is only used for the handshake, where it isn't meant
+/// to be secure.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (index, word) in block.chunks(4).enumerate() {
+            words[index] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, &word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *value = value.wrapping_add(*added);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+/// Encodes `data` in standard base64, with padding.
+fn encode_base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity((data.len() + 2) / 3 * 4);
+    for group in data.chunks(3) {
+        let bytes = [
+            group[0],
+            *group.get(1).unwrap_or(&0),
+            *group.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for index in 0..4 {
+            if index <= group.len() {
+                output.push(ALPHABET[(bits >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WsHandshakeError;
+    use super::{accept_key, check_handshake, encode_base64, select_protocol, sha1};
+    use crate::{HTTPVersion, Header, Method};
+
+    fn headers(lines: &[&str]) -> Vec<Header> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    fn hex(digest: &[u8]) -> String {
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn sha1_vectors() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        assert_eq!(
+            hex(&sha1(&[b'a'; 1000][..])),
+            "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+        );
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"f"), "Zg==");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert_eq!(encode_base64(b"foo"), "Zm9v");
+        assert_eq!(encode_base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn rfc_6455_accept_key() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn handshakes() {
+        let valid = [
+            "Upgrade: WebSocket",
+            "Connection: keep-alive, Upgrade",
+            "Sec-WebSocket-Version: 13",
+            "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==",
+        ];
+        let check = |method: Method, version: HTTPVersion, lines: &[&str]| {
+            check_handshake(&method, &version, &headers(lines))
+        };
+        assert_eq!(
+            check(Method::Get, HTTPVersion(1, 1), &valid).unwrap(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        assert_eq!(
+            check(Method::Post, HTTPVersion(1, 1), &valid),
+            Err(WsHandshakeError::WrongRequest)
+        );
+        assert_eq!(
+            check(Method::Get, HTTPVersion(1, 0), &valid),
+            Err(WsHandshakeError::WrongRequest)
+        );
+        assert_eq!(
+            check(Method::Get, HTTPVersion(1, 1), &valid[1..]),
+            Err(WsHandshakeError::NotUpgrade)
+        );
+        let mut lines = valid;
+        lines[1] = "Connection: keep-alive";
+        assert_eq!(
+            check(Method::Get, HTTPVersion(1, 1), &lines),
+            Err(WsHandshakeError::NotUpgrade)
+        );
+        lines = valid;
+        lines[2] = "Sec-WebSocket-Version: 8";
+        assert_eq!(
+            check(Method::Get, HTTPVersion(1, 1), &lines),
+            Err(WsHandshakeError::UnsupportedVersion)
+        );
+        for key in [
+            "",
+            "abc",
+            "dGhlIHNhbXBsZSBub25jZSE=",
+            "dGhlIHNhbXBsZSBub25jZQ",
+        ]
+        .iter()
+        {
+            lines = valid;
+            let line = format!("Sec-WebSocket-Key: {}", key);
+            lines[3] = &line;
+            assert_eq!(
+                check(Method::Get, HTTPVersion(1, 1), &lines),
+                Err(WsHandshakeError::InvalidKey),
+                "{}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn subprotocols() {
+        let offered = headers(&["Sec-WebSocket-Protocol: chat, superchat"]);
+        assert_eq!(
+            select_protocol(&offered, &["superchat", "chat"]),
+            Some("superchat")
+        );
+        assert_eq!(select_protocol(&offered, &["other", "chat"]), Some("chat"));
+        assert_eq!(select_protocol(&offered, &["other"]), None);
+        assert_eq!(select_protocol(&[], &["chat"]), None);
+    }
+}
@@ -0,0 +1,522 @@
+//! Messages of the WebSocket protocol (RFC 6455 §5) over an upgraded connection, see
+//! `WebSocket`.
+
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+
+/// Default of `WebSocket::with_max_message_size`.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Largest payload of a control frame.
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Status codes of close frames.
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_LARGE: u16 = 1009;
+
+/// A message of the WebSocket protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsMessage {
+    /// A text message, whose fragments were joined.
+    Text(String),
+    /// A binary message, whose fragments were joined.
+    Binary(Vec<u8>),
+    /// A ping, answered with a pong with the same data. `WebSocket::read_message()` answers
+    /// the pings of the client itself and never returns them.
+    Ping(Vec<u8>),
+    /// A pong, in response to a ping.
+    Pong(Vec<u8>),
+    /// The closing of the connection, with an optional status code and its reason. The
+    /// reason is only sent along with a status code.
+    Close(Option<u16>, String),
+}
+
+/// The server side of a WebSocket connection, which reads and writes whole messages.
+///
+/// This works over any stream, typically the one returned by `Request::upgrade_websocket()`.
+/// The frames of the client must be masked, and the frames of the server are not.
+///
+/// ```no_run
+/// # let server = tiny_http::Server::http("0.0.0.0:0").unwrap();
+/// use tiny_http::{WebSocket, WsMessage};
+///
+/// let request = server.recv().unwrap();
+/// let mut socket = WebSocket::new(request.upgrade_websocket(&[]).unwrap());
+/// loop {
+///     match socket.read_message().unwrap() {
+///         WsMessage::Text(text) => socket.send_message(&WsMessage::Text(text)).unwrap(),
+///         WsMessage::Close(..) => break,
+///         _ => (),
+///     }
+/// }
+/// ```
+///
+/// A protocol error of the client, such as an unmasked frame or a message over the size
+/// limit, fails with `ErrorKind::InvalidData` after sending a close frame with the matching
+/// status code.
+pub struct WebSocket<S> {
+    stream: S,
+    max_message_size: usize,
+    // true once a close frame was sent, after which only control frames can be sent
+    close_sent: bool,
+    // true once a close frame was received, after which nothing can be read
+    close_received: bool,
+}
+
+impl<S> WebSocket<S>
+where
+    S: Read + Write,
+{
+    /// Wraps the stream of a connection whose handshake was completed.
+    pub fn new(stream: S) -> WebSocket<S> {
+        WebSocket {
+            stream,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// Sets the maximum size in bytes of a message, all its fragments included. Defaults to
+    /// 16 MiB.
+    pub fn with_max_message_size(mut self, bytes: usize) -> WebSocket<S> {
+        self.max_message_size = bytes;
+        self
+    }
+
+    /// Returns the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Reads the next message of the client, blocking until it is complete.
+    ///
+    /// The pings of the client are answered automatically. A `Close` message is answered with
+    /// a close frame with the same status code, unless one was already sent, and is the last
+    /// message; reading after it fails with `ErrorKind::NotConnected`.
+    pub fn read_message(&mut self) -> IoResult<WsMessage> {
+        if self.close_received {
+            return Err(IoError::new(
+                ErrorKind::NotConnected,
+                "WebSocket connection closed",
+            ));
+        }
+
+        // opcode and data of the message being reassembled
+        let mut fragments: Option<(u8, Vec<u8>)> = None;
+        loop {
+            let buffered = fragments.as_ref().map_or(0, |(_, data)| data.len());
+            let (fin, opcode, payload) =
+                self.read_frame(self.max_message_size.saturating_sub(buffered))?;
+
+            match opcode {
+                OPCODE_PING => {
+                    if !self.close_sent {
+                        self.write_frame(OPCODE_PONG, &payload)?;
+                    }
+                    continue;
+                }
+                OPCODE_PONG => return Ok(WsMessage::Pong(payload)),
+                OPCODE_CLOSE => return self.received_close(&payload),
+                OPCODE_TEXT | OPCODE_BINARY if fragments.is_none() => {
+                    fragments = Some((opcode, payload));
+                }
+                OPCODE_CONTINUATION if fragments.is_some() => {
+                    if let Some((_, ref mut data)) = fragments {
+                        data.extend_from_slice(&payload);
+                    }
+                }
+                _ => {
+                    return Err(self.fail(CLOSE_PROTOCOL_ERROR, "unexpected WebSocket frame"));
+                }
+            }
+
+            if fin {
+                return match fragments.take() {
+                    Some((OPCODE_TEXT, data)) => match String::from_utf8(data) {
+                        Ok(text) => Ok(WsMessage::Text(text)),
+                        Err(_) => Err(self.fail(CLOSE_INVALID_DATA, "invalid UTF-8 in message")),
+                    },
+                    Some((_, data)) => Ok(WsMessage::Binary(data)),
+                    None => unreachable!(),
+                };
+            }
+        }
+    }
+
+    /// Sends a message to the client, in a single frame.
+    ///
+    /// Sending a `Close` message starts the closing handshake: the client answers with its own
+    /// close frame, returned by `read_message()`. Only pings and pongs can be sent after it.
+    ///
+    /// Fails with `ErrorKind::InvalidInput`, without sending anything, if the data of a ping or
+    /// a pong, or the code and reason of a close, are over 125 bytes.
+    pub fn send_message(&mut self, message: &WsMessage) -> IoResult<()> {
+        let (opcode, payload) = match message {
+            WsMessage::Text(text) => (OPCODE_TEXT, text.as_bytes().to_vec()),
+            WsMessage::Binary(data) => (OPCODE_BINARY, data.clone()),
+            WsMessage::Ping(data) => (OPCODE_PING, data.clone()),
+            WsMessage::Pong(data) => (OPCODE_PONG, data.clone()),
+            WsMessage::Close(code, reason) => {
+                let mut payload = Vec::with_capacity(2 + reason.len());
+                if let Some(code) = code {
+                    payload.extend_from_slice(&code.to_be_bytes());
+                    payload.extend_from_slice(reason.as_bytes());
+                }
+                (OPCODE_CLOSE, payload)
+            }
+        };
+        if opcode & 0x8 != 0 && payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "Payload of WebSocket control frame too large",
+            ));
+        }
+        if self.close_sent && (opcode == OPCODE_TEXT || opcode == OPCODE_BINARY) {
+            return Err(IoError::new(
+                ErrorKind::NotConnected,
+                "WebSocket close frame already sent",
+            ));
+        }
+
+        self.write_frame(opcode, &payload)?;
+        if opcode == OPCODE_CLOSE {
+            self.close_sent = true;
+        }
+        Ok(())
+    }
+
+    /// Reads a frame and unmasks its payload. The payload of a data frame can't be over
+    /// `max_len`.
+    fn read_frame(&mut self, max_len: usize) -> IoResult<(bool, u8, Vec<u8>)> {
+        let mut head = [0; 2];
+        self.stream.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        if head[0] & 0x70 != 0 {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "reserved bits set in WebSocket frame"));
+        }
+        if head[1] & 0x80 == 0 {
+            return Err(self.fail(CLOSE_PROTOCOL_ERROR, "unmasked WebSocket frame"));
+        }
+
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0; 2];
+                self.stream.read_exact(&mut len)?;
+                u64::from(u16::from_be_bytes(len))
+            }
+            127 => {
+                let mut len = [0; 8];
+                self.stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => u64::from(len),
+        };
+        if opcode & 0x8 != 0 {
+            if !fin || len > MAX_CONTROL_PAYLOAD as u64 {
+                return Err(self.fail(CLOSE_PROTOCOL_ERROR, "invalid WebSocket control frame"));
+            }
+        } else if len > max_len as u64 {
+            return Err(self.fail(CLOSE_TOO_LARGE, "WebSocket message too large"));
+        }
+
+        let mut mask = [0; 4];
+        self.stream.read_exact(&mut mask)?;
+        let mut payload = vec![0; len as usize];
+        self.stream.read_exact(&mut payload)?;
+        for (index, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[index % 4];
+        }
+        Ok((fin, opcode, payload))
+    }
+
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> IoResult<()> {
+        let mut head = Vec::with_capacity(10);
+        head.push(0x80 | opcode);
+        match payload.len() {
+            len if len < 126 => head.push(len as u8),
+            len if len <= usize::from(u16::MAX) => {
+                head.push(126);
+                head.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                head.push(127);
+                head.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        self.stream.write_all(&head)?;
+        self.stream.write_all(payload)?;
+        self.stream.flush()
+    }
+
+    /// Handles a close frame of the client.
+    fn received_close(&mut self, payload: &[u8]) -> IoResult<WsMessage> {
+        let (code, reason) = match payload {
+            [] => (None, String::new()),
+            [high, low, reason @ ..] => {
+                let code = u16::from_be_bytes([*high, *low]);
+                let valid_code = match code {
+                    1000..=1003 | 1007..=1011 | 3000..=4999 => true,
+                    _ => false,
+                };
+                if !valid_code {
+                    return Err(self.fail(CLOSE_PROTOCOL_ERROR, "invalid WebSocket close code"));
+                }
+                match String::from_utf8(reason.to_vec()) {
+                    Ok(reason) => (Some(code), reason),
+                    Err(_) => {
+                        return Err(self.fail(CLOSE_INVALID_DATA, "invalid UTF-8 in close reason"))
+                    }
+                }
+            }
+            _ => return Err(self.fail(CLOSE_PROTOCOL_ERROR, "invalid WebSocket close frame")),
+        };
+
+        self.close_received = true;
+        if !self.close_sent {
+            self.close_sent = true;
+            let payload = code.map(u16::to_be_bytes);
+            self.write_frame(OPCODE_CLOSE, payload.as_ref().map_or(&[], |code| &code[..]))?;
+        }
+        Ok(WsMessage::Close(code, reason))
+    }
+
+    /// Sends a close frame with `code`, unless one was already sent, and returns the error
+    /// that ends the connection.
+    fn fail(&mut self, code: u16, message: &'static str) -> IoError {
+        if !self.close_sent {
+            self.close_sent = true;
+            let _ = self.write_frame(OPCODE_CLOSE, &code.to_be_bytes());
+        }
+        self.close_received = true;
+        IoError::new(ErrorKind::InvalidData, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WebSocket, WsMessage};
+    use std::io::{Cursor, ErrorKind, Read, Result as IoResult, Write};
+
+    /// A connection whose client sends `input`, and which keeps what the server writes.
+    struct Connection {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    fn socket(input: Vec<u8>) -> WebSocket<Connection> {
+        WebSocket::new(Connection {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        })
+    }
+
+    /// Encodes a frame of the client, masked.
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn rfc_6455_examples() {
+        // a single-frame masked text message
+        let mut socket = socket(vec![
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ]);
+        assert_eq!(
+            socket.read_message().unwrap(),
+            WsMessage::Text("Hello".to_owned())
+        );
+
+        // unmasked frames from the server
+        socket
+            .send_message(&WsMessage::Text("Hello".to_owned()))
+            .unwrap();
+        socket
+            .send_message(&WsMessage::Binary(vec![0; 256]))
+            .unwrap();
+        let output = socket.into_inner().output;
+        assert_eq!(&output[..7], b"\x81\x05Hello");
+        assert_eq!(&output[7..11], [0x82, 0x7E, 0x01, 0x00]);
+        assert_eq!(output.len(), 11 + 256);
+    }
+
+    #[test]
+    fn fragmented_message() {
+        let mut input = client_frame(false, 0x1, b"Hel");
+        // control frames can come between the fragments
+        input.extend(client_frame(true, 0x9, b"beat"));
+        input.extend(client_frame(false, 0x0, b"lo "));
+        input.extend(client_frame(true, 0x0, "w\u{f6}rld".as_bytes()));
+        input.extend(client_frame(true, 0x2, &[1, 2, 3]));
+
+        let mut socket = socket(input);
+        assert_eq!(
+            socket.read_message().unwrap(),
+            WsMessage::Text("Hello w\u{f6}rld".to_owned())
+        );
+        assert_eq!(
+            socket.read_message().unwrap(),
+            WsMessage::Binary(vec![1, 2, 3])
+        );
+        // the ping was answered
+        assert_eq!(socket.into_inner().output, b"\x8A\x04beat");
+    }
+
+    #[test]
+    fn control_payload_too_large() {
+        let mut socket = socket(Vec::new());
+        let err = socket
+            .send_message(&WsMessage::Ping(vec![0; 126]))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = socket
+            .send_message(&WsMessage::Close(Some(1000), "a".repeat(124)))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // nothing was sent, and the connection can still be used
+        socket.send_message(&WsMessage::Ping(vec![0; 125])).unwrap();
+        let output = socket.into_inner().output;
+        assert_eq!(&output[..2], [0x89, 0x7D]);
+        assert_eq!(output.len(), 2 + 125);
+    }
+
+    #[test]
+    fn close_handshake() {
+        let mut payload = 1000u16.to_be_bytes().to_vec();
+        payload.extend_from_slice(b"bye");
+        let mut socket = socket(client_frame(true, 0x8, &payload));
+
+        assert_eq!(
+            socket.read_message().unwrap(),
+            WsMessage::Close(Some(1000), "bye".to_owned())
+        );
+        let err = socket.read_message().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        assert_eq!(socket.into_inner().output, [0x88, 0x02, 0x03, 0xE8]);
+    }
+
+    #[test]
+    fn close_started_by_server() {
+        let mut socket = socket(client_frame(true, 0x8, &[]));
+        socket
+            .send_message(&WsMessage::Close(Some(1001), "going away".to_owned()))
+            .unwrap();
+        let err = socket
+            .send_message(&WsMessage::Text("late".to_owned()))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+
+        // the close frame of the client isn't answered again
+        assert_eq!(
+            socket.read_message().unwrap(),
+            WsMessage::Close(None, String::new())
+        );
+        assert_eq!(socket.into_inner().output, b"\x88\x0c\x03\xe9going away");
+    }
+
+    #[test]
+    fn unmasked_frame() {
+        let mut socket = socket(b"\x81\x05Hello".to_vec());
+        let err = socket.read_message().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        // closed with a protocol error
+        assert_eq!(socket.into_inner().output, [0x88, 0x02, 0x03, 0xEA]);
+    }
+
+    #[test]
+    fn protocol_errors() {
+        let cases = vec![
+            // continuation without a message
+            client_frame(true, 0x0, b"a"),
+            // a message in the middle of another
+            [
+                client_frame(false, 0x1, b"a"),
+                client_frame(true, 0x1, b"b"),
+            ]
+            .concat(),
+            // fragmented control frame
+            client_frame(false, 0x9, b""),
+            // unknown opcode
+            client_frame(true, 0x3, b""),
+            // reserved bits
+            {
+                let mut frame = client_frame(true, 0x1, b"a");
+                frame[0] |= 0x40;
+                frame
+            },
+            // close frame with a single byte, or a reserved code
+            client_frame(true, 0x8, &[0x03]),
+            client_frame(true, 0x8, &1005u16.to_be_bytes()),
+        ];
+        for input in cases {
+            let mut socket = socket(input.clone());
+            let err = socket.read_message().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{:?}", input);
+            assert_eq!(socket.into_inner().output, [0x88, 0x02, 0x03, 0xEA]);
+        }
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut socket = socket(client_frame(true, 0x1, &[0xC0, 0xAF]));
+        let err = socket.read_message().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(socket.into_inner().output, [0x88, 0x02, 0x03, 0xEF]);
+    }
+
+    #[test]
+    fn max_message_size() {
+        let input = [
+            client_frame(false, 0x2, &[0; 100]),
+            client_frame(true, 0x0, &[0; 100]),
+        ]
+        .concat();
+
+        let mut socket = self::socket(input.clone()).with_max_message_size(200);
+        assert_eq!(
+            socket.read_message().unwrap(),
+            WsMessage::Binary(vec![0; 200])
+        );
+
+        let mut socket = self::socket(input).with_max_message_size(199);
+        let err = socket.read_message().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(socket.into_inner().output, [0x88, 0x02, 0x03, 0xF1]);
+    }
+}
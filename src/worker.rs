@@ -0,0 +1,56 @@
+//! Retiring the threads that receive requests one at a time.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::util::MessagesQueue;
+use crate::Message;
+
+/// Identifies a thread receiving requests with `Server::recv_with_token()`, so that it can be
+/// told to stop without affecting the other ones. Returned by `Server::register_worker()`.
+///
+/// Clones of a token refer to the same worker: the worker keeps one and passes it to
+/// `recv_with_token()`, while another thread may call `retire()` on a clone.
+#[derive(Clone)]
+pub struct WorkerToken {
+    retired: Arc<AtomicBool>,
+    messages: Arc<MessagesQueue<Message>>,
+}
+
+impl WorkerToken {
+    pub(crate) fn new(messages: Arc<MessagesQueue<Message>>) -> WorkerToken {
+        WorkerToken {
+            retired: Arc::new(AtomicBool::new(false)),
+            messages,
+        }
+    }
+
+    /// Tells the worker to stop receiving requests.
+    ///
+    /// The request that the worker is handling, if any, isn't interrupted. The current call
+    /// to `recv_with_token()` returns `RecvError::Retired` if it is blocked, and so do all the
+    /// following ones. The requests in the queue are left to the other workers.
+    pub fn retire(&self) {
+        self.retired.store(true, Ordering::SeqCst);
+        self.messages.wake_all();
+    }
+
+    /// Returns true if `retire()` was called.
+    pub fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn flag(&self) -> &AtomicBool {
+        &self.retired
+    }
+}
+
+impl fmt::Debug for WorkerToken {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("WorkerToken")
+            .field("retired", &self.is_retired())
+            .finish()
+    }
+}
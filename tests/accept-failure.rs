@@ -0,0 +1,50 @@
+// Lowers the limit of open files of the whole process, so this test must have its own file.
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+extern crate nix;
+extern crate tiny_http;
+
+use nix::sys::resource::{getrlimit, setrlimit, Resource};
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[allow(dead_code)]
+mod support;
+
+#[test]
+fn accept_recovers_from_fd_exhaustion() {
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let server = {
+        let errors = errors.clone();
+        support::server_with_config(
+            tiny_http::ServerConfigAdvanced::default().with_accept_error_handler(Arc::new(
+                move |err| errors.lock().unwrap().push(err.raw_os_error()),
+            )),
+        )
+    };
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    // below the number of files that are already open, so that the accept thread fails as
+    // soon as its accept timeout expires
+    let (soft, hard) = getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+    setrlimit(Resource::RLIMIT_NOFILE, 1, hard).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while errors.lock().unwrap().is_empty() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    setrlimit(Resource::RLIMIT_NOFILE, soft, hard).unwrap();
+
+    // EMFILE
+    assert_eq!(errors.lock().unwrap().first(), Some(&Some(24)));
+    assert!(server.stats().accept_errors >= 1);
+
+    // the server still accepts connections
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+    let rq = server.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(rq.unwrap().url(), "/");
+    assert_eq!(server.shutdown_reason(), None);
+}
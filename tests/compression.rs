@@ -0,0 +1,373 @@
+#![cfg(feature = "compression")]
+
+extern crate tiny_http;
+
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use tiny_http::{CompressedCache, Encoding, Header, Response, ResponseBox, TestRequest};
+
+#[allow(dead_code)]
+mod support;
+
+const BODY: &str = "{\"widgets\": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10]}";
+
+fn request(accept_encoding: Option<&str>) -> tiny_http::Request {
+    let mut request = TestRequest::new();
+    if let Some(value) = accept_encoding {
+        let header = Header::from_str(&format!("Accept-Encoding: {}", value)).unwrap();
+        request = request.with_header(header);
+    }
+    request.into()
+}
+
+fn build(counter: &AtomicUsize) -> ResponseBox {
+    counter.fetch_add(1, Ordering::SeqCst);
+    Response::from_string(BODY)
+        .with_header(Header::from_str("Content-Type: application/json").unwrap())
+        .boxed()
+}
+
+fn header(response: &ResponseBox, name: &'static str) -> Option<String> {
+    response
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.to_string())
+}
+
+fn gunzip(response: ResponseBox) -> String {
+    let mut body = String::new();
+    GzDecoder::new(response.into_reader())
+        .read_to_string(&mut body)
+        .unwrap();
+    body
+}
+
+#[test]
+fn cached_body_is_not_rebuilt() {
+    let cache = CompressedCache::new(16);
+    let builds = AtomicUsize::new(0);
+
+    for _ in 0..2 {
+        let response =
+            cache.respond_compressed_cached(&request(Some("gzip")), "v1", || build(&builds));
+        assert_eq!(
+            header(&response, "Content-Encoding").as_deref(),
+            Some("gzip")
+        );
+        assert_eq!(header(&response, "ETag").as_deref(), Some("\"v1\""));
+        assert_eq!(
+            header(&response, "Content-Type").as_deref(),
+            Some("application/json")
+        );
+        assert_eq!(response.vary_fields(), ["Accept-Encoding"]);
+        assert_eq!(gunzip(response), BODY);
+    }
+    assert_eq!(builds.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn encodings_are_cached_separately() {
+    let cache = CompressedCache::new(16);
+    let builds = AtomicUsize::new(0);
+
+    let gzip = cache.respond_compressed_cached(&request(Some("gzip")), "v1", || build(&builds));
+    let identity = cache.respond_compressed_cached(&request(None), "v1", || build(&builds));
+    assert_eq!(builds.load(Ordering::SeqCst), 2);
+    assert_eq!(cache.len(), 2);
+
+    assert_eq!(header(&identity, "Content-Encoding"), None);
+    let mut body = String::new();
+    identity.into_reader().read_to_string(&mut body).unwrap();
+    assert_eq!(body, BODY);
+    assert_eq!(gunzip(gzip), BODY);
+
+    // both variants are cached
+    cache.respond_compressed_cached(&request(Some("gzip;q=0")), "v1", || build(&builds));
+    cache.respond_compressed_cached(&request(Some("compress, gzip")), "v1", || build(&builds));
+    assert_eq!(builds.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn invalidation_rebuilds() {
+    let cache = CompressedCache::new(16);
+    let builds = AtomicUsize::new(0);
+
+    cache.respond_compressed_cached(&request(Some("gzip")), "page", || build(&builds));
+    cache.invalidate("page");
+    assert!(cache.is_empty());
+    cache.respond_compressed_cached(&request(Some("gzip")), "page", || build(&builds));
+    assert_eq!(builds.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn not_modified_end_to_end() {
+    let builds = Arc::new(AtomicUsize::new(0));
+    let (server, mut client) = support::new_one_server_one_client();
+    {
+        let builds = builds.clone();
+        thread::spawn(move || {
+            let cache = CompressedCache::new(16);
+            for rq in server.incoming_requests() {
+                let response = cache.respond_compressed_cached(&rq, "v1", || build(&builds));
+                rq.respond(response).unwrap();
+            }
+        });
+    }
+
+    write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nIf-None-Match: \"v0\", \"v1\"\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 304"), "{}", response);
+    assert!(response.contains("\r\nETag: \"v1\"\r\n"));
+    assert!(response.contains("\r\nVary: Accept-Encoding\r\n"));
+    assert!(response.ends_with("\r\n\r\n"));
+    assert_eq!(builds.load(Ordering::SeqCst), 0);
+}
+
+/// A JSON body long enough to be compressed by `Response::with_compression`.
+fn long_body() -> String {
+    let widgets: Vec<_> = (0..500).map(|i| i.to_string()).collect();
+    format!("{{\"widgets\": [{}]}}", widgets.join(", "))
+}
+
+/// Sends a request with the given headers, answers it with `response` and
+/// `Response::with_compression`, and returns the head and the decoded body received by the
+/// client.
+fn fetch(headers: &str, response: ResponseBox) -> (String, Vec<u8>) {
+    let (server, mut client) = support::new_one_server_one_client();
+    write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+        headers
+    )
+    .unwrap();
+
+    let handler = thread::spawn(move || {
+        let rq = server.recv().unwrap();
+        let response = response.with_compression(&rq);
+        rq.respond(response).unwrap();
+    });
+
+    let mut content = Vec::new();
+    client.read_to_end(&mut content).unwrap();
+    handler.join().unwrap();
+
+    let split = content.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let head = String::from_utf8(content[..split].to_vec()).unwrap();
+    let mut body = Vec::new();
+    if head.contains("Transfer-Encoding: chunked\r\n") {
+        chunked_transfer::Decoder::new(&content[split..])
+            .read_to_end(&mut body)
+            .unwrap();
+    } else {
+        body = content[split..].to_vec();
+    }
+    (head, body)
+}
+
+#[test]
+fn gzip_response() {
+    let body = long_body();
+    let response = Response::from_string(body.clone())
+        .with_header(Header::from_str("Content-Type: application/json").unwrap())
+        .boxed();
+    let (head, compressed) = fetch("Accept-Encoding: br;q=0.5, gzip\r\n", response);
+
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+    assert!(head.contains("Content-Encoding: gzip\r\n"), "{}", head);
+    assert!(head.contains("Vary: Accept-Encoding\r\n"), "{}", head);
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{}", head);
+    assert!(!head.contains("Content-Length"), "{}", head);
+    assert!(compressed.len() < body.len());
+
+    let mut decoded = String::new();
+    GzDecoder::new(&compressed[..])
+        .read_to_string(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, body);
+}
+
+#[test]
+fn gzip_refused() {
+    let body = long_body();
+    for headers in ["Accept-Encoding: gzip;q=0, compress\r\n", ""].iter() {
+        let response = Response::from_string(body.clone()).boxed();
+        let (head, received) = fetch(headers, response);
+
+        assert!(!head.contains("Content-Encoding"), "{}", head);
+        assert!(head.contains("Vary: Accept-Encoding\r\n"), "{}", head);
+        assert!(
+            head.contains(&format!("Content-Length: {}\r\n", body.len())),
+            "{}",
+            head
+        );
+        assert_eq!(received, body.as_bytes());
+    }
+}
+
+#[test]
+fn compression_skipped() {
+    let body = long_body();
+    let responses = vec![
+        // too short to be worth it
+        Response::from_string(BODY).boxed(),
+        // already compressed
+        Response::from_data(body.clone())
+            .with_header(Header::from_str("Content-Type: image/png").unwrap())
+            .boxed(),
+        Response::from_data(body.clone())
+            .with_header(Header::from_str("Content-Encoding: br").unwrap())
+            .boxed(),
+        Response::from_string(body.clone())
+            .with_status_code(206)
+            .boxed(),
+    ];
+
+    for response in responses {
+        let (head, _) = fetch("Accept-Encoding: gzip\r\n", response);
+        assert!(!head.contains("Content-Encoding: gzip"), "{}", head);
+        assert!(!head.contains("Vary"), "{}", head);
+        assert!(head.contains("Content-Length: "), "{}", head);
+    }
+}
+
+/// Decompresses a body compressed with the given `Content-Encoding`.
+fn decompress(encoding: &str, compressed: &[u8]) -> String {
+    let mut decoded = String::new();
+    match encoding {
+        "gzip" => GzDecoder::new(compressed).read_to_string(&mut decoded),
+        "deflate" => ZlibDecoder::new(compressed).read_to_string(&mut decoded),
+        #[cfg(feature = "brotli")]
+        "br" => brotli_crate::Decompressor::new(compressed, 4096).read_to_string(&mut decoded),
+        _ => unreachable!(),
+    }
+    .unwrap();
+    decoded
+}
+
+#[test]
+fn each_encoding() {
+    let body = long_body();
+    let mut encodings = vec!["gzip", "deflate"];
+    if cfg!(feature = "brotli") {
+        encodings.push("br");
+    }
+
+    for encoding in encodings {
+        let response = Response::from_string(body.clone()).boxed();
+        let headers = format!("Accept-Encoding: identity;q=0.5, {}\r\n", encoding);
+        let (head, compressed) = fetch(&headers, response);
+        assert!(
+            head.contains(&format!("Content-Encoding: {}\r\n", encoding)),
+            "{}",
+            head
+        );
+        assert!(compressed.len() < body.len());
+        assert_eq!(decompress(encoding, &compressed), body);
+    }
+}
+
+#[test]
+fn cached_encodings() {
+    let cache = CompressedCache::new(16);
+    let builds = AtomicUsize::new(0);
+
+    for encoding in ["gzip", "deflate"].iter() {
+        let response =
+            cache.respond_compressed_cached(&request(Some(encoding)), "v1", || build(&builds));
+        assert_eq!(
+            header(&response, "Content-Encoding").as_deref(),
+            Some(*encoding)
+        );
+        let mut compressed = Vec::new();
+        response.into_reader().read_to_end(&mut compressed).unwrap();
+        assert_eq!(decompress(encoding, &compressed), BODY);
+    }
+}
+
+/// Sends a `POST` request with the given headers and body, and returns the request received
+/// by the server.
+fn post(headers: &str, body: &[u8]) -> (tiny_http::Request, std::net::TcpStream) {
+    let (server, mut client) = support::new_one_server_one_client();
+    write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+        headers
+    )
+    .unwrap();
+    client.write_all(body).unwrap();
+    (server.recv().unwrap(), client)
+}
+
+#[test]
+fn gzip_request_body() {
+    let body = long_body();
+    let compressed = Encoding::Gzip.compress(body.as_bytes()).unwrap();
+    let headers = format!(
+        "Content-Encoding: gzip\r\nContent-Length: {}\r\n",
+        compressed.len()
+    );
+    let (mut rq, _client) = post(&headers, &compressed);
+
+    assert_eq!(rq.body_length(), None);
+    assert!(!rq
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Content-Encoding")));
+    let mut received = String::new();
+    rq.as_reader().read_to_string(&mut received).unwrap();
+    assert_eq!(received, body);
+}
+
+#[test]
+fn chunked_deflate_request_body() {
+    let compressed = Encoding::Deflate.compress(BODY.as_bytes()).unwrap();
+    let mut chunked = format!("{:x}\r\n", compressed.len()).into_bytes();
+    chunked.extend_from_slice(&compressed);
+    chunked.extend_from_slice(b"\r\n0\r\n\r\n");
+    let headers = "Content-Encoding: Deflate\r\nTransfer-Encoding: chunked\r\n";
+    let (mut rq, _client) = post(headers, &chunked);
+
+    let mut received = String::new();
+    rq.as_reader().read_to_string(&mut received).unwrap();
+    assert_eq!(received, BODY);
+}
+
+#[test]
+fn corrupted_request_body() {
+    let mut compressed = Encoding::Gzip.compress(BODY.as_bytes()).unwrap();
+    let middle = compressed.len() / 2;
+    for byte in &mut compressed[middle..] {
+        *byte = !*byte;
+    }
+    let headers = format!(
+        "Content-Encoding: gzip\r\nContent-Length: {}\r\n",
+        compressed.len()
+    );
+    let (mut rq, _client) = post(&headers, &compressed);
+
+    let mut received = Vec::new();
+    assert!(rq.as_reader().read_to_end(&mut received).is_err());
+}
+
+#[test]
+fn unknown_request_encoding_unchanged() {
+    let headers = "Content-Encoding: compress\r\nContent-Length: 5\r\n";
+    let (mut rq, _client) = post(headers, b"hello");
+
+    assert_eq!(rq.body_length(), Some(5));
+    let mut received = String::new();
+    rq.as_reader().read_to_string(&mut received).unwrap();
+    assert_eq!(received, "hello");
+}
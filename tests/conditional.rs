@@ -0,0 +1,156 @@
+extern crate tiny_http;
+
+use std::io::{Read, Write};
+use std::thread;
+
+use tiny_http::{HTTPDate, Header, Precondition, Response};
+
+#[allow(dead_code)]
+mod support;
+
+const LAST_MODIFIED: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+/// Sends a request with the given method and headers, answers it according to its
+/// preconditions for the entity tag `"v1"` and the date `LAST_MODIFIED`, and returns what
+/// the client received.
+fn fetch(method: &str, headers: &str) -> String {
+    let (server, mut client) = support::new_one_server_one_client();
+    (write!(
+        client,
+        "{} / HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: 0\r\nConnection: close\r\n\r\n",
+        method, headers
+    ))
+    .unwrap();
+
+    let handler = thread::spawn(move || {
+        let rq = server.recv().unwrap();
+        let cache_control = Header::from_bytes(&b"Cache-Control"[..], &b"max-age=60"[..]).unwrap();
+        let content_type = Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap();
+        let last_modified = HTTPDate::parse(LAST_MODIFIED).unwrap();
+        let response = Response::from_string("hello")
+            .with_etag("v1")
+            .with_last_modified(last_modified)
+            .with_header(cache_control)
+            .with_header(content_type);
+        match rq.evaluate_preconditions(Some("\"v1\""), Some(last_modified)) {
+            Precondition::Proceed => rq.respond(response),
+            Precondition::NotModified => rq.respond(response.not_modified()),
+            Precondition::Failed => rq.respond(Response::empty(412)),
+        }
+        .unwrap();
+    });
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    handler.join().unwrap();
+    content
+}
+
+#[test]
+fn etag_sent() {
+    let content = fetch("GET", "");
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(content.contains("ETag: \"v1\"\r\n"), "{}", content);
+    assert!(content.ends_with("\r\n\r\nhello"), "{}", content);
+}
+
+#[test]
+fn not_modified() {
+    for headers in [
+        "If-None-Match: \"v1\"\r\n",
+        "If-None-Match: W/\"v1\"\r\n",
+        "If-None-Match: \"v0\", \"v1\"\r\n",
+        "If-None-Match: *\r\n",
+    ]
+    .iter()
+    {
+        let content = fetch("GET", headers);
+        assert!(
+            content.starts_with("HTTP/1.1 304 Not Modified\r\n"),
+            "{}",
+            content
+        );
+        assert!(content.contains("ETag: \"v1\"\r\n"), "{}", content);
+        assert!(
+            content.contains("Cache-Control: max-age=60\r\n"),
+            "{}",
+            content
+        );
+        assert!(!content.contains("Content-Type"), "{}", content);
+        assert!(!content.contains("Content-Length"), "{}", content);
+        assert!(content.ends_with("\r\n\r\n"), "{}", content);
+    }
+}
+
+#[test]
+fn modified() {
+    let content = fetch("GET", "If-None-Match: \"v0\", W/\"v2\"\r\n");
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(content.ends_with("\r\n\r\nhello"), "{}", content);
+}
+
+#[test]
+fn head_not_modified() {
+    let content = fetch("HEAD", "If-None-Match: \"v1\"\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 304 Not Modified\r\n"),
+        "{}",
+        content
+    );
+    assert!(content.ends_with("\r\n\r\n"), "{}", content);
+}
+
+#[test]
+fn precondition_failed() {
+    let content = fetch("PUT", "If-Match: W/\"v1\"\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 412 Precondition Failed\r\n"),
+        "{}",
+        content
+    );
+
+    let content = fetch("PUT", "If-None-Match: \"v1\"\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 412 Precondition Failed\r\n"),
+        "{}",
+        content
+    );
+
+    let content = fetch("PUT", "If-Match: \"v0\", \"v1\"\r\n");
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+}
+
+#[test]
+fn last_modified() {
+    let content = fetch("GET", "");
+    assert!(
+        content.contains("Last-Modified: Sun, 06 Nov 1994 08:49:37 GMT\r\n"),
+        "{}",
+        content
+    );
+
+    let content = fetch(
+        "GET",
+        "If-Modified-Since: Sunday, 06-Nov-94 08:49:37 GMT\r\n",
+    );
+    assert!(
+        content.starts_with("HTTP/1.1 304 Not Modified\r\n"),
+        "{}",
+        content
+    );
+    assert!(content.contains("Last-Modified: "), "{}", content);
+
+    let content = fetch(
+        "GET",
+        "If-Modified-Since: Sat, 05 Nov 1994 08:49:37 GMT\r\n",
+    );
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(content.ends_with("\r\n\r\nhello"), "{}", content);
+
+    let content = fetch("PUT", "If-Unmodified-Since: Sat Nov  5 08:49:37 1994\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 412 Precondition Failed\r\n"),
+        "{}",
+        content
+    );
+}
@@ -0,0 +1,122 @@
+#![cfg(feature = "log")]
+
+extern crate tiny_http;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tiny_http::{ConnectionTraceFilter, Response, Server};
+
+/// Logger keeping the trace lines of the library.
+struct Capture {
+    lines: Mutex<Vec<String>>,
+}
+
+impl log::Log for Capture {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        metadata.target().starts_with("tiny_http::trace")
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if self.enabled(record.metadata()) {
+            self.lines.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sends a request on a new connection and waits for the response.
+fn request(server: &Server, path: &str) -> TcpStream {
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    ))
+    .unwrap();
+
+    let rq = server.recv().unwrap();
+    rq.respond(Response::from_string("hello")).unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    client
+}
+
+/// Returns the bytes dumped by the lines of `conn` in direction `dir`.
+fn dumped(lines: &[String], conn: &str, dir: &str) -> Vec<u8> {
+    let prefix = format!("{} {} ", conn, dir);
+    lines
+        .iter()
+        .filter(|line| line.starts_with(&prefix))
+        .flat_map(|line| {
+            let hex = line[..line.find('|').unwrap()].split_whitespace().skip(3);
+            hex.map(|byte| u8::from_str_radix(byte, 16).unwrap())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[test]
+fn traced_connections() {
+    let capture: &'static Capture = Box::leak(Box::new(Capture {
+        lines: Mutex::new(Vec::new()),
+    }));
+    log::set_logger(capture).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let server = Server::http("127.0.0.1:0").unwrap();
+
+    // only the first connection is traced
+    server
+        .trace_connection(ConnectionTraceFilter::new(1).with_peer_ip("127.0.0.1".parse().unwrap()));
+    let first = request(&server, "/first");
+    let second = request(&server, "/second");
+
+    let lines = capture.lines.lock().unwrap().clone();
+    assert!(lines
+        .iter()
+        .all(|line| line.starts_with("conn#1 ") || line.starts_with("conn#1:")));
+    assert_eq!(
+        lines[0],
+        format!(
+            "conn#1: tracing the connection of Some({})",
+            first.local_addr().unwrap()
+        )
+    );
+    let second_addr = second.local_addr().unwrap().to_string();
+    assert!(!lines.iter().any(|line| line.contains(&second_addr)));
+
+    let received = String::from_utf8(dumped(&lines, "conn#1", "recv")).unwrap();
+    assert_eq!(
+        received,
+        "GET /first HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    );
+    let sent = String::from_utf8(dumped(&lines, "conn#1", "send")).unwrap();
+    assert!(sent.starts_with("HTTP/1.1 200 OK\r\n"), "{}", sent);
+    assert!(sent.ends_with("\r\n\r\nhello"), "{}", sent);
+
+    // the dump of a connection is cut after the limit
+    capture.lines.lock().unwrap().clear();
+    server.trace_connection(ConnectionTraceFilter::new(1).with_max_bytes(20));
+    request(&server, "/truncated");
+
+    // the connections are numbered like `Request::connection_id()`, traced or not
+    let lines = capture.lines.lock().unwrap().clone();
+    assert_eq!(dumped(&lines, "conn#3", "recv"), b"GET /truncated HTTP/");
+    assert!(dumped(&lines, "conn#3", "send").is_empty());
+    assert_eq!(lines.last().unwrap(), "conn#3: trace truncated");
+    assert_eq!(
+        lines
+            .iter()
+            .filter(|line| line.ends_with("truncated"))
+            .count(),
+        1
+    );
+}
@@ -0,0 +1,80 @@
+extern crate tiny_http;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[allow(dead_code)]
+mod support;
+
+/// Reads lines up to and including the first empty one.
+fn read_until_empty_line<R: BufRead>(reader: &mut R) -> Vec<String> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(!line.is_empty(), "connection closed");
+        if line == "\r\n" || line == "\n" {
+            return lines;
+        }
+        lines.push(line);
+    }
+}
+
+#[test]
+fn events_arrive_promptly() {
+    let (server, client) = support::new_one_server_one_client();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (&client)
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+
+    let (read_tx, read_rx) = channel();
+    let handler = thread::spawn(move || {
+        let request = server.recv().unwrap();
+        let mut events = request.into_event_stream(Vec::new()).unwrap();
+        events.send_event("greeting", "hello\nworld").unwrap();
+        // the second event is only sent once the client got the first one
+        read_rx.recv().unwrap();
+        events.send_retry(Duration::from_secs(3)).unwrap();
+        events.send_comment("ping").unwrap();
+        events.send_data("second").unwrap();
+        read_rx.recv().unwrap();
+    });
+
+    let mut reader = BufReader::new(&client);
+    let head = read_until_empty_line(&mut reader);
+    assert_eq!(head[0], "HTTP/1.1 200 OK\r\n");
+    assert!(head.contains(&"Content-Type: text/event-stream\r\n".to_owned()));
+    assert!(head.contains(&"Cache-Control: no-cache\r\n".to_owned()));
+    assert!(head.contains(&"Transfer-Encoding: chunked\r\n".to_owned()));
+
+    let start = Instant::now();
+    let chunk = read_until_empty_line(&mut reader);
+    assert_eq!(
+        chunk[1..],
+        ["event: greeting\n", "data: hello\n", "data: world\n"]
+    );
+    // the chunk's line break follows the empty line ending the event
+    assert_eq!(read_until_empty_line(&mut reader), Vec::<String>::new());
+    read_tx.send(()).unwrap();
+
+    let mut events = Vec::new();
+    while events.len() < 3 {
+        let chunk = read_until_empty_line(&mut reader);
+        events.extend(chunk.into_iter().skip(1));
+        read_until_empty_line(&mut reader);
+    }
+    assert_eq!(events, ["retry: 3000\n", ": ping\n", "data: second\n"]);
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    // dropping the writer ends the body
+    read_tx.send(()).unwrap();
+    handler.join().unwrap();
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "0\r\n\r\n");
+}
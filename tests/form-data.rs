@@ -0,0 +1,110 @@
+#![cfg(feature = "multipart")]
+
+extern crate tiny_http;
+
+use std::io::{ErrorKind, Read};
+
+use tiny_http::{Header, Request, TestRequest};
+
+/// Upload of a text field and two files, with a preamble and an epilogue.
+const TWO_FILES: &str = include_str!("form-data/two-files.http");
+
+fn form_request(content_type: &str, body: &'static str) -> Request {
+    TestRequest::new()
+        .with_header(Header::from_bytes(&b"Content-Type"[..], content_type).unwrap())
+        .with_body(body)
+        .into()
+}
+
+#[test]
+fn two_file_upload() {
+    let mut request = form_request("multipart/form-data; boundary=X-BOUNDARY", TWO_FILES);
+    let mut parts = Vec::new();
+    for part in request.multipart().unwrap() {
+        let mut part = part.unwrap();
+        // one byte at a time, so that delimiters are split between reads
+        let mut content = Vec::new();
+        let mut byte = [0];
+        while part.read(&mut byte).unwrap() == 1 {
+            content.push(byte[0]);
+        }
+        parts.push((
+            part.name().map(str::to_owned),
+            part.filename().map(str::to_owned),
+            part.content_type().map(str::to_owned),
+            String::from_utf8(content).unwrap(),
+        ));
+    }
+
+    let owned = |value: &str| Some(value.to_owned());
+    assert_eq!(
+        parts,
+        vec![
+            (owned("title"), None, None, "Holiday photos".to_owned()),
+            (
+                owned("files"),
+                owned("a \"quoted\"; name.txt"),
+                owned("text/plain"),
+                "first file\r\nwith two lines\r\n".to_owned()
+            ),
+            (
+                owned("files"),
+                owned("b.bin"),
+                owned("application/octet-stream"),
+                "second file\r\n--X-BOUNDAR".to_owned()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn skipped_parts() {
+    let mut request = form_request("multipart/form-data; boundary=\"X-BOUNDARY\"", TWO_FILES);
+    let mut multipart = request.multipart().unwrap();
+    let mut title = multipart.next().unwrap().unwrap();
+    let mut first_file = multipart.next().unwrap().unwrap();
+    let mut second_file = multipart.next().unwrap().unwrap();
+
+    // only the current part can be read
+    let mut content = String::new();
+    assert_eq!(title.read_to_string(&mut content).unwrap(), 0);
+    assert_eq!(first_file.read_to_string(&mut content).unwrap(), 0);
+    second_file.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "second file\r\n--X-BOUNDAR");
+
+    assert!(multipart.next().is_none());
+    assert_eq!(second_file.read_to_string(&mut content).unwrap(), 0);
+}
+
+#[test]
+fn not_form_data() {
+    let mut request = form_request("application/json", "{}");
+    assert!(request.multipart().is_none());
+    let mut request = form_request("multipart/form-data", TWO_FILES);
+    assert!(request.multipart().is_none());
+}
+
+#[test]
+fn malformed_boundary() {
+    // the boundary of the header is never found in the body
+    let mut request = form_request("multipart/form-data; boundary=OTHER", TWO_FILES);
+    let mut multipart = request.multipart().unwrap();
+    let err = multipart.next().unwrap().err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    assert!(multipart.next().is_none());
+
+    // the boundary is a prefix of the one in the body
+    let mut request = form_request("multipart/form-data; boundary=X-BOUND", TWO_FILES);
+    let err = request.multipart().unwrap().next().unwrap().err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    // the body stops in the middle of a part
+    let body = "--X-BOUNDARY\r\nContent-Disposition: form-data; name=a\r\n\r\ntruncated";
+    let mut request = form_request("multipart/form-data; boundary=X-BOUNDARY", body);
+    let mut multipart = request.multipart().unwrap();
+    let mut part = multipart.next().unwrap().unwrap();
+    let err = part.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    drop(part);
+    assert!(multipart.next().is_none());
+}
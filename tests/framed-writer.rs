@@ -0,0 +1,170 @@
+extern crate tiny_http;
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use tiny_http::{Header, Response, Server, StatusCode};
+
+#[allow(dead_code)]
+mod support;
+
+/// Sends a keep-alive request followed by one closing the connection.
+fn send_two_requests(client: &mut TcpStream) {
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    client
+        .write_all(
+            b"GET /cgi HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET /second HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+}
+
+/// Answers the second request sent by `send_two_requests()` and returns everything the
+/// client received.
+fn respond_to_second(server: &Server, client: &mut TcpStream) -> String {
+    let rq = server.recv().unwrap();
+    assert_eq!(rq.url(), "/second");
+    rq.respond(Response::from_string("second")).unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    content
+}
+
+#[test]
+fn declared_length_keeps_connection_alive() {
+    let (server, mut client) = support::new_one_server_one_client();
+    send_two_requests(&mut client);
+
+    let rq = server.recv().unwrap();
+    assert_eq!(rq.url(), "/cgi");
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap();
+    let mut writer = rq
+        .into_framed_writer(StatusCode(200), vec![header], Some(11))
+        .unwrap();
+    assert_eq!(writer.remaining(), Some(11));
+    writer.write_all(b"hello ").unwrap();
+    writer.write_all(b"world").unwrap();
+    assert_eq!(writer.remaining(), Some(0));
+    writer.finish().unwrap();
+
+    let content = respond_to_second(&server, &mut client);
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(content.contains("Content-Length: 11\r\n"), "{}", content);
+    assert!(
+        content.contains("Content-Type: text/plain\r\n"),
+        "{}",
+        content
+    );
+    assert!(content.contains("\r\n\r\nhello worldHTTP/1.1 200 OK\r\n"));
+    assert!(content.ends_with("\r\n\r\nsecond"), "{}", content);
+}
+
+#[test]
+fn unknown_length_is_chunked() {
+    let (server, mut client) = support::new_one_server_one_client();
+    send_two_requests(&mut client);
+
+    let rq = server.recv().unwrap();
+    let mut writer = rq
+        .into_framed_writer(StatusCode(200), Vec::new(), None)
+        .unwrap();
+    assert_eq!(writer.remaining(), None);
+    writer.write_all(b"hello").unwrap();
+    writer.write_all(b" world").unwrap();
+    writer.finish().unwrap();
+
+    let content = respond_to_second(&server, &mut client);
+    assert!(
+        content.contains("Transfer-Encoding: chunked\r\n"),
+        "{}",
+        content
+    );
+    assert!(content.contains("\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\nHTTP/1.1 200 OK\r\n"));
+    assert!(content.ends_with("\r\n\r\nsecond"), "{}", content);
+}
+
+#[test]
+fn writing_past_declared_length_fails() {
+    let (server, mut client) = support::new_one_server_one_client();
+    send_two_requests(&mut client);
+
+    let rq = server.recv().unwrap();
+    let mut writer = rq
+        .into_framed_writer(StatusCode(200), Vec::new(), Some(5))
+        .unwrap();
+    let err = writer.write_all(b"hello world").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    writer.finish().unwrap();
+
+    // the bytes that fit were sent, and the connection is still usable
+    let content = respond_to_second(&server, &mut client);
+    assert!(
+        content.contains("\r\n\r\nhelloHTTP/1.1 200 OK\r\n"),
+        "{}",
+        content
+    );
+    assert!(content.ends_with("\r\n\r\nsecond"), "{}", content);
+}
+
+#[test]
+fn short_body_is_padded() {
+    let (server, mut client) = support::new_one_server_one_client();
+    send_two_requests(&mut client);
+
+    let rq = server.recv().unwrap();
+    let mut writer = rq
+        .into_framed_writer(StatusCode(200), Vec::new(), Some(8))
+        .unwrap()
+        .with_short_body_padding(true);
+    writer.write_all(b"hello").unwrap();
+    writer.finish().unwrap();
+
+    let content = respond_to_second(&server, &mut client);
+    assert!(content.contains("\r\n\r\nhello\0\0\0HTTP/1.1 200 OK\r\n"));
+}
+
+#[test]
+fn short_body_closes_connection() {
+    let (server, mut client) = support::new_one_server_one_client();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+
+    let rq = server.recv().unwrap();
+    let mut writer = rq
+        .into_framed_writer(StatusCode(200), Vec::new(), Some(8))
+        .unwrap();
+    writer.write_all(b"hello").unwrap();
+    let err = writer.finish().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.ends_with("\r\n\r\nhello"), "{}", content);
+}
+
+#[test]
+fn raw_writer_closes_connection() {
+    let (server, mut client) = support::new_one_server_one_client();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+
+    let rq = server.recv().unwrap();
+    let mut writer = rq.into_writer();
+    writer
+        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+        .unwrap();
+    drop(writer);
+
+    // the connection is closed although the client didn't ask for it
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+}
@@ -0,0 +1,80 @@
+extern crate tiny_http;
+
+use std::fs;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use tiny_http::{Response, StatusCode};
+
+/// Creates an empty directory for the files of a test.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tiny-http-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn content_type(response: &Response<File>) -> Option<&str> {
+    response
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Content-Type"))
+        .map(|h| h.value.as_str())
+}
+
+#[test]
+fn html_file() {
+    let dir = test_dir("html-file");
+    let path = dir.join("index.HTML");
+    fs::write(&path, "<p>hello</p>").unwrap();
+
+    let response = Response::from_path(&path).unwrap();
+    assert_eq!(response.status_code(), StatusCode(200));
+    assert_eq!(response.data_length(), Some(12));
+    assert_eq!(content_type(&response), Some("text/html; charset=utf-8"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn unknown_extension() {
+    let dir = test_dir("unknown-extension");
+    for name in ["data.unknown", "no-extension"].iter() {
+        let path = dir.join(name);
+        fs::write(&path, [0, 1, 2]).unwrap();
+        let response = Response::from_path(&path).unwrap();
+        assert_eq!(response.data_length(), Some(3));
+        assert_eq!(content_type(&response), Some("application/octet-stream"));
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn missing_file() {
+    let dir = test_dir("missing-file");
+    let err = Response::from_path(dir.join("missing.html")).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn directory() {
+    let dir = test_dir("directory");
+    let err = Response::from_path(&dir).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().ends_with("is a directory"), "{}", err);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn symbolic_link() {
+    let dir = test_dir("symbolic-link");
+    fs::write(dir.join("target.txt"), "hello").unwrap();
+    std::os::unix::fs::symlink(dir.join("target.txt"), dir.join("link.txt")).unwrap();
+
+    let err = Response::from_path(dir.join("link.txt")).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().ends_with("is a symbolic link"), "{}", err);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -4,6 +4,7 @@ use std::io::{Read, Write};
 use std::net::Shutdown;
 use std::sync::mpsc;
 use std::thread;
+use std::time::Duration;
 
 #[allow(dead_code)]
 mod support;
@@ -55,6 +56,11 @@ fn expect_100_continue() {
         let mut output = String::new();
         request.as_reader().read_to_string(&mut output).unwrap();
         assert_eq!(output, "hello");
+
+        // the interim response is only sent once
+        request.as_reader();
+        let interim: Vec<_> = request.interim_responses().iter().map(|r| r.0).collect();
+        assert_eq!(interim, [tiny_http::StatusCode(100)]);
         tx.send(()).unwrap();
     });
 
@@ -70,6 +76,118 @@ fn expect_100_continue() {
     rx.recv().unwrap();
 }
 
+fn manual_continue_server() -> (tiny_http::Server, std::net::TcpStream) {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_automatic_continue(false),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let client = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    (server, client)
+}
+
+#[test]
+fn manual_continue_accepted() {
+    let (server, mut client) = manual_continue_server();
+    (write!(client, "POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n")).unwrap();
+
+    let mut request = server.recv().unwrap();
+    assert!(request.expects_continue());
+    request.send_continue();
+    assert!(!request.expects_continue());
+
+    let mut content = vec![0; 23];
+    client.read_exact(&mut content).unwrap();
+    assert_eq!(&content[..], b"HTTP/1.1 100 Continue\r\n");
+    (write!(client, "hello")).unwrap();
+
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "hello");
+    request
+        .respond(tiny_http::Response::from_string("thanks"))
+        .unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.contains("\r\n\r\nHTTP/1.1 200 OK"), "{}", content);
+    assert!(content.ends_with("thanks"), "{}", content);
+}
+
+#[test]
+fn manual_continue_rejected() {
+    let (server, mut client) = manual_continue_server();
+    (write!(client, "PUT /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 1000000000\r\n\r\n")).unwrap();
+
+    let request = server.recv().unwrap();
+    assert!(request.expects_continue());
+    request
+        .reject_expectation(tiny_http::Response::empty(413))
+        .unwrap();
+
+    // the response is final and the connection is closed, without waiting for the body
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(
+        content.starts_with("HTTP/1.1 413 Payload Too Large"),
+        "{}",
+        content
+    );
+    assert!(content.contains("Connection: close"), "{}", content);
+    assert!(!content.contains("100 Continue"), "{}", content);
+}
+
+#[test]
+fn manual_continue_rejected_body_sent_anyway() {
+    let (server, mut client) = manual_continue_server();
+    // the client doesn't wait for the `100 Continue`, and the body looks like a request
+    (write!(client, "POST / HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 37\r\n\r\nGET /admin HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+
+    let request = server.recv().unwrap();
+    request
+        .reject_expectation(tiny_http::Response::empty(401))
+        .unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(
+        content.starts_with("HTTP/1.1 401 Unauthorized"),
+        "{}",
+        content
+    );
+    assert_eq!(content.matches("HTTP/1.1").count(), 1, "{}", content);
+    assert!(server
+        .recv_timeout(std::time::Duration::from_millis(200))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn manual_continue_rejected_body_sent_late() {
+    let (server, mut client) = manual_continue_server();
+    (write!(client, "PUT /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 32768\r\n\r\n")).unwrap();
+
+    let request = server.recv().unwrap();
+    let rejecting = thread::spawn(move || {
+        let response = tiny_http::Response::from_data(vec![b'x'; 4 << 20]).with_status_code(413);
+        request.reject_expectation(response).unwrap()
+    });
+
+    // the client stops waiting and sends the body while the response is written, which must
+    // not make the server reset the connection before the client reads the whole response
+    thread::sleep(Duration::from_millis(200));
+    client.write_all(&[b'a'; 32768]).unwrap();
+    let mut content = Vec::new();
+    client.read_to_end(&mut content).unwrap();
+    assert!(content.starts_with(b"HTTP/1.1 413 Payload Too Large"));
+    // the response is chunked
+    assert!(content.len() > 4 << 20);
+    assert!(content.ends_with(b"xx\r\n0\r\n\r\n"));
+    rejecting.join().unwrap();
+}
+
 #[test]
 fn unsupported_expect_header() {
     let mut client = support::new_client_to_hello_world_server();
@@ -94,6 +212,301 @@ fn invalid_header_name() {
     assert!(&content[9..].starts_with("400 Bad Request")); // 400 status code
 }
 
+/// Sends a request with the given header lines to a hello world server, returns the response.
+fn request_with_headers(headers: &str) -> String {
+    let mut client = support::new_client_to_hello_world_server();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\n{}Connection: close\r\n\r\n",
+        headers
+    ))
+    .unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    content
+}
+
+#[test]
+fn tab_before_header_colon() {
+    let content = request_with_headers("Host: localhost\r\nContent-Length\t: 5\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 400 Bad Request"),
+        "{}",
+        content
+    );
+}
+
+#[test]
+fn whitespace_before_first_header() {
+    let content = request_with_headers(" Host: localhost\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 400 Bad Request"),
+        "{}",
+        content
+    );
+
+    let content = request_with_headers("\tHost: localhost\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 400 Bad Request"),
+        "{}",
+        content
+    );
+}
+
+#[test]
+fn folded_header() {
+    let content = request_with_headers("Host: localhost\r\n Content-Length: 5\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 400 Bad Request"),
+        "{}",
+        content
+    );
+}
+
+#[test]
+fn folded_user_agent_rejected() {
+    let content =
+        request_with_headers("Host: localhost\r\nUser-Agent: Mozilla/5.0\r\n\t(X11; Linux)\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 400 Bad Request"),
+        "{}",
+        content
+    );
+}
+
+#[test]
+fn folded_user_agent_unfolded() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_header_unfolding(true),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: Mozilla/5.0\r\n\t(X11; Linux)\r\n  \
+         Gecko\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let request = server.recv().unwrap();
+    let user_agent = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("User-Agent"))
+        .unwrap();
+    assert_eq!(user_agent.value.as_str(), "Mozilla/5.0 (X11; Linux) Gecko");
+    assert_eq!(request.headers().len(), 3);
+    request.respond(tiny_http::Response::empty(204)).unwrap();
+
+    // a continuation needs a header to continue
+    let mut client = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(client, "GET / HTTP/1.1\r\n Host: localhost\r\n\r\n")).unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(
+        content.starts_with("HTTP/1.1 400 Bad Request"),
+        "{}",
+        content
+    );
+}
+
+#[test]
+fn missing_host_header() {
+    let content = request_with_headers("");
+    assert!(
+        content.starts_with("HTTP/1.1 400 Bad Request"),
+        "{}",
+        content
+    );
+
+    // not required before HTTP/1.1
+    let mut client = support::new_client_to_hello_world_server();
+    (write!(client, "GET / HTTP/1.0\r\n\r\n")).unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.0 200 OK"), "{}", content);
+}
+
+#[test]
+fn conflicting_host_headers() {
+    let content = request_with_headers("Host: localhost\r\nHost: example.com\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 400 Bad Request"),
+        "{}",
+        content
+    );
+
+    let content = request_with_headers("Host: localhost\r\nHost: localhost\r\n");
+    assert!(content.starts_with("HTTP/1.1 200 OK"), "{}", content);
+}
+
+#[test]
+fn host_header_not_required() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_require_host_header(false),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(client, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n")).unwrap();
+
+    let request = server.recv().unwrap();
+    assert_eq!(request.host(), None);
+    request.respond(tiny_http::Response::empty(204)).unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 204"), "{}", content);
+}
+
+#[test]
+fn malformed_request_target() {
+    for target in ["hello", "http://", "http://user@localhost/", "*"].iter() {
+        let mut client = support::new_client_to_hello_world_server();
+        (write!(
+            client,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            target
+        ))
+        .unwrap();
+
+        let mut content = String::new();
+        client.read_to_string(&mut content).unwrap();
+        assert!(
+            content.starts_with("HTTP/1.1 400 Bad Request"),
+            "{}: {}",
+            target,
+            content
+        );
+    }
+}
+
+#[test]
+fn request_target_forms() {
+    let (server, mut client) = support::new_one_server_one_client();
+    (write!(
+        client,
+        "GET http://example.com:8080/a?b=c HTTP/1.1\r\nHost: example.com:8080\r\n\r\n\
+         OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n"
+    ))
+    .unwrap();
+
+    let request = server.recv().unwrap();
+    assert_eq!(request.url(), "/a?b=c");
+    assert_eq!(
+        request.target(),
+        &tiny_http::RequestTarget::Absolute {
+            scheme: "http".to_owned(),
+            host: "example.com".to_owned(),
+            port: Some(8080),
+            path: "/a?b=c".to_owned(),
+        }
+    );
+    request.respond(tiny_http::Response::empty(204)).unwrap();
+
+    let request = server.recv().unwrap();
+    assert_eq!(request.url(), "*");
+    assert_eq!(request.target(), &tiny_http::RequestTarget::Asterisk);
+}
+
+/// Sends `request` to a hello world server without closing the connection, and returns
+/// everything the server sent before closing it.
+fn raw_exchange(request: &str) -> String {
+    let mut client = support::new_client_to_hello_world_server();
+    client
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    client.write_all(request.as_bytes()).unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    content
+}
+
+#[test]
+fn request_smuggling_payloads() {
+    let payloads = [
+        // CL.TE
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 13\r\n\
+         Transfer-Encoding: chunked\r\n\r\n0\r\n\r\nSMUGGLED",
+        // TE.CL
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\
+         Content-Length: 3\r\n\r\n8\r\nSMUGGLED\r\n0\r\n\r\n",
+        // CL.CL
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nContent-Length: 22\r\n\r\n\
+         GET /admin HTTP/1.1\r\n\r\n",
+        // TE.TE, with an encoding that a proxy could ignore
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\
+         Transfer-Encoding: identity\r\n\r\n0\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked, gzip\r\n\r\n0\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: xchunked\r\n\r\n0\r\n\r\n",
+    ];
+    for payload in payloads.iter() {
+        let content = raw_exchange(payload);
+        assert!(
+            content.starts_with("HTTP/1.1 400 Bad Request"),
+            "{:?}: {}",
+            payload,
+            content
+        );
+        assert_eq!(content.matches("HTTP/1.1 ").count(), 1, "{}", content);
+    }
+}
+
+#[test]
+fn invalid_content_length() {
+    for value in ["18446744073709551616", "+5", "5 5", "abc"].iter() {
+        let content = raw_exchange(&format!(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\nhello",
+            value
+        ));
+        assert!(
+            content.starts_with("HTTP/1.1 400 Bad Request"),
+            "{}: {}",
+            value,
+            content
+        );
+        assert_eq!(content.matches("HTTP/1.1 ").count(), 1, "{}", content);
+    }
+
+    let content = raw_exchange(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5, 5\r\nConnection: close\r\n\r\nhello",
+    );
+    assert!(content.starts_with("HTTP/1.1 200 OK"), "{}", content);
+}
+
+#[test]
+fn unambiguous_lengths() {
+    let content = raw_exchange(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nContent-Length: 5\r\n\
+         Connection: close\r\n\r\nhello",
+    );
+    assert!(content.starts_with("HTTP/1.1 200 OK"), "{}", content);
+
+    let content = raw_exchange(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip, Chunked\r\n\
+         Connection: close\r\n\r\n0\r\n\r\n",
+    );
+    assert!(content.starts_with("HTTP/1.1 200 OK"), "{}", content);
+}
+
+#[test]
+fn header_value_whitespace_is_trimmed() {
+    let (server, mut client) = support::new_one_server_one_client();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nX-Value:\t hello world \t\r\n\r\n"
+    ))
+    .unwrap();
+
+    let rq = server.recv().unwrap();
+    let value = rq
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("X-Value"))
+        .unwrap();
+    assert_eq!(value.value.as_str(), "hello world");
+}
+
 #[test]
 fn custom_content_type_response_header() {
     let (server, mut stream) = support::new_one_server_one_client();
@@ -120,3 +533,183 @@ fn custom_content_type_response_header() {
     assert!(content.ends_with("{\"custom\": \"Content-Type\"}"));
     assert_ne!(content.find("Content-Type: application/json"), None);
 }
+
+#[test]
+fn body_lines_chunked_ndjson() {
+    let (server, mut client) = support::new_one_server_one_client();
+
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n"
+    ))
+    .unwrap();
+    (write!(client, "a\r\n{{\"a\": 1}}\n{{\r\n")).unwrap();
+    (write!(client, "11\r\n\"b\": 2}}\n{{\"c\": 3}}\n\r\n0\r\n\r\n")).unwrap();
+
+    let mut request = server.recv().unwrap();
+    let lines: Vec<_> = request.body_lines_str().map(Result::unwrap).collect();
+    assert_eq!(lines, vec!["{\"a\": 1}", "{\"b\": 2}", "{\"c\": 3}"]);
+}
+
+#[test]
+fn chunked_trailers() {
+    let (server, mut client) = support::new_one_server_one_client();
+
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\
+         Trailer: X-Checksum\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\
+         X-Checksum: 5eb63bbbe01eeed093cb22bb8f5acdc3\r\n\r\n\
+         GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let mut request = server.recv().unwrap();
+    assert!(request.trailers().is_empty());
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "hello world");
+
+    let trailers = request.trailers();
+    assert_eq!(trailers.len(), 1);
+    assert!(trailers[0].field.equiv("X-Checksum"));
+    assert_eq!(
+        trailers[0].value.as_str(),
+        "5eb63bbbe01eeed093cb22bb8f5acdc3"
+    );
+    request.respond(tiny_http::Response::empty(204)).unwrap();
+
+    // the trailers are not mistaken for the next request
+    let request = server.recv().unwrap();
+    assert_eq!(request.url(), "/");
+    assert!(request.trailers().is_empty());
+}
+
+#[test]
+fn body_lines_too_long() {
+    let (server, mut client) = support::new_one_server_one_client();
+
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 20\r\n\r\nabc\r\nabcdefghijk\r\nab"
+    ))
+    .unwrap();
+
+    let mut request = server.recv().unwrap();
+    let mut lines = request
+        .body_lines()
+        .with_strip_cr(true)
+        .with_max_line_length(10);
+    assert_eq!(lines.next().unwrap().unwrap(), b"abc");
+    let err = lines.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(lines.next().is_none());
+}
+
+#[test]
+fn body_lines_without_trailing_delimiter() {
+    let (server, mut client) = support::new_one_server_one_client();
+
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\r\nhello;world"
+    ))
+    .unwrap();
+
+    let mut request = server.recv().unwrap();
+    let lines: Vec<_> = request
+        .body_lines()
+        .with_delimiter(b';')
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(lines, vec![b"hello".to_vec(), b"world".to_vec()]);
+}
+
+#[test]
+fn body_lines_then_reader() {
+    let (server, mut client) = support::new_one_server_one_client();
+
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 19\r\n\r\nheader\nfirst\nsecond"
+    ))
+    .unwrap();
+
+    let mut request = server.recv().unwrap();
+    {
+        let mut lines = request.body_lines();
+        assert_eq!(lines.next().unwrap().unwrap(), b"header");
+    }
+    let mut rest = String::new();
+    request.as_reader().read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "first\nsecond");
+}
+
+#[test]
+fn form_urlencoded_body() {
+    let (server, mut client) = support::new_one_server_one_client();
+    let body = "name=John+Doe&city=S%C3%A3o%20Paulo&empty=&tag=a&tag=b";
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+         Content-Type: application/x-www-form-urlencoded; charset=utf-8\r\n\
+         Expect: 100-continue\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    ))
+    .unwrap();
+
+    let handler = thread::spawn(move || {
+        let mut request = server.recv().unwrap();
+        let form = request.form_urlencoded(1024).unwrap();
+        request
+            .respond(tiny_http::Response::from_string("ok"))
+            .unwrap();
+        form
+    });
+
+    // the body is only sent after the 100 Continue
+    let mut interim = [0; 12];
+    client.read_exact(&mut interim).unwrap();
+    assert_eq!(&interim, b"HTTP/1.1 100");
+    client.write_all(body.as_bytes()).unwrap();
+
+    let form = handler.join().unwrap();
+    let expected = [
+        ("name", "John Doe"),
+        ("city", "S\u{e3}o Paulo"),
+        ("empty", ""),
+        ("tag", "a"),
+        ("tag", "b"),
+    ];
+    let expected: Vec<(String, String)> = expected
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    assert_eq!(form, expected);
+}
+
+#[test]
+fn form_urlencoded_limits() {
+    let (server, mut client) = support::new_one_server_one_client();
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\n\
+         Content-Type: application/x-www-form-urlencoded\r\nContent-Length: 7\r\n\r\na=12345\
+         POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+         Content-Type: text/plain\r\nContent-Length: 3\r\n\r\na=1"
+    ))
+    .unwrap();
+
+    let mut request = server.recv().unwrap();
+    match request.form_urlencoded(6) {
+        Err(tiny_http::FormError::TooLarge) => (),
+        other => panic!("{:?}", other),
+    }
+    request.respond(tiny_http::Response::empty(413)).unwrap();
+
+    let mut request = server.recv().unwrap();
+    match request.form_urlencoded(1024) {
+        Err(tiny_http::FormError::WrongContentType) => (),
+        other => panic!("{:?}", other),
+    }
+}
@@ -0,0 +1,155 @@
+extern crate tiny_http;
+
+use std::io::{Read, Write};
+use std::str::FromStr;
+use std::thread;
+
+use tiny_http::{Header, MultipartResponse, ResponseBox};
+
+#[allow(dead_code)]
+mod support;
+
+/// Sends `response` to a request and returns the head and the decoded body found on the wire.
+fn send(response: ResponseBox) -> (String, Vec<u8>) {
+    let (server, mut client) = support::new_one_server_one_client();
+    thread::spawn(move || {
+        let rq = server.recv().unwrap();
+        rq.respond(response).unwrap();
+    });
+
+    write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut data = Vec::new();
+    client.read_to_end(&mut data).unwrap();
+
+    let split = data.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8(data[..split].to_vec()).unwrap();
+    let mut body = data[split + 4..].to_vec();
+    if head.contains("\r\nTransfer-Encoding: chunked") {
+        let mut decoded = Vec::new();
+        chunked_transfer::Decoder::new(&body[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        body = decoded;
+    }
+    (head, body)
+}
+
+/// Returns the boundary of the `Content-Type` header in `head`.
+fn boundary(head: &str) -> String {
+    let content_type = head
+        .split("\r\n")
+        .find_map(|line| line.strip_prefix("Content-Type: "))
+        .unwrap();
+    content_type.split("; boundary=").nth(1).unwrap().to_owned()
+}
+
+/// Splits a multipart body into the headers and body of each part.
+fn split_parts(body: &[u8], boundary: &str) -> Vec<(Vec<String>, Vec<u8>)> {
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    let closing = format!("--{}--\r\n", boundary);
+    assert!(body.ends_with(&closing), "{:?}", body);
+
+    let delimiter = format!("--{}\r\n", boundary);
+    let mut parts = Vec::new();
+    let mut rest = &body[..body.len() - closing.len()];
+    while !rest.is_empty() {
+        rest = rest.strip_prefix(&delimiter).unwrap();
+        let end = rest
+            .find(&format!("\r\n--{}", boundary))
+            .map_or(rest.len() - 2, |end| end);
+        // a part without headers starts with the empty line
+        let part = format!("\r\n{}", &rest[..end]);
+        let (head, part_body) = part.split_once("\r\n\r\n").unwrap();
+        let headers = head
+            .split("\r\n")
+            .filter(|h| !h.is_empty())
+            .map(str::to_owned)
+            .collect();
+        parts.push((headers, part_body.as_bytes().to_vec()));
+        rest = &rest[end + 2..];
+    }
+    parts
+}
+
+#[test]
+fn sized_and_unsized_parts() {
+    let mut multipart = MultipartResponse::new("related; type=\"application/json\"");
+    let boundary_sent = multipart.boundary().to_owned();
+    multipart.add_part(
+        vec![Header::from_str("Content-Type: application/json").unwrap()],
+        &b"{\"signed\": true}"[..],
+        Some(16),
+    );
+    multipart.add_part(
+        vec![
+            Header::from_str("Content-Type: application/octet-stream").unwrap(),
+            Header::from_str("Content-ID: <payload>").unwrap(),
+        ],
+        std::io::repeat(b'x').take(5000),
+        None,
+    );
+
+    let mut response = multipart.into_response();
+    // the boundary can't be lost
+    response.add_header(Header::from_str("Content-Type: text/plain").unwrap());
+
+    let (head, body) = send(response);
+    assert!(head.contains("\r\nTransfer-Encoding: chunked"), "{}", head);
+    assert!(
+        head.contains(&format!(
+            "\r\nContent-Type: multipart/related; type=\"application/json\"; boundary={}",
+            boundary_sent
+        )),
+        "{}",
+        head
+    );
+
+    let parts = split_parts(&body, &boundary(&head));
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].0, ["Content-Type: application/json"]);
+    assert_eq!(parts[0].1, b"{\"signed\": true}");
+    assert_eq!(
+        parts[1].0,
+        [
+            "Content-Type: application/octet-stream",
+            "Content-ID: <payload>"
+        ]
+    );
+    assert_eq!(parts[1].1, vec![b'x'; 5000]);
+}
+
+#[test]
+fn sized_parts_have_content_length() {
+    let mut multipart = MultipartResponse::new("mixed");
+    multipart.add_part(vec![], &b"first"[..], Some(5));
+    // bytes after the declared length are ignored
+    multipart.add_part(vec![], &b"second part"[..], Some(6));
+
+    let response = multipart.into_response();
+    let length = response.data_length().unwrap();
+    let (head, body) = send(response);
+    assert!(
+        head.contains(&format!("\r\nContent-Length: {}", length)),
+        "{}",
+        head
+    );
+    assert_eq!(body.len(), length);
+
+    let parts = split_parts(&body, &boundary(&head));
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[0].1, b"first");
+    assert_eq!(parts[1].1, b"second");
+}
+
+#[test]
+fn no_parts() {
+    let response = MultipartResponse::new("mixed").into_response();
+    let (head, body) = send(response);
+    let boundary = boundary(&head);
+    assert_eq!(body, format!("--{}--\r\n", boundary).into_bytes());
+    assert!(split_parts(&body, &boundary).is_empty());
+}
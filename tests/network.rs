@@ -1,7 +1,9 @@
 extern crate tiny_http;
 
-use std::io::{Read, Write};
-use std::net::{Shutdown, TcpStream};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
@@ -12,10 +14,18 @@ mod support;
 fn connection_close_header() {
     let mut client = support::new_client_to_hello_world_server();
 
-    (write!(client, "GET / HTTP/1.1\r\nConnection: keep-alive\r\n\r\n")).unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n"
+    ))
+    .unwrap();
     thread::sleep(Duration::from_millis(1000));
 
-    (write!(client, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n")).unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
 
     // if the connection was not closed, this will err with timeout
     // client.set_keepalive(Some(1)).unwrap(); FIXME: reenable this
@@ -39,7 +49,11 @@ fn http_1_0_connection_close() {
 fn detect_connection_closed() {
     let mut client = support::new_client_to_hello_world_server();
 
-    (write!(client, "GET / HTTP/1.1\r\nConnection: keep-alive\r\n\r\n")).unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n"
+    ))
+    .unwrap();
     thread::sleep(Duration::from_millis(1000));
 
     client.shutdown(Shutdown::Write).unwrap();
@@ -50,6 +64,28 @@ fn detect_connection_closed() {
     client.read_to_end(&mut out).unwrap();
 }
 
+#[test]
+fn idle_keep_alive_connection() {
+    let mut client = support::new_client_to_hello_world_server();
+
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n"
+    ))
+    .unwrap();
+    // longer than the accept timeout of the listener
+    thread::sleep(Duration::from_millis(500));
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let mut out = String::new();
+    client.read_to_string(&mut out).unwrap();
+    assert_eq!(out.matches("HTTP/1.1 200 OK").count(), 2, "{}", out);
+}
+
 #[test]
 fn poor_network_test() {
     let mut client = support::new_client_to_hello_world_server();
@@ -220,3 +256,2285 @@ fn chunked_threshold() {
     assert_eq!(resp.chunked_threshold(), 32768);
     assert_eq!(resp.with_chunked_threshold(42).chunked_threshold(), 42);
 }
+
+#[test]
+fn security_headers_on_error_response() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_security_headers(tiny_http::SecurityHeaders::new()),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+    (write!(client, "qsd qsd qsd\r\n\r\n")).unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 400"));
+    assert!(content.contains("\r\nX-Content-Type-Options: nosniff\r\n"));
+}
+
+#[test]
+fn pipelining_across_fairness_budget() {
+    // a single worker thread, which the first connection would hold without the budget
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_worker_threads(1)
+            .with_inline_fairness_budget(2),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut first = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    let mut served = Vec::new();
+    let mut serve = |count| {
+        for _ in 0..count {
+            let rq = server.recv().unwrap();
+            served.push(rq.url().to_string());
+            let response = tiny_http::Response::from_string(rq.url().to_string());
+            rq.respond(response).unwrap();
+        }
+    };
+
+    // the worker thread then waits for the next request of the first connection
+    for i in 0..4 {
+        (write!(
+            first,
+            "GET /first/{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            i
+        ))
+        .unwrap();
+    }
+    serve(4);
+
+    let mut second = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(
+        second,
+        "GET /second HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    // giving the server time to queue the second connection behind the first one
+    thread::sleep(Duration::from_millis(200));
+
+    let mut backlog = String::new();
+    for i in 4..9 {
+        backlog.push_str(&format!(
+            "GET /first/{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            i
+        ));
+    }
+    backlog.push_str("GET /first/9 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    first.write_all(backlog.as_bytes()).unwrap();
+    serve(7);
+
+    // the second connection is served once the first one exhausts its budget, before the
+    // rest of its backlog
+    let position = |url: &str| served.iter().position(|served| served == url).unwrap();
+    assert!(position("/second") < position("/first/9"));
+    assert!(position("/second") > position("/first/3"));
+
+    // the order of the requests of the first connection is kept
+    let mut data = String::new();
+    first.read_to_string(&mut data).unwrap();
+    let bodies: Vec<_> = data
+        .split("HTTP/1.1 200")
+        .skip(1)
+        .map(|response| response.rsplit("\r\n\r\n").next().unwrap())
+        .collect();
+    let expected: Vec<_> = (0..10).map(|i| format!("/first/{}", i)).collect();
+    assert_eq!(bodies, expected);
+
+    let mut data = String::new();
+    second.read_to_string(&mut data).unwrap();
+    assert!(data.ends_with("/second"));
+}
+
+#[test]
+fn static_headers_on_generated_responses() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_static_response_headers(vec!["X-Served-By: edge-fra-3".parse().unwrap()]),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    // error response generated by the server
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(client, "qsd qsd qsd\r\n\r\n")).unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 400"));
+    assert!(content.contains("\r\nX-Served-By: edge-fra-3\r\n"));
+
+    // request dropped without a response
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    drop(server.recv().unwrap());
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 500"));
+    assert!(content.contains("\r\nX-Served-By: edge-fra-3\r\n"));
+}
+
+#[test]
+fn queue_latency_warning() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_queue_latency_warning(Duration::from_millis(50)),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    let clients: Vec<_> = (0..3)
+        .map(|_| {
+            let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+            client
+        })
+        .collect();
+
+    // handlers that can't keep up
+    thread::sleep(Duration::from_millis(300));
+
+    for _ in &clients {
+        let rq = server.recv().unwrap();
+        assert!(rq.queue_latency() >= Duration::from_millis(200));
+        assert!(rq.queue_latency() < Duration::from_secs(5));
+    }
+    assert_eq!(server.stats().queue_latency_warnings, 1);
+}
+
+#[test]
+fn request_timing() {
+    let (server, mut client) = support::new_one_server_one_client();
+    let before = std::time::SystemTime::now();
+    (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+
+    thread::sleep(Duration::from_millis(300));
+    let rq = server.recv().unwrap();
+    assert!(rq.queue_latency() >= Duration::from_millis(250));
+    assert!(rq.queue_latency() < Duration::from_secs(5));
+    assert!(rq.received_at().elapsed() >= rq.queue_latency());
+    assert!(rq.received_time() >= before);
+}
+
+#[test]
+fn connection_and_request_ids() {
+    let (server, mut client) = support::new_one_server_one_client();
+    let port = server.server_addr().to_ip().unwrap().port();
+    (write!(
+        client,
+        "GET /a HTTP/1.1\r\nHost: localhost\r\n\r\nGET /b HTTP/1.1\r\nHost: localhost\r\n\r\n"
+    ))
+    .unwrap();
+
+    let first = server.recv().unwrap();
+    let id = first.connection_id();
+    assert_eq!(first.request_seq(), 1);
+    first.respond(tiny_http::Response::empty(204)).unwrap();
+    let second = server.recv().unwrap();
+    assert_eq!(second.connection_id(), id);
+    assert_eq!(second.request_seq(), 2);
+    assert!(format!("{:?}", second).ends_with(&format!("connection {} #2)", id)));
+    second.respond(tiny_http::Response::empty(204)).unwrap();
+
+    let mut other = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(other, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+    let rq = server.recv().unwrap();
+    assert_ne!(rq.connection_id(), id);
+    assert_eq!(rq.request_seq(), 1);
+}
+
+#[test]
+fn respond_through_parts() {
+    let (server, mut client) = support::new_one_server_one_client();
+    (write!(
+        client,
+        "POST /a HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nX-Id: 1\r\n\r\nhello\
+         GET /b HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let (head, mut body, first) = server.recv().unwrap().into_parts();
+    assert_eq!(head.method, tiny_http::Method::Post);
+    assert_eq!(head.url, "/a");
+    assert!(head.headers.iter().any(|h| h.field.equiv("X-Id")));
+    assert_eq!(head.remote_addr, Some(client.local_addr().unwrap()));
+    let mut content = String::new();
+    body.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "hello");
+    drop((head, body));
+
+    let (head, body, second) = server.recv().unwrap().into_parts();
+    assert_eq!(head.url, "/b");
+    drop((head, body));
+
+    // the second response waits for the first one, which dropping the responder sends
+    let handler = thread::spawn(move || {
+        second
+            .respond(tiny_http::Response::from_string("second"))
+            .unwrap();
+    });
+    thread::sleep(Duration::from_millis(100));
+    drop(first);
+    handler.join().unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(
+        content.starts_with("HTTP/1.1 500 Internal Server Error\r\n"),
+        "{}",
+        content
+    );
+    let second = content.find("HTTP/1.1 200 OK\r\n").unwrap();
+    assert!(content[second..].ends_with("\r\n\r\nsecond"), "{}", content);
+}
+
+#[test]
+fn pipelining_with_independent_buffering() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_read_buffering(tiny_http::BufferingMode::BufferedWithCapacity(64 * 1024))
+            .with_write_buffering(tiny_http::BufferingMode::Unbuffered),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+    let mut burst = String::new();
+    for i in 0..49 {
+        burst.push_str(&format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", i));
+    }
+    burst.push_str("GET /49 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    client.write_all(burst.as_bytes()).unwrap();
+
+    thread::spawn(move || {
+        for rq in server.incoming_requests().take(50) {
+            let response = tiny_http::Response::from_string(rq.url().to_string());
+            rq.respond(response).unwrap();
+        }
+    });
+
+    let mut data = String::new();
+    client.read_to_string(&mut data).unwrap();
+    let bodies: Vec<_> = data
+        .split("HTTP/1.1 200")
+        .skip(1)
+        .map(|response| response.rsplit("\r\n\r\n").next().unwrap())
+        .collect();
+    let expected: Vec<_> = (0..50).map(|i| format!("/{}", i)).collect();
+    assert_eq!(bodies, expected);
+}
+
+#[test]
+fn connection_handoff() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_connection_handoff(true),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+    let body: Vec<u8> = (0..4000).map(|i| (i % 251) as u8).collect();
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4000\r\n\r\n"
+    ))
+    .unwrap();
+    client.write_all(&body).unwrap();
+
+    let mut request = server.recv().unwrap();
+    let mut first_half = vec![0; 2000];
+    request.as_reader().read_exact(&mut first_half).unwrap();
+    assert_eq!(first_half, &body[..2000]);
+
+    let parts = request.into_raw_connection().unwrap();
+    assert_eq!(parts.body_remaining, Some(2000));
+    assert_eq!(parts.url, "/");
+
+    // continue the request as if we were another process
+    let mut stream = parts.stream;
+    let mut second_half = Vec::new();
+    std::io::Cursor::new(parts.leftover)
+        .chain(stream.try_clone().unwrap())
+        .take(2000)
+        .read_to_end(&mut second_half)
+        .unwrap();
+    assert_eq!(second_half, &body[2000..]);
+
+    (write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: close\r\n\r\nhandoff"
+    ))
+    .unwrap();
+    drop(stream);
+    drop(server);
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert_eq!(
+        content,
+        "HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: close\r\n\r\nhandoff"
+    );
+}
+
+#[test]
+fn connection_handoff_chunked() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_connection_handoff(true),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+         5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"
+    ))
+    .unwrap();
+
+    let mut request = server.recv().unwrap();
+    let mut first_chunk = [0; 5];
+    request.as_reader().read_exact(&mut first_chunk).unwrap();
+    assert_eq!(&first_chunk, b"hello");
+
+    // the rest of the body is handed off, starting with the next chunk
+    let parts = request.into_raw_connection().unwrap();
+    assert_eq!(parts.body_remaining, None);
+    let mut rest = vec![0; 16];
+    std::io::Cursor::new(parts.leftover)
+        .chain(parts.stream)
+        .read_exact(&mut rest)
+        .unwrap();
+    assert_eq!(rest, b"6\r\n world\r\n0\r\n\r\n");
+}
+
+#[test]
+fn connection_handoff_not_enabled() {
+    let (server, mut client) = support::new_one_server_one_client();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let request = server.recv().unwrap();
+    let request = match request.into_raw_connection() {
+        Err((request, tiny_http::HandoffError::NotEnabled)) => request,
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    };
+
+    // the request can still be answered
+    request
+        .respond(tiny_http::Response::from_string("not handed off"))
+        .unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 200"));
+    assert!(content.ends_with("not handed off"));
+}
+
+#[test]
+fn load_shedding() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_load_shedding(tiny_http::LoadShedding {
+            queue_depth_threshold: 2,
+            shed_probability_at_threshold: 1.0,
+            max_shed_probability: 1.0,
+            retry_after: Duration::from_secs(3),
+            exempt_path_prefixes: vec!["/health".to_owned()],
+        }),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    let send = |path: &str| {
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (write!(
+            client,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        ))
+        .unwrap();
+        // lets the request reach the queue before the next one is sent
+        thread::sleep(Duration::from_millis(100));
+        client
+    };
+
+    let queued: Vec<_> = (0..2).map(|_| send("/")).collect();
+
+    let mut shed = send("/");
+    let mut content = String::new();
+    shed.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 503"), "{}", content);
+    assert!(content.contains("Retry-After: 3\r\n"), "{}", content);
+
+    let _health = send("/health");
+
+    for path in &["/", "/", "/health"] {
+        let rq = server.recv().unwrap();
+        assert_eq!(rq.url(), *path);
+    }
+    assert_eq!(server.stats().shed_requests, 1);
+    drop(queued);
+}
+
+#[test]
+fn path_prefix_allowlist() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_path_prefix_allowlist(vec!["/api".to_owned()], tiny_http::StatusCode(404)),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let send = |data: &str| {
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.write_all(data.as_bytes()).unwrap();
+        client
+    };
+    let read = |mut client: TcpStream| {
+        let mut content = String::new();
+        client.read_to_string(&mut content).unwrap();
+        content
+    };
+
+    // the body of the rejected request isn't read, so the connection is closed
+    let rejected = send(
+        "POST /apix HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello\
+         GET /api/users HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    let content = read(rejected);
+    assert_eq!(
+        content,
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    );
+
+    // the rejection has the version of the request
+    let rejected = send("GET /static HTTP/1.0\r\n\r\n");
+    let content = read(rejected);
+    assert!(
+        content.starts_with("HTTP/1.0 404 Not Found\r\n"),
+        "{}",
+        content
+    );
+
+    let allowed = send("GET /api/users HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let rq = server.recv().unwrap();
+    assert_eq!(rq.url(), "/api/users");
+    rq.respond(tiny_http::Response::from_string("users"))
+        .unwrap();
+    let content = read(allowed);
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(content.ends_with("\r\n\r\nusers"));
+    assert_eq!(server.stats().rejected_paths, 2);
+}
+
+#[test]
+fn path_prefix_allowlist_before_load_shedding() {
+    // every request that reaches load shedding is shed
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_path_prefix_allowlist(vec!["/api".to_owned()], tiny_http::StatusCode(404))
+            .with_load_shedding(tiny_http::LoadShedding {
+                queue_depth_threshold: 0,
+                shed_probability_at_threshold: 1.0,
+                max_shed_probability: 1.0,
+                retry_after: Duration::from_secs(3),
+                exempt_path_prefixes: Vec::new(),
+            }),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let send = |path: &str| {
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        (write!(
+            client,
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        ))
+        .unwrap();
+        let mut content = String::new();
+        client.read_to_string(&mut content).unwrap();
+        content
+    };
+
+    let content = send("/admin");
+    assert!(
+        content.starts_with("HTTP/1.1 404 Not Found\r\n"),
+        "{}",
+        content
+    );
+    let content = send("/api/users");
+    assert!(
+        content.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "{}",
+        content
+    );
+    assert!(content.contains("\r\nConnection: close\r\n"), "{}", content);
+
+    let stats = server.stats();
+    assert_eq!(stats.rejected_paths, 1);
+    assert_eq!(stats.shed_requests, 1);
+}
+
+#[test]
+fn pipelined_request_after_close() {
+    let server = tiny_http::Server::http("0.0.0.0:0").unwrap();
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    // sent with a single write, so that both requests are read at once
+    let surplus = "GET /ignored HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    let data = format!(
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n{}",
+        surplus
+    );
+    client.write_all(data.as_bytes()).unwrap();
+
+    let rq = server.recv().unwrap();
+    assert_eq!(rq.url(), "/");
+    rq.respond(tiny_http::Response::from_string("hello"))
+        .unwrap();
+
+    // the connection is closed cleanly after the first response
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert_eq!(content.matches("HTTP/1.1").count(), 1, "{}", content);
+    assert!(content.ends_with("hello"));
+
+    assert!(server
+        .recv_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+    assert_eq!(server.stats().surplus_bytes_after_close, surplus.len());
+}
+
+fn server_with_body_read_timeout(timeout: Duration) -> (tiny_http::Server, TcpStream) {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_body_read_timeout(timeout),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (server, client)
+}
+
+#[test]
+fn body_read_timeout() {
+    let (server, mut client) = server_with_body_read_timeout(Duration::from_millis(200));
+
+    // half of the declared body, then nothing
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4000\r\n\r\n"
+    ))
+    .unwrap();
+    client.write_all(&[b'a'; 2000]).unwrap();
+
+    let mut rq = server.recv().unwrap();
+    let mut body = Vec::new();
+    let start = std::time::Instant::now();
+    let err = rq.as_reader().read_to_end(&mut body).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(4));
+    assert_eq!(body.len(), 2000);
+    rq.respond(tiny_http::Response::empty(408)).unwrap();
+
+    // the connection is closed after the response
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 408 "), "{}", content);
+    assert_eq!(content.matches("HTTP/1.1").count(), 1, "{}", content);
+}
+
+#[test]
+fn body_read_timeout_cleared_between_requests() {
+    let (server, mut client) = server_with_body_read_timeout(Duration::from_millis(100));
+
+    let body = vec![b'a'; 2000];
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2000\r\n\r\n"
+    ))
+    .unwrap();
+    client.write_all(&body).unwrap();
+    let mut rq = server.recv().unwrap();
+    let mut received = Vec::new();
+    rq.as_reader().read_to_end(&mut received).unwrap();
+    assert_eq!(received, body);
+    rq.respond(tiny_http::Response::empty(204)).unwrap();
+
+    // waiting for the next request of the connection isn't limited
+    thread::sleep(Duration::from_millis(300));
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    let rq = server.recv().unwrap();
+    rq.respond(tiny_http::Response::from_string("second"))
+        .unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 204 "), "{}", content);
+    assert!(content.ends_with("second"), "{}", content);
+}
+
+#[test]
+fn keep_alive_timeout() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_keep_alive_timeout(Duration::from_millis(200)),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\n"
+    ))
+    .unwrap();
+
+    // a body slower than the timeout is still read
+    let mut body_writer = client.try_clone().unwrap();
+    let sender = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(400));
+        body_writer.write_all(b"hello").unwrap();
+    });
+    let mut rq = server.recv().unwrap();
+    let mut body = String::new();
+    rq.as_reader().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "hello");
+    sender.join().unwrap();
+    rq.respond(tiny_http::Response::from_string("received"))
+        .unwrap();
+
+    // the server closes the idle connection without a response
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert_eq!(content.matches("HTTP/1.1").count(), 1, "{}", content);
+    assert!(content.ends_with("received"));
+    assert_eq!(server.stats().keep_alive_timeouts, 1);
+}
+
+#[test]
+fn connection_setup() {
+    let ttls = Arc::new(Mutex::new(Vec::new()));
+    let setup = {
+        let ttls = ttls.clone();
+        move |stream: &TcpStream, addr: SocketAddr| {
+            assert!(addr.ip().is_loopback());
+            stream.set_ttl(42)?;
+            ttls.lock().unwrap().push(stream.ttl()?);
+            Ok(())
+        }
+    };
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_connection_setup(Arc::new(setup)),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    server
+        .recv()
+        .unwrap()
+        .respond(tiny_http::Response::from_string("hello"))
+        .unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.ends_with("hello"));
+    assert_eq!(*ttls.lock().unwrap(), [42]);
+    assert_eq!(server.stats().rejected_connections, 0);
+}
+
+#[test]
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn tcp_options() {
+    let options = Arc::new(Mutex::new(Vec::new()));
+    let setup = {
+        let options = options.clone();
+        move |stream: &TcpStream, _: SocketAddr| {
+            let keepalive = socket2::SockRef::from(stream).keepalive()?;
+            options.lock().unwrap().push((stream.nodelay()?, keepalive));
+            Ok(())
+        }
+    };
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_tcp_nodelay(true)
+            .with_tcp_keepalive(Duration::from_secs(75))
+            .with_connection_setup(Arc::new(setup)),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    server
+        .recv()
+        .unwrap()
+        .respond(tiny_http::Response::from_string("hello"))
+        .unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.ends_with("hello"));
+    assert_eq!(*options.lock().unwrap(), [(true, true)]);
+}
+
+/// Sends a request to `addr`, answered by `server`, and returns the response.
+fn hello_through(server: &tiny_http::Server, addr: SocketAddr) -> String {
+    let mut client = TcpStream::connect(addr).unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    server
+        .recv()
+        .unwrap()
+        .respond(tiny_http::Response::from_string("hello"))
+        .unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    content
+}
+
+/// Returns false if the IPv6 loopback address can't be used, for example in some containers.
+fn ipv6_available() -> bool {
+    std::net::TcpListener::bind("[::1]:0").is_ok()
+}
+
+#[test]
+fn multiple_listeners() {
+    if !ipv6_available() {
+        eprintln!("skipped: IPv6 is unavailable");
+        return;
+    }
+    // the two listeners share the port picked by the system, so they need distinct addresses
+    let addrs: Vec<SocketAddr> = vec!["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()];
+    let server = tiny_http::Server::new(tiny_http::ServerConfig {
+        addr: tiny_http::ConfigListenAddr::IP(addrs),
+        ssl: None,
+    })
+    .unwrap();
+    let addrs: Vec<SocketAddr> = server
+        .server_addrs()
+        .into_iter()
+        .map(|addr| addr.to_ip().unwrap())
+        .collect();
+    assert_eq!(addrs.len(), 2);
+    assert_ne!(addrs[0], addrs[1]);
+    assert_eq!(server.server_addr().to_ip(), Some(addrs[0]));
+
+    for &addr in &addrs {
+        assert!(hello_through(&server, addr).ends_with("hello"));
+    }
+
+    // every listener is closed once the server is dropped
+    drop(server);
+    for &addr in &addrs {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while TcpStream::connect(addr).is_ok() {
+            assert!(std::time::Instant::now() < deadline, "{} still open", addr);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+#[test]
+fn ephemeral_port_is_shared() {
+    if !ipv6_available() {
+        eprintln!("skipped: IPv6 is unavailable");
+        return;
+    }
+    // what `localhost:0` usually resolves to
+    let addrs: Vec<SocketAddr> = vec!["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()];
+    let server = tiny_http::Server::new(tiny_http::ServerConfig {
+        addr: tiny_http::ConfigListenAddr::IP(addrs),
+        ssl: None,
+    })
+    .unwrap();
+    let addrs: Vec<SocketAddr> = server
+        .server_addrs()
+        .into_iter()
+        .map(|addr| addr.to_ip().unwrap())
+        .collect();
+    let port = server.server_addr().to_ip().unwrap().port();
+    assert_eq!(addrs.len(), 2);
+    assert!(addrs.iter().all(|addr| addr.port() == port), "{:?}", addrs);
+
+    for &addr in &addrs {
+        assert!(hello_through(&server, addr).ends_with("hello"));
+    }
+}
+
+#[test]
+fn unbindable_addresses_are_skipped() {
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addrs = vec![taken.local_addr().unwrap(), "127.0.0.1:0".parse().unwrap()];
+    let server = tiny_http::Server::new(tiny_http::ServerConfig {
+        addr: tiny_http::ConfigListenAddr::IP(addrs),
+        ssl: None,
+    })
+    .unwrap();
+    let listening = server.server_addrs();
+    assert_eq!(listening.len(), 1);
+    let addr = listening[0].clone().to_ip().unwrap();
+    assert_ne!(addr, taken.local_addr().unwrap());
+    assert!(hello_through(&server, addr).ends_with("hello"));
+
+    // failing to bind every address is an error
+    let addrs = vec![taken.local_addr().unwrap()];
+    assert!(tiny_http::Server::new(tiny_http::ServerConfig {
+        addr: tiny_http::ConfigListenAddr::IP(addrs),
+        ssl: None,
+    })
+    .is_err());
+}
+
+/// Server answering the requests of `OldDevice` clients as if they used HTTP 1.0.
+fn legacy_client_server(http10_status: bool) -> tiny_http::Server {
+    let matcher = |rq: &tiny_http::Request| {
+        rq.headers()
+            .iter()
+            .any(|h| h.field.equiv("User-Agent") && h.value.as_str().starts_with("OldDevice/"))
+    };
+    support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_legacy_client_mode(Arc::new(matcher))
+            .with_legacy_client_http10_status(http10_status),
+    )
+}
+
+/// Sends `head`, answers it with a body of unknown length and returns everything the client
+/// received until the connection was closed.
+fn legacy_client_response(server: &tiny_http::Server, head: &str) -> String {
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    client.write_all(head.as_bytes()).unwrap();
+
+    let response = tiny_http::Response::new(
+        tiny_http::StatusCode(200),
+        Vec::new(),
+        io::Cursor::new(b"hello".to_vec()),
+        None,
+        None,
+    );
+    server.recv().unwrap().respond(response).unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    content
+}
+
+#[test]
+fn legacy_client_mode() {
+    let server = legacy_client_server(false);
+
+    // the connection is closed although the client asked for chunks and keep-alive
+    let content = legacy_client_response(
+        &server,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: OldDevice/2.1\r\nTE: chunked\r\n\r\n",
+    );
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(content.contains("Connection: close\r\n"), "{}", content);
+    assert!(content.contains("Content-Length: 5\r\n"), "{}", content);
+    assert!(!content.contains("Transfer-Encoding"), "{}", content);
+    assert!(content.ends_with("\r\n\r\nhello"), "{}", content);
+
+    // the other clients are unaffected
+    let content = legacy_client_response(
+        &server,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nTE: chunked\r\nConnection: close\r\n\r\n",
+    );
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(
+        content.contains("Transfer-Encoding: chunked\r\n"),
+        "{}",
+        content
+    );
+    assert!(!content.contains("Connection: close"), "{}", content);
+
+    // the status line can be downgraded as well
+    let server = legacy_client_server(true);
+    let content = legacy_client_response(
+        &server,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nUser-Agent: OldDevice/2.1\r\n\r\n",
+    );
+    assert!(content.starts_with("HTTP/1.0 200 OK\r\n"), "{}", content);
+    assert!(content.contains("Connection: close\r\n"), "{}", content);
+}
+
+#[test]
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reuse_port() {
+    let reusable_server = |addr: SocketAddr| {
+        tiny_http::ServerBuilder::new()
+            .with_addr(tiny_http::ConfigListenAddr::IP(vec![addr]))
+            .with_advanced(tiny_http::ServerConfigAdvanced::default().with_reuse_port(true))
+            .build()
+            .unwrap()
+    };
+    let first = reusable_server("127.0.0.1:0".parse().unwrap());
+    let addr = first.server_addr().to_ip().unwrap();
+    let second = reusable_server(addr);
+    assert_eq!(second.server_addr().to_ip(), Some(addr));
+
+    // each server answers with its name until the other one was reached as well
+    let servers = vec![(Arc::new(first), "first"), (Arc::new(second), "second")];
+    let answered = Arc::new(Mutex::new(Vec::new()));
+    let handlers: Vec<_> = servers
+        .iter()
+        .map(|(server, name)| {
+            let (server, name, answered) = (server.clone(), *name, answered.clone());
+            thread::spawn(move || {
+                while let Ok(rq) = server.recv() {
+                    let mut answered = answered.lock().unwrap();
+                    if !answered.contains(&name) {
+                        answered.push(name);
+                    }
+                    drop(answered);
+                    let _ = rq.respond(tiny_http::Response::from_string(name));
+                }
+            })
+        })
+        .collect();
+
+    // the system distributes the connections depending on the port of the client
+    for _ in 0..200 {
+        let mut client = TcpStream::connect(addr).unwrap();
+        (write!(
+            client,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        ))
+        .unwrap();
+        let mut content = String::new();
+        client.read_to_string(&mut content).unwrap();
+        assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+        if answered.lock().unwrap().len() == 2 {
+            break;
+        }
+    }
+    assert_eq!(answered.lock().unwrap().len(), 2);
+
+    for (server, _) in &servers {
+        server.shutdown_gracefully();
+    }
+    for handler in handlers {
+        handler.join().unwrap();
+    }
+}
+
+#[test]
+fn in_flight_requests() {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    // three requests that are received but not answered yet
+    let mut clients = Vec::new();
+    let mut requests = Vec::new();
+    for i in 1..=3 {
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        (write!(
+            client,
+            "GET /slow/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            i
+        ))
+        .unwrap();
+        requests.push(server.recv().unwrap());
+        clients.push(client);
+    }
+    thread::sleep(Duration::from_millis(20));
+
+    let in_flight = server.in_flight();
+    let paths: Vec<&str> = in_flight.iter().map(|rq| rq.path.as_str()).collect();
+    assert_eq!(paths, ["/slow/1", "/slow/2", "/slow/3"]);
+    for (rq, client) in in_flight.iter().zip(&clients) {
+        assert_eq!(rq.method, tiny_http::Method::Get);
+        assert_eq!(rq.sequence, 1);
+        assert_eq!(rq.remote_addr, Some(client.local_addr().unwrap()));
+        assert!(rq.age() >= Duration::from_millis(20), "{:?}", rq.age());
+        assert!(rq.age() < Duration::from_secs(5), "{:?}", rq.age());
+    }
+    assert!(in_flight[0].connection < in_flight[1].connection);
+
+    // an answered request disappears
+    requests
+        .remove(1)
+        .respond(tiny_http::Response::empty(204))
+        .unwrap();
+    let paths: Vec<String> = server.in_flight().into_iter().map(|rq| rq.path).collect();
+    assert_eq!(paths, ["/slow/1", "/slow/3"]);
+
+    let response = server.in_flight_response();
+    let mut table = String::new();
+    response.into_reader().read_to_string(&mut table).unwrap();
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 3, "{}", table);
+    assert!(lines[0].contains("PATH"), "{}", table);
+    assert!(lines[1].ends_with("  /slow/1"), "{}", table);
+    assert!(lines[2].contains("GET"), "{}", table);
+
+    drop(requests);
+    assert!(server.in_flight().is_empty());
+}
+
+/// Opens a keep-alive connection to `port` and sends one request.
+fn keep_alive_request(port: u16, path: &str) -> TcpStream {
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(client, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)).unwrap();
+    client
+}
+
+#[test]
+fn fixed_worker_threads() {
+    let server = |threads| {
+        support::server_with_config(
+            tiny_http::ServerConfigAdvanced::default().with_worker_threads(threads),
+        )
+    };
+
+    // the only thread serves the first connection until it is closed
+    let single = server(1);
+    let port = single.server_addr().to_ip().unwrap().port();
+    let first = keep_alive_request(port, "/first");
+    let rq = single.recv().unwrap();
+    assert_eq!(rq.url(), "/first");
+    rq.respond(tiny_http::Response::empty(204)).unwrap();
+    let _second = keep_alive_request(port, "/second");
+    assert!(single
+        .recv_timeout(Duration::from_millis(300))
+        .unwrap()
+        .is_none());
+    drop(first);
+    let rq = single
+        .recv_timeout(Duration::from_secs(5))
+        .unwrap()
+        .unwrap();
+    assert_eq!(rq.url(), "/second");
+
+    // with two threads, both connections are served at the same time
+    let pair = server(2);
+    let port = pair.server_addr().to_ip().unwrap().port();
+    let _first = keep_alive_request(port, "/first");
+    let _second = keep_alive_request(port, "/second");
+    let mut urls: Vec<String> = (0..2)
+        .map(|_| {
+            let rq = pair.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+            rq.url().to_owned()
+        })
+        .collect();
+    urls.sort();
+    assert_eq!(urls, ["/first", "/second"]);
+}
+
+#[test]
+fn task_queue_limit() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_worker_threads(1)
+            .with_task_queue_limit(2, tiny_http::TaskQueueLimitMode::Drop),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    // the only thread is busy with this connection, the next ones wait or are dropped
+    let first = keep_alive_request(port, "/first");
+    let rq = server.recv().unwrap();
+    rq.respond(tiny_http::Response::empty(204)).unwrap();
+    let mut clients: Vec<TcpStream> = (0..10)
+        .map(|i| {
+            let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            // the dropped connections may already be closed
+            let _ = write!(client, "GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", i);
+            client
+        })
+        .collect();
+
+    for client in &mut clients[2..] {
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut data = Vec::new();
+        assert_eq!(client.read_to_end(&mut data).unwrap_or(0), 0);
+    }
+    assert_eq!(server.stats().task_queue_rejections, 8);
+
+    // the waiting connections are served once the thread is free
+    drop(first);
+    for i in 0..2 {
+        let rq = server
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert_eq!(rq.url(), format!("/{}", i));
+        drop(rq);
+        clients.remove(0);
+    }
+}
+
+#[test]
+fn connection_filter() {
+    let decisions = Arc::new(Mutex::new(vec![
+        tiny_http::ConnectionDecision::Accept,
+        tiny_http::ConnectionDecision::RejectWith(tiny_http::StatusCode(403)),
+        tiny_http::ConnectionDecision::Drop,
+    ]));
+    let filter = {
+        let decisions = decisions.clone();
+        move |addr: &SocketAddr| {
+            assert!(addr.ip().is_loopback());
+            decisions.lock().unwrap().pop().unwrap()
+        }
+    };
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_connection_filter(Arc::new(filter)),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let connect = || {
+        let client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+    };
+
+    // dropped without a response, nothing is sent so that closing it doesn't reset it
+    let mut content = Vec::new();
+    assert_eq!(connect().read_to_end(&mut content).unwrap(), 0);
+
+    // rejected with the status code, even though the request is never read
+    let mut client = connect();
+    write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 8192\r\n\r\n{}",
+        "a".repeat(8192)
+    )
+    .unwrap();
+    let mut content = Vec::new();
+    client.read_to_end(&mut content).unwrap();
+    assert_eq!(
+        String::from_utf8(content).unwrap(),
+        "HTTP/1.1 403 Forbidden\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+    );
+    assert!(server
+        .recv_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+    assert_eq!(server.stats().filtered_connections, 2);
+
+    // accepted
+    let mut client = connect();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    server
+        .recv()
+        .unwrap()
+        .respond(tiny_http::Response::from_string("hello"))
+        .unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.ends_with("hello"));
+    assert_eq!(server.stats().filtered_connections, 2);
+}
+
+#[test]
+fn max_requests_per_connection() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_max_requests_per_connection(2),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "GET /1 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /2 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         POST /3 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 32768\r\n\r\n{}",
+        "a".repeat(32768)
+    ))
+    .unwrap();
+
+    for url in ["/1", "/2"].iter() {
+        let rq = server.recv().unwrap();
+        assert_eq!(rq.url(), *url);
+        rq.respond(tiny_http::Response::from_string(*url)).unwrap();
+    }
+    assert!(server
+        .recv_timeout(Duration::from_millis(200))
+        .unwrap()
+        .is_none());
+
+    // the connection is closed after the second response, the third request is never answered
+    // but doesn't make the server reset the connection
+    let mut content = Vec::new();
+    client.read_to_end(&mut content).unwrap();
+    let content = String::from_utf8(content).unwrap();
+    let responses: Vec<&str> = content.split("HTTP/1.1 ").skip(1).collect();
+    assert_eq!(responses.len(), 2, "{}", content);
+    assert!(!responses[0].contains("Connection: close"), "{}", content);
+    assert!(responses[1].contains("Connection: close"), "{}", content);
+    assert!(responses[1].ends_with("/2"), "{}", content);
+}
+
+#[test]
+fn activity_counters() {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let port = server.server_addr().to_ip().unwrap().port();
+    assert_eq!(server.pending_requests(), 0);
+
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "GET /1 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /2 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    // both requests are queued before the first one is received
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while server.pending_requests() < 2 && std::time::Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(server.pending_requests(), 2);
+    let stats = server.stats();
+    assert_eq!(stats.accepted_connections, 1);
+    assert_eq!(stats.parsed_requests, 2);
+    assert_eq!(stats.written_responses, 0);
+
+    for _ in 0..2 {
+        let rq = server.recv().unwrap();
+        rq.respond(tiny_http::Response::empty(204)).unwrap();
+    }
+    assert_eq!(server.pending_requests(), 0);
+    assert_eq!(server.stats().written_responses, 2);
+
+    let mut content = Vec::new();
+    client.read_to_end(&mut content).unwrap();
+    assert_eq!(server.stats().accepted_connections, 1);
+}
+
+#[test]
+fn access_log() {
+    let entries = Arc::new(Mutex::new(Vec::new()));
+    let server = {
+        let entries = entries.clone();
+        support::server_with_config(tiny_http::ServerConfigAdvanced::default().with_access_log(
+            Arc::new(move |entry: &tiny_http::AccessLogEntry| {
+                entries.lock().unwrap().push(entry.clone())
+            }),
+        ))
+    };
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "GET /hello?name=world HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /dropped HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         HEAD /head HTTP/1.0\r\nHost: localhost\r\n\r\n"
+    ))
+    .unwrap();
+
+    let rq = server.recv().unwrap();
+    thread::sleep(Duration::from_millis(20));
+    rq.respond(tiny_http::Response::from_string("hello"))
+        .unwrap();
+    drop(server.recv().unwrap());
+    let rq = server.recv().unwrap();
+    rq.respond(tiny_http::Response::from_string("hello"))
+        .unwrap();
+
+    let mut content = Vec::new();
+    client.read_to_end(&mut content).unwrap();
+
+    let entries = entries.lock().unwrap();
+    let summary: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            (
+                entry.method.clone(),
+                entry.path.as_str(),
+                entry.http_version.clone(),
+                entry.status_code,
+                entry.body_bytes,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (
+                tiny_http::Method::Get,
+                "/hello?name=world",
+                tiny_http::HTTPVersion(1, 1),
+                tiny_http::StatusCode(200),
+                5
+            ),
+            (
+                tiny_http::Method::Get,
+                "/dropped",
+                tiny_http::HTTPVersion(1, 1),
+                tiny_http::StatusCode(500),
+                0
+            ),
+            (
+                tiny_http::Method::Head,
+                "/head",
+                tiny_http::HTTPVersion(1, 0),
+                tiny_http::StatusCode(200),
+                0
+            ),
+        ]
+    );
+    assert_eq!(entries[0].remote_addr, Some(client.local_addr().unwrap()));
+    assert!(entries[0].elapsed >= Duration::from_millis(20));
+}
+
+#[test]
+fn access_log_interim_responses() {
+    let entries = Arc::new(Mutex::new(Vec::new()));
+    let server = {
+        let entries = entries.clone();
+        support::server_with_config(tiny_http::ServerConfigAdvanced::default().with_access_log(
+            Arc::new(move |entry: &tiny_http::AccessLogEntry| {
+                entries.lock().unwrap().push(entry.clone())
+            }),
+        ))
+    };
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\n\
+         Content-Length: 5\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let mut rq = server.recv().unwrap();
+    let client_thread = thread::spawn(move || {
+        let mut status_line = [0; 12];
+        client.read_exact(&mut status_line).unwrap();
+        assert_eq!(&status_line, b"HTTP/1.1 100");
+        client.write_all(b"hello").unwrap();
+        let mut content = String::new();
+        client.read_to_string(&mut content).unwrap();
+        content
+    });
+    let mut body = String::new();
+    rq.as_reader().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "hello");
+    rq.respond(tiny_http::Response::empty(201)).unwrap();
+    assert!(client_thread
+        .join()
+        .unwrap()
+        .contains("HTTP/1.1 201 Created\r\n"));
+
+    let entries = entries.lock().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].status_code, tiny_http::StatusCode(201));
+    assert_eq!(entries[0].body_read_bytes, 5);
+    let interim: Vec<_> = entries[0].interim_responses.iter().map(|r| r.0).collect();
+    assert_eq!(interim, [tiny_http::StatusCode(100)]);
+}
+
+#[test]
+fn connection_setup_rejection() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let setup = {
+        let calls = calls.clone();
+        move |_: &TcpStream, addr: SocketAddr| match calls.fetch_add(1, Ordering::SeqCst) {
+            0 if addr.ip().is_loopback() => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "no loopback clients",
+            )),
+            1 => panic!("broken setup"),
+            _ => Ok(()),
+        }
+    };
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_connection_setup(Arc::new(setup)),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    // the connections are closed without being read
+    for _ in 0..2 {
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let _ = write!(
+            client,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        );
+        let mut content = Vec::new();
+        assert_eq!(client.read_to_end(&mut content).unwrap_or(0), 0);
+    }
+    assert!(server
+        .recv_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+    assert_eq!(server.stats().rejected_connections, 2);
+
+    // the server still accepts the next connections
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    server
+        .recv()
+        .unwrap()
+        .respond(tiny_http::Response::from_string("hello"))
+        .unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.ends_with("hello"));
+}
+
+#[test]
+fn oversized_response_headers() {
+    let (server, mut client) = support::new_one_server_one_client();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /next HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    // a buggy handler generating megabytes of headers
+    let link = "</style.css>; rel=preload, ".repeat(80_000);
+    let response = tiny_http::Response::from_string("hello")
+        .with_header(tiny_http::Header::from_bytes(&b"Link"[..], link.as_bytes()).unwrap());
+    let err = server.recv().unwrap().respond(response).unwrap_err();
+    assert_eq!(
+        tiny_http::RespondError::from_io_error(&err),
+        Some(&tiny_http::RespondError::HeaderValueTooLong {
+            name: "Link".to_owned(),
+            len: link.len()
+        })
+    );
+
+    // many headers that are small enough on their own
+    let mut response = tiny_http::Response::from_string("hello");
+    for _ in 0..3000 {
+        response.add_header(
+            tiny_http::Header::from_bytes(&b"Link"[..], &b"</style.css>; rel=preload"[..]).unwrap(),
+        );
+    }
+    let err = server.recv().unwrap().respond(response).unwrap_err();
+    assert!(matches!(
+        tiny_http::RespondError::from_io_error(&err),
+        Some(&tiny_http::RespondError::HeadersTooLarge { size }) if size > 64 * 1024
+    ));
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert_eq!(
+        content,
+        "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n\
+         HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
+    );
+}
+
+#[test]
+fn header_read_deadline() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default()
+            .with_max_header_read_time(Duration::from_millis(500)),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    // a client sending its headers one byte at a time
+    let mut dribbler = client.try_clone().unwrap();
+    thread::spawn(move || {
+        for byte in b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n" {
+            thread::sleep(Duration::from_millis(200));
+            if dribbler.write_all(&[*byte]).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 408 "), "{}", content);
+    assert!(server
+        .recv_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+
+    // the body isn't limited
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 5\r\n\r\n"
+    ))
+    .unwrap();
+    let mut body_writer = client.try_clone().unwrap();
+    let sender = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(700));
+        body_writer.write_all(b"hello").unwrap();
+    });
+    let mut rq = server.recv().unwrap();
+    let mut body = String::new();
+    rq.as_reader().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "hello");
+    sender.join().unwrap();
+    rq.respond(tiny_http::Response::from_string("received"))
+        .unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.ends_with("received"), "{}", content);
+}
+
+/// Sends a request with `headers` to a new server configured with `advanced` and returns the
+/// response, the request being answered with an empty 200 if the server accepts it.
+fn response_to_headers(advanced: tiny_http::ServerConfigAdvanced, headers: &str) -> String {
+    let server = support::server_with_config(advanced);
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\r\n",
+        headers
+    ))
+    .unwrap();
+
+    if let Some(rq) = server.recv_timeout(Duration::from_millis(500)).unwrap() {
+        rq.respond(tiny_http::Response::empty(200)).unwrap();
+    }
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    content
+}
+
+#[test]
+fn header_line_length_limit() {
+    let header = |len| format!("X-Token: {}\r\n", "a".repeat(len - "X-Token: ".len()));
+
+    let advanced = tiny_http::ServerConfigAdvanced::default;
+    let content = response_to_headers(advanced(), &header(4096));
+    assert!(content.starts_with("HTTP/1.1 200 "), "{}", content);
+
+    let lowered = advanced().with_max_header_line_length(2048);
+    let content = response_to_headers(lowered.clone(), &header(2048));
+    assert!(content.starts_with("HTTP/1.1 200 "), "{}", content);
+    let content = response_to_headers(lowered, &header(2049));
+    assert!(content.starts_with("HTTP/1.1 431 "), "{}", content);
+
+    let raised = advanced().with_max_header_line_length(8192);
+    let content = response_to_headers(raised.clone(), &header(4096));
+    assert!(content.starts_with("HTTP/1.1 200 "), "{}", content);
+    let content = response_to_headers(raised, &header(8193));
+    assert!(content.starts_with("HTTP/1.1 431 "), "{}", content);
+
+    // 0 removes the limit
+    let unlimited = advanced().with_max_header_line_length(0);
+    let content = response_to_headers(unlimited, &header(64 * 1024));
+    assert!(content.starts_with("HTTP/1.1 200 "), "{}", content);
+}
+
+#[test]
+fn request_line_length_limit() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_max_header_line_length(100),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let request_line = |path_len: usize| format!("GET /{} HTTP/1.1\r\n", "a".repeat(path_len - 1));
+    assert_eq!(request_line(87).len(), 100 + 2);
+
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(
+        client,
+        "{}Host: localhost\r\nConnection: close\r\n\r\n",
+        request_line(87)
+    ))
+    .unwrap();
+    server
+        .recv()
+        .unwrap()
+        .respond(tiny_http::Response::empty(200))
+        .unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 200 "), "{}", content);
+
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(
+        client,
+        "{}Host: localhost\r\nConnection: close\r\n\r\n",
+        request_line(88)
+    ))
+    .unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 414 "), "{}", content);
+    assert!(server
+        .recv_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn total_headers_length_limit() {
+    // `Host: localhost\r\nConnection: close\r\n` is 36 bytes, and each cookie line 1000
+    let cookies = |count| {
+        (0..count)
+            .map(|i| format!("Cookie: c{:02}={}\r\n", i, "a".repeat(1000 - 14)))
+            .collect::<String>()
+    };
+    assert_eq!(cookies(1).len(), 1000);
+
+    let advanced = tiny_http::ServerConfigAdvanced::default;
+    let lowered = advanced().with_max_total_headers_length(36 + 4000);
+    let content = response_to_headers(lowered.clone(), &cookies(4));
+    assert!(content.starts_with("HTTP/1.1 200 "), "{}", content);
+    let content = response_to_headers(lowered, &format!("{}X: y\r\n", cookies(4)));
+    assert!(content.starts_with("HTTP/1.1 431 "), "{}", content);
+
+    let raised = advanced().with_max_total_headers_length(16 * 1024);
+    let content = response_to_headers(raised.clone(), &cookies(16));
+    assert!(content.starts_with("HTTP/1.1 200 "), "{}", content);
+    let content = response_to_headers(raised, &cookies(17));
+    assert!(content.starts_with("HTTP/1.1 431 "), "{}", content);
+
+    // both limits apply together
+    let both = advanced()
+        .with_max_header_line_length(500)
+        .with_max_total_headers_length(16 * 1024);
+    let content = response_to_headers(both, &cookies(1));
+    assert!(content.starts_with("HTTP/1.1 431 "), "{}", content);
+}
+
+/// Answers a `GET` request made with the given HTTP version with `response`, and returns
+/// the head and the body received by the client.
+fn response_with_version(
+    version: &str,
+    response: tiny_http::Response<io::Cursor<Vec<u8>>>,
+) -> (String, Vec<u8>) {
+    let (server, mut client) = support::new_one_server_one_client();
+    (write!(
+        client,
+        "GET / HTTP/{}\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        version
+    ))
+    .unwrap();
+
+    let handler = thread::spawn(move || {
+        let rq = server.recv().unwrap();
+        rq.respond(response).unwrap();
+    });
+    let mut content = Vec::new();
+    client.read_to_end(&mut content).unwrap();
+    handler.join().unwrap();
+
+    let split = content.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let body = content.split_off(split);
+    (String::from_utf8(content).unwrap(), body)
+}
+
+#[test]
+fn identity_transfer_forced() {
+    let data = vec![b'x'; 1024 * 1024];
+    let response = tiny_http::Response::from_data(data.clone()).with_identity_transfer();
+    let (head, body) = response_with_version("1.1", response);
+
+    assert!(head.contains("Content-Length: 1048576\r\n"), "{}", head);
+    assert!(!head.contains("Transfer-Encoding"), "{}", head);
+    assert_eq!(body, data);
+
+    // the default threshold would send it chunked
+    let response = tiny_http::Response::from_data(data);
+    let (head, _) = response_with_version("1.1", response);
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{}", head);
+}
+
+#[test]
+fn chunked_transfer_forced() {
+    let response = tiny_http::Response::from_string("hello").with_forced_chunked();
+    let (head, body) = response_with_version("1.1", response);
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{}", head);
+    assert!(!head.contains("Content-Length"), "{}", head);
+    assert_eq!(body, b"5\r\nhello\r\n0\r\n\r\n");
+
+    // HTTP 1.0 doesn't support it
+    let response = tiny_http::Response::from_string("hello").with_forced_chunked();
+    let (head, body) = response_with_version("1.0", response);
+    assert!(head.contains("Content-Length: 5\r\n"), "{}", head);
+    assert_eq!(body, b"hello");
+}
+
+/// A reader sending the sum of the bytes it returned as an `X-Checksum` trailer once it
+/// reaches the end of its data.
+struct ChecksumReader<R> {
+    reader: R,
+    sum: u32,
+    trailers: Option<std::sync::mpsc::Sender<tiny_http::Header>>,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.reader.read(buf)?;
+        self.sum = buf[..len]
+            .iter()
+            .fold(self.sum, |sum, &b| sum.wrapping_add(b as u32));
+        if len == 0 {
+            if let Some(sender) = self.trailers.take() {
+                let value = self.sum.to_string();
+                let trailer =
+                    tiny_http::Header::from_bytes(&b"X-Checksum"[..], value.as_bytes()).unwrap();
+                sender.send(trailer).unwrap();
+            }
+        }
+        Ok(len)
+    }
+}
+
+#[test]
+fn checksum_trailer() {
+    let (server, mut client) = support::new_one_server_one_client();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nTE: trailers\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let handler = thread::spawn(move || {
+        let rq = server.recv().unwrap();
+        let data = vec![7u8; 100_000];
+        let mut response =
+            tiny_http::Response::new(200.into(), Vec::new(), io::empty(), None, None)
+                .with_announced_trailer("X-Checksum");
+        let trailers = response.trailer_sender();
+        let reader = ChecksumReader {
+            reader: io::Cursor::new(data),
+            sum: 0,
+            trailers: Some(trailers),
+        };
+        rq.respond(response.with_data(reader, Some(100_000)))
+            .unwrap();
+    });
+
+    let mut content = Vec::new();
+    client.read_to_end(&mut content).unwrap();
+    handler.join().unwrap();
+
+    let split = content.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let head = String::from_utf8_lossy(&content[..split]).into_owned();
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{}", head);
+    assert!(head.contains("Trailer: X-Checksum\r\n"), "{}", head);
+
+    let trailer: &[u8] = b"\r\n0\r\nX-Checksum: 700000\r\n\r\n";
+    assert!(content.ends_with(trailer));
+
+    // the chunks, without the trailers that the decoder doesn't support
+    let mut chunks = content[split..content.len() - trailer.len()].to_vec();
+    chunks.extend_from_slice(b"\r\n0\r\n\r\n");
+    let mut body = Vec::new();
+    chunked_transfer::Decoder::new(&chunks[..])
+        .read_to_end(&mut body)
+        .unwrap();
+    assert_eq!(body, vec![7u8; 100_000]);
+}
+
+#[test]
+fn encoded_bodies_rejected() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_reject_encoded_bodies(true),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    // the rejected body is never read, which doesn't make the server reset the connection
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Encoding: identity\r\nContent-Length: 5\r\n\r\nhello\
+         POST / HTTP/1.1\r\nHost: localhost\r\nContent-Encoding: gzip\r\nContent-Length: 32768\r\n\r\n{}",
+        "a".repeat(32768)
+    ))
+    .unwrap();
+
+    // identity isn't an encoding
+    let mut rq = server.recv().unwrap();
+    let mut body = String::new();
+    rq.as_reader().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "hello");
+    rq.respond(tiny_http::Response::from_string("accepted"))
+        .unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(
+        content.contains("acceptedHTTP/1.1 415 Unsupported Media Type\r\n"),
+        "{}",
+        content
+    );
+    assert!(server
+        .recv_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+}
+
+fn server_with_max_body_size(bytes: u64) -> (tiny_http::Server, TcpStream) {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_max_body_size(bytes),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (server, client)
+}
+
+#[test]
+fn max_body_size_declared_length() {
+    let (server, mut client) = server_with_max_body_size(10);
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n0123456789\
+         POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\r\n0123456789a"
+    ))
+    .unwrap();
+
+    // the body at the limit is accepted
+    let mut rq = server.recv().unwrap();
+    let mut body = String::new();
+    rq.as_reader().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "0123456789");
+    rq.respond(tiny_http::Response::from_string("accepted"))
+        .unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(
+        content.contains("acceptedHTTP/1.1 413 Payload Too Large\r\n"),
+        "{}",
+        content
+    );
+    assert!(server
+        .recv_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn max_body_size_replaces_continue() {
+    let (server, mut client) = server_with_max_body_size(10);
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 1000000\r\n\r\n"
+    ))
+    .unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 413 "), "{}", content);
+    assert!(!content.contains("100 Continue"), "{}", content);
+    assert!(server
+        .recv_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn max_body_size_chunked() {
+    let (server, mut client) = server_with_max_body_size(10);
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+         6\r\n012345\r\n6\r\n6789ab\r\n0\r\n\r\n\
+         GET /next HTTP/1.1\r\nHost: localhost\r\n\r\n"
+    ))
+    .unwrap();
+
+    let mut rq = server.recv().unwrap();
+    let mut body = Vec::new();
+    let err = rq.as_reader().read_to_end(&mut body).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(body, b"0123456789");
+    rq.respond(tiny_http::Response::empty(413)).unwrap();
+
+    // the connection is closed after the response
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 413 "), "{}", content);
+    assert!(!content.contains("200 OK"), "{}", content);
+    assert!(server
+        .recv_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn max_chunk_size() {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_max_chunk_size(8),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+         4;name=value\r\nabcd\r\nffffffffffffffff\r\n\
+         GET /next HTTP/1.1\r\nHost: localhost\r\n\r\n"
+    ))
+    .unwrap();
+
+    let mut rq = server.recv().unwrap();
+    let mut body = Vec::new();
+    let err = rq.as_reader().read_to_end(&mut body).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(body, b"abcd");
+    rq.respond(tiny_http::Response::empty(400)).unwrap();
+
+    // the connection is closed after the response
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 400 "), "{}", content);
+    assert!(server
+        .recv_timeout(Duration::from_millis(100))
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn head_only_response() {
+    let (server, mut client) = support::new_one_server_one_client();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "HEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let representation = tiny_http::Response::from_data(vec![b'a'; 100]);
+    let rq = server.recv().unwrap();
+    assert_eq!(*rq.method(), tiny_http::Method::Head);
+    let head = representation.head_only();
+    assert_eq!(head.data_length(), Some(100));
+    rq.respond(head).unwrap();
+    let rq = server.recv().unwrap();
+    rq.respond(tiny_http::Response::from_string("next"))
+        .unwrap();
+
+    // no body follows the headers of the HEAD response
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    let (head, next) = content.split_at(content.find("\r\n\r\n").unwrap() + 4);
+    assert!(head.contains("\r\nContent-Length: 100\r\n"), "{}", head);
+    assert!(next.starts_with("HTTP/1.1 200 OK\r\n"), "{}", next);
+    assert!(next.ends_with("\r\n\r\nnext"), "{}", next);
+}
+
+fn server_with_connection_limit(
+    limit: usize,
+    mode: tiny_http::ConnectionLimitMode,
+) -> tiny_http::Server {
+    support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_max_connections(limit, mode),
+    )
+}
+
+/// Opens a keep-alive connection and waits for the server to receive its first request.
+fn open_served_connection(server: &tiny_http::Server, port: u16) -> TcpStream {
+    let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+    server
+        .recv()
+        .unwrap()
+        .respond(tiny_http::Response::from_string("ok"))
+        .unwrap();
+    client
+}
+
+#[test]
+fn connection_limit_respond_unavailable() {
+    let server =
+        server_with_connection_limit(2, tiny_http::ConnectionLimitMode::RespondUnavailable);
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    let first = open_served_connection(&server, port);
+    let _second = open_served_connection(&server, port);
+
+    // the request that the server never reads doesn't make it reset the connection
+    let mut over_limit = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    over_limit
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        over_limit,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 8192\r\n\r\n{}",
+        "a".repeat(8192)
+    )
+    .unwrap();
+    let mut content = String::new();
+    over_limit.read_to_string(&mut content).unwrap();
+    assert!(
+        content.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "{}",
+        content
+    );
+    assert_eq!(server.stats().connection_limit_rejections, 1);
+
+    // closing a connection frees its slot
+    drop(first);
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // rejected connections may already be closed
+        let _ = write!(
+            client,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        );
+        if let Some(rq) = server.recv_timeout(Duration::from_millis(50)).unwrap() {
+            rq.respond(tiny_http::Response::from_string("recovered"))
+                .unwrap();
+            let mut content = String::new();
+            client.read_to_string(&mut content).unwrap();
+            assert!(content.ends_with("recovered"), "{}", content);
+            break;
+        }
+        assert!(std::time::Instant::now() < deadline);
+    }
+}
+
+#[test]
+fn connection_limit_stop_accepting() {
+    let server = server_with_connection_limit(1, tiny_http::ConnectionLimitMode::StopAccepting);
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    let first = open_served_connection(&server, port);
+
+    // waits in the backlog
+    let mut waiting = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(
+        waiting,
+        "GET /waiting HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    assert!(server
+        .recv_timeout(Duration::from_millis(300))
+        .unwrap()
+        .is_none());
+
+    drop(first);
+    let rq = server
+        .recv_timeout(Duration::from_secs(5))
+        .unwrap()
+        .unwrap();
+    assert_eq!(rq.url(), "/waiting");
+    assert_eq!(server.stats().connection_limit_rejections, 0);
+}
+
+/// Body returning its parts one at a time, the last one only once `release` receives a message.
+struct StreamingBody {
+    parts: Vec<&'static [u8]>,
+    release: std::sync::mpsc::Receiver<()>,
+}
+
+impl Read for StreamingBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.parts.is_empty() {
+            return Ok(0);
+        }
+        if self.parts.len() == 1 {
+            let _ = self.release.recv();
+        }
+        let part = self.parts.remove(0);
+        buf[..part.len()].copy_from_slice(part);
+        Ok(part.len())
+    }
+}
+
+#[test]
+fn pipelined_request_parsed_while_previous_response_streams() {
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    let (server, mut client) = support::new_one_server_one_client();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    client
+        .write_all(
+            b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET /fast HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+
+    let slow = server.recv().unwrap();
+    assert_eq!(slow.url(), "/slow");
+    let (release, released) = mpsc::channel();
+    let streaming = thread::spawn(move || {
+        let body = StreamingBody {
+            parts: vec![b"first-part,", b"first-end"],
+            release: released,
+        };
+        slow.respond(tiny_http::Response::empty(200).with_data(body, None))
+            .unwrap();
+    });
+
+    // the next request is parsed while the first response is streaming
+    let start = Instant::now();
+    let fast = server
+        .recv_timeout(Duration::from_secs(1))
+        .unwrap()
+        .expect("the second request wasn't parsed");
+    assert_eq!(fast.url(), "/fast");
+    assert!(start.elapsed() < Duration::from_millis(500));
+
+    // but its response waits for the first one
+    let (responded_tx, responded) = mpsc::channel();
+    thread::spawn(move || {
+        fast.respond(tiny_http::Response::from_string("second"))
+            .unwrap();
+        responded_tx.send(()).unwrap();
+    });
+    assert!(responded.recv_timeout(Duration::from_millis(200)).is_err());
+
+    release.send(()).unwrap();
+    streaming.join().unwrap();
+    responded.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    let first_end = content.find("first-end").unwrap();
+    let second = content.rfind("HTTP/1.1 200 OK\r\n").unwrap();
+    assert!(content.find("first-part,").unwrap() < first_end);
+    assert!(first_end < second, "{}", content);
+    assert!(content.ends_with("second"), "{}", content);
+}
+
+fn proxy_protocol_server() -> (tiny_http::Server, TcpStream) {
+    let server = support::server_with_config(
+        tiny_http::ServerConfigAdvanced::default().with_accept_proxy_protocol(true),
+    );
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    let client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (server, client)
+}
+
+fn remote_addr_behind_proxy(preamble: &[u8]) -> SocketAddr {
+    let (server, mut client) = proxy_protocol_server();
+    client.write_all(preamble).unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let rq = server.recv().unwrap();
+    let addr = rq.remote_addr().copied().unwrap();
+    rq.respond(tiny_http::Response::empty(204)).unwrap();
+    addr
+}
+
+#[test]
+fn proxy_protocol_v1() {
+    let addr = remote_addr_behind_proxy(b"PROXY TCP4 192.0.2.1 192.0.2.2 5000 80\r\n");
+    assert_eq!(addr, "192.0.2.1:5000".parse().unwrap());
+
+    let addr = remote_addr_behind_proxy(b"PROXY TCP6 2001:db8::1 2001:db8::2 5000 80\r\n");
+    assert_eq!(addr, "[2001:db8::1]:5000".parse().unwrap());
+
+    // health checks keep the address of the load balancer
+    let addr = remote_addr_behind_proxy(b"PROXY UNKNOWN\r\n");
+    assert!(addr.ip().is_loopback());
+}
+
+#[test]
+fn proxy_protocol_v2() {
+    let mut preamble = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\0\x0c".to_vec();
+    preamble.extend_from_slice(&[192, 0, 2, 1, 192, 0, 2, 2, 0x13, 0x88, 0, 80]);
+    let addr = remote_addr_behind_proxy(&preamble);
+    assert_eq!(addr, "192.0.2.1:5000".parse().unwrap());
+}
+
+#[test]
+fn proxy_protocol_malformed_header() {
+    for preamble in [&b""[..], b"PROXY TCP4 192.0.2.1 5000\r\n"].iter() {
+        let (server, mut client) = proxy_protocol_server();
+        client.write_all(preamble).unwrap();
+        (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+
+        // closed without a response
+        let mut content = Vec::new();
+        let _ = client.read_to_end(&mut content);
+        assert!(content.is_empty());
+        assert!(server
+            .recv_timeout(Duration::from_millis(100))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,113 @@
+// Forks the test process, so this test must have its own file.
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+extern crate nix;
+extern crate tiny_http;
+
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, ForkResult, Pid};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::panic;
+use std::time::Duration;
+
+/// Serves requests in a forked child, answering each with the id of the process, until a
+/// request for `/stop/<id>` arrives. The server is dropped before the process exits.
+fn serve_in_child(prepared: tiny_http::PreparedServer) -> ! {
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let server = prepared.start();
+        let id = std::process::id().to_string();
+        let stop_url = format!("/stop/{}", id);
+        for rq in server.incoming_requests() {
+            let stop = rq.url() == stop_url;
+            rq.respond(tiny_http::Response::from_string(id.clone()))
+                .unwrap();
+            if stop {
+                break;
+            }
+        }
+    }));
+    // skips the destructors and the exit handlers of the parent, that the child inherited
+    unsafe { nix::libc::_exit(if result.is_ok() { 0 } else { 1 }) }
+}
+
+/// Kills the children that weren't stopped when the test fails.
+struct Children(Vec<Pid>);
+
+impl Drop for Children {
+    fn drop(&mut self) {
+        for &pid in &self.0 {
+            let _ = kill(pid, Signal::SIGKILL);
+            let _ = waitpid(pid, None);
+        }
+    }
+}
+
+/// Sends a request over a new connection, and returns the id of the process that answered.
+fn request(addr: SocketAddr, url: &str) -> String {
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        url
+    ))
+    .unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 200"), "{}", content);
+    content.split("\r\n\r\n").nth(1).unwrap().to_owned()
+}
+
+/// Stops the child `pid` and waits for it to exit. The other children may answer the stop
+/// requests in the meantime.
+fn stop(addr: SocketAddr, children: &mut Children, pid: Pid) {
+    let url = format!("/stop/{}", pid);
+    while request(addr, &url) != pid.to_string() {}
+    assert_eq!(waitpid(pid, None).unwrap(), WaitStatus::Exited(pid, 0));
+    children.0.retain(|&child| child != pid);
+}
+
+#[test]
+fn forked_children_share_the_listener() {
+    let prepared = tiny_http::Server::prepare(tiny_http::ServerConfig {
+        addr: tiny_http::ConfigListenAddr::from_socket_addrs("127.0.0.1:0").unwrap(),
+        ssl: None,
+    })
+    .unwrap();
+    let addr = prepared.server_addr().to_ip().unwrap();
+
+    // the accept threads are started after the fork, by each child
+    let mut children = Children(Vec::new());
+    for _ in 0..2 {
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => serve_in_child(prepared),
+            ForkResult::Parent { child } => children.0.push(child),
+        }
+    }
+    drop(prepared);
+    let (first, second) = (children.0[0], children.0[1]);
+
+    // the connections are spread between both children
+    let mut served = (false, false);
+    for _ in 0..200 {
+        let id = request(addr, "/");
+        served.0 |= id == first.to_string();
+        served.1 |= id == second.to_string();
+        if served == (true, true) {
+            break;
+        }
+    }
+    assert_eq!(served, (true, true));
+
+    // once the first child is gone, the second one gets all the connections
+    stop(addr, &mut children, first);
+    for _ in 0..20 {
+        assert_eq!(request(addr, "/"), second.to_string());
+    }
+
+    stop(addr, &mut children, second);
+}
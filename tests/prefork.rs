@@ -0,0 +1,106 @@
+extern crate tiny_http;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// Serves requests with a server that answers with its name, until something is sent to the
+/// returned channel. The server is dropped before the thread exits.
+fn serve(
+    server: tiny_http::Server,
+    name: &'static str,
+) -> (mpsc::Sender<()>, thread::JoinHandle<()>) {
+    let (stop, stopped) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        while stopped.try_recv().is_err() {
+            if let Some(rq) = server.recv_timeout(Duration::from_millis(20)).unwrap() {
+                rq.respond(tiny_http::Response::from_string(name)).unwrap();
+            }
+        }
+    });
+    (stop, handle)
+}
+
+fn request(addr: SocketAddr) -> String {
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    content
+}
+
+#[test]
+fn prepared_server() {
+    let prepared = tiny_http::Server::prepare(tiny_http::ServerConfig {
+        addr: tiny_http::ConfigListenAddr::from_socket_addrs("127.0.0.1:0").unwrap(),
+        ssl: None,
+    })
+    .unwrap();
+    let addr = prepared.server_addr().to_ip().unwrap();
+
+    // the socket is bound, so clients can already connect
+    let mut client = TcpStream::connect(addr).unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let (stop, handle) = serve(prepared.start(), "prepared");
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.ends_with("prepared"));
+
+    stop.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+// Only Linux lets the accept thread notice by itself that its server is closed.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn shared_listener_shutdown() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let first = tiny_http::Server::from_listener(listener.try_clone().unwrap(), None).unwrap();
+    let second = tiny_http::Server::from_listener(listener, None).unwrap();
+    let (stop_first, first_handle) = serve(first, "first");
+    let (stop_second, second_handle) = serve(second, "second");
+
+    // the connections are spread between both servers
+    let mut served = (false, false);
+    for _ in 0..100 {
+        let content = request(addr);
+        if content.ends_with("first") {
+            served.0 = true;
+        } else if content.ends_with("second") {
+            served.1 = true;
+        } else {
+            panic!("unexpected response: {}", content);
+        }
+        if served == (true, true) {
+            break;
+        }
+    }
+    assert_eq!(served, (true, true));
+
+    // once the first server is gone, the second one gets all the connections
+    stop_first.send(()).unwrap();
+    first_handle.join().unwrap();
+    thread::sleep(Duration::from_millis(300));
+    for _ in 0..10 {
+        assert!(request(addr).ends_with("second"));
+    }
+
+    stop_second.send(()).unwrap();
+    second_handle.join().unwrap();
+}
@@ -0,0 +1,63 @@
+#![cfg(feature = "profiling")]
+
+extern crate tiny_http;
+
+use std::io::{Read, Write};
+use std::thread;
+use std::time::Duration;
+
+#[allow(dead_code)]
+mod support;
+
+#[test]
+fn profile_counts_and_reset() {
+    let (server, mut client) = support::new_one_server_one_client();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    for _ in 0..5 {
+        (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+        let rq = server.recv().unwrap();
+        rq.respond(tiny_http::Response::from_string("hello world"))
+            .unwrap();
+    }
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    server
+        .recv()
+        .unwrap()
+        .respond(tiny_http::Response::from_string("hello world"))
+        .unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+
+    let profile = server.profile_snapshot();
+    for phase in &[&profile.parse, &profile.queue, &profile.serialize] {
+        assert_eq!(phase.count, 6);
+        assert!(phase.wall.p50 <= phase.wall.p95);
+        assert!(phase.wall.p95 <= phase.wall.p99);
+        if let Some(ref cpu) = phase.cpu {
+            // the thread can't run for longer than the phase lasts
+            assert!(cpu.p99 <= phase.wall.p99);
+        }
+    }
+    assert!(profile.queue.cpu.is_none());
+    #[cfg(target_os = "linux")]
+    assert!(profile.parse.cpu.is_some());
+    assert!(profile.parse.wall.p50 < Duration::from_secs(1));
+
+    server.reset_profile();
+    assert_eq!(server.profile_snapshot().parse.count, 0);
+
+    // requests created for tests don't count
+    let _ = thread::spawn(|| {
+        let rq: tiny_http::Request = tiny_http::TestRequest::new().into();
+        rq.respond(tiny_http::Response::empty(204))
+    })
+    .join();
+    assert_eq!(server.profile_snapshot().serialize.count, 0);
+}
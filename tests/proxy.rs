@@ -0,0 +1,254 @@
+extern crate tiny_http;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tiny_http::proxy::{proxy_request, proxy_request_pooled, PoolOptions, UpstreamPool};
+
+/// Starts an upstream server that answers each request with the port of the connection
+/// it came from.
+fn port_echoing_upstream() -> SocketAddr {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_ip().unwrap();
+    thread::spawn(move || {
+        for mut rq in server.incoming_requests() {
+            let mut body = String::new();
+            rq.as_reader().read_to_string(&mut body).unwrap();
+            let port = rq.remote_addr().unwrap().port();
+            let response = tiny_http::Response::from_string(format!("{} {}", port, body));
+            rq.respond(response).unwrap();
+        }
+    });
+    addr
+}
+
+/// Starts a proxy that forwards all its requests through `pool`.
+fn proxy_server(pool: Arc<UpstreamPool>) -> SocketAddr {
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_ip().unwrap();
+    thread::spawn(move || {
+        for rq in server.incoming_requests() {
+            proxy_request_pooled(rq, &pool).unwrap();
+        }
+    });
+    addr
+}
+
+/// Sends a request over a new connection and returns the body of the response.
+fn get(addr: SocketAddr, body: &str) -> String {
+    let mut client = TcpStream::connect(addr).unwrap();
+    write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    response.split("\r\n\r\n").nth(1).unwrap().to_owned()
+}
+
+/// Starts an upstream server that answers every request of a connection with `response`.
+fn scripted_upstream(response: &'static [u8]) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                loop {
+                    // the requests of the tests have no body
+                    line.clear();
+                    match reader.read_line(&mut line) {
+                        Ok(0) | Err(_) => return,
+                        Ok(_) if line == "\r\n" => stream.write_all(response).unwrap(),
+                        Ok(_) => (),
+                    }
+                }
+            });
+        }
+    });
+    upstream
+}
+
+#[test]
+fn pooled_requests_reuse_upstream_connection() {
+    let pool = Arc::new(UpstreamPool::new(
+        port_echoing_upstream(),
+        PoolOptions::default(),
+    ));
+    let proxy = proxy_server(pool.clone());
+
+    let first = get(proxy, "first");
+    let second = get(proxy, "second");
+    let first_port = first.split(' ').next().unwrap();
+    let second_port = second.split(' ').next().unwrap();
+
+    assert_eq!(first_port, second_port);
+    assert!(first.ends_with(" first"));
+    assert!(second.ends_with(" second"));
+
+    // the connection goes back to the pool after the response is sent
+    thread::sleep(Duration::from_millis(100));
+    let stats = pool.stats();
+    assert_eq!(stats.opened, 1);
+    assert_eq!(stats.reused, 1);
+    assert_eq!(stats.idle, 1);
+}
+
+#[test]
+fn unpooled_request() {
+    let upstream = port_echoing_upstream();
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_ip().unwrap();
+    thread::spawn(move || {
+        for rq in server.incoming_requests() {
+            proxy_request(rq, upstream).unwrap();
+        }
+    });
+
+    assert!(get(addr, "hello").ends_with(" hello"));
+}
+
+#[test]
+fn upstream_closing_connections() {
+    // answers a single request per connection, then closes it
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+                .unwrap();
+            // the connection is closed when the stream is dropped, without telling the proxy
+        }
+    });
+
+    let pool = Arc::new(UpstreamPool::new(upstream, PoolOptions::default()));
+    let proxy = proxy_server(pool.clone());
+
+    for _ in 0..3 {
+        assert_eq!(get(proxy, ""), "hello");
+        // leaves time for the upstream to close its end
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let stats = pool.stats();
+    assert_eq!(stats.opened, 3);
+    assert_eq!(stats.reused, 0);
+    assert_eq!(stats.discarded, 2);
+}
+
+#[test]
+fn idle_connections_expire() {
+    let now = Arc::new(Mutex::new(Instant::now()));
+    let clock = {
+        let now = now.clone();
+        Arc::new(move || *now.lock().unwrap())
+    };
+    let options = PoolOptions {
+        idle_timeout: Duration::from_secs(10),
+        ..PoolOptions::default()
+    };
+    let pool = Arc::new(UpstreamPool::with_clock(
+        port_echoing_upstream(),
+        options,
+        clock,
+    ));
+    let proxy = proxy_server(pool.clone());
+
+    // waits for the connection to be back in the pool before moving the clock
+    let get_and_wait = || {
+        get(proxy, "");
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    get_and_wait();
+    *now.lock().unwrap() += Duration::from_secs(5);
+    get_and_wait();
+    assert_eq!(pool.stats().reused, 1);
+
+    *now.lock().unwrap() += Duration::from_secs(11);
+    get(proxy, "");
+
+    let stats = pool.stats();
+    assert_eq!(stats.opened, 2);
+    assert_eq!(stats.reused, 1);
+    assert_eq!(stats.discarded, 1);
+}
+
+#[test]
+fn chunked_upstream_response_keeps_connection() {
+    let upstream = scripted_upstream(
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+          5\r\nhello\r\n0\r\n\r\n",
+    );
+    let pool = Arc::new(UpstreamPool::new(upstream, PoolOptions::default()));
+    let proxy = proxy_server(pool.clone());
+
+    for _ in 0..3 {
+        assert!(get(proxy, "").contains("hello"));
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let stats = pool.stats();
+    assert_eq!(stats.opened, 1);
+    assert_eq!(stats.reused, 2);
+    assert_eq!(stats.idle, 1);
+}
+
+#[test]
+fn chunked_upstream_response_with_trailers() {
+    let upstream = scripted_upstream(
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: X-Checksum\r\n\r\n\
+          5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Checksum: 1234\r\nX-Other: a\r\n\r\n",
+    );
+    let pool = Arc::new(UpstreamPool::new(upstream, PoolOptions::default()));
+    let proxy = proxy_server(pool.clone());
+
+    for _ in 0..2 {
+        let body = get(proxy, "");
+        assert!(
+            body.contains("hello") && body.contains(" world"),
+            "{}",
+            body
+        );
+        assert!(!body.contains("X-Checksum"));
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let stats = pool.stats();
+    assert_eq!(stats.opened, 1);
+    assert_eq!(stats.reused, 1);
+}
+
+#[test]
+fn interim_upstream_responses_are_skipped() {
+    let upstream = scripted_upstream(
+        b"HTTP/1.1 100 Continue\r\n\r\n\
+          HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n\
+          HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello",
+    );
+    let pool = Arc::new(UpstreamPool::new(upstream, PoolOptions::default()));
+    let proxy = proxy_server(pool.clone());
+
+    // `get()` checks that the status is the final one
+    assert_eq!(get(proxy, ""), "hello");
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(get(proxy, ""), "hello");
+    assert_eq!(pool.stats().reused, 1);
+}
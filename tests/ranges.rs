@@ -0,0 +1,136 @@
+extern crate tiny_http;
+
+use std::io::{Read, Write};
+use std::thread;
+
+use tiny_http::Response;
+
+#[allow(dead_code)]
+mod support;
+
+const BLOB: &[u8] = b"0123456789abcdefghij";
+
+/// Sends a request with the given method and headers, answers it with `BLOB` and
+/// `Response::with_range`, and returns what the client received.
+fn fetch(method: &str, headers: &str) -> String {
+    let (server, mut client) = support::new_one_server_one_client();
+    (write!(
+        client,
+        "{} /blob HTTP/1.1\r\nHost: localhost\r\n{}Connection: close\r\n\r\n",
+        method, headers
+    ))
+    .unwrap();
+
+    let handler = thread::spawn(move || {
+        let rq = server.recv().unwrap();
+        let response = Response::from_data(BLOB).with_range(&rq).unwrap();
+        rq.respond(response).unwrap();
+    });
+
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    handler.join().unwrap();
+    content
+}
+
+#[test]
+fn no_range() {
+    let content = fetch("GET", "");
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(content.contains("Accept-Ranges: bytes\r\n"), "{}", content);
+    assert!(
+        content.ends_with("\r\n\r\n0123456789abcdefghij"),
+        "{}",
+        content
+    );
+}
+
+#[test]
+fn open_ended_range() {
+    let content = fetch("GET", "Range: bytes=12-\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 206 Partial Content\r\n"),
+        "{}",
+        content
+    );
+    assert!(
+        content.contains("Content-Range: bytes 12-19/20\r\n"),
+        "{}",
+        content
+    );
+    assert!(content.contains("Content-Length: 8\r\n"), "{}", content);
+    assert!(content.contains("Accept-Ranges: bytes\r\n"), "{}", content);
+    assert!(content.ends_with("\r\n\r\ncdefghij"), "{}", content);
+}
+
+#[test]
+fn suffix_range() {
+    let content = fetch("GET", "Range: bytes=-3\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 206 Partial Content\r\n"),
+        "{}",
+        content
+    );
+    assert!(
+        content.contains("Content-Range: bytes 17-19/20\r\n"),
+        "{}",
+        content
+    );
+    assert!(content.ends_with("\r\n\r\nhij"), "{}", content);
+}
+
+#[test]
+fn out_of_bounds_range() {
+    let content = fetch("GET", "Range: bytes=20-30\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"),
+        "{}",
+        content
+    );
+    assert!(
+        content.contains("Content-Range: bytes */20\r\n"),
+        "{}",
+        content
+    );
+    assert!(content.ends_with("\r\n\r\n"), "{}", content);
+}
+
+#[test]
+fn several_ranges_get_the_whole_body() {
+    let content = fetch("GET", "Range: bytes=0-1,5-6\r\n");
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(!content.contains("Content-Range"), "{}", content);
+    assert!(
+        content.ends_with("\r\n\r\n0123456789abcdefghij"),
+        "{}",
+        content
+    );
+}
+
+#[test]
+fn head_with_range() {
+    let content = fetch("HEAD", "Range: bytes=0-4\r\n");
+    assert!(
+        content.starts_with("HTTP/1.1 206 Partial Content\r\n"),
+        "{}",
+        content
+    );
+    assert!(
+        content.contains("Content-Range: bytes 0-4/20\r\n"),
+        "{}",
+        content
+    );
+    assert!(content.contains("Content-Length: 5\r\n"), "{}", content);
+    assert!(content.ends_with("\r\n\r\n"), "{}", content);
+}
+
+#[test]
+fn range_ignored_for_post() {
+    let content = fetch("POST", "Range: bytes=0-4\r\nContent-Length: 0\r\n");
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+    assert!(
+        content.ends_with("\r\n\r\n0123456789abcdefghij"),
+        "{}",
+        content
+    );
+}
@@ -38,3 +38,12 @@ pub fn new_client_to_hello_world_server() -> TcpStream {
 
     client
 }
+
+/// Creates a server listening on a port of the loopback interface, with the given settings.
+pub fn server_with_config(advanced: tiny_http::ServerConfigAdvanced) -> tiny_http::Server {
+    tiny_http::ServerBuilder::new()
+        .with_addr(tiny_http::ConfigListenAddr::from_socket_addrs("127.0.0.1:0").unwrap())
+        .with_advanced(advanced)
+        .build()
+        .unwrap()
+}
@@ -0,0 +1,46 @@
+#![cfg(all(feature = "tcp-diagnostics", target_os = "linux"))]
+
+extern crate tiny_http;
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+#[allow(dead_code)]
+mod support;
+
+#[test]
+fn diagnostics_of_live_connection() {
+    let (server, mut client) = support::new_one_server_one_client();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    // a first round trip, so that the kernel has measured the round-trip time
+    (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+    let rq = server.recv().unwrap();
+    rq.respond(tiny_http::Response::from_string("hello"))
+        .unwrap();
+    let mut buf = [0; 1024];
+    assert!(client.read(&mut buf).unwrap() > 0);
+
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    let rq = server.recv().unwrap();
+    let diagnostics = rq.tcp_diagnostics().unwrap();
+    assert!(
+        diagnostics.rtt > Duration::from_secs(0),
+        "{:?}",
+        diagnostics
+    );
+    assert!(diagnostics.snd_mss > 0, "{:?}", diagnostics);
+    rq.respond(tiny_http::Response::empty(204)).unwrap();
+}
+
+#[test]
+fn test_requests_have_no_diagnostics() {
+    let rq: tiny_http::Request = tiny_http::TestRequest::new().into();
+    assert!(rq.tcp_diagnostics().is_none());
+}
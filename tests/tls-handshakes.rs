@@ -0,0 +1,362 @@
+#![cfg(feature = "ssl-rustls")]
+
+extern crate tiny_http;
+
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tiny_http::{
+    ConfigListenAddr, Server, ServerBuilder, ServerConfigAdvanced, ServerStats, TlsClientAuthMode,
+};
+
+// self-signed certificate for `localhost`, trusted by the clients of these tests
+const CERTIFICATE: &[u8] = include_bytes!("tls/cert.pem");
+const PRIVATE_KEY: &[u8] = include_bytes!("tls/key.pem");
+
+// certificate of a client, signed by the authority of `CLIENT_CA`
+const CLIENT_CA: &[u8] = include_bytes!("tls/client-ca.pem");
+const CLIENT_CERTIFICATE: &[u8] = include_bytes!("tls/client-cert.pem");
+const CLIENT_PRIVATE_KEY: &[u8] = include_bytes!("tls/client-key.pem");
+
+type TlsClient = rustls::StreamOwned<rustls::ClientConnection, TcpStream>;
+
+fn tls_server(advanced: ServerConfigAdvanced) -> Server {
+    ServerBuilder::new()
+        .with_addr(ConfigListenAddr::from_socket_addrs("127.0.0.1:0").unwrap())
+        .with_ssl(tiny_http::SslConfig {
+            certificate: CERTIFICATE.to_vec(),
+            private_key: PRIVATE_KEY.to_vec(),
+        })
+        .with_advanced(advanced)
+        .build()
+        .unwrap()
+}
+
+/// Connects to `addr` and completes the TLS handshake.
+fn tls_client(addr: SocketAddr) -> TlsClient {
+    handshake(addr, client_config().with_no_client_auth()).unwrap()
+}
+
+/// Connects to `addr` and completes the TLS handshake, sending `CLIENT_CERTIFICATE`.
+fn authenticated_tls_client(addr: SocketAddr) -> TlsClient {
+    let certificates = rustls_pemfile::certs(&mut &CLIENT_CERTIFICATE[..])
+        .unwrap()
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+    let key = rustls_pemfile::pkcs8_private_keys(&mut &CLIENT_PRIVATE_KEY[..])
+        .unwrap()
+        .remove(0);
+    let config = client_config()
+        .with_single_cert(certificates, rustls::PrivateKey(key))
+        .unwrap();
+    handshake(addr, config).unwrap()
+}
+
+/// Starts the configuration of a client that trusts `CERTIFICATE`.
+fn client_config(
+) -> rustls::ConfigBuilder<rustls::ClientConfig, rustls::client::WantsTransparencyPolicyOrClientCert>
+{
+    let mut roots = rustls::RootCertStore::empty();
+    for certificate in rustls_pemfile::certs(&mut &CERTIFICATE[..]).unwrap() {
+        roots.add(&rustls::Certificate(certificate)).unwrap();
+    }
+    rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+}
+
+fn handshake(addr: SocketAddr, config: rustls::ClientConfig) -> std::io::Result<TlsClient> {
+    let name = rustls::ServerName::try_from("localhost").unwrap();
+    let connection = rustls::ClientConnection::new(Arc::new(config), name).unwrap();
+
+    let sock = TcpStream::connect(addr).unwrap();
+    sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut client = rustls::StreamOwned::new(connection, sock);
+    while client.conn.is_handshaking() {
+        client.conn.complete_io(&mut client.sock)?;
+    }
+    Ok(client)
+}
+
+/// Waits until the stats of the server satisfy `condition`.
+fn wait_for_stats(server: &Server, condition: impl Fn(&ServerStats) -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition(&server.stats()) {
+        assert!(Instant::now() < deadline, "{:?}", server.stats());
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn established_connections_are_served_during_handshake_flood() {
+    let server = Arc::new(tls_server(
+        ServerConfigAdvanced::default()
+            .with_max_concurrent_tls_handshakes(1)
+            .with_tls_handshake_queue_timeout(Duration::from_millis(300)),
+    ));
+    let addr = server.server_addr().to_ip().unwrap();
+
+    let mut established = tls_client(addr);
+
+    // a client that never sends its hello keeps the only handshake busy
+    let _stalled = TcpStream::connect(addr).unwrap();
+    wait_for_stats(&server, |stats| stats.tls_handshakes_in_progress == 1);
+
+    // a client over the limit waits for the handshake
+    let mut over_limit = TcpStream::connect(addr).unwrap();
+    over_limit
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(over_limit, "\x16\x03\x01")).unwrap();
+
+    let handler = {
+        let server = server.clone();
+        thread::spawn(move || {
+            let rq = server.recv().unwrap();
+            assert!(rq.secure());
+            rq.respond(tiny_http::Response::from_string("served"))
+                .unwrap();
+        })
+    };
+    (write!(
+        established,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    let mut response = Vec::new();
+    // rustls reports the missing close_notify of the server as an error
+    let _ = established.read_to_end(&mut response);
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.ends_with("served"), "{}", response);
+    handler.join().unwrap();
+    assert_eq!(server.stats().tls_handshakes_in_progress, 1);
+
+    // closed without a handshake once its wait expires
+    let mut data = Vec::new();
+    assert_eq!(over_limit.read_to_end(&mut data).unwrap_or(0), 0);
+    wait_for_stats(&server, |stats| stats.tls_handshake_queue_timeouts == 1);
+}
+
+#[test]
+fn handshake_timeout_and_queue_limit() {
+    let server = tls_server(
+        ServerConfigAdvanced::default()
+            .with_max_concurrent_tls_handshakes(1)
+            .with_max_queued_tls_handshakes(1)
+            .with_tls_handshake_timeout(Duration::from_millis(300)),
+    );
+    let addr = server.server_addr().to_ip().unwrap();
+    let connect = || {
+        let client = TcpStream::connect(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+    };
+
+    // neither of them sends its hello
+    let mut stalled = connect();
+    wait_for_stats(&server, |stats| stats.tls_handshakes_in_progress == 1);
+    let mut queued = connect();
+
+    // no room left in the queue, closed right away
+    let mut rejected = connect();
+    let mut data = Vec::new();
+    assert_eq!(rejected.read_to_end(&mut data).unwrap_or(0), 0);
+    wait_for_stats(&server, |stats| stats.tls_handshake_queue_rejections == 1);
+
+    // each stalled handshake is given up after its timeout
+    let started = Instant::now();
+    assert_eq!(stalled.read_to_end(&mut data).unwrap_or(0), 0);
+    assert_eq!(queued.read_to_end(&mut data).unwrap_or(0), 0);
+    assert!(started.elapsed() < Duration::from_secs(3));
+    wait_for_stats(&server, |stats| stats.tls_handshakes_in_progress == 0);
+    assert_eq!(server.stats().tls_handshake_queue_timeouts, 0);
+
+    let mut client = tls_client(addr);
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    assert!(server.recv().unwrap().secure());
+}
+
+#[test]
+fn filtered_before_handshake() {
+    let server = tls_server(
+        ServerConfigAdvanced::default().with_connection_filter(Arc::new(|_: &SocketAddr| {
+            tiny_http::ConnectionDecision::RejectWith(tiny_http::StatusCode(403))
+        })),
+    );
+    let addr = server.server_addr().to_ip().unwrap();
+
+    // closed without a response, in plain text or not
+    let mut client = TcpStream::connect(addr).unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let _ = client.write_all(b"\x16\x03\x01");
+    let mut data = Vec::new();
+    assert_eq!(client.read_to_end(&mut data).unwrap_or(0), 0);
+    wait_for_stats(&server, |stats| stats.filtered_connections == 1);
+    assert_eq!(server.stats().tls_handshakes_in_progress, 0);
+}
+
+#[test]
+fn no_peer_certificates_without_client_auth() {
+    let server = tls_server(ServerConfigAdvanced::default());
+    let mut client = tls_client(server.server_addr().to_ip().unwrap());
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    // the server doesn't ask for a certificate, so the client doesn't send one
+    let rq = server.recv().unwrap();
+    assert!(rq.secure());
+    assert_eq!(rq.peer_certificates(), None);
+}
+
+#[test]
+fn client_certificate() {
+    let server = tls_server(
+        ServerConfigAdvanced::default()
+            .with_tls_client_auth(CLIENT_CA.to_vec(), TlsClientAuthMode::Required),
+    );
+    let mut client = authenticated_tls_client(server.server_addr().to_ip().unwrap());
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let rq = server.recv().unwrap();
+    let expected = rustls_pemfile::certs(&mut &CLIENT_CERTIFICATE[..]).unwrap();
+    assert_eq!(rq.peer_certificates(), Some(&expected[..]));
+}
+
+#[test]
+fn client_certificate_required() {
+    let server = tls_server(
+        ServerConfigAdvanced::default()
+            .with_tls_client_auth(CLIENT_CA.to_vec(), TlsClientAuthMode::Required),
+    );
+    let addr = server.server_addr().to_ip().unwrap();
+
+    // the server rejects the client without a certificate, which only notices it once it
+    // sends its request
+    let client = handshake(addr, client_config().with_no_client_auth());
+    let failed = client.and_then(|mut client| {
+        (write!(
+            client,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        ))?;
+        client.flush()?;
+        client.read_to_end(&mut Vec::new())
+    });
+    assert!(failed.is_err());
+    assert!(server
+        .recv_timeout(Duration::from_millis(200))
+        .unwrap()
+        .is_none());
+
+    // without a certificate, the client of the optional mode is still served
+    let server = tls_server(
+        ServerConfigAdvanced::default()
+            .with_tls_client_auth(CLIENT_CA.to_vec(), TlsClientAuthMode::Optional),
+    );
+    let mut client = tls_client(server.server_addr().to_ip().unwrap());
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    assert_eq!(server.recv().unwrap().peer_certificates(), None);
+}
+
+#[test]
+fn sni_hostname() {
+    let server = tls_server(ServerConfigAdvanced::default());
+    // `tls_client` asks for `localhost`
+    let mut client = tls_client(server.server_addr().to_ip().unwrap());
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let rq = server.recv().unwrap();
+    assert_eq!(rq.sni_hostname(), Some("localhost"));
+    assert_eq!(rq.host(), Some(("example.com", None)));
+}
+
+#[test]
+fn keep_alive_timeout() {
+    let server = tls_server(
+        ServerConfigAdvanced::default().with_keep_alive_timeout(Duration::from_millis(200)),
+    );
+    let mut client = tls_client(server.server_addr().to_ip().unwrap());
+
+    // closed without a request, rustls reports the missing close_notify as an error
+    let started = Instant::now();
+    let mut data = Vec::new();
+    let _ = client.read_to_end(&mut data);
+    assert!(data.is_empty());
+    assert!(started.elapsed() < Duration::from_secs(3));
+    wait_for_stats(&server, |stats| stats.keep_alive_timeouts == 1);
+}
+
+#[test]
+fn header_read_deadline() {
+    let server = tls_server(
+        ServerConfigAdvanced::default().with_max_header_read_time(Duration::from_millis(300)),
+    );
+    let mut client = tls_client(server.server_addr().to_ip().unwrap());
+
+    // the headers never end
+    (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n")).unwrap();
+    let started = Instant::now();
+    let mut data = Vec::new();
+    let _ = client.read_to_end(&mut data);
+    let response = String::from_utf8(data).unwrap();
+    assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+    assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn body_read_timeout() {
+    let server = tls_server(
+        ServerConfigAdvanced::default().with_body_read_timeout(Duration::from_millis(200)),
+    );
+    let mut client = tls_client(server.server_addr().to_ip().unwrap());
+
+    // half of the declared body, then nothing
+    (write!(
+        client,
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4000\r\n\r\n"
+    ))
+    .unwrap();
+    client.write_all(&[b'a'; 2000]).unwrap();
+    client.flush().unwrap();
+
+    let mut rq = server.recv().unwrap();
+    let mut body = Vec::new();
+    let started = Instant::now();
+    let err = rq.as_reader().read_to_end(&mut body).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert!(started.elapsed() < Duration::from_secs(3));
+    assert_eq!(body.len(), 2000);
+    rq.respond(tiny_http::Response::empty(408)).unwrap();
+
+    let mut data = Vec::new();
+    let _ = client.read_to_end(&mut data);
+    let response = String::from_utf8(data).unwrap();
+    assert!(response.starts_with("HTTP/1.1 408"), "{}", response);
+}
@@ -32,3 +32,305 @@ fn unblock_threads() {
     h1.join().unwrap();
     h2.join().unwrap();
 }
+
+/// Spawns a thread that receives requests until an error is returned, and returns the error.
+fn worker(server: Arc<tiny_http::Server>) -> thread::JoinHandle<tiny_http::RecvError> {
+    thread::spawn(move || loop {
+        match server.recv_checked() {
+            Ok(rq) => drop(rq),
+            Err(err) => return err,
+        }
+    })
+}
+
+#[test]
+fn graceful_shutdown() {
+    use std::io::Write;
+
+    let server = Arc::new(tiny_http::Server::http("0.0.0.0:0").unwrap());
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    // a request that is already queued when the shutdown starts
+    let mut client = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+    thread::sleep(std::time::Duration::from_millis(200));
+
+    assert_eq!(server.shutdown_reason(), None);
+    server.shutdown_gracefully();
+    assert_eq!(
+        server.shutdown_reason(),
+        Some(tiny_http::ShutdownReason::Graceful)
+    );
+
+    let rq = server.recv_checked().unwrap();
+    assert_eq!(rq.url(), "/");
+
+    let blocked = worker(server.clone());
+    assert_eq!(
+        blocked.join().unwrap(),
+        tiny_http::RecvError::Shutdown(tiny_http::ShutdownReason::Graceful)
+    );
+
+    // the plain API fails as well
+    assert!(server.recv().is_err());
+}
+
+#[test]
+fn shutdown_unblocks_all_workers() {
+    let server = Arc::new(tiny_http::Server::http("0.0.0.0:0").unwrap());
+    let workers: Vec<_> = (0..3).map(|_| worker(server.clone())).collect();
+    thread::sleep(std::time::Duration::from_millis(100));
+
+    server.shutdown_gracefully();
+    for w in workers {
+        assert_eq!(
+            w.join().unwrap(),
+            tiny_http::RecvError::Shutdown(tiny_http::ShutdownReason::Graceful)
+        );
+    }
+    assert_eq!(
+        server
+            .recv_timeout_checked(std::time::Duration::from_secs(5))
+            .unwrap_err(),
+        tiny_http::RecvError::Shutdown(tiny_http::ShutdownReason::Graceful)
+    );
+}
+
+#[test]
+fn unblock_is_not_shutdown() {
+    let server = Arc::new(tiny_http::Server::http("0.0.0.0:0").unwrap());
+    let blocked = worker(server.clone());
+    server.unblock();
+    assert_eq!(blocked.join().unwrap(), tiny_http::RecvError::Unblocked);
+    assert_eq!(server.shutdown_reason(), None);
+}
+
+// Only Linux lets the listener be shut down while a server uses it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn listener_closed() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let server =
+        Arc::new(tiny_http::Server::from_listener(listener.try_clone().unwrap(), None).unwrap());
+    let blocked = worker(server.clone());
+    thread::sleep(std::time::Duration::from_millis(100));
+
+    socket2::SockRef::from(&listener)
+        .shutdown(std::net::Shutdown::Both)
+        .unwrap();
+
+    assert_eq!(
+        blocked.join().unwrap(),
+        tiny_http::RecvError::Shutdown(tiny_http::ShutdownReason::ListenerClosed)
+    );
+
+    // a later shutdown doesn't override the first reason
+    server.shutdown_gracefully();
+    assert_eq!(
+        server.shutdown_reason(),
+        Some(tiny_http::ShutdownReason::ListenerClosed)
+    );
+}
+
+// Only Linux lets the listener be shut down while a server uses it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn listener_closed_unblocks_every_receiver() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let server =
+        Arc::new(tiny_http::Server::from_listener(listener.try_clone().unwrap(), None).unwrap());
+    let blocked = [worker(server.clone()), worker(server.clone())];
+    let iterating = {
+        let server = server.clone();
+        thread::spawn(move || server.incoming_requests().count())
+    };
+    thread::sleep(std::time::Duration::from_millis(100));
+
+    socket2::SockRef::from(&listener)
+        .shutdown(std::net::Shutdown::Both)
+        .unwrap();
+
+    for blocked in blocked {
+        assert_eq!(
+            blocked.join().unwrap(),
+            tiny_http::RecvError::Shutdown(tiny_http::ShutdownReason::ListenerClosed)
+        );
+    }
+    assert_eq!(iterating.join().unwrap(), 0);
+}
+
+#[test]
+fn retire_one_worker() {
+    use std::io::{Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let server = Arc::new(tiny_http::Server::http("0.0.0.0:0").unwrap());
+    let port = server.server_addr().to_ip().unwrap().port();
+    let handled = Arc::new(AtomicUsize::new(0));
+
+    let tokens: Vec<_> = (0..4).map(|_| server.register_worker()).collect();
+    let workers: Vec<_> = tokens
+        .iter()
+        .map(|token| {
+            let server = server.clone();
+            let token = token.clone();
+            let handled = handled.clone();
+            thread::spawn(move || loop {
+                match server.recv_with_token(&token) {
+                    Ok(rq) => {
+                        rq.respond(tiny_http::Response::from_string("ok")).unwrap();
+                        handled.fetch_add(1, Ordering::SeqCst);
+                    }
+                    Err(err) => return err,
+                }
+            })
+        })
+        .collect();
+
+    // requests keep coming while the worker retires
+    let clients = thread::spawn(move || {
+        for _ in 0..40 {
+            let mut client = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+            (write!(
+                client,
+                "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+            ))
+            .unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.ends_with("ok"), "{}", response);
+        }
+    });
+    thread::sleep(std::time::Duration::from_millis(10));
+    tokens[2].retire();
+    clients.join().unwrap();
+    // a worker counts its request after the client got the response
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while handled.load(Ordering::SeqCst) < 40 && std::time::Instant::now() < deadline {
+        thread::sleep(std::time::Duration::from_millis(1));
+    }
+    assert_eq!(handled.load(Ordering::SeqCst), 40);
+
+    server.shutdown_gracefully();
+    for (i, w) in workers.into_iter().enumerate() {
+        let expected = if i == 2 {
+            tiny_http::RecvError::Retired
+        } else {
+            tiny_http::RecvError::Shutdown(tiny_http::ShutdownReason::Graceful)
+        };
+        assert_eq!(w.join().unwrap(), expected);
+    }
+}
+
+#[test]
+fn retired_worker_stops_right_away() {
+    let server = Arc::new(tiny_http::Server::http("0.0.0.0:0").unwrap());
+    let token = server.register_worker();
+    let other = server.register_worker();
+    token.retire();
+    assert!(token.is_retired() && !other.is_retired());
+    assert_eq!(
+        server.recv_with_token(&token).unwrap_err(),
+        tiny_http::RecvError::Retired
+    );
+    // and so do the following calls
+    assert_eq!(
+        server.recv_with_token(&token).unwrap_err(),
+        tiny_http::RecvError::Retired
+    );
+}
+
+/// Body that takes a while to produce, to keep a response in the middle of being sent.
+struct SlowBody(Option<&'static [u8]>);
+
+impl std::io::Read for SlowBody {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.take() {
+            Some(data) => {
+                thread::sleep(std::time::Duration::from_millis(300));
+                buf[..data.len()].copy_from_slice(data);
+                Ok(data.len())
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+#[test]
+fn drain_waits_for_response_in_progress() {
+    use std::io::{Read, Write};
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    let server = Arc::new(tiny_http::Server::http("0.0.0.0:0").unwrap());
+    let port = server.server_addr().to_ip().unwrap().port();
+    let handle = server.shutdown_handle();
+
+    let (responding_tx, responding_rx) = mpsc::channel();
+    let handler = {
+        let server = server.clone();
+        thread::spawn(move || {
+            let rq = server.recv().unwrap();
+            responding_tx.send(()).unwrap();
+            let response =
+                tiny_http::Response::empty(200).with_data(SlowBody(Some(b"finished")), Some(8));
+            rq.respond(response).unwrap();
+            server.recv_checked().unwrap_err()
+        })
+    };
+
+    let mut client = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+    responding_rx.recv().unwrap();
+
+    // the handle is used from another thread, like a signal handler would
+    let start = Instant::now();
+    let drained = thread::spawn(move || handle.shutdown_gracefully(Duration::from_secs(10)))
+        .join()
+        .unwrap();
+    assert!(drained);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(
+        handler.join().unwrap(),
+        tiny_http::RecvError::Shutdown(tiny_http::ShutdownReason::Graceful)
+    );
+
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("finished"), "{}", response);
+}
+
+#[test]
+fn drain_timeout_expires() {
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    let server = Arc::new(tiny_http::Server::http("0.0.0.0:0").unwrap());
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    let mut client = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    (write!(client, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")).unwrap();
+    // held without being answered
+    let rq = server.recv().unwrap();
+    let blocked = worker(server.clone());
+
+    let start = Instant::now();
+    assert!(!server
+        .shutdown_handle()
+        .shutdown_gracefully(Duration::from_millis(200)));
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(
+        server.shutdown_reason(),
+        Some(tiny_http::ShutdownReason::Graceful)
+    );
+    assert_eq!(
+        blocked.join().unwrap(),
+        tiny_http::RecvError::Shutdown(tiny_http::ShutdownReason::Graceful)
+    );
+    drop(rq);
+}
@@ -0,0 +1,191 @@
+#![cfg(feature = "websocket")]
+
+extern crate tiny_http;
+
+use std::io::{Read, Write};
+use std::thread;
+use std::time::Duration;
+
+use tiny_http::{WebSocket, WsHandshakeError, WsMessage};
+
+#[allow(dead_code)]
+mod support;
+
+/// Reads the head of a response, up to the empty line.
+fn read_head(client: &mut std::net::TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        client.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+#[test]
+fn handshake() {
+    let (server, mut client) = support::new_one_server_one_client();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    // the example handshake of RFC 6455 §1.3
+    (write!(
+        client,
+        "GET /chat HTTP/1.1\r\nHost: server.example.com\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Origin: http://example.com\r\nSec-WebSocket-Protocol: chat, superchat\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    ))
+    .unwrap();
+
+    let handler = thread::spawn(move || {
+        let request = server.recv().unwrap();
+        assert!(request.is_websocket_upgrade());
+        let mut stream = request.upgrade_websocket(&["superchat"]).unwrap();
+
+        let mut data = [0; 4];
+        stream.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"ping");
+        stream.write_all(b"pong").unwrap();
+        stream.flush().unwrap();
+    });
+
+    let head = read_head(&mut client);
+    assert!(
+        head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+        "{}",
+        head
+    );
+    assert!(head.contains("Upgrade: websocket\r\n"), "{}", head);
+    assert!(
+        head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"),
+        "{}",
+        head
+    );
+    assert!(
+        head.contains("Sec-WebSocket-Protocol: superchat\r\n"),
+        "{}",
+        head
+    );
+    assert!(!head.contains("Content-Length"), "{}", head);
+
+    // the connection now belongs to the handler
+    client.write_all(b"ping").unwrap();
+    let mut data = [0; 4];
+    client.read_exact(&mut data).unwrap();
+    assert_eq!(&data, b"pong");
+    handler.join().unwrap();
+}
+
+#[test]
+fn invalid_handshakes() {
+    let cases = [
+        (
+            "Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n",
+            WsHandshakeError::UnsupportedVersion,
+            "HTTP/1.1 426 Upgrade Required",
+        ),
+        (
+            "Sec-WebSocket-Version: 13\r\n",
+            WsHandshakeError::InvalidKey,
+            "HTTP/1.1 400 Bad Request",
+        ),
+    ];
+
+    for (headers, expected, status_line) in cases.iter() {
+        let (server, mut client) = support::new_one_server_one_client();
+        (write!(
+            client,
+            "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\n{}Connection: close\r\n\r\n",
+            headers
+        ))
+        .unwrap();
+
+        let request = server.recv().unwrap();
+        assert!(request.is_websocket_upgrade());
+        let (request, err) = request.upgrade_websocket(&[]).err().unwrap();
+        assert_eq!(err, *expected);
+        request.respond(err.response()).unwrap();
+
+        let mut content = String::new();
+        client.read_to_string(&mut content).unwrap();
+        assert!(content.starts_with(status_line), "{}", content);
+        if err.status_code() == 426 {
+            assert!(
+                content.contains("Sec-WebSocket-Version: 13\r\n"),
+                "{}",
+                content
+            );
+        }
+    }
+}
+
+#[test]
+fn not_an_upgrade() {
+    let (server, mut client) = support::new_one_server_one_client();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    ))
+    .unwrap();
+
+    let request = server.recv().unwrap();
+    assert!(!request.is_websocket_upgrade());
+    let (request, err) = request.upgrade_websocket(&[]).err().unwrap();
+    assert_eq!(err, WsHandshakeError::NotUpgrade);
+
+    // the request can still be answered normally
+    request
+        .respond(tiny_http::Response::from_string("hello"))
+        .unwrap();
+    let mut content = String::new();
+    client.read_to_string(&mut content).unwrap();
+    assert!(content.starts_with("HTTP/1.1 200 OK\r\n"), "{}", content);
+}
+
+#[test]
+fn echo_messages() {
+    let (server, mut client) = support::new_one_server_one_client();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (write!(
+        client,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    ))
+    .unwrap();
+
+    let handler = thread::spawn(move || {
+        let request = server.recv().unwrap();
+        let mut socket = WebSocket::new(request.upgrade_websocket(&[]).unwrap());
+        loop {
+            match socket.read_message().unwrap() {
+                WsMessage::Close(code, _) => return code,
+                message => socket.send_message(&message).unwrap(),
+            }
+        }
+    });
+    read_head(&mut client);
+
+    // "Hello" from RFC 6455 §5.7, masked, then a close frame with the code 1000
+    client
+        .write_all(&[
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ])
+        .unwrap();
+    let mut frame = [0; 7];
+    client.read_exact(&mut frame).unwrap();
+    assert_eq!(&frame, b"\x81\x05Hello");
+
+    client
+        .write_all(&[0x88, 0x82, 0, 0, 0, 0, 0x03, 0xE8])
+        .unwrap();
+    let mut frame = [0; 4];
+    client.read_exact(&mut frame).unwrap();
+    assert_eq!(frame, [0x88, 0x02, 0x03, 0xE8]);
+    assert_eq!(handler.join().unwrap(), Some(1000));
+}
@@ -0,0 +1,178 @@
+//! Exact bytes written for a table of responses, compared with the fixtures of `wire-format/`.
+//!
+//! Run with `TINY_HTTP_BLESS=1` to write the fixtures from the current output, then review the
+//! diff of the fixtures.
+
+extern crate tiny_http;
+
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+
+use tiny_http::{HTTPVersion, Header, Response, ResponseBox};
+
+/// How the request being answered was made.
+struct RequestCtx {
+    http_version: HTTPVersion,
+    headers: &'static [&'static str],
+    head: bool,
+    upgrade: Option<&'static str>,
+}
+
+const HTTP11: RequestCtx = RequestCtx {
+    http_version: HTTPVersion(1, 1),
+    headers: &[],
+    head: false,
+    upgrade: None,
+};
+
+const HTTP10: RequestCtx = RequestCtx {
+    http_version: HTTPVersion(1, 0),
+    ..HTTP11
+};
+
+/// Reader whose length isn't known in advance.
+fn unknown_length(data: &'static str) -> ResponseBox {
+    Response::empty(200)
+        .with_data(Cursor::new(data.as_bytes()), None)
+        .boxed()
+}
+
+/// Builds the responses, all with a fixed `Date` so that the output is reproducible.
+fn cases() -> Vec<(&'static str, RequestCtx, ResponseBox)> {
+    vec![
+        (
+            "known-length",
+            HTTP11,
+            Response::from_string("hello world").boxed(),
+        ),
+        ("empty-body", HTTP11, Response::empty(200).boxed()),
+        ("unknown-length", HTTP11, unknown_length("hello world")),
+        (
+            "above-chunked-threshold",
+            HTTP11,
+            Response::from_string("hello world")
+                .with_chunked_threshold(4)
+                .boxed(),
+        ),
+        (
+            "http10-unknown-length",
+            HTTP10,
+            unknown_length("hello world"),
+        ),
+        (
+            "te-identity",
+            RequestCtx {
+                headers: &["TE: identity"],
+                ..HTTP11
+            },
+            unknown_length("hello world"),
+        ),
+        (
+            "te-chunked-refused",
+            RequestCtx {
+                headers: &["TE: chunked;q=0"],
+                ..HTTP11
+            },
+            unknown_length("hello world"),
+        ),
+        (
+            "head-request",
+            RequestCtx {
+                head: true,
+                ..HTTP11
+            },
+            Response::from_string("hello world").boxed(),
+        ),
+        (
+            "no-content",
+            HTTP11,
+            Response::from_string("ignored")
+                .with_status_code(204)
+                .boxed(),
+        ),
+        (
+            "not-modified",
+            HTTP11,
+            unknown_length("ignored").with_status_code(304),
+        ),
+        (
+            "upgrade",
+            RequestCtx {
+                upgrade: Some("websocket"),
+                ..HTTP11
+            },
+            Response::empty(101).boxed(),
+        ),
+        (
+            "custom-headers",
+            HTTP11,
+            Response::from_string("{}")
+                .with_header(Header::from_bytes("Server", "golden").unwrap())
+                .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+                .with_header(Header::from_bytes("X-Multi", "1").unwrap())
+                .with_header(Header::from_bytes("X-Multi", "2").unwrap())
+                .boxed(),
+        ),
+        ("trailers", HTTP11, {
+            let mut response = unknown_length("hello world");
+            let sender = response.trailer_sender();
+            sender
+                .send(Header::from_bytes("X-Checksum", "abc").unwrap())
+                .unwrap();
+            response
+        }),
+    ]
+}
+
+fn render(ctx: &RequestCtx, response: ResponseBox) -> Vec<u8> {
+    let headers: Vec<Header> = ctx
+        .headers
+        .iter()
+        .map(|header| header.parse().unwrap())
+        .collect();
+    let response =
+        response.with_header(Header::from_bytes("Date", "Thu, 01 Jan 2026 00:00:00 GMT").unwrap());
+
+    let mut output = Vec::new();
+    response
+        .raw_print(
+            &mut output,
+            ctx.http_version.clone(),
+            &headers,
+            ctx.head,
+            ctx.upgrade,
+        )
+        .unwrap();
+    output
+}
+
+#[test]
+fn wire_format() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/wire-format");
+    let bless = std::env::var_os("TINY_HTTP_BLESS").is_some();
+
+    let mut mismatches = Vec::new();
+    for (name, ctx, response) in cases() {
+        let output = render(&ctx, response);
+        let path = dir.join(format!("{}.http", name));
+        if bless {
+            fs::write(&path, &output).unwrap();
+            continue;
+        }
+
+        let mut expected = Vec::new();
+        fs::File::open(&path)
+            .and_then(|mut file| file.read_to_end(&mut expected))
+            .unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        if output != expected {
+            mismatches.push(format!(
+                "{}:\n--- expected\n{}\n--- actual\n{}",
+                name,
+                String::from_utf8_lossy(&expected),
+                String::from_utf8_lossy(&output)
+            ));
+        }
+    }
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}